@@ -0,0 +1,3 @@
+fn main() {
+    mnemnk_app_lib::run_flow_test()
+}
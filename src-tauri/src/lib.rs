@@ -35,6 +35,9 @@ pub fn run() {
                 mnemnk::settings::init(&app_handle).unwrap_or_else(|e| {
                     panic!("Failed to initialize settings: {}", e);
                 });
+                mnemnk::secrets::init(&app_handle).unwrap_or_else(|e| {
+                    log::error!("Failed to initialize secrets, secret configs cannot be saved: {}", e);
+                });
                 mnemnk::store::init(&app_handle).await.unwrap_or_else(|e| {
                     panic!("Failed to initialize store: {}\nTo restore from a backup, please move `store.db` to some other name and copy a backup file and rename it to `restore.surql` in the data directory.", e);
                 });
@@ -67,26 +70,55 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             exit_app_cmd,
             mnemnk::agent::get_agent_defs_cmd,
+            mnemnk::agent::reload_agent_defs_cmd,
             mnemnk::agent::set_agent_config_cmd,
             mnemnk::agent::start_agent_cmd,
             mnemnk::agent::stop_agent_cmd,
+            mnemnk::agent::get_agent_metrics_cmd,
+            mnemnk::agent::get_agent_queue_stats_cmd,
+            mnemnk::agent::get_agent_traces_cmd,
+            mnemnk::agent::clear_agent_traces_cmd,
             mnemnk::agent::get_agent_flows_cmd,
+            mnemnk::agent::validate_agent_flow_cmd,
             mnemnk::agent::new_agent_flow_cmd,
             mnemnk::agent::rename_agent_flow_cmd,
             mnemnk::agent::delete_agent_flow_cmd,
+            mnemnk::agent::start_agent_flow_cmd,
+            mnemnk::agent::stop_agent_flow_cmd,
+            mnemnk::agent::restart_agent_flow_cmd,
+            mnemnk::agent::set_agent_flow_enabled_cmd,
+            mnemnk::agent::set_agent_flow_trace_cmd,
+            mnemnk::agent::set_agent_flow_variables_cmd,
             mnemnk::agent::add_agent_flow_edge_cmd,
             mnemnk::agent::remove_agent_flow_edge_cmd,
             mnemnk::agent::new_agent_flow_node_cmd,
             mnemnk::agent::add_agent_flow_node_cmd,
             mnemnk::agent::remove_agent_flow_node_cmd,
             mnemnk::agent::import_agent_flow_cmd,
+            mnemnk::agent::export_agent_flow_bundle_cmd,
+            mnemnk::agent::inspect_agent_flow_bundle_cmd,
+            mnemnk::agent::import_agent_flow_bundle_cmd,
+            mnemnk::agent::list_agent_flow_instances_cmd,
+            mnemnk::agent::create_agent_flow_instance_cmd,
+            mnemnk::agent::start_agent_flow_instance_cmd,
+            mnemnk::agent::stop_agent_flow_instance_cmd,
+            mnemnk::agent::delete_agent_flow_instance_cmd,
             mnemnk::agent::save_agent_flow_cmd,
             mnemnk::agent::insert_agent_flow_cmd,
             mnemnk::agent::copy_sub_flow_cmd,
+            mnemnk::agent::get_agent_flow_history_cmd,
+            mnemnk::agent::undo_agent_flow_cmd,
+            mnemnk::agent::redo_agent_flow_cmd,
+            mnemnk::agent::get_agent_flow_revisions_cmd,
+            mnemnk::agent::diff_agent_flow_revision_cmd,
+            mnemnk::agent::restore_agent_flow_revision_cmd,
             mnemnk::settings::get_core_settings_cmd,
             mnemnk::settings::set_core_settings_cmd,
             mnemnk::settings::get_agent_global_configs_cmd,
             mnemnk::settings::set_agent_global_config_cmd,
+            mnemnk::secrets::get_secret_names_cmd,
+            mnemnk::secrets::set_secret_cmd,
+            mnemnk::secrets::remove_secret_cmd,
             mnemnk::store::daily_stats_cmd,
             mnemnk::store::find_events_by_ymd_cmd,
             mnemnk::store::reindex_ymd_cmd,
@@ -141,6 +173,41 @@ pub fn run() {
         });
 }
 
+/// Runs the agent flows without a Tauri window.
+///
+/// `mnemnk-headless --dir <mnemnk_dir> [--events <file|->]`
+pub fn run_headless() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let options = mnemnk::headless::HeadlessOptions::from_args(std::env::args().skip(1))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        });
+
+    tauri::async_runtime::block_on(mnemnk::headless::run(options)).unwrap_or_else(|e| {
+        log::error!("{:#}", e);
+        std::process::exit(1);
+    });
+}
+
+/// Runs flow test cases on a virtual clock, and exits with 1 if any of them failed.
+///
+/// `mnemnk-flow-test [--events] <case.json>...`
+#[cfg(feature = "flow-test")]
+pub fn run_flow_test() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    match mnemnk::agent::run_flow_test_cli(std::env::args().skip(1)) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
 #[tauri::command]
 fn exit_app_cmd(app: AppHandle) -> Result<(), String> {
     // The application will not exit immediately;
@@ -1,8 +1,9 @@
-use anyhow::Result;
-use tauri::{AppHandle, Manager, State};
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result};
 use thiserror::Error;
 
-use crate::mnemnk::settings;
+use crate::mnemnk::store::MnemnkDatabase;
 
 use super::config::AgentConfig;
 use super::data::AgentData;
@@ -34,7 +35,7 @@ pub enum AgentMessage {
 
 pub trait Agent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
@@ -42,7 +43,7 @@ pub trait Agent {
     where
         Self: Sized;
 
-    fn app(&self) -> &AppHandle;
+    fn env(&self) -> &Arc<AgentEnv>;
 
     fn id(&self) -> &str;
 
@@ -63,35 +64,31 @@ pub trait Agent {
 
     // Utility methods
 
-    fn env(&self) -> State<AgentEnv> {
-        self.app().state::<AgentEnv>()
+    fn store(&self) -> Result<MnemnkDatabase> {
+        self.env().host().store().context("Store is not available")
     }
 
     fn global_config(&self) -> Option<AgentConfig> {
-        settings::get_agent_global_config(self.app(), self.def_name())
+        self.env()
+            .host()
+            .agent_global_config(self.def_name())
+            .map(|config| self.env().resolve_secrets(&config))
     }
 
+    /// The global config overridden by the node config, with the secrets resolved.
     fn merged_config(&self) -> Option<AgentConfig> {
-        let mut merged_config = self.global_config().unwrap_or_default();
-        let config = self.config();
-        if let Some(config) = config {
-            for (key, value) in config {
-                merged_config.set(key.clone(), value.clone());
-            }
-        }
-        Some(merged_config)
+        let merged_config = self.env().merged_config(self.def_name(), self.config());
+        Some(self.env().resolve_secrets(&merged_config))
     }
 
+    /// The name of the flow this agent runs in, as in `AgentEnv::flow_of_agent`.
     fn flow_name(&self) -> String {
-        self.id()
-            .split_once(':')
-            .map(|(flow_name, _)| flow_name.to_string())
-            .unwrap_or_default()
+        self.env().flow_of_agent(self.id()).unwrap_or_default()
     }
 }
 
 pub struct AsAgentData {
-    pub app: AppHandle,
+    pub env: Arc<AgentEnv>,
 
     pub id: String,
     pub status: AgentStatus,
@@ -100,9 +97,14 @@ pub struct AsAgentData {
 }
 
 impl AsAgentData {
-    pub fn new(app: AppHandle, id: String, def_name: String, config: Option<AgentConfig>) -> Self {
+    pub fn new(
+        env: Arc<AgentEnv>,
+        id: String,
+        def_name: String,
+        config: Option<AgentConfig>,
+    ) -> Self {
         Self {
-            app,
+            env,
             id,
             status: AgentStatus::Init,
             def_name,
@@ -113,7 +115,7 @@ impl AsAgentData {
 
 pub trait AsAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
@@ -144,18 +146,18 @@ pub trait AsAgent {
 
 impl<T: AsAgent> Agent for T {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
-        let mut agent = T::new(app, id, def_name, config)?;
+        let mut agent = T::new(env, id, def_name, config)?;
         agent.mut_data().status = AgentStatus::Init;
         Ok(agent)
     }
 
-    fn app(&self) -> &AppHandle {
-        &self.data().app
+    fn env(&self) -> &Arc<AgentEnv> {
+        &self.data().env
     }
 
     fn id(&self) -> &str {
@@ -183,6 +185,8 @@ impl<T: AsAgent> Agent for T {
         self.mut_data().status = AgentStatus::Start;
 
         if let Err(e) = self.start() {
+            // not running, so that it can be started again
+            self.mut_data().status = AgentStatus::Init;
             self.env()
                 .emit_error(self.id().to_string(), e.to_string())?;
             return Err(e);
@@ -212,17 +216,16 @@ pub trait AsyncAgent: Agent + Send + Sync + 'static {}
 impl<T: Agent + Send + Sync + 'static> AsyncAgent for T {}
 
 pub fn new_boxed<T: AsyncAgent>(
-    app: AppHandle,
+    env: Arc<AgentEnv>,
     id: String,
     def_name: String,
     config: Option<AgentConfig>,
 ) -> Result<Box<dyn AsyncAgent>> {
-    Ok(Box::new(T::new(app, id, def_name, config)?))
+    Ok(Box::new(T::new(env, id, def_name, config)?))
 }
 
 pub fn agent_new(
-    app: AppHandle,
-    env: &AgentEnv,
+    env: &Arc<AgentEnv>,
     agent_id: String,
     def_name: &str,
     config: Option<AgentConfig>,
@@ -237,18 +240,14 @@ pub fn agent_new(
     }
 
     if let Some(new_boxed) = def.new_boxed {
-        return new_boxed(app, agent_id, def_name.to_string(), config);
+        return new_boxed(env.clone(), agent_id, def_name.to_string(), config);
     }
 
-    match def.kind.as_str() {
-        "Command" => {
-            return new_boxed::<super::builtins::CommandAgent>(
-                app,
-                agent_id,
-                def_name.to_string(),
-                config,
-            );
-        }
+    let new_boxed = match def.kind.as_str() {
+        "Command" => new_boxed::<super::builtins::CommandAgent>,
+        "Wasm" => new_boxed::<super::builtins::WasmAgent>,
+        "Plugin" => new_boxed::<super::builtins::PluginAgent>,
         _ => return Err(AgentError::UnknownDefKind(def.kind.to_string()).into()),
-    }
+    };
+    new_boxed(env.clone(), agent_id, def_name.to_string(), config)
 }
@@ -0,0 +1,329 @@
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context as _, Result};
+use photon_rs::PhotonImage;
+use serde_json::Value;
+
+use super::data::{AgentData, AgentValue, AgentValueMap};
+
+/// Key of the objects referring to a blob in the JSON of command agents, as `{"$blob": "<id>"}`.
+pub const BLOB_KEY: &str = "$blob";
+
+// blobs written by the app are removed after this time, whether the agent has read them or not
+const BLOB_TTL: Duration = Duration::from_secs(60);
+
+// number of recently written images remembered, to write an image sent to many agents only once
+const RECENT_IMAGES: usize = 16;
+
+/// Files holding the images exchanged with command agents, instead of base64 in JSON lines.
+///
+/// The app writes an image as `<dir>/<id>` and sends `{"$blob": "<id>"}` in place of it. Blobs
+/// written by the agents are read and removed on receipt, while the ones written by the app may be
+/// shared by several agents, and are left to expire.
+pub struct BlobStore {
+    dir: PathBuf,
+    counter: AtomicUsize,
+    recent: Mutex<Vec<(Weak<PhotonImage>, String)>>,
+
+    // ids of the blobs written by the app and not expired yet
+    written: Mutex<HashSet<String>>,
+}
+
+impl Default for BlobStore {
+    fn default() -> Self {
+        Self::new(std::env::temp_dir().join(format!("mnemnk-blobs-{}", std::process::id())))
+    }
+}
+
+impl BlobStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            counter: AtomicUsize::new(0),
+            recent: Mutex::new(Vec::new()),
+            written: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Writes the image as a PNG file, and returns its id.
+    pub fn put_image(&self, image: &Arc<PhotonImage>) -> Result<String> {
+        {
+            // the address of a dropped image may be reused by another one
+            let mut recent = self.recent.lock().unwrap();
+            recent.retain(|(w, _)| w.strong_count() > 0);
+            if let Some((_, id)) = recent.iter().find(|(w, id)| {
+                w.upgrade().is_some_and(|a| Arc::ptr_eq(&a, image)) && self.dir.join(id).exists()
+            }) {
+                return Ok(id.clone());
+            }
+        }
+
+        std::fs::create_dir_all(&self.dir).context("Failed to create blob directory")?;
+        self.remove_expired();
+
+        let rgba = image::RgbaImage::from_raw(
+            image.get_width(),
+            image.get_height(),
+            image.get_raw_pixels(),
+        )
+        .context("Invalid image size")?;
+        let mut png = Vec::new();
+        rgba.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .context("Failed to encode image")?;
+
+        let count = self.counter.fetch_add(1, Ordering::Relaxed);
+        let id = format!("{:x}-{}.png", chrono::Utc::now().timestamp_millis(), count);
+        std::fs::write(self.dir.join(&id), png).context("Failed to write blob")?;
+        self.written.lock().unwrap().insert(id.clone());
+
+        let mut recent = self.recent.lock().unwrap();
+        recent.retain(|(w, _)| w.strong_count() > 0);
+        if recent.len() >= RECENT_IMAGES {
+            recent.remove(0);
+        }
+        recent.push((Arc::downgrade(image), id.clone()));
+        Ok(id)
+    }
+
+    /// Reads the image of the blob, and removes the file if an agent wrote it.
+    pub fn take_image(&self, id: &str) -> Result<PhotonImage> {
+        let path = self.path(id)?;
+        let bytes = std::fs::read(&path).with_context(|| format!("Blob not found: {}", id))?;
+        // an agent may send back a blob of its input, which other agents are reading
+        if !self.written.lock().unwrap().contains(id) {
+            let _ = std::fs::remove_file(&path);
+        }
+        let image = image::load_from_memory(&bytes)
+            .with_context(|| format!("Failed to decode blob: {}", id))?
+            .to_rgba8();
+        let (width, height) = image.dimensions();
+        Ok(PhotonImage::new(image.into_raw(), width, height))
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf> {
+        if id.is_empty() || id.contains(['/', '\\']) || id.starts_with('.') {
+            bail!("Invalid blob id: {}", id);
+        }
+        Ok(self.dir.join(id))
+    }
+
+    fn remove_expired(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let now = SystemTime::now();
+        for entry in entries.flatten() {
+            let expired = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|t| now.duration_since(t).unwrap_or_default() > BLOB_TTL);
+            if expired {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+        let mut written = self.written.lock().unwrap();
+        written.retain(|id| self.dir.join(id).exists());
+    }
+
+    /// Removes the directory with all the blobs, when the app quits.
+    pub fn remove_dir(&self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+        self.written.lock().unwrap().clear();
+    }
+
+    /// JSON of the data for command agents, with the images written as blobs.
+    pub fn data_to_json(&self, data: &AgentData) -> Result<Value> {
+        data_to_json_with(data, BLOB_KEY, &mut |image| {
+            Ok(Value::String(self.put_image(image)?))
+        })
+    }
+
+    /// Data from the JSON of command agents, reading the blobs back into images.
+    pub fn data_from_json(&self, kind: &str, value: Value) -> Result<AgentData> {
+        data_from_json_with(kind, value, BLOB_KEY, &mut |id| {
+            let id = id.as_str().context("Invalid blob id")?;
+            self.take_image(id)
+        })
+    }
+}
+
+/// JSON of the data, with each image replaced by `{<key>: <ref>}`, the ref being returned by
+/// `image_ref`.
+pub fn data_to_json_with(
+    data: &AgentData,
+    key: &str,
+    image_ref: &mut impl FnMut(&Arc<PhotonImage>) -> Result<Value>,
+) -> Result<Value> {
+    Ok(serde_json::json!({
+        "kind": data.kind,
+        "value": value_to_json_with(&data.value, key, image_ref)?,
+    }))
+}
+
+fn value_to_json_with(
+    value: &AgentValue,
+    key: &str,
+    image_ref: &mut impl FnMut(&Arc<PhotonImage>) -> Result<Value>,
+) -> Result<Value> {
+    match value {
+        AgentValue::Image(image) => Ok(serde_json::json!({ key: image_ref(image)? })),
+        AgentValue::Array(array) => Ok(Value::Array(
+            array
+                .iter()
+                .map(|v| value_to_json_with(v, key, image_ref))
+                .collect::<Result<_>>()?,
+        )),
+        AgentValue::Object(object) => Ok(Value::Object(
+            object
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), value_to_json_with(v, key, image_ref)?)))
+                .collect::<Result<_>>()?,
+        )),
+        _ => Ok(value.to_json_value()),
+    }
+}
+
+/// Data from the JSON, with the objects `{<key>: <ref>}` resolved by `image` into images.
+pub fn data_from_json_with(
+    kind: &str,
+    value: Value,
+    key: &str,
+    image: &mut impl FnMut(&Value) -> Result<PhotonImage>,
+) -> Result<AgentData> {
+    let value = value_from_json_with(Some(kind), value, key, image)?;
+    Ok(AgentData {
+        kind: kind.to_string(),
+        value,
+    })
+}
+
+// kind is None inside objects, where the values are taken without a kind
+fn value_from_json_with(
+    kind: Option<&str>,
+    value: Value,
+    key: &str,
+    image: &mut impl FnMut(&Value) -> Result<PhotonImage>,
+) -> Result<AgentValue> {
+    if let Some(image_ref) = as_image_ref(&value, key) {
+        return Ok(AgentValue::new_image(image(image_ref)?));
+    }
+    match value {
+        Value::Array(array) if array.iter().any(|v| has_ref(v, key)) => Ok(AgentValue::new_array(
+            array
+                .into_iter()
+                .map(|v| value_from_json_with(kind, v, key, image))
+                .collect::<Result<_>>()?,
+        )),
+        Value::Object(object) if object.values().any(|v| has_ref(v, key)) => {
+            let mut map = AgentValueMap::new();
+            for (k, v) in object {
+                map.insert(k, value_from_json_with(None, v, key, image)?);
+            }
+            Ok(AgentValue::new_object(map))
+        }
+        value => match kind {
+            Some(kind) => AgentValue::from_kind_value(kind, value),
+            None => AgentValue::from_json_value(value),
+        },
+    }
+}
+
+// the ref of an object with the key as its only entry
+fn as_image_ref<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(object) if object.len() == 1 => object.get(key),
+        _ => None,
+    }
+}
+
+fn has_ref(value: &Value, key: &str) -> bool {
+    if as_image_ref(value, key).is_some() {
+        return true;
+    }
+    match value {
+        Value::Array(array) => array.iter().any(|v| has_ref(v, key)),
+        Value::Object(object) => object.values().any(|v| has_ref(v, key)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_round_trip() {
+        let dir = std::env::temp_dir().join(format!("mnemnk-blobs-test-{}", std::process::id()));
+        let store = BlobStore::new(dir.clone());
+
+        let pixels = vec![255u8, 0, 0, 255, 0, 255, 0, 255];
+        let image = Arc::new(PhotonImage::new(pixels.clone(), 2, 1));
+        let data = AgentData::new_object(AgentValueMap::from([
+            ("image".to_string(), AgentValue::Image(image.clone())),
+            ("title".to_string(), AgentValue::new_string("screen")),
+        ]));
+
+        let json = store.data_to_json(&data).unwrap();
+        let blob = json["value"]["image"][BLOB_KEY]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // the same image is written only once
+        assert_eq!(
+            store.data_to_json(&data).unwrap()["value"]["image"][BLOB_KEY],
+            blob.as_str()
+        );
+
+        let value = json["value"].clone();
+        let data = store.data_from_json("object", value).unwrap();
+        let image = data.get_image("image").unwrap();
+        assert_eq!(image.get_width(), 2);
+        assert_eq!(image.get_raw_pixels(), pixels);
+        assert_eq!(data.get_str("title"), Some("screen"));
+
+        // blobs written by the app are kept for the other agents reading them
+        assert!(store.take_image(&blob).is_ok());
+        assert!(store.take_image("../secret").is_err());
+
+        // while the ones written by an agent are removed on receipt
+        std::fs::copy(dir.join(&blob), dir.join("agent.png")).unwrap();
+        assert!(store.take_image("agent.png").is_ok());
+        assert!(store.take_image("agent.png").is_err());
+
+        // strings that look like refs are left as they are
+        let data = store
+            .data_from_json("string", Value::String(format!("blob:{}", blob)))
+            .unwrap();
+        assert_eq!(data.as_str(), Some(format!("blob:{}", blob).as_str()));
+
+        store.remove_dir();
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_dropped_image_is_written_again() {
+        let dir = std::env::temp_dir().join(format!("mnemnk-blobs-drop-{}", std::process::id()));
+        let store = BlobStore::new(dir.clone());
+
+        let image = Arc::new(PhotonImage::new(vec![0u8; 4], 1, 1));
+        let id = store.put_image(&image).unwrap();
+        drop(image);
+
+        // a new image never gets the id of a dropped one, even at the same address
+        let image = Arc::new(PhotonImage::new(vec![255u8; 4], 1, 1));
+        assert_ne!(store.put_image(&image).unwrap(), id);
+        assert_eq!(store.recent.lock().unwrap().len(), 1);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,17 +1,25 @@
 #[cfg(feature = "api")]
 mod implementation {
     use anyhow::{Context as _, Result};
-    use axum::{extract::State, routing::post, Json, Router};
+    use axum::{
+        extract::State,
+        http::header,
+        response::IntoResponse,
+        routing::{get, post},
+        Json, Router,
+    };
     use axum_auth::AuthBearer;
     use serde::{Deserialize, Serialize};
     use serde_json::{json, Value};
     use std::sync::{Arc, Mutex};
-    use tauri::{AppHandle, Manager};
     use tokio::net::TcpListener;
     use tokio::time::Duration;
     use tower_http::timeout::TimeoutLayer;
 
-    use crate::mnemnk::agent::{Agent, AgentConfig, AgentContext, AgentData, AsAgent, AsAgentData};
+    use crate::mnemnk::agent::{
+        to_prometheus_text, Agent, AgentConfig, AgentContext, AgentData, AgentEnv, AsAgent,
+        AsAgentData,
+    };
 
     pub struct ApiAgent {
         data: AsAgentData,
@@ -20,13 +28,13 @@ mod implementation {
 
     impl AsAgent for ApiAgent {
         fn new(
-            app: AppHandle,
+            env: Arc<AgentEnv>,
             id: String,
             def_name: String,
             config: Option<AgentConfig>,
         ) -> Result<Self> {
             Ok(Self {
-                data: AsAgentData::new(app, id, def_name, config),
+                data: AsAgentData::new(env, id, def_name, config),
                 server_handle: Arc::new(Mutex::new(None)),
             })
         }
@@ -59,7 +67,7 @@ mod implementation {
 
     impl ApiAgent {
         fn start_server(&mut self) -> Result<()> {
-            let app_handle = self.app().clone();
+            let env = self.env().clone();
             let agent_id = self.id().to_string();
 
             let global_config = self.global_config().context("no global config")?;
@@ -72,13 +80,14 @@ mod implementation {
                 log::info!("Starting API server on {}", address);
 
                 let app_state = AppState {
-                    app_handle,
+                    env,
                     agent_id,
                     api_key,
                 };
 
                 let app = Router::new()
-                    .route("/out", post(handle_out).with_state(app_state))
+                    .route("/out", post(handle_out).with_state(app_state.clone()))
+                    .route("/metrics", get(handle_metrics).with_state(app_state))
                     .layer((TimeoutLayer::new(Duration::from_secs(10)),));
 
                 if let Ok(listener) = TcpListener::bind(&address).await {
@@ -109,7 +118,7 @@ mod implementation {
 
     #[derive(Clone)]
     struct AppState {
-        app_handle: AppHandle,
+        env: Arc<AgentEnv>,
         agent_id: String,
         api_key: Option<String>,
     }
@@ -138,8 +147,7 @@ mod implementation {
             .map_err(|e| format!("Failed to create AgentData: {}", e))?;
 
         // Get the environment and try to send the output
-        let env = state.app_handle.state::<crate::mnemnk::agent::AgentEnv>();
-        if let Err(e) = env.try_send_agent_out(
+        if let Err(e) = state.env.try_send_agent_out(
             state.agent_id,
             AgentContext::new_with_ch(out_data.ch),
             agent_data,
@@ -150,15 +158,30 @@ mod implementation {
 
         Ok(Json(json!({"status": "ok"})))
     }
+
+    async fn handle_metrics(
+        AuthBearer(token): AuthBearer,
+        State(state): State<AppState>,
+    ) -> Result<impl IntoResponse, String> {
+        // Check API key if configured
+        if let Some(key) = &state.api_key {
+            if token != *key {
+                return Err("Unauthorized".to_string());
+            }
+        }
+
+        let text = to_prometheus_text(&state.env.get_agent_metrics());
+        Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text))
+    }
 }
 
 // Dummy API Agent implementation when the feature is not enabled
 #[cfg(not(feature = "api"))]
 mod implementation {
     use anyhow::Result;
-    use tauri::AppHandle;
+    use std::sync::Arc;
 
-    use crate::mnemnk::agent::{AgentConfig, AgentData, AsAgent, AsAgentData};
+    use crate::mnemnk::agent::{AgentConfig, AgentData, AgentEnv, AsAgent, AsAgentData};
 
     pub struct ApiAgent {
         data: AsAgentData,
@@ -166,13 +189,13 @@ mod implementation {
 
     impl AsAgent for ApiAgent {
         fn new(
-            app: AppHandle,
+            env: Arc<AgentEnv>,
             id: String,
             def_name: String,
             config: Option<AgentConfig>,
         ) -> Result<Self> {
             Ok(Self {
-                data: AsAgentData::new(app, id, def_name, config),
+                data: AsAgentData::new(env, id, def_name, config),
             })
         }
 
@@ -221,7 +244,8 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
                     CONFIG_API_KEY.into(),
                     AgentConfigEntry::new(AgentValue::new_string(""), "string")
                         .with_title("API Key")
-                        .with_description("API key for authentication"),
+                        .with_description("API key for authentication")
+                        .with_secret(),
                 ),
             ]),
     );
@@ -1,10 +1,10 @@
 use anyhow::{Context as _, Result};
-use tauri::AppHandle;
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::{
     Agent, AgentConfig, AgentConfigEntry, AgentContext, AgentData, AgentDefinition,
-    AgentDefinitions, AgentValue, AsAgent, AsAgentData,
+    AgentDefinitions, AgentEnv, AgentValue, AsAgent, AsAgentData,
 };
 
 struct BoardInAgent {
@@ -14,7 +14,7 @@ struct BoardInAgent {
 
 impl AsAgent for BoardInAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
@@ -23,7 +23,7 @@ impl AsAgent for BoardInAgent {
             .as_ref()
             .and_then(|c| c.get_string(CONFIG_BOARD_NAME));
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             board_name,
         })
     }
@@ -73,7 +73,7 @@ struct BoardOutAgent {
 
 impl AsAgent for BoardOutAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
@@ -82,7 +82,7 @@ impl AsAgent for BoardOutAgent {
             .as_ref()
             .and_then(|c| c.get_string(CONFIG_BOARD_NAME));
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             board_name,
         })
     }
@@ -144,13 +144,16 @@ impl AsAgent for BoardOutAgent {
     }
 }
 
-static CONFIG_BOARD_NAME: &str = "$board";
+pub static BOARD_IN_DEF_NAME: &str = "$board_in";
+pub static BOARD_OUT_DEF_NAME: &str = "$board_out";
+
+pub static CONFIG_BOARD_NAME: &str = "$board";
 
 pub fn init_agent_defs(defs: &mut AgentDefinitions) {
     // BoardInAgent
     defs.insert(
-        "$board_in".into(),
-        AgentDefinition::new("Board", "$board_in", Some(new_boxed::<BoardInAgent>))
+        BOARD_IN_DEF_NAME.into(),
+        AgentDefinition::new("Board", BOARD_IN_DEF_NAME, Some(new_boxed::<BoardInAgent>))
             .with_title("Board In")
             .with_category("Core")
             .with_inputs(vec!["*"])
@@ -164,15 +167,18 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
 
     // BoardOutAgent
     defs.insert(
-        "$board_out".into(),
-        AgentDefinition::new("Board", "$board_out", Some(new_boxed::<BoardOutAgent>))
-            .with_title("Board Out")
-            .with_category("Core")
-            .with_outputs(vec!["*"])
-            .with_default_config(vec![(
-                CONFIG_BOARD_NAME.into(),
-                AgentConfigEntry::new(AgentValue::new_string(""), "string")
-                    .with_title("Board Name"),
-            )]),
+        BOARD_OUT_DEF_NAME.into(),
+        AgentDefinition::new(
+            "Board",
+            BOARD_OUT_DEF_NAME,
+            Some(new_boxed::<BoardOutAgent>),
+        )
+        .with_title("Board Out")
+        .with_category("Core")
+        .with_outputs(vec!["*"])
+        .with_default_config(vec![(
+            CONFIG_BOARD_NAME.into(),
+            AgentConfigEntry::new(AgentValue::new_string(""), "string").with_title("Board Name"),
+        )]),
     );
 }
@@ -1,28 +1,36 @@
-use anyhow::{Context as _, Result};
+use anyhow::{bail, Context as _, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::vec;
-use tauri::{AppHandle, Manager};
-use tauri_plugin_shell::process::CommandEvent;
-use tauri_plugin_shell::ShellExt;
 
+use crate::mnemnk::agent::blob::BlobStore;
+use crate::mnemnk::agent::definition::{RestartPolicy, SupervisorConfig};
 use crate::mnemnk::agent::{
     Agent, AgentConfig, AgentContext, AgentData, AgentDefinition, AgentDefinitionError, AgentEnv,
-    AsAgent, AsAgentData,
+    AsAgent, AsAgentData, CommandEvent,
 };
+use crate::mnemnk::secrets;
 
 pub struct CommandAgent {
     data: AsAgentData,
+    supervisor: Arc<Supervisor>,
+
+    // set when the process accepts images as blobs in its .HELLO
+    blob: Arc<AtomicBool>,
 }
 
 impl AsAgent for CommandAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
-        CommandAgent::new(app, id, def_name, config)
+        CommandAgent::new(env, id, def_name, config)
     }
 
     fn data(&self) -> &AsAgentData {
@@ -42,6 +50,9 @@ impl AsAgent for CommandAgent {
         let agent_cmd;
         let agent_args;
         let agent_dir;
+        let def_supervisor;
+        let protocol_version;
+        let secret_keys: Vec<String>;
         {
             let env_defs = env.defs.lock().unwrap();
             if env_defs.contains_key(def_name) {
@@ -56,6 +67,9 @@ impl AsAgent for CommandAgent {
                     .dir
                     .clone()
                     .context(format!("Agent path not found: {}", def_name))?;
+                def_supervisor = def_command.supervisor.clone();
+                protocol_version = def_command.protocol_version;
+                secret_keys = def.secret_keys().into_iter().map(String::from).collect();
             } else {
                 log::error!("Agent {} not found", def_name);
                 return Err(anyhow::anyhow!("Agent not found"));
@@ -66,6 +80,10 @@ impl AsAgent for CommandAgent {
             return Err(anyhow::anyhow!("Agent command.cmd not found"));
         }
 
+        if let Some(version) = protocol_version {
+            check_protocol_version(version)?;
+        }
+
         log::info!("Starting agent: {} {}", def_name, agent_id);
 
         // prepare args
@@ -74,37 +92,108 @@ impl AsAgent for CommandAgent {
         } else {
             agent_args.unwrap().clone().into_iter().collect()
         };
-        let config = self.merged_config();
-        if let Some(config) = config {
-            args.push("-c".to_string());
-            args.push(serde_json::to_string(&config).unwrap());
+        // secrets are sent over stdin, as the command line can be seen by other processes
+        let raw_config = env.merged_config(def_name, self.config());
+        let config = env.resolve_secrets(&raw_config);
+        let mut public_config = AgentConfig::default();
+        let mut has_secrets = false;
+        for (key, value) in &config {
+            if secret_keys.contains(key)
+                || raw_config.get(key).and_then(secrets::secret_ref).is_some()
+            {
+                has_secrets = true;
+            } else {
+                public_config.set(key.clone(), value.clone());
+            }
         }
-
-        // prepare sidecar command
-        let sidecar_command = if args.is_empty() {
-            self.app().shell().command(agent_cmd).current_dir(agent_dir)
-        } else {
-            self.app()
-                .shell()
-                .command(agent_cmd)
-                .args(args)
-                .current_dir(agent_dir)
-        };
+        args.push("-c".to_string());
+        args.push(serde_json::to_string(&public_config).context("Failed to serialize config")?);
 
         // spawn the sidecar command
-        let (mut rx, child) = sidecar_command.spawn().context("Failed to spawn sidecar")?;
+        let (mut rx, child) = env
+            .host()
+            .spawn_command(&agent_cmd, &args, &agent_dir)
+            .context("Failed to spawn sidecar")?;
 
         {
             let mut agent_commands = env.commands.lock().unwrap();
             agent_commands.insert(agent_id.to_string(), child);
+            if let Some(version) = protocol_version {
+                // agents without the version in their definition don't know .HELLO
+                let hello = serde_json::json!({
+                    "protocol_version": version,
+                    "agent_id": agent_id,
+                    "def_name": def_name,
+                    "blob_dir": env.blobs.dir(),
+                });
+                let child = agent_commands.get_mut(agent_id).unwrap();
+                if let Err(e) = child.write(format!(".HELLO {}\n", hello).as_bytes()) {
+                    log::error!("Failed to write to {}: {}", agent_id, e);
+                }
+            }
+            if has_secrets {
+                let json_config =
+                    serde_json::to_string(&config).context("Failed to serialize config")?;
+                let child = agent_commands.get_mut(agent_id).unwrap();
+                if let Err(e) = child.write(format!(".CONFIG {}\n", json_config).as_bytes()) {
+                    log::error!("Failed to set config to {}: {}", agent_id, e);
+                }
+            }
+        }
+
+        // the supervisor of the node takes precedence over the one of the definition
+        let supervisor_config = {
+            let agent_supervisors = env.agent_supervisors.lock().unwrap();
+            agent_supervisors.get(agent_id).cloned()
         }
+        .or(def_supervisor)
+        .unwrap_or_default();
+        let supervisor = self.supervisor.clone();
+        let run = supervisor.start_run();
+        let blob = self.blob.clone();
+        blob.store(false, Ordering::Relaxed);
+
+        env.emit_status(agent_id.to_string(), STATUS_RUNNING, None)
+            .unwrap_or_else(|e| log::error!("Failed to emit status: {}", e));
 
-        let app_handle = self.app().clone();
+        let env = env.clone();
         let agent_id = agent_id.to_string();
         let def_name = def_name.to_string();
-        tauri::async_runtime::spawn(async move {
+        self.env().spawn(async move {
+            // set once the process sends .READY
+            let mut ready = false;
+
+            // cleared once the process answers .HELLO
+            let mut hello_deadline =
+                protocol_version.map(|_| tokio::time::Instant::now() + HELLO_TIMEOUT);
+
             // read events such as stdout
-            while let Some(event) = rx.recv().await {
+            loop {
+                let event = match hello_deadline {
+                    Some(deadline) => tokio::select! {
+                        event = rx.recv() => event,
+                        _ = tokio::time::sleep_until(deadline) => {
+                            hello_deadline = None;
+                            let message = format!(
+                                "Protocol mismatch: {} did not reply to .HELLO within {} seconds",
+                                &def_name,
+                                HELLO_TIMEOUT.as_secs()
+                            );
+                            log::error!("{}: {}", &agent_id, message);
+                            env.emit_error(agent_id.clone(), message.clone())
+                                .unwrap_or_else(|e| log::error!("Failed to emit error: {}", e));
+                            env.emit_status(agent_id.clone(), STATUS_FAILED, Some(message))
+                                .unwrap_or_else(|e| {
+                                    log::error!("Failed to emit status: {}", e);
+                                });
+                            continue;
+                        }
+                    },
+                    None => rx.recv().await,
+                };
+                let Some(event) = event else {
+                    break;
+                };
                 match event {
                     CommandEvent::Stdout(line_bytes) => {
                         if line_bytes.is_empty() || line_bytes[0] != b'.' {
@@ -118,32 +207,116 @@ impl AsAgent for CommandAgent {
                         }
 
                         let line = String::from_utf8_lossy(&line_bytes);
-                        let (cmd, args) = parse_stdout(&line);
-                        match cmd {
-                            ".OUT" => match parse_out_args(args) {
-                                Ok((ctx, ch, data)) => {
-                                    let new_ctx = ctx.with_ch(ch);
-                                    let env = app_handle.state::<AgentEnv>();
-                                    env.send_agent_out(agent_id.clone(), new_ctx, data)
-                                        .await
+                        let blobs = blob.load(Ordering::Relaxed).then_some(&env.blobs);
+                        let command = match parse_command(&line, blobs) {
+                            Ok(command) => command,
+                            Err(e) => {
+                                log::error!("{} {}: {:#}", &def_name, &agent_id, e);
+                                continue;
+                            }
+                        };
+                        match command {
+                            StdoutCommand::Out { ctx, ch, data } => {
+                                let new_ctx = ctx.with_ch(ch);
+                                env.send_agent_out(agent_id.clone(), new_ctx, data)
+                                    .await
+                                    .unwrap_or_else(|e| {
+                                        log::error!("Failed to send agent out: {}", e);
+                                    });
+                            }
+                            StdoutCommand::Log { level, message } => {
+                                log::log!(level, "{} {}: {}", &def_name, &agent_id, message);
+                            }
+                            StdoutCommand::Display { key, data } => {
+                                env.emit_display(agent_id.clone(), key, data)
+                                    .unwrap_or_else(|e| {
+                                        log::error!("Failed to emit display: {}", e);
+                                    });
+                            }
+                            StdoutCommand::Error(message) => {
+                                env.emit_error(agent_id.clone(), message)
+                                    .unwrap_or_else(|e| log::error!("Failed to emit error: {}", e));
+                            }
+                            StdoutCommand::Status { status, message } => {
+                                env.emit_status(agent_id.clone(), &status, message)
+                                    .unwrap_or_else(|e| {
+                                        log::error!("Failed to emit status: {}", e);
+                                    });
+                            }
+                            StdoutCommand::Hello {
+                                protocol_version: agent_version,
+                                capabilities,
+                            } => {
+                                hello_deadline = None;
+                                let expected = protocol_version.unwrap_or(PROTOCOL_VERSION);
+                                if agent_version != expected {
+                                    let message = format!(
+                                        "Protocol version mismatch: {} speaks version {}, but version {} is expected",
+                                        &def_name, agent_version, expected
+                                    );
+                                    log::error!("{}: {}", &agent_id, message);
+                                    env.emit_error(agent_id.clone(), message.clone())
                                         .unwrap_or_else(|e| {
-                                            log::error!("Failed to send agent out: {}", e);
+                                            log::error!("Failed to emit error: {}", e);
+                                        });
+                                    env.emit_status(
+                                        agent_id.clone(),
+                                        STATUS_FAILED,
+                                        Some(message),
+                                    )
+                                    .unwrap_or_else(|e| {
+                                        log::error!("Failed to emit status: {}", e);
+                                    });
+
+                                    // the process should not be restarted with the same version
+                                    supervisor.stop();
+                                    let mut commands = env.commands.lock().unwrap();
+                                    if let Some(child) = commands.get_mut(&agent_id) {
+                                        child.write(".QUIT\n".as_bytes()).unwrap_or_else(|e| {
+                                            log::error!("Failed to write to {}: {}", agent_id, e);
                                         });
+                                    }
+                                } else {
+                                    log::info!(
+                                        "Agent {} {} speaks protocol version {} with capabilities: {:?}",
+                                        &def_name,
+                                        &agent_id,
+                                        agent_version,
+                                        capabilities
+                                    );
+                                    if capabilities.iter().any(|c| c == CAPABILITY_BLOB) {
+                                        blob.store(true, Ordering::Relaxed);
+                                    }
                                 }
-                                Err(e) => {
-                                    log::error!("Failed to parse OUT command: {}", e);
+                            }
+                            StdoutCommand::Ready => {
+                                ready = true;
+                                env.emit_status(agent_id.clone(), STATUS_READY, None)
+                                    .unwrap_or_else(|e| {
+                                        log::error!("Failed to emit status: {}", e);
+                                    });
+                            }
+                            StdoutCommand::Board { name, data } => {
+                                {
+                                    let mut board_data = env.board_data.lock().unwrap();
+                                    board_data.insert(name.clone(), data.clone());
                                 }
-                            },
-                            _ => {
-                                log::error!("Unknown command: {} {}", agent_id, cmd);
+                                let ctx = AgentContext::new_with_ch(name.clone());
+                                env.try_send_board_out(name, ctx, data).unwrap_or_else(|e| {
+                                    log::error!("Failed to send board: {}", e);
+                                });
                             }
                         }
                     }
 
                     CommandEvent::Stderr(line_bytes) => {
                         let line = String::from_utf8_lossy(&line_bytes);
+                        if ready {
+                            // agents speaking the full protocol report errors with .ERROR
+                            log::warn!("stderr from {} {}: {:}", def_name, agent_id, line);
+                            continue;
+                        }
                         log::debug!("stderr from {} {}: {:}", def_name, agent_id, line);
-                        let env = app_handle.state::<AgentEnv>();
                         env.emit_error(agent_id.clone(), line.to_string())
                             .unwrap_or_else(|e| log::error!("Failed to emit error: {}", e));
                     }
@@ -155,28 +328,24 @@ impl AsAgent for CommandAgent {
                             agent_id,
                             status
                         );
-                        let env = app_handle.state::<AgentEnv>();
                         {
                             let mut commands = env.commands.lock().unwrap();
                             commands.remove(&agent_id);
                         }
                         rx.close();
-                        // TODO: Emit an event to the frontend indicating the agent has stopped
+
+                        if !supervisor.is_current(run) {
+                            // stopped by the user, or started again since
+                            return;
+                        }
+                        supervise(env, agent_id, supervisor, run, supervisor_config, status)
+                            .await;
                         return;
                     }
 
                     CommandEvent::Error(e) => {
                         log::error!("CommandEvent Error {} {}: {}", def_name, agent_id, e);
                     }
-
-                    _ => {
-                        log::error!(
-                            "Unknown CommandEvent: {} {} {:?}",
-                            def_name,
-                            agent_id,
-                            event
-                        );
-                    }
                 }
             }
         });
@@ -205,8 +374,12 @@ impl AsAgent for CommandAgent {
     }
 
     fn stop(&mut self) -> Result<()> {
+        self.supervisor.stop();
+
         let agent_id = &self.data.id;
         let env = self.env();
+        env.emit_status(agent_id.clone(), STATUS_STOPPED, None)
+            .unwrap_or_else(|e| log::error!("Failed to emit status: {}", e));
         {
             let mut commands = env.commands.lock().unwrap();
             if let Some(child) = commands.get_mut(agent_id) {
@@ -220,15 +393,20 @@ impl AsAgent for CommandAgent {
 
     fn process(&mut self, ctx: AgentContext, data: AgentData) -> Result<()> {
         #[derive(Debug, Serialize)]
-        struct InData {
+        struct InData<T> {
             ctx: AgentContext,
-            data: AgentData,
+            data: T,
         }
 
-        let in_data = InData { ctx, data };
-        let in_json = serde_json::to_string(&in_data).context("Failed to serialize input data")?;
-
         let env = self.env();
+        let in_json = if self.blob.load(Ordering::Relaxed) {
+            let data = env.blobs.data_to_json(&data)?;
+            serde_json::to_string(&InData { ctx, data })
+        } else {
+            serde_json::to_string(&InData { ctx, data })
+        }
+        .context("Failed to serialize input data")?;
+
         let mut env_commands = env.commands.lock().unwrap();
         let command = env_commands
             .get_mut(self.id())
@@ -241,19 +419,21 @@ impl AsAgent for CommandAgent {
 
 impl CommandAgent {
     pub fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
+            supervisor: Default::default(),
+            blob: Default::default(),
         })
     }
 
     pub fn read_def(
         def: &mut AgentDefinition,
-        agent_dir: &PathBuf,
+        agent_dir: &Path,
     ) -> Result<(), AgentDefinitionError> {
         let command = def.command.as_mut().ok_or_else(|| {
             AgentDefinitionError::MissingEntry(def.name.clone(), "command".into())
@@ -290,6 +470,283 @@ impl CommandAgent {
     }
 }
 
+const STATUS_RUNNING: &str = "running";
+const STATUS_STOPPED: &str = "stopped";
+const STATUS_EXITED: &str = "exited";
+const STATUS_RESTARTING: &str = "restarting";
+const STATUS_FAILED: &str = "failed";
+const STATUS_READY: &str = "ready";
+
+// Restarts of the process of a command agent, kept across the restarts.
+#[derive(Default)]
+struct Supervisor {
+    // set while the agent is stopped, so that its exit is not taken as a crash
+    stopped: AtomicBool,
+
+    // counts the starts, so that the tasks of an earlier run leave the current one alone
+    run: AtomicU64,
+
+    restarts: Mutex<VecDeque<Instant>>,
+}
+
+impl Supervisor {
+    /// Starts a new run of the process, and returns its number.
+    fn start_run(&self) -> u64 {
+        self.stopped.store(false, Ordering::Relaxed);
+        self.run.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Whether the run is the last one started, and the agent has not been stopped since.
+    fn is_current(&self, run: u64) -> bool {
+        !self.stopped.load(Ordering::Relaxed) && self.run.load(Ordering::Relaxed) == run
+    }
+
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.restarts.lock().unwrap().clear();
+    }
+
+    /// Returns the delay before restarting the process which exited with the status,
+    /// or None if it should not be restarted.
+    fn next_restart(
+        &self,
+        config: &SupervisorConfig,
+        status: Option<i32>,
+        now: Instant,
+    ) -> Option<Duration> {
+        match config.restart {
+            RestartPolicy::Never => return None,
+            RestartPolicy::OnFailure if status == Some(0) => return None,
+            _ => {}
+        }
+
+        let mut restarts = self.restarts.lock().unwrap();
+        let window = Duration::from_secs(config.window_secs);
+        while restarts
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > window)
+        {
+            restarts.pop_front();
+        }
+        if restarts.len() >= config.max_restarts {
+            return None;
+        }
+
+        let backoff = config
+            .backoff_ms
+            .saturating_mul(1 << restarts.len().min(32))
+            .min(config.max_backoff_ms);
+        restarts.push_back(now);
+        Some(Duration::from_millis(backoff))
+    }
+}
+
+async fn supervise(
+    env: Arc<AgentEnv>,
+    agent_id: String,
+    supervisor: Arc<Supervisor>,
+    run: u64,
+    config: SupervisorConfig,
+    status: Option<i32>,
+) {
+    let exit_message = match status {
+        Some(code) => format!("exited with status {}", code),
+        None => "terminated by a signal".to_string(),
+    };
+
+    let Some(delay) = supervisor.next_restart(&config, status, Instant::now()) else {
+        let status = if config.restart == RestartPolicy::Never || status == Some(0) {
+            STATUS_EXITED
+        } else {
+            log::error!("Agent {} {}, giving up restarting", agent_id, exit_message);
+            STATUS_FAILED
+        };
+        env.emit_status(agent_id, status, Some(exit_message))
+            .unwrap_or_else(|e| log::error!("Failed to emit status: {}", e));
+        return;
+    };
+
+    log::info!(
+        "Restarting agent {} in {} ms: {}",
+        agent_id,
+        delay.as_millis(),
+        exit_message
+    );
+    env.emit_status(
+        agent_id.clone(),
+        STATUS_RESTARTING,
+        Some(format!(
+            "{}, restarting in {} ms",
+            exit_message,
+            delay.as_millis()
+        )),
+    )
+    .unwrap_or_else(|e| log::error!("Failed to emit status: {}", e));
+
+    tokio::time::sleep(delay).await;
+    // the agent may have been stopped, or stopped and started again by then
+    if !supervisor.is_current(run) {
+        return;
+    }
+
+    let agent = {
+        let agents = env.agents.lock().unwrap();
+        agents.get(&agent_id).cloned()
+    };
+    let Some(agent) = agent else {
+        return;
+    };
+    // starts the process with the merged config at this time
+    let result = agent.lock().unwrap().start();
+    if let Err(e) = result {
+        log::error!("Failed to restart agent {}: {}", agent_id, e);
+        env.emit_status(agent_id, STATUS_FAILED, Some(e.to_string()))
+            .unwrap_or_else(|e| log::error!("Failed to emit status: {}", e));
+    }
+}
+
+/// Version of the stdio protocol between the app and command agents.
+///
+/// It changes when the messages or the serialization of `AgentData` change incompatibly.
+const PROTOCOL_VERSION: u32 = 1;
+
+// time for a process to answer .HELLO before it is reported as not speaking the protocol
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+// capability of agents exchanging images as blobs instead of base64
+const CAPABILITY_BLOB: &str = "blob";
+
+fn check_protocol_version(version: u32) -> Result<()> {
+    if version == 0 || version > PROTOCOL_VERSION {
+        bail!(
+            "Unsupported protocol version {}: this app supports versions 1 to {}",
+            version,
+            PROTOCOL_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Commands sent by the process on its stdout.
+#[derive(Debug)]
+enum StdoutCommand {
+    // .OUT {"ctx": ..., "ch": ..., "data": ...}
+    Out {
+        ctx: AgentContext,
+        ch: String,
+        data: AgentData,
+    },
+
+    // .LOG <level> <message>
+    Log {
+        level: log::Level,
+        message: String,
+    },
+
+    // .DISPLAY <key> <data>
+    Display {
+        key: String,
+        data: AgentData,
+    },
+
+    // .ERROR <message>
+    Error(String),
+
+    // .STATUS {"status": ..., "message": ...}
+    Status {
+        status: String,
+        message: Option<String>,
+    },
+
+    // .HELLO {"protocol_version": ..., "capabilities": [...]}
+    Hello {
+        protocol_version: u32,
+        capabilities: Vec<String>,
+    },
+
+    // .READY
+    Ready,
+
+    // .BOARD <name> <data>
+    Board {
+        name: String,
+        data: AgentData,
+    },
+}
+
+// blobs is given if the agent sends images as blobs
+fn parse_command(line: &str, blobs: Option<&BlobStore>) -> Result<StdoutCommand> {
+    let (cmd, args) = parse_stdout(line);
+    match cmd {
+        ".OUT" => {
+            let (ctx, ch, data) = parse_out_args(args, blobs)?;
+            Ok(StdoutCommand::Out { ctx, ch, data })
+        }
+        ".LOG" => {
+            let (level, message) = parse_stdout(args);
+            let level = level
+                .parse::<log::Level>()
+                .with_context(|| format!("Invalid log level: {}", level))?;
+            Ok(StdoutCommand::Log {
+                level,
+                message: message.to_string(),
+            })
+        }
+        ".DISPLAY" => {
+            let (key, data) = parse_stdout(args);
+            if key.is_empty() {
+                bail!("DISPLAY command has no key");
+            }
+            let data = parse_data(data, blobs).context("Failed to parse DISPLAY command")?;
+            Ok(StdoutCommand::Display {
+                key: key.to_string(),
+                data,
+            })
+        }
+        ".ERROR" => Ok(StdoutCommand::Error(args.to_string())),
+        ".STATUS" => {
+            #[derive(Deserialize)]
+            struct StatusArg {
+                status: String,
+                message: Option<String>,
+            }
+            let arg: StatusArg =
+                serde_json::from_str(args).context("Failed to parse STATUS command")?;
+            Ok(StdoutCommand::Status {
+                status: arg.status,
+                message: arg.message,
+            })
+        }
+        ".HELLO" => {
+            #[derive(Deserialize)]
+            struct HelloArg {
+                protocol_version: u32,
+                #[serde(default)]
+                capabilities: Vec<String>,
+            }
+            let arg: HelloArg =
+                serde_json::from_str(args).context("Failed to parse HELLO command")?;
+            Ok(StdoutCommand::Hello {
+                protocol_version: arg.protocol_version,
+                capabilities: arg.capabilities,
+            })
+        }
+        ".READY" => Ok(StdoutCommand::Ready),
+        ".BOARD" => {
+            let (name, data) = parse_stdout(args);
+            if name.is_empty() {
+                bail!("BOARD command has no name");
+            }
+            let data = parse_data(data, blobs).context("Failed to parse BOARD command")?;
+            Ok(StdoutCommand::Board {
+                name: name.to_string(),
+                data,
+            })
+        }
+        _ => bail!("Unknown command: {}", cmd),
+    }
+}
+
 fn parse_stdout(line: &str) -> (&str, &str) {
     let (cmd, args) = line.split_once(" ").unwrap_or((line, ""));
     (cmd.trim(), args.trim())
@@ -299,11 +756,168 @@ fn parse_stdout(line: &str) -> (&str, &str) {
 struct OutArg {
     ctx: Option<AgentContext>,
     ch: String,
-    data: AgentData,
+    data: serde_json::Value,
 }
 
-fn parse_out_args(args: &str) -> Result<(AgentContext, String, AgentData)> {
-    let data: OutArg = serde_json::from_str(args).context("Failed to parse OUT command")?;
-    let ctx = data.ctx.unwrap_or_default();
-    return Ok((ctx, data.ch, data.data));
+fn parse_out_args(
+    args: &str,
+    blobs: Option<&BlobStore>,
+) -> Result<(AgentContext, String, AgentData)> {
+    let arg: OutArg = serde_json::from_str(args).context("Failed to parse OUT command")?;
+    let ctx = arg.ctx.unwrap_or_default();
+    let data = data_from_json(arg.data, blobs).context("Failed to parse OUT command")?;
+    return Ok((ctx, arg.ch, data));
+}
+
+fn parse_data(json: &str, blobs: Option<&BlobStore>) -> Result<AgentData> {
+    data_from_json(serde_json::from_str(json)?, blobs)
+}
+
+fn data_from_json(json: serde_json::Value, blobs: Option<&BlobStore>) -> Result<AgentData> {
+    let Some(blobs) = blobs else {
+        return Ok(serde_json::from_value(json)?);
+    };
+    let serde_json::Value::Object(mut obj) = json else {
+        bail!("not a JSON object");
+    };
+    let Some(serde_json::Value::String(kind)) = obj.remove("kind") else {
+        bail!("missing kind");
+    };
+    let value = obj.remove("value").context("Missing value")?;
+    blobs.data_from_json(&kind, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(restart: RestartPolicy) -> SupervisorConfig {
+        SupervisorConfig {
+            restart,
+            backoff_ms: 100,
+            max_backoff_ms: 300,
+            max_restarts: 3,
+            window_secs: 60,
+        }
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        let supervisor = Supervisor::default();
+        let now = Instant::now();
+
+        let never = config(RestartPolicy::Never);
+        assert_eq!(supervisor.next_restart(&never, Some(1), now), None);
+
+        let on_failure = config(RestartPolicy::OnFailure);
+        assert_eq!(supervisor.next_restart(&on_failure, Some(0), now), None);
+        assert!(supervisor.next_restart(&on_failure, Some(1), now).is_some());
+        assert!(supervisor.next_restart(&on_failure, None, now).is_some());
+
+        let always = config(RestartPolicy::Always);
+        supervisor.stop();
+        assert!(supervisor.next_restart(&always, Some(0), now).is_some());
+    }
+
+    #[test]
+    fn test_supervisor_runs() {
+        let supervisor = Supervisor::default();
+        let first = supervisor.start_run();
+        assert!(supervisor.is_current(first));
+
+        supervisor.stop();
+        assert!(!supervisor.is_current(first));
+
+        // a task of the first run does not restart the agent started again
+        let second = supervisor.start_run();
+        assert!(!supervisor.is_current(first));
+        assert!(supervisor.is_current(second));
+    }
+
+    #[test]
+    fn test_restart_backoff_and_window() {
+        let supervisor = Supervisor::default();
+        let config = config(RestartPolicy::Always);
+        let now = Instant::now();
+
+        let delays: Vec<_> = (0..4)
+            .map(|_| supervisor.next_restart(&config, Some(1), now))
+            .collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(300)),
+                None,
+            ]
+        );
+
+        // restarts older than the window no longer count
+        let later = now + Duration::from_secs(61);
+        assert_eq!(
+            supervisor.next_restart(&config, Some(1), later),
+            Some(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let out = parse_command(
+            r#".OUT {"ch":"data","data":{"kind":"integer","value":1}}"#,
+            None,
+        );
+        assert!(matches!(out, Ok(StdoutCommand::Out { ch, .. }) if ch == "data"));
+
+        let log = parse_command(".LOG warn disk is almost full", None).unwrap();
+        assert!(matches!(
+            log,
+            StdoutCommand::Log { level: log::Level::Warn, message } if message == "disk is almost full"
+        ));
+        assert!(parse_command(".LOG loud message", None).is_err());
+
+        let display =
+            parse_command(r#".DISPLAY count {"kind":"integer","value":3}"#, None).unwrap();
+        assert!(matches!(
+            display,
+            StdoutCommand::Display { key, data } if key == "count" && data.as_i64() == Some(3)
+        ));
+
+        let status =
+            parse_command(r#".STATUS {"status":"busy","message":"indexing"}"#, None).unwrap();
+        assert!(matches!(
+            status,
+            StdoutCommand::Status { status, message } if status == "busy" && message.as_deref() == Some("indexing")
+        ));
+
+        let board = parse_command(r#".BOARD notes {"kind":"string","value":"hi"}"#, None).unwrap();
+        assert!(matches!(board, StdoutCommand::Board { name, .. } if name == "notes"));
+        assert!(parse_command(".BOARD", None).is_err());
+
+        assert!(matches!(
+            parse_command(".READY", None),
+            Ok(StdoutCommand::Ready)
+        ));
+        assert!(
+            matches!(parse_command(".ERROR oops", None), Ok(StdoutCommand::Error(m)) if m == "oops")
+        );
+        assert!(parse_command(".UNKNOWN", None).is_err());
+    }
+
+    #[test]
+    fn test_hello() {
+        let hello = parse_command(
+            r#".HELLO {"protocol_version":1,"capabilities":["display"]}"#,
+            None,
+        );
+        assert!(matches!(
+            hello,
+            Ok(StdoutCommand::Hello { protocol_version: 1, capabilities }) if capabilities == vec!["display"]
+        ));
+        assert!(parse_command(".HELLO {}", None).is_err());
+
+        assert!(check_protocol_version(PROTOCOL_VERSION).is_ok());
+        assert!(check_protocol_version(0).is_err());
+        assert!(check_protocol_version(PROTOCOL_VERSION + 1).is_err());
+    }
 }
@@ -1,11 +1,11 @@
 use anyhow::{Context as _, Result};
-use tauri::AppHandle;
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_BUILTIN;
 use crate::mnemnk::agent::{
     Agent, AgentConfig, AgentConfigEntry, AgentContext, AgentData, AgentDefinition,
-    AgentDefinitions, AgentOutput, AgentValue, AsAgent, AsAgentData,
+    AgentDefinitions, AgentEnv, AgentOutput, AgentValue, AsAgent, AsAgentData,
 };
 
 // To String
@@ -15,13 +15,13 @@ struct ToStringAgent {
 
 impl AsAgent for ToStringAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -58,13 +58,13 @@ struct ToTextAgent {
 
 impl AsAgent for ToTextAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -101,13 +101,13 @@ struct ToJsonAgent {
 
 impl AsAgent for ToJsonAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -134,13 +134,13 @@ struct FromJsonAgent {
 
 impl AsAgent for FromJsonAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -170,13 +170,13 @@ struct GetPropertyAgent {
 
 impl AsAgent for GetPropertyAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
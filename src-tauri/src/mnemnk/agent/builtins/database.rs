@@ -1,11 +1,11 @@
 use anyhow::{bail, Context as _, Ok, Result};
-use tauri::AppHandle;
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_DATABASE;
 use crate::mnemnk::agent::{
     Agent, AgentConfig, AgentConfigEntry, AgentContext, AgentData, AgentDefinition,
-    AgentDefinitions, AgentOutput, AgentValue, AgentValueMap, AsAgent, AsAgentData,
+    AgentDefinitions, AgentEnv, AgentOutput, AgentValue, AgentValueMap, AsAgent, AsAgentData,
 };
 use crate::mnemnk::store;
 
@@ -16,13 +16,13 @@ struct EventDatabaseAgent {
 
 impl AsAgent for EventDatabaseAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -35,7 +35,7 @@ impl AsAgent for EventDatabaseAgent {
     }
 
     fn process(&mut self, _ctx: AgentContext, data: AgentData) -> Result<()> {
-        store::create_event(self.app(), data)
+        store::create_event(&self.store()?, data)
     }
 }
 
@@ -46,13 +46,13 @@ struct DatabaseDeleteAgent {
 
 impl AsAgent for DatabaseDeleteAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -74,7 +74,7 @@ impl AsAgent for DatabaseDeleteAgent {
             bail!("key is empty");
         }
 
-        let result = store::delete(self.app(), db, table, key.to_string(), return_before)?;
+        let result = store::delete(&self.store()?, db, table, key.to_string(), return_before)?;
         if return_before {
             if let Some(json_value) = result {
                 let value = AgentValue::from_json_value(json_value)?;
@@ -100,13 +100,13 @@ struct DatabaseInsertAgent {
 
 impl AsAgent for DatabaseInsertAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -123,7 +123,7 @@ impl AsAgent for DatabaseInsertAgent {
         let (db, table) = get_db_table(config)?;
         let (key, value) = get_kv(&data)?;
         let json_value = value.to_json_value();
-        store::insert(self.app(), db, table, key, json_value)?;
+        store::insert(&self.store()?, db, table, key, json_value)?;
 
         self.try_output(ctx, CH_KV, data)
     }
@@ -136,13 +136,13 @@ struct DatabaseQueryAgent {
 
 impl AsAgent for DatabaseQueryAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -186,7 +186,7 @@ impl AsAgent for DatabaseQueryAgent {
             bail!("data is not a string or object");
         }
 
-        let result = store::query(self.app(), db, query, bindings)?;
+        let result = store::query(&self.store()?, db, query, bindings)?;
         let mut arr = Vec::with_capacity(result.len());
         for r in result.into_iter() {
             let value = AgentValue::from_json_value(r)?;
@@ -205,13 +205,13 @@ struct DatabaseSelectAgent {
 
 impl AsAgent for DatabaseSelectAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -232,7 +232,7 @@ impl AsAgent for DatabaseSelectAgent {
             bail!("key is empty");
         }
 
-        let result = store::select(self.app(), db, table, key.to_string())?;
+        let result = store::select(&self.store()?, db, table, key.to_string())?;
         if let Some(json_value) = result {
             let value = AgentValue::from_json_value(json_value)?;
             let kv_data = new_kv_data(key, value);
@@ -251,13 +251,13 @@ struct DatabaseUpdateAgent {
 
 impl AsAgent for DatabaseUpdateAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -274,7 +274,7 @@ impl AsAgent for DatabaseUpdateAgent {
         let (db, table) = get_db_table(config)?;
         let (key, value) = get_kv(&data)?;
         let json_value = value.to_json_value();
-        store::update(self.app(), db, table, key, json_value)?;
+        store::update(&self.store()?, db, table, key, json_value)?;
 
         self.try_output(ctx, CH_KV, data)
     }
@@ -287,13 +287,13 @@ struct DatabaseUpdateMergeAgent {
 
 impl AsAgent for DatabaseUpdateMergeAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -313,8 +313,14 @@ impl AsAgent for DatabaseUpdateMergeAgent {
         let json_value = value.to_json_value();
 
         if return_after {
-            let result =
-                store::update_merge(self.app(), db, table, key.clone(), json_value, return_after)?;
+            let result = store::update_merge(
+                &self.store()?,
+                db,
+                table,
+                key.clone(),
+                json_value,
+                return_after,
+            )?;
             if let Some(json_value) = result {
                 let value = AgentValue::from_json_value(json_value)?;
                 let kv_data = new_kv_data(key, value);
@@ -324,7 +330,7 @@ impl AsAgent for DatabaseUpdateMergeAgent {
             }
         } else {
             // return_after is false
-            store::update_merge(self.app(), db, table, key, json_value, return_after)?;
+            store::update_merge(&self.store()?, db, table, key, json_value, return_after)?;
             self.try_output(ctx, CH_KV, AgentData::new_unit())?;
         }
 
@@ -339,13 +345,13 @@ struct DatabaseUpsertAgent {
 
 impl AsAgent for DatabaseUpsertAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -362,7 +368,7 @@ impl AsAgent for DatabaseUpsertAgent {
         let (db, table) = get_db_table(config)?;
         let (key, value) = get_kv(&data)?;
         let json_value = value.to_json_value();
-        store::upsert(self.app(), db, table, key, json_value)?;
+        store::upsert(&self.store()?, db, table, key, json_value)?;
 
         self.try_output(ctx, CH_KV, data)
     }
@@ -375,13 +381,13 @@ struct DatabaseUpsertMergeAgent {
 
 impl AsAgent for DatabaseUpsertMergeAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -401,8 +407,14 @@ impl AsAgent for DatabaseUpsertMergeAgent {
         let json_value = value.to_json_value();
 
         if return_after {
-            let result =
-                store::upsert_merge(self.app(), db, table, key.clone(), json_value, return_after)?;
+            let result = store::upsert_merge(
+                &self.store()?,
+                db,
+                table,
+                key.clone(),
+                json_value,
+                return_after,
+            )?;
             if let Some(json_value) = result {
                 let value = AgentValue::from_json_value(json_value)?;
                 let kv_data = new_kv_data(key, value);
@@ -412,7 +424,7 @@ impl AsAgent for DatabaseUpsertMergeAgent {
             }
         } else {
             // return_after is false
-            store::upsert_merge(self.app(), db, table, key, json_value, return_after)?;
+            store::upsert_merge(&self.store()?, db, table, key, json_value, return_after)?;
             self.try_output(ctx, CH_KV, AgentData::new_unit())?;
         }
 
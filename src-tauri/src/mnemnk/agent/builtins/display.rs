@@ -1,11 +1,12 @@
 use anyhow::Result;
-use tauri::AppHandle;
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_BUILTIN;
 use crate::mnemnk::agent::{
     AgentConfig, AgentContext, AgentData, AgentDefinition, AgentDefinitions,
-    AgentDisplayConfigEntry, AgentOutput, AgentValue, AgentValueMap, AsAgent, AsAgentData,
+    AgentDisplayConfigEntry, AgentEnv, AgentOutput, AgentValue, AgentValueMap, AsAgent,
+    AsAgentData,
 };
 
 // Display Data
@@ -15,13 +16,13 @@ struct DisplayDataAgent {
 
 impl AsAgent for DisplayDataAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -45,13 +46,13 @@ struct DebugDataAgent {
 
 impl AsAgent for DebugDataAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
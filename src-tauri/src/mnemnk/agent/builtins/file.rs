@@ -1,12 +1,12 @@
 use anyhow::{bail, Context as _, Result};
 use std::fs;
 use std::path::Path;
-use tauri::AppHandle;
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_BUILTIN;
 use crate::mnemnk::agent::{
-    AgentConfig, AgentContext, AgentData, AgentDefinition, AgentDefinitions, AgentOutput,
+    AgentConfig, AgentContext, AgentData, AgentDefinition, AgentDefinitions, AgentEnv, AgentOutput,
     AgentValue, AsAgent, AsAgentData,
 };
 
@@ -17,13 +17,13 @@ struct ListFilesAgent {
 
 impl AsAgent for ListFilesAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -69,13 +69,13 @@ struct ReadTextFileAgent {
 
 impl AsAgent for ReadTextFileAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -113,13 +113,13 @@ struct WriteTextFileAgent {
 
 impl AsAgent for WriteTextFileAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
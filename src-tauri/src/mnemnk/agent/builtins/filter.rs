@@ -1,12 +1,12 @@
 use anyhow::{bail, Context as _, Result};
 use regex::RegexSet;
-use tauri::AppHandle;
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_BUILTIN;
 use crate::mnemnk::agent::{
     Agent, AgentConfig, AgentConfigEntry, AgentContext, AgentData, AgentDefinition,
-    AgentDefinitions, AgentOutput, AgentValue, AsAgent, AsAgentData,
+    AgentDefinitions, AgentEnv, AgentOutput, AgentValue, AsAgent, AsAgentData,
 };
 
 /// `BooleanFilterAgent` filters data based on a boolean condition.
@@ -17,13 +17,13 @@ struct BooleanFilterAgent {
 
 impl AsAgent for BooleanFilterAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -107,7 +107,7 @@ impl RegexListFilterAgent {
 
 impl AsAgent for RegexListFilterAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
@@ -118,7 +118,7 @@ impl AsAgent for RegexListFilterAgent {
             .unwrap_or_default();
 
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             regex_set: Self::parse_regex_list(&allow_list),
         })
     }
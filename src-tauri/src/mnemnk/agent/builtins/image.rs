@@ -1,12 +1,12 @@
 use anyhow::{Context as _, Result};
 use photon_rs::PhotonImage;
-use tauri::AppHandle;
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_BUILTIN;
 use crate::mnemnk::agent::{
     Agent, AgentConfig, AgentConfigEntry, AgentContext, AgentData, AgentDefinition,
-    AgentDefinitions, AgentOutput, AgentValue, AsAgent, AsAgentData,
+    AgentDefinitions, AgentEnv, AgentOutput, AgentValue, AsAgent, AsAgentData,
 };
 
 // Image Crop
@@ -17,13 +17,13 @@ struct ImageCropAgent {
 
 impl AsAgent for ImageCropAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             bounding: None,
         })
     }
@@ -98,13 +98,13 @@ struct ImageScaleDownAgent {
 
 impl AsAgent for ImageScaleDownAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -195,6 +195,8 @@ static CATEGORY: &str = "Core/Image";
 static CH_BOUNDING: &str = "bounding";
 static CH_IMAGE: &str = "image";
 
+static KIND_IMAGE: &str = "image";
+
 static CONFIG_MAX_WIDTH: &str = "width";
 static CONFIG_MAX_HEIGHT: &str = "height";
 
@@ -209,7 +211,9 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         .with_title("Image Crop")
         .with_category(CATEGORY)
         .with_inputs(vec![CH_IMAGE, CH_BOUNDING])
-        .with_outputs(vec![CH_IMAGE]),
+        .with_required_inputs(vec![CH_IMAGE, CH_BOUNDING])
+        .with_outputs(vec![CH_IMAGE])
+        .with_output_kinds(vec![(CH_IMAGE, KIND_IMAGE)]),
     );
 
     defs.insert(
@@ -222,15 +226,19 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         .with_title("Image Scale Down")
         .with_category(CATEGORY)
         .with_inputs(vec![CH_IMAGE])
+        .with_required_inputs(vec![CH_IMAGE])
         .with_outputs(vec![CH_IMAGE])
+        .with_output_kinds(vec![(CH_IMAGE, KIND_IMAGE)])
         .with_default_config(vec![
             (
                 CONFIG_MAX_WIDTH.into(),
-                AgentConfigEntry::new(AgentValue::new_integer(0), "integer"),
+                AgentConfigEntry::new(AgentValue::new_integer(0), "integer")
+                    .with_range(Some(0.0), None),
             ),
             (
                 CONFIG_MAX_HEIGHT.into(),
-                AgentConfigEntry::new(AgentValue::new_integer(0), "integer"),
+                AgentConfigEntry::new(AgentValue::new_integer(0), "integer")
+                    .with_range(Some(0.0), None),
             ),
         ]),
     );
@@ -11,14 +11,21 @@ mod filter;
 mod image;
 mod input;
 mod operator;
+mod plugin;
 mod rhai_script;
 mod rig;
 mod stream;
 mod string;
+mod subflow;
 mod time;
 mod utils;
+mod wasm;
 
+pub(super) use board::{BOARD_IN_DEF_NAME, BOARD_OUT_DEF_NAME, CONFIG_BOARD_NAME};
 pub(super) use command::CommandAgent;
+pub(super) use plugin::{PluginAgent, PluginConfig};
+pub(super) use subflow::{restart_subflows, CONFIG_FLOW as CONFIG_SUBFLOW, SUBFLOW_DEF_NAME};
+pub(super) use wasm::WasmAgent;
 
 pub fn init_agent_defs(defs: &mut AgentDefinitions) {
     api::init_agent_defs(defs);
@@ -35,6 +42,7 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
     rig::init_agent_defs(defs);
     stream::init_agent_defs(defs);
     string::init_agent_defs(defs);
+    subflow::init_agent_defs(defs);
     time::init_agent_defs(defs);
     utils::init_agent_defs(defs);
 }
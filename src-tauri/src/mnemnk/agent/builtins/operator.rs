@@ -1,11 +1,11 @@
 use anyhow::Result;
-use tauri::AppHandle;
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_BUILTIN;
 use crate::mnemnk::agent::{
-    AgentConfig, AgentContext, AgentData, AgentDefinition, AgentDefinitions, AgentOutput, AsAgent,
-    AsAgentData,
+    AgentConfig, AgentContext, AgentData, AgentDefinition, AgentDefinitions, AgentEnv, AgentOutput,
+    AsAgent, AsAgentData,
 };
 
 // Latest agent
@@ -16,13 +16,13 @@ struct LatestAgent {
 
 impl AsAgent for LatestAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             latest: None,
         })
     }
@@ -63,13 +63,13 @@ struct SampleAgent {
 
 impl AsAgent for SampleAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             latest: None,
         })
     }
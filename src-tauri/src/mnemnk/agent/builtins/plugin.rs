@@ -0,0 +1,663 @@
+//! Agents implemented in native shared libraries.
+//!
+//! A plugin is a shared library placed in `agents/<name>/`, with `"plugin": { "lib": "<name>" }`
+//! in its `mnemnk.json` instead of `agents`. The file name is the platform one for the library
+//! name, such as `lib<name>.so`, `lib<name>.dylib` or `<name>.dll`.
+//!
+//! The library exports these C functions. Strings are NUL terminated UTF-8, and the ones given to
+//! a function are only valid during the call. A non-zero `int32_t` means an error, whose message
+//! should be reported through `error` of the host beforehand.
+//!
+//! ```c
+//! uint32_t mnemnk_plugin_abi_version(void);          // PLUGIN_ABI_VERSION
+//! const char *mnemnk_plugin_definitions(void);       // JSON array of agent definitions, kept alive
+//! void *mnemnk_agent_new(const char *def_name, const char *agent_id, const char *config_json,
+//!                        const MnemnkHost *host);   // NULL on failure
+//! int32_t mnemnk_agent_set_config(void *agent, const char *config_json);
+//! int32_t mnemnk_agent_start(void *agent);
+//! int32_t mnemnk_agent_stop(void *agent);
+//! int32_t mnemnk_agent_process(void *agent, const char *ctx_json, const char *data_json,
+//!                              const MnemnkImage *images, size_t n_images);
+//! void mnemnk_agent_free(void *agent);
+//! ```
+//!
+//! Data is passed as the JSON of `AgentData`, except that images are passed as RGBA8 pixels in
+//! `images`, and referred to as `{"$image": <index>}` in the JSON. Other values are serialized on
+//! every call, so large payloads are better sent as images. The host and its functions stay valid
+//! until `mnemnk_agent_free`, and can be called from any thread. Calls to an agent are never made
+//! concurrently.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context as _, Result};
+use photon_rs::PhotonImage;
+use serde::{Deserialize, Serialize};
+
+use crate::mnemnk::agent::blob::{data_from_json_with, data_to_json_with};
+use crate::mnemnk::agent::{
+    Agent, AgentConfig, AgentContext, AgentData, AgentDefinition, AgentEnv, AsAgent, AsAgentData,
+};
+
+/// Version of the C ABI between the app and plugins.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+static AGENT_KIND_PLUGIN: &str = "Plugin";
+
+// key of the objects referring to an image in `images`, as {"$image": <index>}
+const IMAGE_REF_KEY: &str = "$image";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PluginConfig {
+    // library name in mnemnk.json, and the path of the file once loaded
+    pub lib: String,
+}
+
+#[repr(C)]
+pub struct MnemnkImage {
+    pub width: u32,
+    pub height: u32,
+
+    // RGBA8, width * height * 4 bytes
+    pub pixels: *const u8,
+    pub len: usize,
+}
+
+#[repr(C)]
+pub struct MnemnkHost {
+    pub host_data: *mut c_void,
+
+    pub output: extern "C" fn(
+        host_data: *mut c_void,
+        ch: *const c_char,
+        ctx_json: *const c_char,
+        data_json: *const c_char,
+        images: *const MnemnkImage,
+        n_images: usize,
+    ) -> i32,
+
+    pub display: extern "C" fn(
+        host_data: *mut c_void,
+        key: *const c_char,
+        data_json: *const c_char,
+        images: *const MnemnkImage,
+        n_images: usize,
+    ) -> i32,
+
+    pub error: extern "C" fn(host_data: *mut c_void, message: *const c_char) -> i32,
+}
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type DefinitionsFn = unsafe extern "C" fn() -> *const c_char;
+type AgentNewFn = unsafe extern "C" fn(
+    def_name: *const c_char,
+    agent_id: *const c_char,
+    config_json: *const c_char,
+    host: *const MnemnkHost,
+) -> *mut c_void;
+type AgentSetConfigFn = unsafe extern "C" fn(agent: *mut c_void, config_json: *const c_char) -> i32;
+type AgentCallFn = unsafe extern "C" fn(agent: *mut c_void) -> i32;
+type AgentProcessFn = unsafe extern "C" fn(
+    agent: *mut c_void,
+    ctx_json: *const c_char,
+    data_json: *const c_char,
+    images: *const MnemnkImage,
+    n_images: usize,
+) -> i32;
+type AgentFreeFn = unsafe extern "C" fn(agent: *mut c_void);
+
+// Loaded library with its functions.
+struct PluginLibrary {
+    definitions: DefinitionsFn,
+    agent_new: AgentNewFn,
+    agent_set_config: AgentSetConfigFn,
+    agent_start: AgentCallFn,
+    agent_stop: AgentCallFn,
+    agent_process: AgentProcessFn,
+    agent_free: AgentFreeFn,
+
+    // keeps the functions above valid, None for functions linked into the app
+    _library: Option<libloading::Library>,
+}
+
+// path -> modification time of the file and the library loaded from it
+//
+// A library is loaded again when its file has been modified, and the old one is unloaded once the
+// agents running its code have been dropped.
+static LIBRARIES: LazyLock<Mutex<HashMap<PathBuf, (Option<SystemTime>, Arc<PluginLibrary>)>>> =
+    LazyLock::new(Default::default);
+
+// number of the copies of modified libraries, to give each a new path
+static RELOADS: AtomicUsize = AtomicUsize::new(0);
+
+impl PluginLibrary {
+    fn load(path: &Path) -> Result<Self> {
+        // SAFETY: plugins are trusted like the agents run as commands, and the symbols are
+        // declared with the types of the ABI documented above.
+        unsafe {
+            let library = libloading::Library::new(path)
+                .with_context(|| format!("Failed to load plugin: {}", path.display()))?;
+            let abi_version = library.get::<AbiVersionFn>(b"mnemnk_plugin_abi_version\0")?();
+            check_abi_version(abi_version, path)?;
+            Ok(Self {
+                definitions: *library.get(b"mnemnk_plugin_definitions\0")?,
+                agent_new: *library.get(b"mnemnk_agent_new\0")?,
+                agent_set_config: *library.get(b"mnemnk_agent_set_config\0")?,
+                agent_start: *library.get(b"mnemnk_agent_start\0")?,
+                agent_stop: *library.get(b"mnemnk_agent_stop\0")?,
+                agent_process: *library.get(b"mnemnk_agent_process\0")?,
+                agent_free: *library.get(b"mnemnk_agent_free\0")?,
+                _library: Some(library),
+            })
+        }
+    }
+
+    fn get(path: &Path) -> Result<Arc<Self>> {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut libraries = LIBRARIES.lock().unwrap();
+        if let Some((loaded, library)) = libraries.get(path) {
+            if *loaded == modified {
+                return Ok(library.clone());
+            }
+        }
+        let library = if libraries.contains_key(path) {
+            Self::load_copy(path)?
+        } else {
+            Self::load(path)?
+        };
+        let library = Arc::new(library);
+        libraries.insert(path.to_path_buf(), (modified, library.clone()));
+        Ok(library)
+    }
+
+    // The old library stays loaded while its agents run, and loading the same path again returns
+    // it, so a modified library is loaded from a copy of its file at a new path.
+    fn load_copy(path: &Path) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("mnemnk-plugins-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).context("Failed to create plugin copy directory")?;
+        let file_name = path.file_name().context("Invalid plugin path")?;
+        let copy = dir.join(format!(
+            "{}-{}",
+            RELOADS.fetch_add(1, Ordering::Relaxed),
+            file_name.to_string_lossy()
+        ));
+        std::fs::copy(path, &copy)
+            .with_context(|| format!("Failed to copy plugin: {}", path.display()))?;
+        let library = Self::load(&copy);
+        // the loaded library stays mapped after its file is removed, except on Windows where the
+        // file cannot be removed while it is loaded
+        let _ = std::fs::remove_file(&copy);
+        library
+    }
+
+    fn definitions(path: &Path) -> Result<Vec<AgentDefinition>> {
+        let library = Self::get(path)?;
+        // SAFETY: see load
+        let json = unsafe {
+            let json = (library.definitions)();
+            if json.is_null() {
+                bail!("Plugin has no definitions: {}", path.display());
+            }
+            CStr::from_ptr(json).to_string_lossy().into_owned()
+        };
+        serde_json::from_str(&json).context("Failed to parse plugin definitions")
+    }
+}
+
+pub struct PluginAgent {
+    data: AsAgentData,
+    library: Arc<PluginLibrary>,
+    agent: PluginHandle,
+
+    // host_data of the plugin agent, freed after it
+    _host: Box<PluginHost>,
+}
+
+// Agent pointer of the plugin.
+struct PluginHandle(*mut c_void);
+
+// SAFETY: the ABI requires plugin agents to be usable from any thread, and calls to them are
+// serialized by the mutex of the agent.
+unsafe impl Send for PluginHandle {}
+unsafe impl Sync for PluginHandle {}
+
+// SAFETY: host_data only points to the PluginHost itself, and the functions are thread safe.
+unsafe impl Send for PluginHost {}
+unsafe impl Sync for PluginHost {}
+
+// Passed to the plugin agent as host_data.
+struct PluginHost {
+    env: Arc<AgentEnv>,
+    agent_id: String,
+    host: MnemnkHost,
+}
+
+impl AsAgent for PluginAgent {
+    fn new(
+        env: Arc<AgentEnv>,
+        id: String,
+        def_name: String,
+        config: Option<AgentConfig>,
+    ) -> Result<Self> {
+        let lib = {
+            let defs = env.defs.lock().unwrap();
+            let def = defs
+                .get(&def_name)
+                .with_context(|| format!("Agent {} not found", def_name))?;
+            def.plugin
+                .as_ref()
+                .map(|p| PathBuf::from(&p.lib))
+                .context("Agent has no plugin")?
+        };
+        let library = PluginLibrary::get(&lib)?;
+
+        let mut host = Box::new(PluginHost {
+            env: env.clone(),
+            agent_id: id.clone(),
+            host: MnemnkHost {
+                host_data: std::ptr::null_mut(),
+                output: host_output,
+                display: host_display,
+                error: host_error,
+            },
+        });
+        host.host.host_data = &mut *host as *mut PluginHost as *mut c_void;
+
+        let data = AsAgentData::new(env, id, def_name, config);
+        let config_json = config_json(merged_config(&data))?;
+        let def_name = CString::new(data.def_name.as_str())?;
+        let agent_id = CString::new(data.id.as_str())?;
+        // SAFETY: the strings live during the call, and the host is kept until the agent is freed
+        let agent = unsafe {
+            (library.agent_new)(
+                def_name.as_ptr(),
+                agent_id.as_ptr(),
+                config_json.as_ptr(),
+                &host.host,
+            )
+        };
+        if agent.is_null() {
+            bail!("Failed to create plugin agent: {}", data.def_name);
+        }
+
+        Ok(Self {
+            data,
+            library,
+            agent: PluginHandle(agent),
+            _host: host,
+        })
+    }
+
+    fn data(&self) -> &AsAgentData {
+        &self.data
+    }
+
+    fn mut_data(&mut self) -> &mut AsAgentData {
+        &mut self.data
+    }
+
+    fn set_config(&mut self, _config: AgentConfig) -> Result<()> {
+        let config_json = config_json(self.merged_config())?;
+        // SAFETY: the agent is alive until drop
+        let result = unsafe { (self.library.agent_set_config)(self.agent.0, config_json.as_ptr()) };
+        check_result(result, "set_config")
+    }
+
+    fn start(&mut self) -> Result<()> {
+        // SAFETY: the agent is alive until drop
+        let result = unsafe { (self.library.agent_start)(self.agent.0) };
+        check_result(result, "start")
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        // SAFETY: the agent is alive until drop
+        let result = unsafe { (self.library.agent_stop)(self.agent.0) };
+        check_result(result, "stop")
+    }
+
+    fn process(&mut self, ctx: AgentContext, data: AgentData) -> Result<()> {
+        let ctx_json = CString::new(serde_json::to_string(&ctx)?)?;
+        let mut images = Vec::new();
+        let data_json = data_to_json_with(&data, IMAGE_REF_KEY, &mut |image| {
+            images.push(image.clone());
+            Ok(serde_json::Value::from(images.len() - 1))
+        })?;
+        let data_json = CString::new(data_json.to_string())?;
+        // a copy of the pixels, which is much cheaper than encoding them
+        let pixels: Vec<Vec<u8>> = images.iter().map(|image| image.get_raw_pixels()).collect();
+        let images: Vec<MnemnkImage> = images
+            .iter()
+            .zip(pixels.iter())
+            .map(|(image, pixels)| MnemnkImage {
+                width: image.get_width(),
+                height: image.get_height(),
+                pixels: pixels.as_ptr(),
+                len: pixels.len(),
+            })
+            .collect();
+
+        // SAFETY: the strings and the pixels of the images live during the call
+        let result = unsafe {
+            (self.library.agent_process)(
+                self.agent.0,
+                ctx_json.as_ptr(),
+                data_json.as_ptr(),
+                images.as_ptr(),
+                images.len(),
+            )
+        };
+        check_result(result, "process")
+    }
+}
+
+impl Drop for PluginAgent {
+    fn drop(&mut self) {
+        // SAFETY: the agent is not used after this
+        unsafe { (self.library.agent_free)(self.agent.0) };
+    }
+}
+
+impl PluginAgent {
+    /// Loads the library of the plugin in the agent dir, and returns its agent definitions.
+    pub fn read_defs(plugin: &PluginConfig, agent_dir: &Path) -> Result<Vec<AgentDefinition>> {
+        if plugin.lib.is_empty() || plugin.lib.contains(['/', '\\']) {
+            bail!("Invalid plugin lib: {}", plugin.lib);
+        }
+        let path = agent_dir.join(libloading::library_filename(&plugin.lib));
+        let mut defs = PluginLibrary::definitions(&path)?;
+        for def in defs.iter_mut() {
+            def.kind = AGENT_KIND_PLUGIN.to_string();
+            // the agents run the code of the library synchronously, which may take long
+            if def.native_thread.is_none() {
+                def.native_thread = Some(true);
+            }
+            def.plugin = Some(PluginConfig {
+                lib: path.to_string_lossy().to_string(),
+            });
+        }
+        Ok(defs)
+    }
+}
+
+fn check_abi_version(abi_version: u32, path: &Path) -> Result<()> {
+    if abi_version != PLUGIN_ABI_VERSION {
+        bail!(
+            "Unsupported plugin ABI version {} of {}: this app supports version {}",
+            abi_version,
+            path.display(),
+            PLUGIN_ABI_VERSION
+        );
+    }
+    Ok(())
+}
+
+fn merged_config(data: &AsAgentData) -> AgentConfig {
+    let merged_config = data.env.merged_config(&data.def_name, data.config.as_ref());
+    data.env.resolve_secrets(&merged_config)
+}
+
+fn config_json(config: impl Into<Option<AgentConfig>>) -> Result<CString> {
+    let json = serde_json::to_string(&config.into().unwrap_or_default())
+        .context("Failed to serialize config")?;
+    Ok(CString::new(json)?)
+}
+
+fn check_result(result: i32, name: &str) -> Result<()> {
+    if result != 0 {
+        bail!("Plugin agent failed in {} with {}", name, result);
+    }
+    Ok(())
+}
+
+// Host functions called by plugins
+
+extern "C" fn host_output(
+    host_data: *mut c_void,
+    ch: *const c_char,
+    ctx_json: *const c_char,
+    data_json: *const c_char,
+    images: *const MnemnkImage,
+    n_images: usize,
+) -> i32 {
+    // SAFETY: host_data is the PluginHost given to the agent, and the rest follows the ABI
+    let result = unsafe {
+        let host = &*(host_data as *const PluginHost);
+        (|| -> Result<()> {
+            let ch = str_arg(ch).context("ch is null")?;
+            let ctx = match str_arg(ctx_json) {
+                Some(json) => serde_json::from_str(&json).context("Invalid ctx")?,
+                None => AgentContext::default(),
+            };
+            let data = data_arg(data_json, images, n_images)?;
+            host.env
+                .try_send_agent_out(host.agent_id.clone(), ctx.with_ch(ch), data)
+        })()
+        .map_err(|e| (host, e))
+    };
+    to_status(result, "output")
+}
+
+extern "C" fn host_display(
+    host_data: *mut c_void,
+    key: *const c_char,
+    data_json: *const c_char,
+    images: *const MnemnkImage,
+    n_images: usize,
+) -> i32 {
+    // SAFETY: see host_output
+    let result = unsafe {
+        let host = &*(host_data as *const PluginHost);
+        (|| -> Result<()> {
+            let key = str_arg(key).context("key is null")?;
+            let data = data_arg(data_json, images, n_images)?;
+            host.env.emit_display(host.agent_id.clone(), key, data)
+        })()
+        .map_err(|e| (host, e))
+    };
+    to_status(result, "display")
+}
+
+extern "C" fn host_error(host_data: *mut c_void, message: *const c_char) -> i32 {
+    // SAFETY: see host_output
+    let result = unsafe {
+        let host = &*(host_data as *const PluginHost);
+        let message = str_arg(message).unwrap_or_default();
+        host.env
+            .emit_error(host.agent_id.clone(), message)
+            .map_err(|e| (host, e))
+    };
+    to_status(result, "error")
+}
+
+fn to_status(result: Result<(), (&PluginHost, anyhow::Error)>, name: &str) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err((host, e)) => {
+            log::error!("Plugin agent {} failed to {}: {:#}", host.agent_id, name, e);
+            -1
+        }
+    }
+}
+
+unsafe fn str_arg(s: *const c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(s).to_string_lossy().into_owned())
+}
+
+unsafe fn data_arg(
+    data_json: *const c_char,
+    images: *const MnemnkImage,
+    n_images: usize,
+) -> Result<AgentData> {
+    let json = str_arg(data_json).context("data is null")?;
+    let images = if images.is_null() || n_images == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(images, n_images)
+    };
+    let serde_json::Value::Object(mut obj) = serde_json::from_str(&json).context("Invalid data")?
+    else {
+        bail!("data is not a JSON object");
+    };
+    let Some(serde_json::Value::String(kind)) = obj.remove("kind") else {
+        bail!("missing kind");
+    };
+    let value = obj.remove("value").context("Missing value")?;
+    data_from_json_with(&kind, value, IMAGE_REF_KEY, &mut |index| {
+        let image = index
+            .as_u64()
+            .and_then(|i| images.get(i as usize))
+            .with_context(|| format!("Invalid image index: {}", index))?;
+        if image.pixels.is_null() || image.len != image.width as usize * image.height as usize * 4 {
+            bail!("Invalid image size");
+        }
+        let pixels = std::slice::from_raw_parts(image.pixels, image.len).to_vec();
+        Ok(PhotonImage::new(pixels, image.width, image.height))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemnk::agent::message::EnvAgentMessage;
+    use crate::mnemnk::agent::{AgentValue, AgentValueMap};
+
+    // A plugin linked into the tests, whose agents send out what they receive on "out".
+
+    struct MockAgent {
+        host: *const MnemnkHost,
+    }
+
+    unsafe extern "C" fn mock_definitions() -> *const c_char {
+        c"[{\"kind\":\"Plugin\",\"name\":\"mock_echo\",\"inputs\":[\"in\"],\"outputs\":[\"out\"]}]"
+            .as_ptr()
+    }
+
+    unsafe extern "C" fn mock_agent_new(
+        _def_name: *const c_char,
+        _agent_id: *const c_char,
+        _config_json: *const c_char,
+        host: *const MnemnkHost,
+    ) -> *mut c_void {
+        Box::into_raw(Box::new(MockAgent { host })) as *mut c_void
+    }
+
+    unsafe extern "C" fn mock_agent_set_config(
+        _agent: *mut c_void,
+        _config_json: *const c_char,
+    ) -> i32 {
+        0
+    }
+
+    unsafe extern "C" fn mock_agent_call(_agent: *mut c_void) -> i32 {
+        0
+    }
+
+    unsafe extern "C" fn mock_agent_process(
+        agent: *mut c_void,
+        ctx_json: *const c_char,
+        data_json: *const c_char,
+        images: *const MnemnkImage,
+        n_images: usize,
+    ) -> i32 {
+        let host = &*(*(agent as *const MockAgent)).host;
+        (host.output)(
+            host.host_data,
+            c"out".as_ptr(),
+            ctx_json,
+            data_json,
+            images,
+            n_images,
+        )
+    }
+
+    unsafe extern "C" fn mock_agent_free(agent: *mut c_void) {
+        drop(Box::from_raw(agent as *mut MockAgent));
+    }
+
+    fn register_mock(dir: &Path) {
+        let library = PluginLibrary {
+            definitions: mock_definitions,
+            agent_new: mock_agent_new,
+            agent_set_config: mock_agent_set_config,
+            agent_start: mock_agent_call,
+            agent_stop: mock_agent_call,
+            agent_process: mock_agent_process,
+            agent_free: mock_agent_free,
+            _library: None,
+        };
+        let path = dir.join(libloading::library_filename("mock"));
+        // without a file, the library is never loaded again
+        LIBRARIES
+            .lock()
+            .unwrap()
+            .insert(path, (None, Arc::new(library)));
+    }
+
+    #[test]
+    fn test_abi_version() {
+        let path = Path::new("libmock.so");
+        assert!(check_abi_version(PLUGIN_ABI_VERSION, path).is_ok());
+        let err = check_abi_version(PLUGIN_ABI_VERSION + 1, path).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Unsupported plugin ABI version 2 of libmock.so"));
+    }
+
+    #[test]
+    fn test_process_round_trip() {
+        let (env, _host, mut rx) = AgentEnv::new_for_test();
+        let dir = std::env::temp_dir().join("mnemnk-plugin-test");
+        register_mock(&dir);
+
+        let plugin = PluginConfig {
+            lib: "mock".to_string(),
+        };
+        let defs = PluginAgent::read_defs(&plugin, &dir).unwrap();
+        assert_eq!(defs.len(), 1);
+        assert_eq!(defs[0].kind, AGENT_KIND_PLUGIN);
+        assert_eq!(defs[0].native_thread, Some(true));
+        {
+            let mut env_defs = env.defs.lock().unwrap();
+            for def in defs {
+                env_defs.insert(def.name.clone(), def);
+            }
+        }
+
+        let mut agent = PluginAgent::new(
+            env.clone(),
+            "echo".to_string(),
+            "mock_echo".to_string(),
+            None,
+        )
+        .unwrap();
+        agent.start().unwrap();
+
+        let image = PhotonImage::new(vec![255, 0, 0, 255, 0, 255, 0, 255], 2, 1);
+        let data = AgentData::new_object(AgentValueMap::from([
+            ("image".to_string(), AgentValue::new_image(image)),
+            ("title".to_string(), AgentValue::new_string("screen")),
+        ]));
+        agent
+            .process(AgentContext::new_with_ch("in"), data.clone())
+            .unwrap();
+
+        let Ok(EnvAgentMessage::AgentOut {
+            agent: agent_id,
+            ctx,
+            data: out,
+        }) = rx.try_recv()
+        else {
+            panic!("no output from the plugin agent");
+        };
+        assert_eq!(agent_id, "echo");
+        assert_eq!(ctx.ch(), "out");
+        assert_eq!(out, data);
+    }
+}
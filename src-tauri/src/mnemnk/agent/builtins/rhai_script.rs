@@ -1,6 +1,6 @@
 use anyhow::{anyhow, bail, Context as _, Result};
 use rhai::{Dynamic, Scope, AST};
-use tauri::{AppHandle, Manager};
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_BUILTIN;
@@ -19,18 +19,17 @@ struct RhaiExprAgent {
 
 impl AsAgent for RhaiExprAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
-        let env = app.state::<AgentEnv>();
         let ast = match &config {
             Some(c) => compile_expr(&env, c)?,
             None => None,
         };
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             ast,
         })
     }
@@ -44,7 +43,7 @@ impl AsAgent for RhaiExprAgent {
     }
 
     fn set_config(&mut self, config: AgentConfig) -> Result<()> {
-        self.ast = compile_expr(&self.env(), &config)?;
+        self.ast = compile_expr(self.env(), &config)?;
         Ok(())
     }
 
@@ -81,18 +80,17 @@ struct RhaiFilterAgent {
 
 impl AsAgent for RhaiFilterAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
-        let env = app.state::<AgentEnv>();
         let ast = match &config {
             Some(c) => compile_expr(&env, c)?,
             None => None,
         };
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             ast,
         })
     }
@@ -106,7 +104,7 @@ impl AsAgent for RhaiFilterAgent {
     }
 
     fn set_config(&mut self, config: AgentConfig) -> Result<()> {
-        self.ast = compile_expr(&self.env(), &config)?;
+        self.ast = compile_expr(self.env(), &config)?;
         Ok(())
     }
 
@@ -346,7 +344,9 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mnemnk::agent::message::EnvAgentMessage;
     use serde_json::json;
+    use std::sync::Arc;
 
     #[test]
     fn test_to_dynamic() {
@@ -749,4 +749,80 @@ mod tests {
         let obj = arr[2].as_object().unwrap();
         assert!(obj.is_empty());
     }
+
+    fn rhai_filter(env: Arc<AgentEnv>, expr: &str) -> RhaiFilterAgent {
+        let mut config = AgentConfig::new();
+        config.set(CONFIG_EXPR.into(), AgentValue::new_string(expr));
+        Agent::new(
+            env,
+            "test:$rhai_filter:1".into(),
+            "$rhai_filter".into(),
+            Some(config),
+        )
+        .unwrap()
+    }
+
+    fn try_recv_out(
+        rx: &mut tokio::sync::mpsc::Receiver<EnvAgentMessage>,
+    ) -> Option<(AgentContext, AgentData)> {
+        match rx.try_recv().ok()? {
+            EnvAgentMessage::AgentOut { ctx, data, .. } => Some((ctx, data)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_rhai_filter() {
+        let (env, _host, mut rx) = AgentEnv::new_for_test();
+        let mut agent = rhai_filter(env, "value > 1");
+
+        let ctx = AgentContext::new_with_ch("in");
+        Agent::process(&mut agent, ctx.clone(), AgentData::new_integer(2)).unwrap();
+        let (out_ctx, out_data) = try_recv_out(&mut rx).unwrap();
+        assert_eq!(out_ctx.ch(), CH_TRUE);
+        assert_eq!(out_data.as_i64(), Some(2));
+
+        Agent::process(&mut agent, ctx.clone(), AgentData::new_integer(1)).unwrap();
+        let (out_ctx, out_data) = try_recv_out(&mut rx).unwrap();
+        assert_eq!(out_ctx.ch(), CH_FALSE);
+        assert_eq!(out_data.as_i64(), Some(1));
+
+        assert!(try_recv_out(&mut rx).is_none());
+    }
+
+    #[test]
+    fn test_rhai_filter_ch_and_kind() {
+        let (env, _host, mut rx) = AgentEnv::new_for_test();
+        let mut agent = rhai_filter(env, r#"ch == "a" && kind == "string""#);
+
+        Agent::process(
+            &mut agent,
+            AgentContext::new_with_ch("a"),
+            AgentData::new_string("x"),
+        )
+        .unwrap();
+        assert_eq!(try_recv_out(&mut rx).unwrap().0.ch(), CH_TRUE);
+
+        Agent::process(
+            &mut agent,
+            AgentContext::new_with_ch("b"),
+            AgentData::new_string("x"),
+        )
+        .unwrap();
+        assert_eq!(try_recv_out(&mut rx).unwrap().0.ch(), CH_FALSE);
+    }
+
+    #[test]
+    fn test_rhai_filter_invalid_expr() {
+        let (env, _host, _rx) = AgentEnv::new_for_test();
+        let mut config = AgentConfig::new();
+        config.set(CONFIG_EXPR.into(), AgentValue::new_string("value >"));
+        let agent: Result<RhaiFilterAgent> = Agent::new(
+            env,
+            "test:$rhai_filter:1".into(),
+            "$rhai_filter".into(),
+            Some(config),
+        );
+        assert!(agent.is_err());
+    }
 }
@@ -4,12 +4,11 @@ mod implementation {
     use rig::completion::CompletionRequestBuilder;
     use rig::OneOrMany;
     use std::sync::{Arc, Mutex};
-    use tauri::AppHandle;
 
     use rig::providers::ollama::Client;
 
     use crate::mnemnk::agent::{
-        Agent, AgentConfig, AgentContext, AgentData, AgentOutput, AgentValueMap, AsAgent,
+        Agent, AgentConfig, AgentContext, AgentData, AgentEnv, AgentOutput, AgentValueMap, AsAgent,
         AsAgentData,
     };
 
@@ -26,13 +25,13 @@ mod implementation {
 
     impl AsAgent for RigMemoryAgent {
         fn new(
-            app: AppHandle,
+            env: Arc<AgentEnv>,
             id: String,
             def_name: String,
             config: Option<AgentConfig>,
         ) -> Result<Self> {
             Ok(Self {
-                data: AsAgentData::new(app, id, def_name, config),
+                data: AsAgentData::new(env, id, def_name, config),
                 memory: vec![],
             })
         }
@@ -219,13 +218,13 @@ mod implementation {
 
     impl AsAgent for RigOllamaAgent {
         fn new(
-            app: AppHandle,
+            env: Arc<AgentEnv>,
             id: String,
             def_name: String,
             config: Option<AgentConfig>,
         ) -> Result<Self> {
             Ok(Self {
-                data: AsAgentData::new(app, id, def_name, config),
+                data: AsAgentData::new(env, id, def_name, config),
                 client: Arc::new(Mutex::new(None)),
             })
         }
@@ -562,13 +561,13 @@ mod implementation {
 
     impl AsAgent for RigPreambleAgent {
         fn new(
-            app: AppHandle,
+            env: Arc<AgentEnv>,
             id: String,
             def_name: String,
             config: Option<AgentConfig>,
         ) -> Result<Self> {
             Ok(Self {
-                data: AsAgentData::new(app, id, def_name, config),
+                data: AsAgentData::new(env, id, def_name, config),
             })
         }
 
@@ -653,13 +652,13 @@ mod implementation {
 
     impl AsAgent for RigUserMessageWithImageAgent {
         fn new(
-            app: AppHandle,
+            env: Arc<AgentEnv>,
             id: String,
             def_name: String,
             config: Option<AgentConfig>,
         ) -> Result<Self> {
             Ok(Self {
-                data: AsAgentData::new(app, id, def_name, config),
+                data: AsAgentData::new(env, id, def_name, config),
             })
         }
 
@@ -772,6 +771,16 @@ static CONFIG_TEXT: &str = "prompt";
 static CONFIG_N: &str = "n";
 
 const DEFAULT_CONFIG_MODEL: &str = "gemma3:4b";
+const CHAT_MODELS: [&str; 8] = [
+    "gemma3:1b",
+    "gemma3:4b",
+    "gemma3:12b",
+    "gemma3:27b",
+    "llama3.2",
+    "mistral",
+    "phi4",
+    "qwen3",
+];
 const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
 const DEFAULT_CONFIG_N: i64 = 10;
 
@@ -792,11 +801,13 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
             .with_category(CATEGORY)
             .with_inputs(vec![CH_MESSAGE, CH_RESET])
             .with_outputs(vec![CH_MESSAGE, CH_MEMORY])
+            .with_output_kinds(vec![(CH_MEMORY, "message[]")])
             .with_default_config(vec![(
                 CONFIG_N.into(),
                 AgentConfigEntry::new(AgentValue::new_integer(DEFAULT_CONFIG_N), "integer")
                     .with_title("Memory Size")
-                    .with_description("-1 = unlimited"),
+                    .with_description("-1 = unlimited")
+                    .with_range(Some(-1.0), None),
             )]),
         );
 
@@ -820,7 +831,13 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
             .with_default_config(vec![(
                 CONFIG_MODEL.into(),
                 AgentConfigEntry::new(AgentValue::new_string(DEFAULT_CONFIG_MODEL), "string")
-                    .with_title("Chat Model"),
+                    .with_title("Chat Model")
+                    .with_enum(
+                        CHAT_MODELS
+                            .iter()
+                            .map(|m| AgentValue::new_string(*m))
+                            .collect(),
+                    ),
             )]),
         );
 
@@ -836,6 +853,7 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
             .with_category(CATEGORY)
             .with_inputs(vec![CH_MESSAGE])
             .with_outputs(vec![CH_MESSAGE])
+            .with_output_kinds(vec![(CH_MESSAGE, "message|message[]")])
             .with_default_config(vec![(
                 CONFIG_TEXT.into(),
                 AgentConfigEntry::new(AgentValue::new_string(""), "text"),
@@ -854,6 +872,7 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
             .with_category(CATEGORY)
             .with_inputs(vec![CH_IMAGE])
             .with_outputs(vec![CH_MESSAGE])
+            .with_output_kinds(vec![(CH_MESSAGE, "message|message[]")])
             .with_default_config(vec![(
                 CONFIG_TEXT.into(),
                 AgentConfigEntry::new(AgentValue::new_string(""), "text"),
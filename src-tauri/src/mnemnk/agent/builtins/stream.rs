@@ -1,11 +1,11 @@
 use anyhow::{bail, Context as _, Result};
-use tauri::AppHandle;
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_BUILTIN;
 use crate::mnemnk::agent::{
     Agent, AgentConfig, AgentConfigEntry, AgentContext, AgentData, AgentDefinition,
-    AgentDefinitions, AgentOutput, AgentValue, AgentValueMap, AsAgent, AsAgentData,
+    AgentDefinitions, AgentEnv, AgentOutput, AgentValue, AgentValueMap, AsAgent, AsAgentData,
 };
 
 // Stream agent
@@ -16,13 +16,13 @@ struct StreamAgent {
 
 impl AsAgent for StreamAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             last_id: 0,
         })
     }
@@ -72,13 +72,13 @@ struct StreamZipAgent {
 
 impl AsAgent for StreamZipAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         let mut this = Self {
-            data: AsAgentData::new(app, id, def_name, config.clone()),
+            data: AsAgentData::new(env, id, def_name, config.clone()),
             n: 0,
             in_channels: Vec::new(),
             keys: Vec::new(),
@@ -237,7 +237,9 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         .with_default_config(vec![
             (
                 CONFIG_N.into(),
-                AgentConfigEntry::new(AgentValue::new_integer(2), "integer").with_hidden(true),
+                AgentConfigEntry::new(AgentValue::new_integer(2), "integer")
+                    .with_hidden(true)
+                    .with_required(),
             ),
             (
                 CONFIG_STREAM.into(),
@@ -268,7 +270,9 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         .with_default_config(vec![
             (
                 CONFIG_N.into(),
-                AgentConfigEntry::new(AgentValue::new_integer(3), "integer").with_hidden(true),
+                AgentConfigEntry::new(AgentValue::new_integer(3), "integer")
+                    .with_hidden(true)
+                    .with_required(),
             ),
             (
                 CONFIG_STREAM.into(),
@@ -303,7 +307,9 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         .with_default_config(vec![
             (
                 CONFIG_N.into(),
-                AgentConfigEntry::new(AgentValue::new_integer(4), "integer").with_hidden(true),
+                AgentConfigEntry::new(AgentValue::new_integer(4), "integer")
+                    .with_hidden(true)
+                    .with_required(),
             ),
             (
                 CONFIG_STREAM.into(),
@@ -328,3 +334,102 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         ]),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemnk::agent::message::EnvAgentMessage;
+    use tokio::sync::mpsc::Receiver;
+
+    fn zip2(env: Arc<AgentEnv>, stream: &str) -> StreamZipAgent {
+        let mut config = AgentConfig::new();
+        config.set(CONFIG_N.into(), AgentValue::new_integer(2));
+        config.set(CONFIG_STREAM.into(), AgentValue::new_string(stream));
+        config.set(CONFIG_KEY1.into(), AgentValue::new_string("a"));
+        config.set(CONFIG_KEY2.into(), AgentValue::new_string("b"));
+        Agent::new(
+            env,
+            "test:$stream_zip2:1".into(),
+            "$stream_zip2".into(),
+            Some(config),
+        )
+        .unwrap()
+    }
+
+    fn input(agent: &mut StreamZipAgent, ctx: AgentContext, ch: &str, value: i64) {
+        Agent::process(agent, ctx.with_ch(ch), AgentData::new_integer(value)).unwrap();
+    }
+
+    fn try_recv_out(rx: &mut Receiver<EnvAgentMessage>) -> Option<(AgentContext, AgentData)> {
+        match rx.try_recv().ok()? {
+            EnvAgentMessage::AgentOut { ctx, data, .. } => Some((ctx, data)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_stream_zip() {
+        let (env, _host, mut rx) = AgentEnv::new_for_test();
+        let mut agent = zip2(env, "");
+
+        input(&mut agent, AgentContext::new(), CH_IN1, 1);
+        assert!(try_recv_out(&mut rx).is_none());
+
+        // the latest value of in1 is used
+        input(&mut agent, AgentContext::new(), CH_IN1, 2);
+        input(&mut agent, AgentContext::new(), CH_IN2, 3);
+        let (ctx, data) = try_recv_out(&mut rx).unwrap();
+        assert_eq!(ctx.ch(), CH_DATA);
+        assert_eq!(data.get("a").unwrap().as_i64(), Some(2));
+        assert_eq!(data.get("b").unwrap().as_i64(), Some(3));
+
+        // inputs are cleared after the output
+        input(&mut agent, AgentContext::new(), CH_IN2, 4);
+        assert!(try_recv_out(&mut rx).is_none());
+    }
+
+    #[test]
+    fn test_stream_zip_with_stream() {
+        let (env, _host, mut rx) = AgentEnv::new_for_test();
+        let mut agent = zip2(env, "s");
+
+        let key = "test:$stream:s".to_string();
+        let ctx1 = AgentContext::new().with_var(key.clone(), AgentValue::new_integer(1));
+        let ctx2 = AgentContext::new().with_var(key.clone(), AgentValue::new_integer(2));
+
+        // data without the stream id is ignored
+        input(&mut agent, AgentContext::new(), CH_IN1, 0);
+
+        input(&mut agent, ctx1.clone(), CH_IN1, 1);
+        // a new stream id discards the pending inputs
+        input(&mut agent, ctx2.clone(), CH_IN2, 2);
+        assert!(try_recv_out(&mut rx).is_none());
+
+        input(&mut agent, ctx2.clone(), CH_IN1, 3);
+        let (_, data) = try_recv_out(&mut rx).unwrap();
+        assert_eq!(data.get("a").unwrap().as_i64(), Some(3));
+        assert_eq!(data.get("b").unwrap().as_i64(), Some(2));
+    }
+
+    #[test]
+    fn test_stream_zip_missing_key() {
+        let (env, host, _rx) = AgentEnv::new_for_test();
+        let mut agent = zip2(env, "");
+        let mut config = agent.config().unwrap().clone();
+        config.set(CONFIG_KEY2.into(), AgentValue::new_string(""));
+        Agent::set_config(&mut agent, config).unwrap();
+
+        let res = Agent::process(
+            &mut agent,
+            AgentContext::new_with_ch(CH_IN1),
+            AgentData::new_integer(1),
+        );
+        assert!(res.is_err());
+
+        // the error is emitted to the host
+        let emitted = host.take_emitted();
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].0, "mnemnk:error");
+        assert_eq!(emitted[0].1["agent_id"], "test:$stream_zip2:1");
+    }
+}
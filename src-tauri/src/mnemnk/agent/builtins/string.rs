@@ -1,12 +1,12 @@
 use anyhow::{bail, Context as _, Result};
 use handlebars::Handlebars;
-use tauri::AppHandle;
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_BUILTIN;
 use crate::mnemnk::agent::{
     Agent, AgentConfig, AgentConfigEntry, AgentContext, AgentData, AgentDefinition,
-    AgentDefinitions, AgentOutput, AgentValue, AsAgent, AsAgentData,
+    AgentDefinitions, AgentEnv, AgentOutput, AgentValue, AsAgent, AsAgentData,
 };
 
 /// The `StringJoinAgent` is responsible for joining an array of strings into a single string
@@ -30,13 +30,13 @@ struct StringJoinAgent {
 
 impl AsAgent for StringJoinAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -94,13 +94,13 @@ struct TextJoinAgent {
 
 impl AsAgent for TextJoinAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -144,13 +144,13 @@ struct TemplateStringAgent {
 
 impl AsAgent for TemplateStringAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -201,13 +201,13 @@ struct TemplateTextAgent {
 
 impl AsAgent for TemplateTextAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -258,13 +258,13 @@ struct TemplateArrayAgent {
 
 impl AsAgent for TemplateArrayAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -323,7 +323,10 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         .with_title("Text Join")
         .with_category(CATEGORY)
         .with_inputs(vec![CH_TEXTS])
+        .with_required_inputs(vec![CH_TEXTS])
         .with_outputs(vec![CH_TEXT])
+        .with_input_kinds(vec![(CH_TEXTS, "text|text[]|string|string[]")])
+        .with_output_kinds(vec![(CH_TEXT, "text|string")])
         .with_default_config(vec![(
             CONFIG_SEP.into(),
             AgentConfigEntry::new(AgentValue::new_string("\\n"), "string"),
@@ -340,7 +343,10 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         .with_title("String Join")
         .with_category(CATEGORY)
         .with_inputs(vec![CH_STRINGS])
+        .with_required_inputs(vec![CH_STRINGS])
         .with_outputs(vec![CH_STRING])
+        .with_input_kinds(vec![(CH_STRINGS, "string|string[]|text|text[]")])
+        .with_output_kinds(vec![(CH_STRING, "string|text")])
         .with_default_config(vec![(
             CONFIG_SEP.into(),
             AgentConfigEntry::new(AgentValue::new_string("\\n"), "string"),
@@ -358,6 +364,7 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         .with_category(CATEGORY)
         .with_inputs(vec![CH_DATA])
         .with_outputs(vec![CH_TEXT])
+        .with_output_kinds(vec![(CH_TEXT, "text")])
         .with_default_config(vec![(
             CONFIG_TEMPLATE.into(),
             AgentConfigEntry::new(AgentValue::new_string("{{value}}"), "text"),
@@ -375,6 +382,7 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         .with_category(CATEGORY)
         .with_inputs(vec![CH_DATA])
         .with_outputs(vec![CH_STRING])
+        .with_output_kinds(vec![(CH_STRING, "string|string[]")])
         .with_default_config(vec![(
             CONFIG_TEMPLATE.into(),
             AgentConfigEntry::new(AgentValue::new_string("{{value}}"), "string"),
@@ -392,6 +400,7 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         .with_category(CATEGORY)
         .with_inputs(vec![CH_DATA])
         .with_outputs(vec![CH_TEXT])
+        .with_output_kinds(vec![(CH_TEXT, "text|text[]")])
         .with_default_config(vec![(
             CONFIG_TEMPLATE.into(),
             AgentConfigEntry::new(AgentValue::new_string("{{value}}"), "text"),
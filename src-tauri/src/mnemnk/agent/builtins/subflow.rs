@@ -0,0 +1,513 @@
+use anyhow::{bail, Context as _, Result};
+use std::sync::Arc;
+
+use crate::mnemnk::agent::agent::new_boxed;
+use crate::mnemnk::agent::{
+    Agent, AgentConfig, AgentConfigEntry, AgentContext, AgentData, AgentDefinition,
+    AgentDefinitions, AgentEnv, AgentFlowEdge, AgentOutput, AgentStatus, AgentValue, AsAgent,
+    AsAgentData,
+};
+
+// Subflow Agent
+//
+// Instantiates a saved agent flow inside the flow of this node.
+// The nodes of the referenced flow are copied with ids prefixed by this agent id, so that
+// every instance runs on its own. $subflow_in nodes receive the input of this agent,
+// and $subflow_out nodes send their input out of this agent, both on their "$ch" channel.
+struct SubflowAgent {
+    data: AsAgentData,
+
+    // instance node id and channel of each $subflow_in node
+    inputs: Vec<(String, String)>,
+
+    // instance node ids
+    nodes: Vec<String>,
+}
+
+impl SubflowAgent {
+    fn instantiate(&mut self) -> Result<()> {
+        let Some(flow_name) = self
+            .config()
+            .and_then(|c| c.get_string(CONFIG_FLOW))
+            .filter(|name| !name.is_empty())
+        else {
+            return Ok(());
+        };
+
+        let env = self.env().clone();
+        let agent_id = self.id().to_string();
+
+        if flow_name == self.flow_name() {
+            bail!("Subflow cannot refer to its own flow: {}", flow_name);
+        }
+        if enclosing_flows(&env, &agent_id).contains(&flow_name) {
+            bail!("Subflows refer to each other: {}", flow_name);
+        }
+
+        let flow = {
+            let flows = env.flows.lock().unwrap();
+            flows
+                .get(&flow_name)
+                .cloned()
+                .with_context(|| format!("Agent flow {} not found", flow_name))?
+        };
+
+        env.subflow_flows
+            .lock()
+            .unwrap()
+            .insert(agent_id.clone(), flow_name.clone());
+
+        // the nodes resolve the variables of their own flow
+        for node in flow.nodes.iter() {
+            let mut node = flow.resolve_node(node);
+            node.id = instance_node_id(&agent_id, &node.id);
+            if let Err(e) = env.add_agent(&flow_name, &node) {
+                self.teardown();
+                return Err(e);
+            }
+            self.nodes.push(node.id.clone());
+            {
+                let mut subflow_parents = env.subflow_parents.lock().unwrap();
+                subflow_parents.insert(node.id.clone(), agent_id.clone());
+            }
+            if node.name == SUBFLOW_IN_DEF_NAME {
+                let ch = node
+                    .config
+                    .as_ref()
+                    .map(|c| c.get_string_or_default(CONFIG_CH))
+                    .unwrap_or_default();
+                self.inputs.push((node.id.clone(), ch));
+            }
+        }
+
+        for edge in flow.edges.iter() {
+            let edge = AgentFlowEdge {
+                id: instance_node_id(&agent_id, &edge.id),
+                source: instance_node_id(&agent_id, &edge.source),
+                source_handle: edge.source_handle.clone(),
+                target: instance_node_id(&agent_id, &edge.target),
+                target_handle: edge.target_handle.clone(),
+            };
+            env.add_edge(&edge).unwrap_or_else(|e| {
+                log::error!("Failed to add_edge {}: {}", edge.source, e);
+            });
+        }
+
+        // the nodes of an instance run while this agent runs, regardless of their enabled flag
+        for node_id in self.nodes.iter() {
+            env.start_agent(node_id).unwrap_or_else(|e| {
+                log::error!("Failed to start agent {}: {}", node_id, e);
+            });
+        }
+
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        let env = self.env().clone();
+        for node_id in self.nodes.drain(..) {
+            env.remove_agent(&node_id).unwrap_or_else(|e| {
+                log::error!("Failed to remove agent {}: {}", node_id, e);
+            });
+            let mut subflow_parents = env.subflow_parents.lock().unwrap();
+            subflow_parents.remove(&node_id);
+        }
+        env.subflow_flows.lock().unwrap().remove(self.id());
+        self.inputs.clear();
+    }
+}
+
+impl AsAgent for SubflowAgent {
+    fn new(
+        env: Arc<AgentEnv>,
+        id: String,
+        def_name: String,
+        config: Option<AgentConfig>,
+    ) -> Result<Self> {
+        Ok(Self {
+            data: AsAgentData::new(env, id, def_name, config),
+            inputs: vec![],
+            nodes: vec![],
+        })
+    }
+
+    fn data(&self) -> &AsAgentData {
+        &self.data
+    }
+
+    fn mut_data(&mut self) -> &mut AsAgentData {
+        &mut self.data
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.instantiate()
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.teardown();
+        Ok(())
+    }
+
+    fn set_config(&mut self, _config: AgentConfig) -> Result<()> {
+        if self.data.status == AgentStatus::Start {
+            // the referenced flow may have changed
+            self.teardown();
+            self.instantiate()?;
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, ctx: AgentContext, data: AgentData) -> Result<()> {
+        let env = self.env();
+        for (node_id, ch) in self.inputs.iter() {
+            if ch != ctx.ch() && ch != "*" {
+                continue;
+            }
+            // send it out of the $subflow_in node, as if that node received it
+            env.try_send_agent_out(node_id.clone(), ctx.clone(), data.clone())
+                .context("Failed to send subflow input")?;
+        }
+        Ok(())
+    }
+}
+
+// Subflow In Agent
+struct SubflowInAgent {
+    data: AsAgentData,
+}
+
+impl AsAgent for SubflowInAgent {
+    fn new(
+        env: Arc<AgentEnv>,
+        id: String,
+        def_name: String,
+        config: Option<AgentConfig>,
+    ) -> Result<Self> {
+        Ok(Self {
+            data: AsAgentData::new(env, id, def_name, config),
+        })
+    }
+
+    fn data(&self) -> &AsAgentData {
+        &self.data
+    }
+
+    fn mut_data(&mut self) -> &mut AsAgentData {
+        &mut self.data
+    }
+
+    fn process(&mut self, ctx: AgentContext, data: AgentData) -> Result<()> {
+        // pass through, so that the referenced flow can also be run by itself
+        let ch = ctx.ch().to_string();
+        self.try_output(ctx, ch, data)
+    }
+}
+
+// Subflow Out Agent
+struct SubflowOutAgent {
+    data: AsAgentData,
+}
+
+impl AsAgent for SubflowOutAgent {
+    fn new(
+        env: Arc<AgentEnv>,
+        id: String,
+        def_name: String,
+        config: Option<AgentConfig>,
+    ) -> Result<Self> {
+        Ok(Self {
+            data: AsAgentData::new(env, id, def_name, config),
+        })
+    }
+
+    fn data(&self) -> &AsAgentData {
+        &self.data
+    }
+
+    fn mut_data(&mut self) -> &mut AsAgentData {
+        &mut self.data
+    }
+
+    fn process(&mut self, ctx: AgentContext, data: AgentData) -> Result<()> {
+        let env = self.env();
+        let parent = {
+            let subflow_parents = env.subflow_parents.lock().unwrap();
+            subflow_parents.get(self.id()).cloned()
+        };
+        let Some(parent) = parent else {
+            // not in a subflow instance
+            return Ok(());
+        };
+
+        let mut ch = self
+            .config()
+            .map(|c| c.get_string_or_default(CONFIG_CH))
+            .unwrap_or_default();
+        if ch.is_empty() || ch == "*" {
+            ch = ctx.ch().to_string();
+        }
+        env.try_send_agent_out(parent, ctx.with_ch(ch), data)
+            .context("Failed to send subflow output")
+    }
+}
+
+/// Restarts the running instances of the given flow, so that they pick up its changes.
+pub fn restart_subflows(env: &AgentEnv, flow_name: &str) {
+    let agents = {
+        let agents = env.agents.lock().unwrap();
+        agents
+            .iter()
+            .map(|(id, agent)| (id.clone(), agent.clone()))
+            .collect::<Vec<_>>()
+    };
+    let agent_ids = agents
+        .into_iter()
+        .filter(|(_, agent)| {
+            let agent = agent.lock().unwrap();
+            agent.def_name() == SUBFLOW_DEF_NAME
+                && *agent.status() == AgentStatus::Start
+                && agent
+                    .config()
+                    .and_then(|c| c.get_string(CONFIG_FLOW))
+                    .is_some_and(|name| name == flow_name)
+        })
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    for agent_id in agent_ids {
+        log::info!("Restarting subflow {}", agent_id);
+        env.stop_agent(&agent_id)
+            .and_then(|_| env.start_agent(&agent_id))
+            .unwrap_or_else(|e| {
+                log::error!("Failed to restart subflow {}: {}", agent_id, e);
+            });
+    }
+}
+
+fn instance_node_id(agent_id: &str, node_id: &str) -> String {
+    format!("{}/{}", agent_id, node_id)
+}
+
+// names of the flows run by the subflow agents this agent is nested in
+fn enclosing_flows(env: &AgentEnv, agent_id: &str) -> Vec<String> {
+    let subflow_parents = env.subflow_parents.lock().unwrap();
+    let subflow_flows = env.subflow_flows.lock().unwrap();
+    let mut flows = Vec::new();
+    let mut id = agent_id;
+    while let Some(parent) = subflow_parents.get(id) {
+        if let Some(flow) = subflow_flows.get(parent) {
+            flows.push(flow.clone());
+        }
+        id = parent;
+    }
+    flows
+}
+
+static CATEGORY: &str = "Core/Subflow";
+
+pub static SUBFLOW_DEF_NAME: &str = "$subflow";
+static SUBFLOW_IN_DEF_NAME: &str = "$subflow_in";
+static SUBFLOW_OUT_DEF_NAME: &str = "$subflow_out";
+
+static CONFIG_CH: &str = "$ch";
+pub static CONFIG_FLOW: &str = "flow";
+
+pub fn init_agent_defs(defs: &mut AgentDefinitions) {
+    // SubflowAgent
+    defs.insert(
+        SUBFLOW_DEF_NAME.into(),
+        AgentDefinition::new("Subflow", SUBFLOW_DEF_NAME, Some(new_boxed::<SubflowAgent>))
+            .with_title("Subflow")
+            .with_description("Runs another agent flow as a node")
+            .with_category(CATEGORY)
+            .with_inputs(vec!["*"])
+            .with_outputs(vec!["*"])
+            .with_default_config(vec![(
+                CONFIG_FLOW.into(),
+                AgentConfigEntry::new(AgentValue::new_string(""), "string")
+                    .with_title("Flow")
+                    .with_description("name of the agent flow"),
+            )]),
+    );
+
+    // SubflowInAgent
+    defs.insert(
+        SUBFLOW_IN_DEF_NAME.into(),
+        AgentDefinition::new(
+            "Subflow",
+            SUBFLOW_IN_DEF_NAME,
+            Some(new_boxed::<SubflowInAgent>),
+        )
+        .with_title("Subflow In")
+        .with_category(CATEGORY)
+        .with_outputs(vec!["*"])
+        .with_default_config(vec![(
+            CONFIG_CH.into(),
+            AgentConfigEntry::new(AgentValue::new_string(""), "string")
+                .with_title("Channel")
+                .with_description("input channel of the subflow, * = all channels"),
+        )]),
+    );
+
+    // SubflowOutAgent
+    defs.insert(
+        SUBFLOW_OUT_DEF_NAME.into(),
+        AgentDefinition::new(
+            "Subflow",
+            SUBFLOW_OUT_DEF_NAME,
+            Some(new_boxed::<SubflowOutAgent>),
+        )
+        .with_title("Subflow Out")
+        .with_category(CATEGORY)
+        .with_inputs(vec!["*"])
+        .with_default_config(vec![(
+            CONFIG_CH.into(),
+            AgentConfigEntry::new(AgentValue::new_string(""), "string")
+                .with_title("Channel")
+                .with_description("output channel of the subflow, * = input channel"),
+        )]),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemnk::agent::harness::{edge, node, FlowHarness};
+    use crate::mnemnk::agent::{AgentConfigVariables, AgentFlow};
+    use serde_json::{json, Value};
+
+    fn flow(name: &str, nodes: Value, edges: Value) -> AgentFlow {
+        serde_json::from_value(json!({ "name": name, "nodes": nodes, "edges": edges })).unwrap()
+    }
+
+    // x -> value * n -> y
+    fn multiply(n: i64) -> AgentFlow {
+        flow(
+            "lib/multiply",
+            json!([
+                node("lib/multiply:in", "$subflow_in", json!({ "$ch": "x" })),
+                node(
+                    "lib/multiply:expr",
+                    "$rhai_expr",
+                    json!({ "expr": format!("value * {}", n) })
+                ),
+                node("lib/multiply:out", "$subflow_out", json!({ "$ch": "y" })),
+            ]),
+            json!([
+                edge("lib/multiply:in", "*", "lib/multiply:expr", "data"),
+                edge("lib/multiply:expr", "data", "lib/multiply:out", "*"),
+            ]),
+        )
+    }
+
+    fn main_flow(subflows: &[&str]) -> AgentFlow {
+        let nodes = subflows
+            .iter()
+            .map(|id| {
+                node(
+                    &format!("main:{}", id),
+                    "$subflow",
+                    json!({ "flow": "lib/multiply" }),
+                )
+            })
+            .collect::<Vec<_>>();
+        flow("main", json!(nodes), json!([]))
+    }
+
+    #[test]
+    fn test_subflow() {
+        let mut h = FlowHarness::new_with_flows(main_flow(&["sub"]), vec![multiply(2)]).unwrap();
+
+        h.input("main:sub", "x", AgentData::new_integer(3)).unwrap();
+        h.input("main:sub", "other", AgentData::new_integer(4))
+            .unwrap();
+
+        assert_eq!(h.outputs("main:sub", "y"), vec![AgentData::new_integer(6)]);
+    }
+
+    #[test]
+    fn test_subflow_instances() {
+        let mut h =
+            FlowHarness::new_with_flows(main_flow(&["sub1", "sub2"]), vec![multiply(2)]).unwrap();
+
+        h.input("main:sub1", "x", AgentData::new_integer(1))
+            .unwrap();
+        h.input("main:sub2", "x", AgentData::new_integer(10))
+            .unwrap();
+
+        assert_eq!(h.outputs("main:sub1", "y"), vec![AgentData::new_integer(2)]);
+        assert_eq!(
+            h.outputs("main:sub2", "y"),
+            vec![AgentData::new_integer(20)]
+        );
+    }
+
+    #[test]
+    fn test_restart_subflows() {
+        let mut h = FlowHarness::new_with_flows(main_flow(&["sub"]), vec![multiply(2)]).unwrap();
+
+        {
+            let mut flows = h.env().flows.lock().unwrap();
+            flows.insert("lib/multiply".into(), multiply(3));
+        }
+        restart_subflows(h.env(), "lib/multiply");
+        h.run_until_idle();
+
+        h.input("main:sub", "x", AgentData::new_integer(3)).unwrap();
+        assert_eq!(h.outputs("main:sub", "y"), vec![AgentData::new_integer(9)]);
+    }
+
+    #[test]
+    fn test_subflow_variables() {
+        let mut lib = multiply(2);
+        lib.variables = AgentConfigVariables::from([("n".to_string(), "3".to_string())]);
+        let mut main = main_flow(&["sub"]);
+        main.variables = AgentConfigVariables::from([("n".to_string(), "5".to_string())]);
+        let mut h = FlowHarness::new_with_flows(main, vec![lib]).unwrap();
+
+        // the node of the subflow resolves the variables of its own flow
+        let mut config = AgentConfig::new();
+        config.set("expr".into(), AgentValue::new_string("value * ${n}"));
+        h.set_config("main:sub/lib/multiply:expr", config).unwrap();
+
+        h.input("main:sub", "x", AgentData::new_integer(2)).unwrap();
+        assert_eq!(h.outputs("main:sub", "y"), vec![AgentData::new_integer(6)]);
+    }
+
+    #[test]
+    fn test_subflow_self_reference() {
+        let mut main = main_flow(&["sub"]);
+        main.nodes[0].config = Some({
+            let mut config = AgentConfig::new();
+            config.set(CONFIG_FLOW.into(), AgentValue::new_string("main"));
+            config
+        });
+        let h = FlowHarness::new(main).unwrap();
+        assert_eq!(h.errors().len(), 1);
+    }
+
+    #[test]
+    fn test_subflow_cycle() {
+        let main = flow(
+            "main",
+            json!([node("main:sub", "$subflow", json!({ "flow": "a" }))]),
+            json!([]),
+        );
+        let a = flow(
+            "a",
+            json!([node("a:sub", "$subflow", json!({ "flow": "b" }))]),
+            json!([]),
+        );
+        let b = flow(
+            "b",
+            json!([node("b:sub", "$subflow", json!({ "flow": "a" }))]),
+            json!([]),
+        );
+        let h = FlowHarness::new_with_flows(main, vec![a, b]).unwrap();
+
+        // main:sub runs a, which runs b, which cannot run a again
+        let errors = h.errors();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("main:sub/a:sub/b:sub: Subflows refer to each other"));
+        assert_eq!(h.env().agents.lock().unwrap().len(), 5);
+    }
+}
@@ -8,7 +8,6 @@ use chrono::{DateTime, Local, Utc};
 use cron::Schedule;
 use regex::Regex;
 use tauri::async_runtime::JoinHandle;
-use tauri::{AppHandle, Manager};
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_BUILTIN;
@@ -25,13 +24,13 @@ struct DelayAgent {
 
 impl AsAgent for DelayAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             num_waiting_data: Arc::new(Mutex::new(0)),
         })
     }
@@ -50,7 +49,7 @@ impl AsAgent for DelayAgent {
         let max_num_data = config.get_integer_or(CONFIG_MAX_NUM_DATA, MAX_NUM_DATA_DEFAULT);
 
         let agent_id = self.id().to_string();
-        let app_handle = self.app().clone();
+        let env = self.env().clone();
 
         // To avoid generating too many timers
         {
@@ -63,10 +62,9 @@ impl AsAgent for DelayAgent {
         }
 
         let num_waiting_data = self.num_waiting_data.clone();
-        tauri::async_runtime::spawn(async move {
+        self.env().spawn(async move {
             tokio::time::sleep(Duration::from_millis(delay_ms as u64)).await;
 
-            let env = app_handle.state::<crate::mnemnk::agent::AgentEnv>();
             if let Err(e) = env.send_agent_out(agent_id, ctx, data).await {
                 log::error!("Failed to send delayed output: {}", e);
             }
@@ -88,12 +86,12 @@ struct IntervalTimerAgent {
 
 impl IntervalTimerAgent {
     fn start_timer(&mut self) -> Result<()> {
-        let app_handle = self.app().clone();
+        let env = self.env().clone();
         let agent_id = self.id().to_string();
         let timer_handle = self.timer_handle.clone();
         let interval_ms = self.interval_ms;
 
-        let handle = tauri::async_runtime::spawn(async move {
+        let handle = self.env().spawn(async move {
             loop {
                 // Sleep for the configured interval
                 tokio::time::sleep(tokio::time::Duration::from_millis(interval_ms)).await;
@@ -106,7 +104,6 @@ impl IntervalTimerAgent {
                 }
 
                 // Create a unit output
-                let env = app_handle.state::<AgentEnv>();
                 if let Err(e) = env.try_send_agent_out(
                     agent_id.clone(),
                     AgentContext::new_with_ch(CH_UNIT),
@@ -138,7 +135,7 @@ impl IntervalTimerAgent {
 
 impl AsAgent for IntervalTimerAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
@@ -150,7 +147,7 @@ impl AsAgent for IntervalTimerAgent {
         let interval_ms = parse_duration_to_ms(&interval)?;
 
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             timer_handle: Default::default(),
             interval_ms,
         })
@@ -196,13 +193,13 @@ struct OnStartAgent {
 
 impl AsAgent for OnStartAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
         })
     }
 
@@ -219,12 +216,11 @@ impl AsAgent for OnStartAgent {
         let delay_ms = config.get_integer_or(CONFIG_DELAY, DELAY_MS_DEFAULT);
 
         let agent_id = self.id().to_string();
-        let app_handle = self.app().clone();
+        let env = self.env().clone();
 
-        tauri::async_runtime::spawn(async move {
+        self.env().spawn(async move {
             tokio::time::sleep(Duration::from_millis(delay_ms as u64)).await;
 
-            let env = app_handle.state::<crate::mnemnk::agent::AgentEnv>();
             if let Err(e) = env
                 .send_agent_out(
                     agent_id,
@@ -254,12 +250,12 @@ impl ScheduleTimerAgent {
             bail!("No schedule set");
         };
 
-        let app_handle = self.app().clone();
+        let env = self.env().clone();
         let agent_id = self.id().to_string();
         let timer_handle = self.timer_handle.clone();
         let schedule = schedule.clone();
 
-        let handle = tauri::async_runtime::spawn(async move {
+        let handle = self.env().spawn(async move {
             loop {
                 // Calculate the next time this schedule should run
                 let now: DateTime<Utc> = Utc::now();
@@ -304,7 +300,6 @@ impl ScheduleTimerAgent {
                 let current_local_time = Local::now().timestamp();
 
                 // Output the timestamp as an integer
-                let env = app_handle.state::<AgentEnv>();
                 if let Err(e) = env.try_send_agent_out(
                     agent_id.clone(),
                     AgentContext::new_with_ch(CH_TIME),
@@ -347,13 +342,13 @@ impl ScheduleTimerAgent {
 
 impl AsAgent for ScheduleTimerAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         let mut agent = Self {
-            data: AsAgentData::new(app, id, def_name, config.clone()),
+            data: AsAgentData::new(env, id, def_name, config.clone()),
             cron_schedule: None,
             timer_handle: Default::default(),
         };
@@ -420,10 +415,10 @@ impl ThrottleTimeAgent {
         let time_ms = self.time_ms;
 
         let waiting_data = self.waiting_data.clone();
-        let app = self.app().clone();
+        let env = self.env().clone();
         let agent_id = self.id().to_string();
 
-        let handle = tauri::async_runtime::spawn(async move {
+        let handle = self.env().spawn(async move {
             loop {
                 // Sleep for the configured interval
                 tokio::time::sleep(tokio::time::Duration::from_millis(time_ms)).await;
@@ -438,7 +433,6 @@ impl ThrottleTimeAgent {
                 let mut wd = waiting_data.lock().unwrap();
                 if wd.len() > 0 {
                     // If there are data waiting, output the first one
-                    let env = app.state::<AgentEnv>();
                     let (ctx, data) = wd.remove(0);
                     env.try_send_agent_out(agent_id.clone(), ctx, data)
                         .unwrap_or_else(|e| {
@@ -475,7 +469,7 @@ impl ThrottleTimeAgent {
 
 impl AsAgent for ThrottleTimeAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
@@ -492,7 +486,7 @@ impl AsAgent for ThrottleTimeAgent {
             .unwrap_or(0);
 
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             timer_handle: Default::default(),
             time_ms,
             max_num_data,
@@ -628,12 +622,15 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
                 (
                     CONFIG_DELAY.into(),
                     AgentConfigEntry::new(AgentValue::new_integer(DELAY_MS_DEFAULT), "integer")
-                        .with_title("delay (ms)"),
+                        .with_title("delay (ms)")
+                        .with_range(Some(0.0), None)
+                        .with_step(100.0),
                 ),
                 (
                     CONFIG_MAX_NUM_DATA.into(),
                     AgentConfigEntry::new(AgentValue::new_integer(MAX_NUM_DATA_DEFAULT), "integer")
-                        .with_title("max num data"),
+                        .with_title("max num data")
+                        .with_range(Some(0.0), None),
                 ),
             ]),
     );
@@ -671,7 +668,9 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
         .with_default_config(vec![(
             CONFIG_DELAY.into(),
             AgentConfigEntry::new(AgentValue::new_integer(DELAY_MS_DEFAULT), "integer")
-                .with_title("delay (ms)"),
+                .with_title("delay (ms)")
+                .with_range(Some(0.0), None)
+                .with_step(100.0),
         )]),
     );
 
@@ -715,8 +714,88 @@ pub fn init_agent_defs(defs: &mut AgentDefinitions) {
                 CONFIG_MAX_NUM_DATA.into(),
                 AgentConfigEntry::new(AgentValue::new_integer(0), "integer")
                     .with_title("max num data")
-                    .with_description("0: no data, -1: all data"),
+                    .with_description("0: no data, -1: all data")
+                    .with_range(Some(-1.0), None),
             ),
         ]),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemnk::agent::harness::{node, FlowHarness};
+    use serde_json::json;
+
+    // on the paused clock of the harness, so that the timer fires at the same time on every run
+    fn throttle(max_num_data: i64) -> FlowHarness {
+        let flow = json!({
+            "nodes": [node(
+                "throttle",
+                "$throttle_time",
+                json!({ "time": "50ms", "max_num_data": max_num_data })
+            )],
+            "edges": [],
+        });
+        FlowHarness::new(serde_json::from_value(flow).unwrap()).unwrap()
+    }
+
+    fn outputs(h: &FlowHarness) -> Vec<Option<i64>> {
+        h.outputs("throttle", "data")
+            .iter()
+            .map(|data| data.as_i64())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_duration_to_ms() {
+        assert_eq!(parse_duration_to_ms("200ms").unwrap(), 200);
+        assert_eq!(parse_duration_to_ms("2s").unwrap(), 2000);
+        assert_eq!(parse_duration_to_ms("3").unwrap(), 3000);
+        assert_eq!(parse_duration_to_ms("1m").unwrap(), 60_000);
+        assert_eq!(parse_duration_to_ms("1ms").unwrap(), 10);
+        assert!(parse_duration_to_ms("1y").is_err());
+        assert!(parse_duration_to_ms("abc").is_err());
+    }
+
+    #[test]
+    fn test_throttle_time() {
+        let mut h = throttle(1);
+
+        // the first data passes, and the timer starts
+        h.input("throttle", "data", AgentData::new_integer(1))
+            .unwrap();
+        assert_eq!(outputs(&h), vec![Some(1)]);
+
+        // only the latest data is kept while the timer is running
+        h.input("throttle", "data", AgentData::new_integer(2))
+            .unwrap();
+        h.input("throttle", "data", AgentData::new_integer(3))
+            .unwrap();
+        assert_eq!(outputs(&h), vec![Some(1)]);
+
+        h.advance(Duration::from_millis(50));
+        assert_eq!(outputs(&h), vec![Some(1), Some(3)]);
+
+        // the timer stops once the waiting data is flushed
+        h.advance(Duration::from_millis(100));
+        assert_eq!(outputs(&h), vec![Some(1), Some(3)]);
+        h.input("throttle", "data", AgentData::new_integer(4))
+            .unwrap();
+        assert_eq!(outputs(&h), vec![Some(1), Some(3), Some(4)]);
+    }
+
+    #[test]
+    fn test_throttle_time_drop_all() {
+        let mut h = throttle(0);
+
+        h.input("throttle", "data", AgentData::new_integer(1))
+            .unwrap();
+        h.input("throttle", "data", AgentData::new_integer(2))
+            .unwrap();
+        assert_eq!(outputs(&h), vec![Some(1)]);
+
+        h.advance(Duration::from_millis(100));
+        assert_eq!(outputs(&h), vec![Some(1)]);
+    }
+}
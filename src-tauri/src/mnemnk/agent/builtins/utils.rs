@@ -1,13 +1,13 @@
 use std::vec;
 
 use anyhow::Result;
-use tauri::AppHandle;
+use std::sync::Arc;
 
 use crate::mnemnk::agent::agent::new_boxed;
 use crate::mnemnk::agent::definition::AGENT_KIND_BUILTIN;
 use crate::mnemnk::agent::{
     AgentConfig, AgentContext, AgentData, AgentDefinition, AgentDefinitions,
-    AgentDisplayConfigEntry, AgentOutput, AsAgent, AsAgentData,
+    AgentDisplayConfigEntry, AgentEnv, AgentOutput, AsAgent, AsAgentData,
 };
 
 // Counter
@@ -18,13 +18,13 @@ struct CounterAgent {
 
 impl AsAgent for CounterAgent {
     fn new(
-        app: AppHandle,
+        env: Arc<AgentEnv>,
         id: String,
         def_name: String,
         config: Option<AgentConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            data: AsAgentData::new(app, id, def_name, config),
+            data: AsAgentData::new(env, id, def_name, config),
             count: 0,
         })
     }
@@ -0,0 +1,713 @@
+//! Agents implemented as WebAssembly modules, run in a sandbox.
+//!
+//! A definition of kind `Wasm` in `mnemnk.json` declares the module and the capabilities granted
+//! to it:
+//!
+//! ```json
+//! { "kind": "Wasm", "name": "my_agent", "inputs": ["data"], "outputs": ["data"],
+//!   "wasm": { "module": "my_agent.wasm", "capabilities": ["file_read", "store", "network"],
+//!             "read_paths": ["../shared"] } }
+//! ```
+//!
+//! The module exports `memory`, `mnemnk_alloc(len: i32) -> i32` and
+//! `mnemnk_process(ptr: i32, len: i32) -> i32`, and optionally `mnemnk_start() -> i32`,
+//! `mnemnk_stop() -> i32` and `mnemnk_set_config(ptr: i32, len: i32) -> i32`. Buffers passed to
+//! the module are allocated with `mnemnk_alloc`, and belong to the module afterwards. A non-zero
+//! `i32` means an error.
+//!
+//! `mnemnk_process` receives `{"ctx": ..., "data": ...}`, and `mnemnk_set_config` the config, as
+//! JSON. The module can import these functions from `mnemnk`:
+//!
+//! - `output(ptr, len) -> i32` with `{"ctx": ..., "ch": ..., "data": ...}`
+//! - `display(ptr, len) -> i32` with `{"key": ..., "data": ...}`
+//! - `error(ptr, len) -> i32` with a message
+//! - `log(level, ptr, len)` with a level from 1 (error) to 5 (trace)
+//! - `read_file(ptr, len) -> i64` with a path relative to the agent dir, which must be in the
+//!   agent dir or `read_paths` (file_read)
+//! - `store_query(ptr, len) -> i64` with `{"db": ..., "query": ..., "bindings": ...}` (store)
+//! - `http_get(ptr, len) -> i64` with a URL, which times out after 30 seconds (network)
+//!
+//! The last three return `ptr << 32 | len` of the result allocated with `mnemnk_alloc`, or -1 if
+//! it failed or the capability is not granted.
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::mnemnk::agent::{AgentDefinition, AgentDefinitionError};
+
+pub use implementation::WasmAgent;
+
+#[cfg(feature = "wasm")]
+mod implementation {
+    use std::ops::Range;
+    use std::path::PathBuf;
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use anyhow::{bail, Context as _, Result};
+    use serde::{Deserialize, Serialize};
+    use wasmi::{
+        AsContextMut, Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store,
+        StoreLimits, StoreLimitsBuilder, TypedFunc,
+    };
+
+    use crate::mnemnk::agent::definition::WasmCapability;
+    use crate::mnemnk::agent::{
+        Agent, AgentConfig, AgentContext, AgentData, AgentEnv, AsAgent, AsAgentData,
+    };
+    use crate::mnemnk::store;
+
+    // fuel for each call to the module, to stop endless loops
+    const FUEL_PER_CALL: u64 = 10_000_000_000;
+
+    const MAX_MEMORY_BYTES: usize = 256 * 1024 * 1024;
+
+    const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+    // the body is written into the memory of the module, whose size is limited
+    const MAX_HTTP_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+    pub struct WasmAgent {
+        data: AsAgentData,
+        engine: Engine,
+        module: Module,
+        capabilities: Vec<WasmCapability>,
+        read_paths: Vec<PathBuf>,
+
+        // instance of the module while the agent is running
+        running: Option<WasmInstance>,
+    }
+
+    struct WasmInstance {
+        store: Store<WasmHost>,
+        instance: Instance,
+        memory: Memory,
+        alloc: TypedFunc<i32, i32>,
+        process: TypedFunc<(i32, i32), i32>,
+    }
+
+    // Host state of the store, which the imported functions access.
+    struct WasmHost {
+        env: Arc<AgentEnv>,
+        agent_id: String,
+        capabilities: Vec<WasmCapability>,
+
+        // canonical paths readable with file_read, the agent dir first
+        read_paths: Vec<PathBuf>,
+
+        limits: StoreLimits,
+    }
+
+    impl AsAgent for WasmAgent {
+        fn new(
+            env: Arc<AgentEnv>,
+            id: String,
+            def_name: String,
+            config: Option<AgentConfig>,
+        ) -> Result<Self> {
+            let wasm = {
+                let defs = env.defs.lock().unwrap();
+                defs.get(&def_name)
+                    .with_context(|| format!("Agent {} not found", def_name))?
+                    .wasm
+                    .clone()
+                    .context("Agent has no wasm")?
+            };
+            let bytes = std::fs::read(&wasm.module)
+                .with_context(|| format!("Failed to read wasm module: {}", wasm.module))?;
+
+            let mut engine_config = Config::default();
+            engine_config.consume_fuel(true);
+            let engine = Engine::new(&engine_config);
+            let module = Module::new(&engine, &bytes).context("Invalid wasm module")?;
+
+            Ok(Self {
+                data: AsAgentData::new(env, id, def_name, config),
+                engine,
+                module,
+                capabilities: wasm.capabilities,
+                read_paths: wasm.read_paths.iter().map(PathBuf::from).collect(),
+                running: None,
+            })
+        }
+
+        fn data(&self) -> &AsAgentData {
+            &self.data
+        }
+
+        fn mut_data(&mut self) -> &mut AsAgentData {
+            &mut self.data
+        }
+
+        fn start(&mut self) -> Result<()> {
+            let mut instance = self.instantiate()?;
+            if let Some(config) = self.merged_config() {
+                instance.set_config(&config)?;
+            }
+            instance.call_optional("mnemnk_start")?;
+            self.running = Some(instance);
+            Ok(())
+        }
+
+        fn stop(&mut self) -> Result<()> {
+            if let Some(mut instance) = self.running.take() {
+                instance.call_optional("mnemnk_stop")?;
+            }
+            Ok(())
+        }
+
+        fn set_config(&mut self, _config: AgentConfig) -> Result<()> {
+            let config = self.merged_config();
+            if let (Some(instance), Some(config)) = (self.running.as_mut(), config) {
+                instance.set_config(&config)?;
+            }
+            Ok(())
+        }
+
+        fn process(&mut self, ctx: AgentContext, data: AgentData) -> Result<()> {
+            #[derive(Serialize)]
+            struct InData {
+                ctx: AgentContext,
+                data: AgentData,
+            }
+
+            let instance = self.running.as_mut().context("Agent is not running")?;
+            let in_json = serde_json::to_vec(&InData { ctx, data })?;
+            let (ptr, len) = instance.write(&in_json)?;
+            instance.store.set_fuel(FUEL_PER_CALL)?;
+            let result = instance.process.call(&mut instance.store, (ptr, len))?;
+            check_result(result, "mnemnk_process")
+        }
+    }
+
+    impl WasmAgent {
+        fn instantiate(&self) -> Result<WasmInstance> {
+            let host = WasmHost {
+                env: self.env().clone(),
+                agent_id: self.id().to_string(),
+                capabilities: self.capabilities.clone(),
+                read_paths: self.read_paths.clone(),
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(MAX_MEMORY_BYTES)
+                    .build(),
+            };
+            let mut store = Store::new(&self.engine, host);
+            store.limiter(|host| &mut host.limits);
+            store.set_fuel(FUEL_PER_CALL)?;
+
+            let linker = host_functions(&self.engine)?;
+            let instance = linker
+                .instantiate(&mut store, &self.module)?
+                .start(&mut store)
+                .context("Failed to instantiate wasm module")?;
+            let memory = instance
+                .get_memory(&store, "memory")
+                .context("wasm module has no memory")?;
+            let alloc = instance.get_typed_func::<i32, i32>(&store, "mnemnk_alloc")?;
+            let process = instance.get_typed_func::<(i32, i32), i32>(&store, "mnemnk_process")?;
+            Ok(WasmInstance {
+                store,
+                instance,
+                memory,
+                alloc,
+                process,
+            })
+        }
+    }
+
+    impl WasmInstance {
+        fn write(&mut self, bytes: &[u8]) -> Result<(i32, i32)> {
+            write_guest(&mut self.store, self.alloc, self.memory, bytes)
+        }
+
+        fn set_config(&mut self, config: &AgentConfig) -> Result<()> {
+            let Ok(set_config) = self
+                .instance
+                .get_typed_func::<(i32, i32), i32>(&self.store, "mnemnk_set_config")
+            else {
+                return Ok(());
+            };
+            let json = serde_json::to_vec(config)?;
+            let (ptr, len) = self.write(&json)?;
+            self.store.set_fuel(FUEL_PER_CALL)?;
+            let result = set_config.call(&mut self.store, (ptr, len))?;
+            check_result(result, "mnemnk_set_config")
+        }
+
+        fn call_optional(&mut self, name: &str) -> Result<()> {
+            let Ok(func) = self.instance.get_typed_func::<(), i32>(&self.store, name) else {
+                return Ok(());
+            };
+            self.store.set_fuel(FUEL_PER_CALL)?;
+            let result = func.call(&mut self.store, ())?;
+            check_result(result, name)
+        }
+    }
+
+    fn check_result(result: i32, name: &str) -> Result<()> {
+        if result != 0 {
+            bail!("wasm agent failed in {} with {}", name, result);
+        }
+        Ok(())
+    }
+
+    fn write_guest(
+        mut ctx: impl AsContextMut,
+        alloc: TypedFunc<i32, i32>,
+        memory: Memory,
+        bytes: &[u8],
+    ) -> Result<(i32, i32)> {
+        let len = i32::try_from(bytes.len()).context("Data is too large")?;
+        let ptr = alloc.call(&mut ctx, len)?;
+        memory.write(&mut ctx, ptr as u32 as usize, bytes)?;
+        Ok((ptr, len))
+    }
+
+    fn read_guest(caller: &Caller<'_, WasmHost>, ptr: i32, len: i32) -> Result<Vec<u8>> {
+        let memory = guest_memory(caller)?;
+        // checked before allocating, so that the module cannot make the host allocate any size
+        let range = guest_range(ptr, len, memory.data_size(caller))?;
+        Ok(memory.data(caller)[range].to_vec())
+    }
+
+    // range of the memory of the given size at ptr and len, which the module passes as i32
+    fn guest_range(ptr: i32, len: i32, size: usize) -> Result<Range<usize>> {
+        let start = ptr as u32 as usize;
+        let Ok(len) = usize::try_from(len) else {
+            bail!("Invalid length {} from the wasm module", len);
+        };
+        match start.checked_add(len) {
+            Some(end) if end <= size => Ok(start..end),
+            _ => bail!("Out of the memory of the wasm module: {} + {}", start, len),
+        }
+    }
+
+    fn guest_memory(caller: &Caller<'_, WasmHost>) -> Result<Memory> {
+        caller
+            .get_export("memory")
+            .and_then(Extern::into_memory)
+            .context("wasm module has no memory")
+    }
+
+    // Writes the result into the module, and returns ptr << 32 | len, or -1.
+    fn return_guest(caller: &mut Caller<'_, WasmHost>, result: Result<Vec<u8>>) -> i64 {
+        let result = result.and_then(|bytes| {
+            let memory = guest_memory(caller)?;
+            let alloc = caller
+                .get_export("mnemnk_alloc")
+                .and_then(Extern::into_func)
+                .context("wasm module has no mnemnk_alloc")?
+                .typed::<i32, i32>(&caller)?;
+            write_guest(&mut *caller, alloc, memory, &bytes)
+        });
+        match result {
+            Ok((ptr, len)) => ((ptr as u32 as i64) << 32) | len as u32 as i64,
+            Err(e) => {
+                let host = caller.data();
+                log::error!("wasm agent {}: {:#}", host.agent_id, e);
+                -1
+            }
+        }
+    }
+
+    fn to_status(caller: &Caller<'_, WasmHost>, result: Result<()>) -> i32 {
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                log::error!("wasm agent {}: {:#}", caller.data().agent_id, e);
+                -1
+            }
+        }
+    }
+
+    impl WasmHost {
+        fn check_capability(&self, capability: WasmCapability) -> Result<()> {
+            if !self.capabilities.contains(&capability) {
+                bail!("Capability {:?} is not granted", capability);
+            }
+            Ok(())
+        }
+
+        // Canonical path of the file, if it is in one of the readable paths.
+        fn readable_path(&self, path: &str) -> Result<PathBuf> {
+            let dir = self.read_paths.first().context("No readable paths")?;
+            let full_path = dir
+                .join(path)
+                .canonicalize()
+                .with_context(|| format!("Failed to read {}", path))?;
+            if !self.read_paths.iter().any(|p| full_path.starts_with(p)) {
+                bail!("Path is not readable: {}", path);
+            }
+            Ok(full_path)
+        }
+    }
+
+    fn host_functions(engine: &Engine) -> Result<Linker<WasmHost>> {
+        let mut linker = Linker::<WasmHost>::new(engine);
+
+        linker.func_wrap(
+            "mnemnk",
+            "output",
+            |caller: Caller<'_, WasmHost>, ptr: i32, len: i32| -> i32 {
+                #[derive(Deserialize)]
+                struct OutArg {
+                    ctx: Option<AgentContext>,
+                    ch: String,
+                    data: AgentData,
+                }
+                let result = read_guest(&caller, ptr, len).and_then(|bytes| {
+                    let arg: OutArg = serde_json::from_slice(&bytes)?;
+                    let host = caller.data();
+                    let ctx = arg.ctx.unwrap_or_default().with_ch(arg.ch);
+                    host.env
+                        .try_send_agent_out(host.agent_id.clone(), ctx, arg.data)
+                });
+                to_status(&caller, result)
+            },
+        )?;
+
+        linker.func_wrap(
+            "mnemnk",
+            "display",
+            |caller: Caller<'_, WasmHost>, ptr: i32, len: i32| -> i32 {
+                #[derive(Deserialize)]
+                struct DisplayArg {
+                    key: String,
+                    data: AgentData,
+                }
+                let result = read_guest(&caller, ptr, len).and_then(|bytes| {
+                    let arg: DisplayArg = serde_json::from_slice(&bytes)?;
+                    let host = caller.data();
+                    host.env
+                        .emit_display(host.agent_id.clone(), arg.key, arg.data)
+                });
+                to_status(&caller, result)
+            },
+        )?;
+
+        linker.func_wrap(
+            "mnemnk",
+            "error",
+            |caller: Caller<'_, WasmHost>, ptr: i32, len: i32| -> i32 {
+                let result = read_guest(&caller, ptr, len).and_then(|bytes| {
+                    let host = caller.data();
+                    let message = String::from_utf8_lossy(&bytes).to_string();
+                    host.env.emit_error(host.agent_id.clone(), message)
+                });
+                to_status(&caller, result)
+            },
+        )?;
+
+        linker.func_wrap(
+            "mnemnk",
+            "log",
+            |caller: Caller<'_, WasmHost>, level: i32, ptr: i32, len: i32| {
+                let level = match level {
+                    1 => log::Level::Error,
+                    2 => log::Level::Warn,
+                    3 => log::Level::Info,
+                    4 => log::Level::Debug,
+                    _ => log::Level::Trace,
+                };
+                if let Ok(bytes) = read_guest(&caller, ptr, len) {
+                    log::log!(
+                        level,
+                        "wasm agent {}: {}",
+                        caller.data().agent_id,
+                        String::from_utf8_lossy(&bytes)
+                    );
+                }
+            },
+        )?;
+
+        linker.func_wrap(
+            "mnemnk",
+            "read_file",
+            |mut caller: Caller<'_, WasmHost>, ptr: i32, len: i32| -> i64 {
+                let result = caller
+                    .data()
+                    .check_capability(WasmCapability::FileRead)
+                    .and_then(|_| read_guest(&caller, ptr, len))
+                    .and_then(|bytes| {
+                        let path = String::from_utf8(bytes)?;
+                        let full_path = caller.data().readable_path(&path)?;
+                        std::fs::read(full_path).with_context(|| format!("Failed to read {}", path))
+                    });
+                return_guest(&mut caller, result)
+            },
+        )?;
+
+        linker.func_wrap(
+            "mnemnk",
+            "store_query",
+            |mut caller: Caller<'_, WasmHost>, ptr: i32, len: i32| -> i64 {
+                #[derive(Deserialize)]
+                struct QueryArg {
+                    db: String,
+                    query: String,
+                    bindings: Option<serde_json::Value>,
+                }
+                let result = caller
+                    .data()
+                    .check_capability(WasmCapability::Store)
+                    .and_then(|_| read_guest(&caller, ptr, len))
+                    .and_then(|bytes| {
+                        let arg: QueryArg = serde_json::from_slice(&bytes)?;
+                        let state = caller
+                            .data()
+                            .env
+                            .host()
+                            .store()
+                            .context("Store is not available")?;
+                        let result = store::query(&state, arg.db, arg.query, arg.bindings)?;
+                        Ok(serde_json::to_vec(&result)?)
+                    });
+                return_guest(&mut caller, result)
+            },
+        )?;
+
+        linker.func_wrap(
+            "mnemnk",
+            "http_get",
+            |mut caller: Caller<'_, WasmHost>, ptr: i32, len: i32| -> i64 {
+                let result = caller
+                    .data()
+                    .check_capability(WasmCapability::Network)
+                    .and_then(|_| read_guest(&caller, ptr, len))
+                    .and_then(|bytes| {
+                        let url = String::from_utf8(bytes)?;
+                        // wasm agents run on native threads, so wait for the request here
+                        let (tx, rx) = std::sync::mpsc::channel();
+                        let request_url = url.clone();
+                        let task = caller.data().env.spawn(async move {
+                            let result = async {
+                                let mut response =
+                                    reqwest::get(&request_url).await?.error_for_status()?;
+                                let mut body = Vec::new();
+                                while let Some(chunk) = response.chunk().await? {
+                                    if body.len() + chunk.len() > MAX_HTTP_BODY_BYTES {
+                                        bail!("HTTP response is too large: {}", request_url);
+                                    }
+                                    body.extend_from_slice(&chunk);
+                                }
+                                Ok(body)
+                            }
+                            .await;
+                            let _ = tx.send(result);
+                        });
+                        match rx.recv_timeout(HTTP_TIMEOUT) {
+                            Ok(result) => result,
+                            Err(RecvTimeoutError::Timeout) => {
+                                task.abort();
+                                bail!("HTTP request timed out: {}", url)
+                            }
+                            Err(RecvTimeoutError::Disconnected) => {
+                                bail!("HTTP request was cancelled: {}", url)
+                            }
+                        }
+                    });
+                return_guest(&mut caller, result)
+            },
+        )?;
+
+        Ok(linker)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::mnemnk::agent::definition::WasmConfig;
+        use crate::mnemnk::agent::message::EnvAgentMessage;
+        use crate::mnemnk::agent::AgentDefinition;
+
+        // Outputs "read" on "out" if it can read data.txt, and "denied" otherwise.
+        const MODULE: &str = r#"
+            (module
+              (import "mnemnk" "output" (func $output (param i32 i32) (result i32)))
+              (import "mnemnk" "read_file" (func $read_file (param i32 i32) (result i64)))
+              (memory (export "memory") 1)
+              (global $next (mut i32) (i32.const 1024))
+              (data (i32.const 0) "{\"ch\":\"out\",\"data\":{\"kind\":\"string\",\"value\":\"read\"}}")
+              (data (i32.const 128) "{\"ch\":\"out\",\"data\":{\"kind\":\"string\",\"value\":\"denied\"}}")
+              (data (i32.const 256) "data.txt")
+              (func (export "mnemnk_alloc") (param $len i32) (result i32)
+                (local $ptr i32)
+                (local.set $ptr (global.get $next))
+                (global.set $next (i32.add (global.get $next) (local.get $len)))
+                (local.get $ptr))
+              (func (export "mnemnk_process") (param i32 i32) (result i32)
+                (if (result i32)
+                  (i64.eq (call $read_file (i32.const 256) (i32.const 8)) (i64.const -1))
+                  (then (call $output (i32.const 128) (i32.const 54)))
+                  (else (call $output (i32.const 0) (i32.const 52))))))
+        "#;
+
+        fn test_dir(name: &str) -> PathBuf {
+            let dir = std::env::temp_dir().join(format!(
+                "mnemnk-wasm-test-{}-{}",
+                name,
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            dir
+        }
+
+        fn process_output(name: &str, capabilities: Vec<WasmCapability>) -> String {
+            let dir = test_dir(name);
+            std::fs::write(dir.join("agent.wasm"), wat::parse_str(MODULE).unwrap()).unwrap();
+            std::fs::write(dir.join("data.txt"), "data").unwrap();
+
+            let mut def = AgentDefinition::new("Wasm", "wasm_test", None);
+            def.wasm = Some(WasmConfig {
+                module: "agent.wasm".to_string(),
+                capabilities,
+                read_paths: vec![],
+            });
+            WasmAgent::read_def(&mut def, &dir).unwrap();
+            let (env, _host, mut rx) = AgentEnv::new_for_test();
+            env.defs.lock().unwrap().insert(def.name.clone(), def);
+
+            let mut agent =
+                WasmAgent::new(env, "wasm".to_string(), "wasm_test".to_string(), None).unwrap();
+            agent.start().unwrap();
+            agent
+                .process(AgentContext::new_with_ch("in"), AgentData::new_string("go"))
+                .unwrap();
+            agent.stop().unwrap();
+            let _ = std::fs::remove_dir_all(dir);
+
+            let Ok(EnvAgentMessage::AgentOut { ctx, data, .. }) = rx.try_recv() else {
+                panic!("no output from the wasm agent");
+            };
+            assert_eq!(ctx.ch(), "out");
+            data.as_str().unwrap().to_string()
+        }
+
+        #[test]
+        fn test_process_and_capabilities() {
+            assert_eq!(process_output("denied", vec![]), "denied");
+            assert_eq!(
+                process_output("granted", vec![WasmCapability::FileRead]),
+                "read"
+            );
+        }
+
+        #[test]
+        fn test_guest_range() {
+            assert_eq!(guest_range(16, 8, 64).unwrap(), 16..24);
+            assert_eq!(guest_range(56, 8, 64).unwrap(), 56..64);
+            assert!(guest_range(57, 8, 64).is_err());
+            assert!(guest_range(0, -1, 64).is_err());
+            assert!(guest_range(0, i32::MAX, 65536).is_err());
+            assert!(guest_range(-1, 2, 65536).is_err());
+        }
+
+        #[test]
+        fn test_readable_path() {
+            let dir = test_dir("paths");
+            let agent_dir = dir.join("agent");
+            std::fs::create_dir_all(&agent_dir).unwrap();
+            std::fs::write(agent_dir.join("data.txt"), "data").unwrap();
+            std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+
+            let (env, _host, _rx) = AgentEnv::new_for_test();
+            let host = WasmHost {
+                env,
+                agent_id: "wasm".to_string(),
+                capabilities: vec![WasmCapability::FileRead],
+                read_paths: vec![agent_dir.canonicalize().unwrap()],
+                limits: StoreLimitsBuilder::new().build(),
+            };
+            assert!(host.readable_path("data.txt").is_ok());
+            assert!(host.readable_path("../secret.txt").is_err());
+            assert!(host
+                .readable_path(dir.join("secret.txt").to_str().unwrap())
+                .is_err());
+
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+// Dummy Wasm Agent implementation when the feature is not enabled
+#[cfg(not(feature = "wasm"))]
+mod implementation {
+    use anyhow::{bail, Result};
+    use std::sync::Arc;
+
+    use crate::mnemnk::agent::{AgentConfig, AgentEnv, AsAgent, AsAgentData};
+
+    pub struct WasmAgent {
+        data: AsAgentData,
+    }
+
+    impl AsAgent for WasmAgent {
+        fn new(
+            _env: Arc<AgentEnv>,
+            _id: String,
+            def_name: String,
+            _config: Option<AgentConfig>,
+        ) -> Result<Self> {
+            bail!("Wasm agents are not supported in this build: {}", def_name);
+        }
+
+        fn data(&self) -> &AsAgentData {
+            &self.data
+        }
+
+        fn mut_data(&mut self) -> &mut AsAgentData {
+            &mut self.data
+        }
+    }
+}
+
+impl WasmAgent {
+    pub fn read_def(
+        def: &mut AgentDefinition,
+        agent_dir: &Path,
+    ) -> Result<(), AgentDefinitionError> {
+        let wasm = def
+            .wasm
+            .as_mut()
+            .ok_or_else(|| AgentDefinitionError::MissingEntry(def.name.clone(), "wasm".into()))?;
+        if wasm.module.is_empty() {
+            return Err(AgentDefinitionError::MissingEntry(
+                def.name.clone(),
+                "wasm.module".into(),
+            ));
+        }
+
+        let invalid_read_paths =
+            || AgentDefinitionError::InvalidEntry(def.name.clone(), "wasm.read_paths".into());
+        let agent_dir = agent_dir.canonicalize().map_err(|_| invalid_read_paths())?;
+        let mut read_paths = vec![agent_dir.to_string_lossy().to_string()];
+        for path in wasm.read_paths.iter() {
+            let path = agent_dir.join(path).canonicalize().map_err(|e| {
+                log::error!("Wasm read path not found: {} for {}: {}", path, def.name, e);
+                invalid_read_paths()
+            })?;
+            read_paths.push(path.to_string_lossy().to_string());
+        }
+        wasm.read_paths = read_paths;
+
+        let module_path = agent_dir.join(&wasm.module);
+        if !module_path.exists() {
+            log::error!(
+                "Wasm module not found: {} for {}",
+                module_path.display(),
+                def.name
+            );
+            return Err(AgentDefinitionError::InvalidEntry(
+                def.name.clone(),
+                "wasm.module".into(),
+            ));
+        }
+        wasm.module = module_path.to_string_lossy().to_string();
+
+        // the module runs synchronously, and waits for the host functions such as http_get
+        def.native_thread = Some(true);
+        Ok(())
+    }
+}
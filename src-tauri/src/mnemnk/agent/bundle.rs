@@ -0,0 +1,482 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Read as _, Write as _};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use super::builtins::{
+    BOARD_IN_DEF_NAME, BOARD_OUT_DEF_NAME, CONFIG_BOARD_NAME, CONFIG_SUBFLOW, SUBFLOW_DEF_NAME,
+};
+use super::config::{AgentConfig, AgentConfigVariables, AgentConfigs};
+use super::data::AgentValue;
+use super::env::AgentEnv;
+use super::flow::{self, AgentFlow, AgentFlows};
+use super::migration::AGENT_FLOW_VERSION;
+use crate::mnemnk::secrets;
+
+const BUNDLE_FORMAT: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// What a flow bundle contains, stored as `manifest.json` next to the flows in `flows/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentFlowBundleManifest {
+    pub format: u32,
+
+    // version of the app which exported the bundle, that of the builtin agents
+    pub app_version: String,
+
+    // the exported flow, followed by its subflows
+    pub flows: Vec<String>,
+
+    pub agents: Vec<AgentFlowBundleAgent>,
+
+    #[serde(default)]
+    pub boards: Vec<String>,
+
+    // global configs of the agents, without the secrets
+    #[serde(default)]
+    pub global_configs: AgentConfigs,
+
+    // variables of the flows with their values, to be overridden on import
+    #[serde(default)]
+    pub variables: AgentConfigVariables,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentFlowBundleAgent {
+    pub name: String,
+    pub kind: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// The manifest of a bundle, with the agents it needs but are not installed.
+#[derive(Debug, Serialize)]
+pub struct AgentFlowBundleInfo {
+    pub manifest: AgentFlowBundleManifest,
+    pub missing_agents: Vec<String>,
+
+    // agents installed in a version other than the one in the bundle
+    pub other_versions: Vec<AgentFlowBundleAgent>,
+}
+
+/// How the flows of a bundle are changed on the way in.
+#[derive(Debug, Default, Deserialize)]
+pub struct AgentFlowBundleImport {
+    // board name in the bundle -> board name to use
+    #[serde(default)]
+    pub boards: HashMap<String, String>,
+
+    // node id in the bundle -> config values to replace the bundled ones
+    #[serde(default)]
+    pub configs: HashMap<String, AgentConfig>,
+
+    // agent name -> global config values to set
+    #[serde(default)]
+    pub global_configs: AgentConfigs,
+
+    // values over the variables declared by the flows
+    #[serde(default)]
+    pub variables: AgentConfigVariables,
+}
+
+/// Writes the flow with the subflows it uses into a zip archive, along with the agents, boards
+/// and global configs they need. Secrets are left out.
+pub fn export_agent_flow_bundle(env: &AgentEnv, flow_name: &str, path: &Path) -> Result<()> {
+    let mut flows = {
+        let all_flows = env.flows.lock().unwrap();
+        collect_flows(&all_flows, flow_name)?
+    };
+
+    let mut agents = Vec::new();
+    let mut global_configs = AgentConfigs::new();
+    {
+        let defs = env.defs.lock().unwrap();
+        for flow in flows.iter_mut() {
+            for node in flow.nodes.iter_mut() {
+                if let Some(config) = node.config.as_mut() {
+                    remove_secrets(config, defs.get(&node.name).map(|d| d.secret_keys()));
+                }
+            }
+        }
+
+        let names: BTreeSet<&str> = flows
+            .iter()
+            .flat_map(|f| f.nodes.iter())
+            .map(|n| n.name.as_str())
+            .collect();
+        for name in names {
+            let Some(def) = defs.get(name) else {
+                // not installed here either, but still needed
+                agents.push(AgentFlowBundleAgent {
+                    name: name.to_string(),
+                    kind: String::new(),
+                    version: None,
+                });
+                continue;
+            };
+            agents.push(AgentFlowBundleAgent {
+                name: name.to_string(),
+                kind: def.kind.clone(),
+                version: def.version.clone(),
+            });
+            if let Some(mut config) = env.host().agent_global_config(name) {
+                remove_secrets(&mut config, Some(def.secret_keys()));
+                if !config.is_empty() {
+                    global_configs.insert(name.to_string(), config);
+                }
+            }
+        }
+    }
+
+    let boards: BTreeSet<String> = flows
+        .iter()
+        .flat_map(|f| f.nodes.iter())
+        .filter(|n| n.name == BOARD_IN_DEF_NAME || n.name == BOARD_OUT_DEF_NAME)
+        .filter_map(|n| n.config.as_ref()?.get_string(CONFIG_BOARD_NAME))
+        .filter(|name| !name.is_empty() && name != "*")
+        .collect();
+
+    let manifest = AgentFlowBundleManifest {
+        format: BUNDLE_FORMAT,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        flows: flows
+            .iter()
+            .map(|f| f.name.clone().unwrap_or_default())
+            .collect(),
+        agents,
+        boards: boards.into_iter().collect(),
+        // the values of the exported flow over those of its subflows
+        variables: flows
+            .iter()
+            .rev()
+            .flat_map(|f| f.variables.clone())
+            .collect(),
+        global_configs,
+    };
+
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file(MANIFEST_FILE, options)?;
+    zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    for mut flow in flows {
+        let name = flow.name.take().unwrap_or_default();
+        flow.version = AGENT_FLOW_VERSION;
+        zip.start_file(flow_file(&name), options)?;
+        zip.write_all(serde_json::to_string_pretty(&flow)?.as_bytes())?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Reads the manifest of the bundle, and checks the agents against the installed ones.
+pub fn inspect_agent_flow_bundle(env: &AgentEnv, path: &Path) -> Result<AgentFlowBundleInfo> {
+    let mut archive = open_bundle(path)?;
+    let manifest = read_manifest(&mut archive)?;
+
+    let defs = env.defs.lock().unwrap();
+    let mut missing_agents = Vec::new();
+    let mut other_versions = Vec::new();
+    for agent in manifest.agents.iter() {
+        match defs.get(&agent.name) {
+            None => missing_agents.push(agent.name.clone()),
+            Some(def) if agent.version.is_some() && def.version != agent.version => {
+                other_versions.push(AgentFlowBundleAgent {
+                    name: agent.name.clone(),
+                    kind: def.kind.clone(),
+                    version: def.version.clone(),
+                });
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(AgentFlowBundleInfo {
+        manifest,
+        missing_agents,
+        other_versions,
+    })
+}
+
+/// Adds the flows of the bundle under unique names, with all the nodes disabled, and returns the
+/// first one. The global configs in `options` are left to the caller, which owns the settings.
+pub fn import_agent_flow_bundle(
+    env: &Arc<AgentEnv>,
+    path: &Path,
+    options: &AgentFlowBundleImport,
+) -> Result<AgentFlow> {
+    let mut archive = open_bundle(path)?;
+    let manifest = read_manifest(&mut archive)?;
+    if manifest.flows.is_empty() {
+        bail!("No flows in the bundle");
+    }
+    // the names become paths of the flow files
+    for name in manifest.flows.iter() {
+        if !AgentEnv::is_valid_flow_name(name) {
+            bail!("Invalid flow name in the bundle: {}", name);
+        }
+    }
+
+    // name in the bundle -> new name
+    let mut names: HashMap<String, String> = HashMap::new();
+    {
+        let mut taken: AgentFlows = env.flows.lock().unwrap().clone();
+        for name in manifest.flows.iter() {
+            let new_name = flow::unique_flow_name(&taken, name);
+            taken.insert(new_name.clone(), AgentFlow::default());
+            names.insert(name.clone(), new_name);
+        }
+    }
+
+    let mut flows = Vec::new();
+    for name in manifest.flows.iter() {
+        let mut content = String::new();
+        archive
+            .by_name(&flow_file(name))
+            .with_context(|| format!("Flow {} not found in the bundle", name))?
+            .read_to_string(&mut content)?;
+        let (mut flow, _) = {
+            let defs = env.defs.lock().unwrap();
+            flow::parse_agent_flow(&content, &defs)?
+        };
+        check_subflow_names(&flow)?;
+        map_flow(&mut flow, &names, options);
+
+        let new_name = names[name].clone();
+        let (nodes, edges) = flow::copy_sub_flow(
+            &new_name,
+            flow.nodes.iter().collect(),
+            flow.edges.iter().collect(),
+        );
+        flow.name = Some(new_name);
+        flow.nodes = nodes;
+        flow.edges = edges;
+        flows.push(flow);
+    }
+
+    for (i, flow) in flows.iter().enumerate() {
+        if let Err(e) = env.add_agent_flow(flow) {
+            // a bundle is imported whole or not at all
+            for added in flows[..i].iter() {
+                remove_added_flow(env, added);
+            }
+            return Err(e.context("Failed to add agent flow"));
+        }
+    }
+    Ok(flows.swap_remove(0))
+}
+
+fn remove_added_flow(env: &AgentEnv, flow: &AgentFlow) {
+    for node in flow.nodes.iter() {
+        env.remove_agent(&node.id).unwrap_or_else(|e| {
+            log::error!("Failed to remove agent {}: {}", node.id, e);
+        });
+    }
+    if let Some(name) = &flow.name {
+        flow::delete_agent_flow(env, name).unwrap_or_else(|e| {
+            log::error!("Failed to remove agent flow {}: {}", name, e);
+        });
+    }
+}
+
+// the flow and the subflows it refers to, recursively
+fn collect_flows(all_flows: &AgentFlows, flow_name: &str) -> Result<Vec<AgentFlow>> {
+    let mut flows: Vec<AgentFlow> = Vec::new();
+    let mut queue = vec![flow_name.to_string()];
+    while let Some(name) = queue.pop() {
+        if flows
+            .iter()
+            .any(|f| f.name.as_deref() == Some(name.as_str()))
+        {
+            continue;
+        }
+        let flow = all_flows
+            .get(&name)
+            .with_context(|| format!("Agent flow {} not found", name))?;
+        for node in flow.nodes.iter().filter(|n| n.name == SUBFLOW_DEF_NAME) {
+            if let Some(subflow) = node
+                .config
+                .as_ref()
+                .and_then(|c| c.get_string(CONFIG_SUBFLOW))
+                .filter(|s| !s.is_empty())
+            {
+                queue.push(subflow);
+            }
+        }
+        let mut flow = flow.clone();
+        flow.name = Some(name);
+        flows.push(flow);
+    }
+    Ok(flows)
+}
+
+fn remove_secrets(config: &mut AgentConfig, secret_keys: Option<Vec<&str>>) {
+    for key in secret_keys.unwrap_or_default() {
+        config.remove(key);
+    }
+    let refs: Vec<String> = (&*config)
+        .into_iter()
+        .filter(|(_, value)| secrets::secret_ref(value).is_some())
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in refs {
+        config.remove(&key);
+    }
+}
+
+fn check_subflow_names(flow: &AgentFlow) -> Result<()> {
+    for node in flow.nodes.iter().filter(|n| n.name == SUBFLOW_DEF_NAME) {
+        if let Some(subflow) = node
+            .config
+            .as_ref()
+            .and_then(|c| c.get_string(CONFIG_SUBFLOW))
+            .filter(|s| !s.is_empty())
+        {
+            if !AgentEnv::is_valid_flow_name(&subflow) {
+                bail!("Invalid subflow name in the bundle: {}", subflow);
+            }
+        }
+    }
+    Ok(())
+}
+
+// applies the options and the new names of the flows to the nodes, before their ids are renewed
+fn map_flow(
+    flow: &mut AgentFlow,
+    names: &HashMap<String, String>,
+    options: &AgentFlowBundleImport,
+) {
+    for (name, value) in flow.variables.iter_mut() {
+        if let Some(new_value) = options.variables.get(name) {
+            value.clone_from(new_value);
+        }
+    }
+    for node in flow.nodes.iter_mut() {
+        node.enabled = false;
+        if let Some(values) = options.configs.get(&node.id) {
+            let config = node.config.get_or_insert_with(AgentConfig::new);
+            for (key, value) in values {
+                config.set(key.clone(), value.clone());
+            }
+        }
+        let Some(config) = node.config.as_mut() else {
+            continue;
+        };
+        if let Some(new_board) = config
+            .get_string(CONFIG_BOARD_NAME)
+            .and_then(|board| options.boards.get(&board))
+        {
+            config.set(
+                CONFIG_BOARD_NAME.to_string(),
+                AgentValue::new_string(new_board.clone()),
+            );
+        }
+        if node.name == SUBFLOW_DEF_NAME {
+            if let Some(new_name) = config
+                .get_string(CONFIG_SUBFLOW)
+                .and_then(|subflow| names.get(&subflow))
+            {
+                config.set(
+                    CONFIG_SUBFLOW.to_string(),
+                    AgentValue::new_string(new_name.clone()),
+                );
+            }
+        }
+    }
+}
+
+fn flow_file(name: &str) -> String {
+    format!("flows/{}.json", name)
+}
+
+fn open_bundle(path: &Path) -> Result<zip::ZipArchive<std::fs::File>> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    zip::ZipArchive::new(file).context("Invalid flow bundle")
+}
+
+fn read_manifest(archive: &mut zip::ZipArchive<std::fs::File>) -> Result<AgentFlowBundleManifest> {
+    let mut content = String::new();
+    archive
+        .by_name(MANIFEST_FILE)
+        .context("No manifest in the bundle")?
+        .read_to_string(&mut content)?;
+    let manifest: AgentFlowBundleManifest =
+        serde_json::from_str(&content).context("Invalid bundle manifest")?;
+    if manifest.format > BUNDLE_FORMAT {
+        bail!("Unsupported bundle format: {}", manifest.format);
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_map_flow() {
+        let mut flow: AgentFlow = serde_json::from_value(json!({
+            "nodes": [
+                { "id": "a", "name": "$board_in", "enabled": true, "config": { "$board": "notes" } },
+                { "id": "b", "name": "$subflow", "enabled": true, "config": { "flow": "summarize" } },
+                { "id": "c", "name": "$rhai_expr", "enabled": true, "config": { "script": "value" } },
+            ],
+            "edges": [],
+        }))
+        .unwrap();
+
+        let names = HashMap::from([("summarize".to_string(), "summarize2".to_string())]);
+        let mut script = AgentConfig::new();
+        script.set("script".into(), AgentValue::new_string("value * 2"));
+        let options = AgentFlowBundleImport {
+            boards: HashMap::from([("notes".to_string(), "team_notes".to_string())]),
+            configs: HashMap::from([("c".to_string(), script)]),
+            ..Default::default()
+        };
+        map_flow(&mut flow, &names, &options);
+
+        let config =
+            |idx: usize, key: &str| flow.nodes[idx].config.as_ref().unwrap().get_string(key);
+        assert!(flow.nodes.iter().all(|n| !n.enabled));
+        assert_eq!(config(0, "$board").as_deref(), Some("team_notes"));
+        assert_eq!(config(1, "flow").as_deref(), Some("summarize2"));
+        assert_eq!(config(2, "script").as_deref(), Some("value * 2"));
+    }
+
+    #[test]
+    fn test_check_subflow_names() {
+        let flow = |subflow: &str| -> AgentFlow {
+            serde_json::from_value(json!({
+                "nodes": [
+                    { "id": "a", "name": "$subflow", "enabled": true, "config": { "flow": subflow } },
+                ],
+                "edges": [],
+            }))
+            .unwrap()
+        };
+        assert!(check_subflow_names(&flow("lib/summarize")).is_ok());
+        assert!(check_subflow_names(&flow("")).is_ok());
+        assert!(check_subflow_names(&flow("../summarize")).is_err());
+        assert!(check_subflow_names(&flow("..")).is_err());
+        assert!(check_subflow_names(&flow("/etc/summarize")).is_err());
+    }
+
+    #[test]
+    fn test_remove_secrets() {
+        let mut config = AgentConfig::new();
+        config.set("api_key".into(), AgentValue::new_string("sk-123"));
+        config.set("token".into(), AgentValue::new_string("secret:other.token"));
+        config.set("model".into(), AgentValue::new_string("gpt"));
+        remove_secrets(&mut config, Some(vec!["api_key"]));
+        assert!(!config.contains_key("api_key"));
+        assert!(!config.contains_key("token"));
+        assert_eq!(config.get_string("model").as_deref(), Some("gpt"));
+    }
+}
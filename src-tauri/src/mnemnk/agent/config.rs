@@ -1,4 +1,5 @@
 use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
@@ -6,6 +7,9 @@ use super::data::AgentValue;
 
 pub type AgentConfigs = HashMap<String, AgentConfig>;
 
+/// Variables of a flow, referred to as `${name}` in the configs of its nodes.
+pub type AgentConfigVariables = BTreeMap<String, String>;
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct AgentConfig(BTreeMap<String, AgentValue>);
 
@@ -18,6 +22,14 @@ impl AgentConfig {
         self.0.insert(key, value);
     }
 
+    pub fn remove(&mut self, key: &str) -> Option<AgentValue> {
+        self.0.remove(key)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     pub fn contains_key(&self, key: &str) -> bool {
         self.0.contains_key(key)
     }
@@ -26,6 +38,22 @@ impl AgentConfig {
         self.0.get(key)
     }
 
+    /// The config with `${name}` in its strings replaced by the variables.
+    ///
+    /// References to undeclared variables are left as they are, so that strings such as the
+    /// template literals of Rhai scripts pass through.
+    pub fn resolve_variables(&self, variables: &AgentConfigVariables) -> AgentConfig {
+        if variables.is_empty() {
+            return self.clone();
+        }
+        Self(
+            self.0
+                .iter()
+                .map(|(key, value)| (key.clone(), resolve_value(value, variables)))
+                .collect(),
+        )
+    }
+
     pub fn get_bool(&self, key: &str) -> Option<bool> {
         self.0.get(key).and_then(|v| v.as_bool())
     }
@@ -102,6 +130,51 @@ impl AgentConfig {
     // }
 }
 
+fn resolve_value(value: &AgentValue, variables: &AgentConfigVariables) -> AgentValue {
+    match value {
+        AgentValue::String(s) if s.contains("${") => {
+            AgentValue::new_string(resolve_string(s, variables))
+        }
+        AgentValue::Array(array) => AgentValue::Array(Arc::new(
+            array
+                .iter()
+                .map(|value| resolve_value(value, variables))
+                .collect(),
+        )),
+        AgentValue::Object(object) => AgentValue::Object(Arc::new(
+            object
+                .iter()
+                .map(|(key, value)| (key.clone(), resolve_value(value, variables)))
+                .collect(),
+        )),
+        _ => value.clone(),
+    }
+}
+
+fn resolve_string(s: &str, variables: &AgentConfigVariables) -> String {
+    let mut resolved = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        resolved.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after
+            .find('}')
+            .and_then(|end| Some((end, variables.get(&after[..end])?)))
+        {
+            Some((end, value)) => {
+                resolved.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                resolved.push_str("${");
+                rest = after;
+            }
+        }
+    }
+    resolved.push_str(rest);
+    resolved
+}
+
 impl IntoIterator for AgentConfig {
     type Item = (String, AgentValue);
     type IntoIter = std::collections::btree_map::IntoIter<String, AgentValue>;
@@ -119,3 +192,41 @@ impl<'a> IntoIterator for &'a AgentConfig {
         self.0.iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_variables() {
+        let variables = AgentConfigVariables::from([
+            ("model".to_string(), "llama3".to_string()),
+            ("dir".to_string(), "/data".to_string()),
+        ]);
+        let mut config = AgentConfig::new();
+        config.set("model".into(), AgentValue::new_string("${model}"));
+        config.set("path".into(), AgentValue::new_string("${dir}/${model}.log"));
+        config.set("script".into(), AgentValue::new_string("`${value} ${dir`"));
+        config.set(
+            "paths".into(),
+            AgentValue::Array(Arc::new(vec![AgentValue::new_string("${dir}/a")])),
+        );
+        config.set("count".into(), AgentValue::Integer(3));
+
+        let resolved = config.resolve_variables(&variables);
+        assert_eq!(resolved.get_string("model").as_deref(), Some("llama3"));
+        assert_eq!(
+            resolved.get_string("path").as_deref(),
+            Some("/data/llama3.log")
+        );
+        assert_eq!(
+            resolved.get_string("script").as_deref(),
+            Some("`${value} ${dir`")
+        );
+        assert_eq!(
+            resolved.get_array("paths").unwrap()[0].as_str(),
+            Some("/data/a")
+        );
+        assert_eq!(resolved.get_integer("count"), Some(3));
+    }
+}
@@ -10,6 +10,10 @@ pub struct AgentContext {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     vars: Option<Arc<BTreeMap<String, AgentValue>>>,
+
+    // id of the trace the data belongs to, whose hops are kept by the recorder of the env
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<String>,
 }
 
 impl AgentContext {
@@ -23,6 +27,7 @@ impl AgentContext {
         Self {
             ch: ch.into(),
             vars: None,
+            trace_id: None,
         }
     }
 
@@ -30,6 +35,7 @@ impl AgentContext {
         Self {
             ch: ch.into(),
             vars: self.vars.clone(),
+            trace_id: self.trace_id.clone(),
         }
     }
 
@@ -53,6 +59,21 @@ impl AgentContext {
         Self {
             ch: self.ch.clone(),
             vars: Some(Arc::new(vars)),
+            trace_id: self.trace_id.clone(),
+        }
+    }
+
+    // trace
+
+    pub fn trace_id(&self) -> Option<&str> {
+        self.trace_id.as_deref()
+    }
+
+    pub fn with_trace(&self, id: String) -> Self {
+        Self {
+            ch: self.ch.clone(),
+            vars: self.vars.clone(),
+            trace_id: Some(id),
         }
     }
 }
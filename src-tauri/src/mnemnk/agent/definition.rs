@@ -1,21 +1,31 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::SystemTime;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use tauri::AppHandle;
 use thiserror::Error;
 
 use super::agent::AsyncAgent;
-use super::builtins;
+use super::builtins::{self, PluginConfig};
 use super::config::AgentConfig;
 use super::data::AgentValue;
-use crate::mnemnk::settings;
+use super::env::AgentEnv;
+use super::host::AgentHost;
 
 static AGENTS_DIR: &str = "agents";
 static MNEMNK_JSON: &str = "mnemnk.json";
 static MNEMNK_LOCAL_JSON: &str = "mnemnk.local.json";
 
+// published schema of mnemnk.json
+static MNEMNK_JSON_SCHEMA: &str = include_str!("../../../schemas/mnemnk.schema.json");
+
+static MNEMNK_JSON_VALIDATOR: LazyLock<jsonschema::Validator> = LazyLock::new(|| {
+    let schema = serde_json::from_str(MNEMNK_JSON_SCHEMA).expect("Invalid mnemnk.json schema");
+    jsonschema::validator_for(&schema).expect("Invalid mnemnk.json schema")
+});
+
 pub static AGENT_KIND_BUILTIN: &str = "Builtin";
 pub static AGENT_KIND_DATABASE: &str = "Database";
 
@@ -26,6 +36,27 @@ pub enum AgentDefinitionError {
 
     #[error("{0}: Agent definition \"{1}\" is invalid")]
     InvalidEntry(String, String),
+
+    #[error("{0}: {1}")]
+    InvalidFile(String, String),
+}
+
+#[derive(Debug, Error)]
+pub enum AgentConfigError {
+    #[error("{0}: Config \"{1}\" is required")]
+    Required(String, String),
+
+    #[error("{0}: Config \"{1}\" must be {2}")]
+    InvalidType(String, String, String),
+
+    #[error("{0}: Config \"{1}\" must be at least {2}")]
+    TooSmall(String, String, f64),
+
+    #[error("{0}: Config \"{1}\" must be at most {2}")]
+    TooLarge(String, String, f64),
+
+    #[error("{0}: Config \"{1}\" must be one of {2}")]
+    NotInEnum(String, String, String),
 }
 
 pub type AgentDefinitions = HashMap<String, AgentDefinition>;
@@ -33,17 +64,36 @@ pub type AgentDefinitions = HashMap<String, AgentDefinition>;
 #[derive(Deserialize)]
 pub struct MnemnkJson {
     pub agents: Option<Vec<AgentDefinition>>,
+
+    // native library providing the agents
+    pub plugin: Option<PluginConfig>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct AgentDefinition {
     pub kind: String,
     pub name: String,
+
+    // version of the agent, recorded in exported flow bundles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
     pub title: Option<String>,
     pub description: Option<String>,
     pub category: Option<String>,
     pub inputs: Option<Vec<String>>,
     pub outputs: Option<Vec<String>>,
+
+    // kinds of data by port, such as "image", "string|text", "message[]" or "*"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_kinds: Option<AgentPortKinds>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_kinds: Option<AgentPortKinds>,
+
+    // inputs that must be connected for the agent to do anything
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required_inputs: Option<Vec<String>>,
+
     pub default_config: Option<AgentDefaultConfig>,
     pub global_config: Option<AgentGlobalConfig>,
     pub display_config: Option<AgentDisplayConfig>,
@@ -51,6 +101,14 @@ pub struct AgentDefinition {
     // CommandAgent
     pub command: Option<CommandConfig>,
 
+    // PluginAgent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plugin: Option<PluginConfig>,
+
+    // WasmAgent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wasm: Option<WasmConfig>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub native_thread: Option<bool>,
 
@@ -58,6 +116,7 @@ pub struct AgentDefinition {
     pub new_boxed: Option<AgentNewBoxedFn>,
 }
 
+pub type AgentPortKinds = HashMap<String, String>;
 pub type AgentDefaultConfig = Vec<(String, AgentConfigEntry)>;
 pub type AgentGlobalConfig = Vec<(String, AgentConfigEntry)>;
 
@@ -75,6 +134,31 @@ pub struct AgentConfigEntry {
     /// Indicates whether this configuration entry should be hidden from the user interface.
     /// If set to `Some(true)`, the entry will be hidden. If `None`, the default behavior is to show the entry.
     pub hidden: Option<bool>,
+
+    /// Lower bound of an integer or number value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+
+    /// Upper bound of an integer or number value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+
+    /// Increment of an integer or number value in the editor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<f64>,
+
+    /// Values allowed for this entry, shown as choices in the editor.
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+    pub enum_: Option<Vec<AgentValue>>,
+
+    /// If set to `Some(true)`, the value must not be null nor an empty string.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required: Option<bool>,
+
+    /// If set to `Some(true)`, the value is masked in the editor, left out of exported flows,
+    /// and stored apart from `settings.json`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<bool>,
 }
 
 pub type AgentDisplayConfig = Vec<(String, AgentDisplayConfigEntry)>;
@@ -95,10 +179,103 @@ pub struct CommandConfig {
     pub args: Option<Vec<String>>,
 
     pub dir: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supervisor: Option<SupervisorConfig>,
+
+    // version of the stdio protocol spoken by the agent, which enables the .HELLO handshake
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u32>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct WasmConfig {
+    // path of the .wasm file, relative to the agent dir in mnemnk.json
+    pub module: String,
+
+    // what the module is allowed to do besides exchanging data
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<WasmCapability>,
+
+    // files and directories readable with file_read besides the agent dir, relative to it in
+    // mnemnk.json, and canonical paths with the agent dir first once loaded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub read_paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WasmCapability {
+    /// Read files in the agent dir and `read_paths`.
+    FileRead,
+
+    /// Query the Mnemnk database.
+    Store,
+
+    /// Send HTTP GET requests.
+    Network,
+}
+
+/// How a command agent is restarted when its process exits while the agent is running.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SupervisorConfig {
+    #[serde(default)]
+    pub restart: RestartPolicy,
+
+    // delay before the first restart, doubled on each restart within the window
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+
+    // gives up when the process has been restarted this many times within the window
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: usize,
+
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            restart: RestartPolicy::default(),
+            backoff_ms: default_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            max_restarts: default_max_restarts(),
+            window_secs: default_window_secs(),
+        }
+    }
+}
+
+fn default_backoff_ms() -> u64 {
+    1000
+}
+
+fn default_max_backoff_ms() -> u64 {
+    60_000
+}
+
+fn default_max_restarts() -> usize {
+    5
+}
+
+fn default_window_secs() -> u64 {
+    600
 }
 
 pub type AgentNewBoxedFn = fn(
-    app: AppHandle,
+    env: Arc<AgentEnv>,
     id: String,
     def_name: String,
     config: Option<AgentConfig>,
@@ -143,6 +320,31 @@ impl AgentDefinition {
         self
     }
 
+    pub fn with_input_kinds(mut self, kinds: Vec<(&str, &str)>) -> Self {
+        self.input_kinds = Some(
+            kinds
+                .into_iter()
+                .map(|(ch, kind)| (ch.into(), kind.into()))
+                .collect(),
+        );
+        self
+    }
+
+    pub fn with_output_kinds(mut self, kinds: Vec<(&str, &str)>) -> Self {
+        self.output_kinds = Some(
+            kinds
+                .into_iter()
+                .map(|(ch, kind)| (ch.into(), kind.into()))
+                .collect(),
+        );
+        self
+    }
+
+    pub fn with_required_inputs(mut self, inputs: Vec<&str>) -> Self {
+        self.required_inputs = Some(inputs.into_iter().map(|x| x.into()).collect());
+        self
+    }
+
     pub fn with_default_config(mut self, config: AgentDefaultConfig) -> Self {
         self.default_config = Some(config);
         self
@@ -188,6 +390,102 @@ impl AgentConfigEntry {
         self.hidden = Some(hidden);
         self
     }
+
+    pub fn with_range(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    pub fn with_step(mut self, step: f64) -> Self {
+        self.step = Some(step);
+        self
+    }
+
+    pub fn with_enum(mut self, values: Vec<AgentValue>) -> Self {
+        self.enum_ = Some(values);
+        self
+    }
+
+    pub fn with_required(mut self) -> Self {
+        self.required = Some(true);
+        self
+    }
+
+    pub fn with_secret(mut self) -> Self {
+        self.secret = Some(true);
+        self
+    }
+
+    pub fn is_secret(&self) -> bool {
+        self.secret.unwrap_or(false)
+    }
+
+    /// Checks the value against the type, range, enum and required flag of this entry.
+    pub fn validate(
+        &self,
+        def_name: &str,
+        key: &str,
+        value: &AgentValue,
+    ) -> Result<(), AgentConfigError> {
+        let is_empty = match value {
+            AgentValue::Null => true,
+            AgentValue::String(s) => s.is_empty(),
+            _ => false,
+        };
+        if is_empty {
+            if self.required.unwrap_or(false) {
+                return Err(AgentConfigError::Required(def_name.into(), key.into()));
+            }
+            // an empty value is left to the agent, which uses its default
+            return Ok(());
+        }
+
+        let type_ok = match self.type_.as_deref() {
+            Some("boolean") => value.is_boolean(),
+            Some("integer") => value.is_integer(),
+            Some("number") => value.is_integer() || value.is_number(),
+            Some("string") | Some("text") | Some("file") | Some("directory") => value.is_string(),
+            _ => true,
+        };
+        if !type_ok {
+            return Err(AgentConfigError::InvalidType(
+                def_name.into(),
+                key.into(),
+                self.type_.clone().unwrap_or_default(),
+            ));
+        }
+
+        if let Some(n) = value.as_f64() {
+            if let Some(min) = self.min {
+                if n < min {
+                    return Err(AgentConfigError::TooSmall(def_name.into(), key.into(), min));
+                }
+            }
+            if let Some(max) = self.max {
+                if n > max {
+                    return Err(AgentConfigError::TooLarge(def_name.into(), key.into(), max));
+                }
+            }
+        }
+
+        if let Some(values) = &self.enum_ {
+            if !values.contains(value) {
+                let values = values
+                    .iter()
+                    .map(|v| v.to_json_value().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                return Err(AgentConfigError::NotInEnum(
+                    def_name.into(),
+                    key.into(),
+                    values,
+                ));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl AgentDisplayConfigEntry {
@@ -216,12 +514,69 @@ impl AgentDisplayConfigEntry {
     }
 }
 
-pub fn agents_dir(app: &AppHandle) -> Option<PathBuf> {
-    let mnemnk_dir = settings::mnemnk_dir(app);
+impl AgentDefinition {
+    /// Kind of the data accepted by the input port, if declared.
+    pub fn input_kind(&self, ch: &str) -> Option<&str> {
+        self.input_kinds
+            .as_ref()
+            .and_then(|kinds| kinds.get(ch))
+            .map(String::as_str)
+    }
+
+    /// Kind of the data sent from the output port, if declared.
+    pub fn output_kind(&self, ch: &str) -> Option<&str> {
+        self.output_kinds
+            .as_ref()
+            .and_then(|kinds| kinds.get(ch))
+            .map(String::as_str)
+    }
+
+    /// Keys of the secret entries in the default and global configs.
+    pub fn secret_keys(&self) -> Vec<&str> {
+        self.default_config
+            .iter()
+            .chain(self.global_config.iter())
+            .flatten()
+            .filter(|(_, entry)| entry.is_secret())
+            .map(|(key, _)| key.as_str())
+            .collect()
+    }
+
+    /// Checks the node config against the entries of the default config.
+    pub fn validate_config(&self, config: &AgentConfig) -> Result<(), AgentConfigError> {
+        let Some(default_config) = &self.default_config else {
+            return Ok(());
+        };
+        for (key, entry) in default_config.iter() {
+            let value = config.get(key).unwrap_or(&AgentValue::Null);
+            entry.validate(&self.name, key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether an output port of the source kind can be connected to an input port of the target kind.
+///
+/// A kind is a list of alternatives separated by `|`, such as `string|text`, where `[]` after a
+/// name stands for an array of it and `*` for any kind. Ports without a kind accept anything.
+pub fn port_kinds_match(source: Option<&str>, target: Option<&str>) -> bool {
+    let (Some(source), Some(target)) = (source, target) else {
+        return true;
+    };
+    source.split('|').map(str::trim).any(|s| {
+        target
+            .split('|')
+            .map(str::trim)
+            .any(|t| s == "*" || t == "*" || s == t)
+    })
+}
+
+pub fn agents_dir(host: &dyn AgentHost) -> Option<PathBuf> {
+    let mnemnk_dir = host.mnemnk_dir();
     if mnemnk_dir.is_none() {
         return None;
     }
-    let agents_dir = PathBuf::from(mnemnk_dir.unwrap()).join(AGENTS_DIR);
+    let agents_dir = mnemnk_dir.unwrap().join(AGENTS_DIR);
     if !agents_dir.exists() {
         if let Err(e) = std::fs::create_dir(&agents_dir) {
             log::error!("Failed to create agents directory: {}", e);
@@ -231,18 +586,63 @@ pub fn agents_dir(app: &AppHandle) -> Option<PathBuf> {
     Some(agents_dir)
 }
 
-pub(super) fn init_agent_defs(app: &AppHandle) -> Result<AgentDefinitions> {
+pub(super) fn init_agent_defs(host: &dyn AgentHost) -> Result<AgentDefinitions> {
+    let (defs, errors) = load_agent_defs(host)?;
+    for e in errors {
+        log::error!("{}", e);
+    }
+    Ok(defs)
+}
+
+/// Reads the builtin and the agents directory definitions, with the errors of the files that
+/// could not be read.
+pub(super) fn load_agent_defs(
+    host: &dyn AgentHost,
+) -> Result<(AgentDefinitions, Vec<AgentDefinitionError>)> {
     let mut defs: AgentDefinitions = Default::default();
+    let mut errors = Vec::new();
 
     builtins::init_agent_defs(&mut defs);
-    read_mnemnk_jsons(app, &mut defs)?;
+    if host.mnemnk_dir().is_some() {
+        read_mnemnk_jsons(host, &mut defs, &mut errors)?;
+    }
 
-    Ok(defs)
+    Ok((defs, errors))
 }
 
-fn read_mnemnk_jsons(app: &AppHandle, defs: &mut AgentDefinitions) -> Result<()> {
+/// Modification times of the definition files in the agents directory, to see if any of them
+/// has been added, changed or removed.
+pub(super) fn agent_defs_modified(host: &dyn AgentHost) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let Some(dir) = agents_dir(host) else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut modified = Vec::new();
+    for entry in entries.flatten() {
+        let agent_dir = entry.path();
+        if !agent_dir.is_dir() {
+            continue;
+        }
+        for file in [MNEMNK_LOCAL_JSON, MNEMNK_JSON] {
+            let path = agent_dir.join(file);
+            if let Ok(metadata) = std::fs::metadata(&path) {
+                modified.push((path, metadata.modified().ok()));
+            }
+        }
+    }
+    modified.sort();
+    modified
+}
+
+fn read_mnemnk_jsons(
+    host: &dyn AgentHost,
+    defs: &mut AgentDefinitions,
+    errors: &mut Vec<AgentDefinitionError>,
+) -> Result<()> {
     // read agent definitions from agents directory
-    let dir = agents_dir(app);
+    let dir = agents_dir(host);
     if dir.is_none() {
         return Err(anyhow::anyhow!("Agents directory not found"));
     }
@@ -253,13 +653,34 @@ fn read_mnemnk_jsons(app: &AppHandle, defs: &mut AgentDefinitions) -> Result<()>
             continue;
         }
         // read mnemnk.json, and post process it
-        let Some(mnemnk_json) = read_mnemnk_json(&agent_dir) else {
-            continue;
+        let (file, mnemnk_json) = match read_mnemnk_json(&agent_dir) {
+            Ok(Some(ret)) => ret,
+            Ok(None) => continue,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
         };
+        if let Some(plugin) = &mnemnk_json.plugin {
+            match builtins::PluginAgent::read_defs(plugin, &agent_dir) {
+                Ok(plugin_defs) => {
+                    for def in plugin_defs {
+                        defs.insert(def.name.clone(), def);
+                    }
+                }
+                Err(e) => errors.push(AgentDefinitionError::InvalidFile(
+                    file.display().to_string(),
+                    format!("plugin: {:#}", e),
+                )),
+            }
+        }
         for def in mnemnk_json.agents.unwrap_or_default() {
             let mut def = def;
             if let Err(e) = post_process_agent_def(&mut def, &agent_dir) {
-                log::error!("Failed to post process agent definition: {}", e);
+                errors.push(AgentDefinitionError::InvalidFile(
+                    file.display().to_string(),
+                    e.to_string(),
+                ));
                 continue;
             }
             defs.insert(def.name.clone(), def);
@@ -269,52 +690,142 @@ fn read_mnemnk_jsons(app: &AppHandle, defs: &mut AgentDefinitions) -> Result<()>
     Ok(())
 }
 
-fn read_mnemnk_json(agent_dir: &PathBuf) -> Option<MnemnkJson> {
+fn read_mnemnk_json(
+    agent_dir: &Path,
+) -> Result<Option<(PathBuf, MnemnkJson)>, AgentDefinitionError> {
     // If mnemnk.local.json exists, prioritize reading it
-    let mnemnk_local_json_file = agent_dir.join(MNEMNK_LOCAL_JSON);
-    if mnemnk_local_json_file.exists() {
-        let content = match std::fs::read_to_string(&mnemnk_local_json_file) {
-            Ok(ret) => ret,
-            Err(e) => {
-                log::error!("I/O Error {}: {}", mnemnk_local_json_file.display(), e);
-                return None;
-            }
-        };
-        let mnemnk_json: MnemnkJson = match serde_json::from_str(&content) {
-            Ok(json) => json,
-            Err(e) => {
-                log::error!("Invalid JSON {}: {}", mnemnk_local_json_file.display(), e);
-                return None;
-            }
-        };
-        return Some(mnemnk_json);
+    let mut file = agent_dir.join(MNEMNK_LOCAL_JSON);
+    if !file.exists() {
+        file = agent_dir.join(MNEMNK_JSON);
+        if !file.exists() {
+            return Ok(None);
+        }
     }
-
-    let mnemnk_json_file = agent_dir.join(MNEMNK_JSON);
-    if !mnemnk_json_file.exists() {
-        return None;
+    let invalid =
+        |message: String| AgentDefinitionError::InvalidFile(file.display().to_string(), message);
+
+    let content = std::fs::read_to_string(&file).map_err(|e| invalid(e.to_string()))?;
+    let value: serde_json::Value =
+        serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+
+    // the errors tell the path of the invalid field, such as "/agents/0/inputs"
+    let errors = MNEMNK_JSON_VALIDATOR
+        .iter_errors(&value)
+        .map(|e| format!("{}: {}", e.instance_path(), e))
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(invalid(errors.join("; ")));
     }
-    let content = match std::fs::read_to_string(&mnemnk_json_file) {
-        Ok(ret) => ret,
-        Err(e) => {
-            log::warn!("Failed to read agent definition file: {}", e);
-            return None;
-        }
-    };
-    let mnemnk_json: MnemnkJson = match serde_json::from_str(&content) {
-        Ok(json) => json,
-        Err(e) => {
-            log::warn!("Failed to parse agent definition file: {}", e);
-            return None;
-        }
-    };
-    Some(mnemnk_json)
+
+    let mnemnk_json: MnemnkJson =
+        serde_path_to_error::deserialize(value).map_err(|e| invalid(e.to_string()))?;
+    Ok(Some((file, mnemnk_json)))
 }
 
-fn post_process_agent_def(def: &mut AgentDefinition, agent_dir: &PathBuf) -> Result<()> {
+fn post_process_agent_def(def: &mut AgentDefinition, agent_dir: &Path) -> Result<()> {
     match def.kind.as_str() {
         "Command" => builtins::CommandAgent::read_def(def, agent_dir)?,
+        "Wasm" => builtins::WasmAgent::read_def(def, agent_dir)?,
         _ => {}
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mnemnk_json_schema() {
+        let valid = serde_json::json!({
+            "agents": [{
+                "kind": "Command",
+                "name": "my_agent",
+                "outputs": ["data"],
+                "default_config": [["interval", { "value": 10, "type": "integer", "min": 1 }]],
+                "command": { "cmd": "./my_agent" }
+            }]
+        });
+        assert!(MNEMNK_JSON_VALIDATOR.is_valid(&valid));
+
+        let typo = serde_json::json!({
+            "agents": [{ "kind": "Command", "name": "my_agent", "ouputs": ["data"] }]
+        });
+        assert!(!MNEMNK_JSON_VALIDATOR.is_valid(&typo));
+
+        let wrong_type = serde_json::json!({
+            "agents": [{
+                "kind": "Builtin",
+                "name": "my_agent",
+                "default_config": [["n", { "value": 1, "type": "int" }]]
+            }]
+        });
+        let paths = MNEMNK_JSON_VALIDATOR
+            .iter_errors(&wrong_type)
+            .map(|e| e.instance_path().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["/agents/0/default_config/0/1/type"]);
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let def = AgentDefinition::new("Builtin", "my_agent", None).with_default_config(vec![
+            (
+                "n".into(),
+                AgentConfigEntry::new(AgentValue::new_integer(2), "integer")
+                    .with_range(Some(1.0), Some(10.0))
+                    .with_required(),
+            ),
+            (
+                "mode".into(),
+                AgentConfigEntry::new(AgentValue::new_string("a"), "string").with_enum(vec![
+                    AgentValue::new_string("a"),
+                    AgentValue::new_string("b"),
+                ]),
+            ),
+        ]);
+
+        let mut config = AgentConfig::new();
+        config.set("n".into(), AgentValue::new_integer(3));
+        config.set("mode".into(), AgentValue::new_string("b"));
+        assert!(def.validate_config(&config).is_ok());
+
+        config.set("mode".into(), AgentValue::new_string("c"));
+        assert!(matches!(
+            def.validate_config(&config),
+            Err(AgentConfigError::NotInEnum(_, key, _)) if key == "mode"
+        ));
+        config.set("mode".into(), AgentValue::new_string(""));
+
+        config.set("n".into(), AgentValue::new_string("3"));
+        assert!(matches!(
+            def.validate_config(&config),
+            Err(AgentConfigError::InvalidType(_, key, _)) if key == "n"
+        ));
+        config.set("n".into(), AgentValue::new_integer(11));
+        assert!(matches!(
+            def.validate_config(&config),
+            Err(AgentConfigError::TooLarge(..))
+        ));
+        config.set("n".into(), AgentValue::Null);
+        assert!(matches!(
+            def.validate_config(&config),
+            Err(AgentConfigError::Required(..))
+        ));
+    }
+
+    #[test]
+    fn test_port_kinds_match() {
+        assert!(port_kinds_match(None, Some("image")));
+        assert!(port_kinds_match(Some("image"), None));
+        assert!(port_kinds_match(Some("image"), Some("image")));
+        assert!(port_kinds_match(Some("*"), Some("string")));
+        assert!(port_kinds_match(Some("string"), Some("string|text")));
+        assert!(port_kinds_match(
+            Some("message[]"),
+            Some("message | message[]")
+        ));
+        assert!(!port_kinds_match(Some("image"), Some("string|text")));
+        assert!(!port_kinds_match(Some("message"), Some("message[]")));
+    }
+}
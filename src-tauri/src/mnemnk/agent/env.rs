@@ -1,35 +1,56 @@
 use anyhow::{bail, Context as _, Result};
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tauri::{AppHandle, Emitter, Manager};
-use tauri_plugin_shell::process::CommandChild;
+use std::time::{Duration, Instant};
+use tauri::async_runtime::{JoinHandle, RuntimeHandle};
 use tokio::sync::mpsc;
 
-use crate::mnemnk::settings;
-
 use super::agent::{self, AgentMessage, AsyncAgent};
-use super::config::AgentConfig;
+use super::blob::BlobStore;
+use super::config::{AgentConfig, AgentConfigVariables};
 use super::data::AgentData;
-use super::definition::{init_agent_defs, AgentDefaultConfig, AgentDefinitions};
-use super::flow::{AgentFlow, AgentFlowEdge, AgentFlowNode, AgentFlows};
+use super::definition::{
+    agent_defs_modified, init_agent_defs, load_agent_defs, port_kinds_match, AgentDefaultConfig,
+    AgentDefinitions, SupervisorConfig,
+};
+use super::flow::{AgentFlow, AgentFlowEdge, AgentFlowNode, AgentFlowNodeResult, AgentFlows};
+use super::history::AgentFlowHistory;
+use super::host::{AgentHost, CommandChild};
+use super::instance::AgentFlowInstance;
 use super::message::{self, EnvAgentMessage};
+use super::metrics::{AgentMetrics, AgentMetricsSnapshot};
+use super::queue::{AgentQueue, AgentQueueStats};
+use super::trace::{AgentTraceHop, TraceRecorder};
 use super::AgentContext;
-
-const EMIT_DISPLAY: &str = "mnemnk:display";
-const EMIT_ERROR: &str = "mnemnk:error";
-const EMIT_INPUT: &str = "mnemnk:input";
-
-#[derive(Clone)]
-pub enum AgentMessageSender {
-    Sync(std::sync::mpsc::Sender<AgentMessage>),
-    Async(mpsc::Sender<AgentMessage>),
+use crate::mnemnk::secrets;
+
+pub const EMIT_DISPLAY: &str = "mnemnk:display";
+pub const EMIT_ERROR: &str = "mnemnk:error";
+pub const EMIT_INPUT: &str = "mnemnk:input";
+pub const EMIT_DROPPED: &str = "mnemnk:dropped";
+pub const EMIT_STATUS: &str = "mnemnk:status";
+pub const EMIT_AGENT_DEFS: &str = "mnemnk:agent_defs";
+
+// interval to check the definition files in the agents directory for changes
+const AGENT_DEFS_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Names of the agent definitions changed by a reload, with the errors of the invalid files.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct AgentDefsChanges {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    pub errors: Vec<String>,
 }
 
 pub struct AgentEnv {
-    // AppHandle
-    app: AppHandle,
+    // host (Tauri app or headless)
+    host: Arc<dyn AgentHost>,
+
+    // runtime for the message loop and the agent tasks
+    runtime: RuntimeHandle,
 
     // agent flows
     pub flows: Mutex<AgentFlows>,
@@ -40,14 +61,20 @@ pub struct AgentEnv {
     // agent id -> agent
     pub agents: Mutex<HashMap<String, Arc<Mutex<Box<dyn AsyncAgent>>>>>,
 
-    // agent id -> sender
-    pub agent_txs: Mutex<HashMap<String, AgentMessageSender>>,
+    // agent id -> inbox
+    pub agent_queues: Mutex<HashMap<String, Arc<AgentQueue>>>,
+
+    // agent id -> runtime metrics
+    pub agent_metrics: Mutex<HashMap<String, Arc<AgentMetrics>>>,
+
+    // agent id -> supervisor config of the node
+    pub agent_supervisors: Mutex<HashMap<String, SupervisorConfig>>,
 
     // sourece agent id -> [target agent id / source handle / target handle]
     pub edges: Mutex<HashMap<String, Vec<(String, String, String)>>>,
 
     // agent id -> child process
-    pub commands: Mutex<HashMap<String, CommandChild>>,
+    pub commands: Mutex<HashMap<String, Box<dyn CommandChild>>>,
 
     // board name -> [board out agent id]
     pub board_out_agents: Mutex<HashMap<String, Vec<String>>>,
@@ -55,45 +82,120 @@ pub struct AgentEnv {
     // board name -> data
     pub board_data: Mutex<HashMap<String, AgentData>>,
 
+    // agent id -> name of the flow of its node
+    pub agent_flow_names: Mutex<HashMap<String, String>>,
+
+    // subflow instance node id -> subflow agent id
+    pub subflow_parents: Mutex<HashMap<String, String>>,
+
+    // running subflow agent id -> name of the flow it runs
+    pub subflow_flows: Mutex<HashMap<String, String>>,
+
     // Rhai engine
     pub rhai_engine: rhai::Engine,
 
+    // recent traces of the flows with trace enabled
+    pub tracer: TraceRecorder,
+
+    // flow name -> undo and redo of the edits
+    pub flow_history: Mutex<HashMap<String, AgentFlowHistory>>,
+
+    // instance name -> instance of a flow
+    pub flow_instances: Mutex<BTreeMap<String, AgentFlowInstance>>,
+
+    // agent id of an instance -> instance name
+    pub instance_parents: Mutex<HashMap<String, String>>,
+
+    // images exchanged with command agents as files
+    pub blobs: BlobStore,
+
     // message sender
     pub tx: Mutex<Option<mpsc::Sender<EnvAgentMessage>>>,
+
+    // set by quit, to stop the background tasks such as the agent defs watcher
+    quitting: AtomicBool,
 }
 
 impl AgentEnv {
-    fn new(app: AppHandle) -> Self {
+    fn new(host: Arc<dyn AgentHost>, runtime: RuntimeHandle) -> Self {
         Self {
-            app,
+            host,
+            runtime,
             flows: Default::default(),
             defs: Default::default(),
             agents: Default::default(),
-            agent_txs: Default::default(),
+            agent_queues: Default::default(),
+            agent_metrics: Default::default(),
+            agent_supervisors: Default::default(),
             edges: Default::default(),
             commands: Default::default(),
             board_out_agents: Default::default(),
             board_data: Default::default(),
+            agent_flow_names: Default::default(),
+            subflow_parents: Default::default(),
+            subflow_flows: Default::default(),
             rhai_engine: rhai::Engine::new(),
+            tracer: Default::default(),
+            flow_history: Default::default(),
+            flow_instances: Default::default(),
+            instance_parents: Default::default(),
+            blobs: Default::default(),
             tx: Default::default(),
+            quitting: Default::default(),
         }
     }
 
-    pub fn init(app: &AppHandle) -> Result<()> {
-        let env = Self::new(app.clone());
+    pub fn init(host: Arc<dyn AgentHost>) -> Result<Arc<Self>> {
+        Self::init_with_runtime(host, tauri::async_runtime::handle())
+    }
+
+    /// Same as `init`, but the message loop and the agent tasks are spawned on the given runtime
+    /// instead of the Tauri one.
+    pub fn init_with_runtime(
+        host: Arc<dyn AgentHost>,
+        runtime: RuntimeHandle,
+    ) -> Result<Arc<Self>> {
+        let env = Self::new(host, runtime);
 
-        let agent_defs = init_agent_defs(app)?;
-        settings::init_agent_global_configs(app, &agent_defs)?;
+        let agent_defs = init_agent_defs(env.host.as_ref())?;
+        env.host.init_agent_global_configs(&agent_defs)?;
         {
             let mut defs = env.defs.lock().unwrap();
             *defs = agent_defs;
         }
 
-        app.manage(env);
-        Ok(())
+        Ok(Arc::new(env))
     }
 
-    pub fn spawn_message_loop(&self) -> Result<()> {
+    pub fn host(&self) -> &Arc<dyn AgentHost> {
+        &self.host
+    }
+
+    /// The global config of the agent overridden by the node config, as stored.
+    pub fn merged_config(&self, def_name: &str, config: Option<&AgentConfig>) -> AgentConfig {
+        let mut merged_config = self.host.agent_global_config(def_name).unwrap_or_default();
+        if let Some(config) = config {
+            for (key, value) in config {
+                merged_config.set(key.clone(), value.clone());
+            }
+        }
+        merged_config
+    }
+
+    /// The config with the `"secret:<name>"` values replaced by the secrets.
+    pub fn resolve_secrets(&self, config: &AgentConfig) -> AgentConfig {
+        secrets::resolve_secrets(config, |name| self.host.secret(name))
+    }
+
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: std::future::Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.runtime.spawn(task)
+    }
+
+    pub fn spawn_message_loop(self: &Arc<Self>) -> Result<()> {
         // TODO: settings for the channel size
         let (tx, mut rx) = mpsc::channel(4096);
         {
@@ -102,17 +204,17 @@ impl AgentEnv {
         }
 
         // spawn the main loop
-        let app_handle = self.app.clone();
-        tauri::async_runtime::spawn(async move {
+        let env = self.clone();
+        self.spawn(async move {
             while let Some(message) = rx.recv().await {
                 use EnvAgentMessage::*;
 
                 match message {
                     AgentOut { agent, ctx, data } => {
-                        message::agent_out(&app_handle, agent, ctx, data).await;
+                        message::agent_out(&env, agent, ctx, data).await;
                     }
                     BoardOut { name, ctx, data } => {
-                        message::board_out(&app_handle, name, ctx, data).await;
+                        message::board_out(&env, name, ctx, data).await;
                     }
                 }
             }
@@ -121,6 +223,182 @@ impl AgentEnv {
         Ok(())
     }
 
+    /// Checks the definition files in the agents directory periodically, and reloads the agent
+    /// definitions when any of them has been added, changed or removed.
+    ///
+    /// Changes are reloaded once the files stay the same for an interval, and the watcher stops
+    /// when the env quits.
+    pub fn spawn_agent_defs_watcher(self: &Arc<Self>) {
+        if self.host.mnemnk_dir().is_none() {
+            return;
+        }
+
+        let mut modified = agent_defs_modified(self.host.as_ref());
+        let env = Arc::downgrade(self);
+        self.spawn(async move {
+            let mut interval = tokio::time::interval(AGENT_DEFS_WATCH_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // modification times seen at the last tick, while the files are being changed
+            let mut pending = None;
+            loop {
+                interval.tick().await;
+                let Some(env) = env.upgrade() else {
+                    break;
+                };
+                if env.quitting.load(Ordering::Relaxed) {
+                    break;
+                }
+                let new_modified = agent_defs_modified(env.host.as_ref());
+                if new_modified == modified {
+                    pending = None;
+                    continue;
+                }
+                if pending.as_ref() != Some(&new_modified) {
+                    pending = Some(new_modified);
+                    continue;
+                }
+                pending = None;
+                modified = new_modified;
+                log::info!("Agent definitions changed, reloading");
+                if let Err(e) = env.reload_agent_defs() {
+                    log::error!("Failed to reload agent definitions: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Reads the agent definitions again, and recreates the agents whose definition has changed.
+    ///
+    /// The agents are restarted if they were running. Nodes whose definition was missing are
+    /// added now, and agents whose definition has been removed are stopped.
+    pub fn reload_agent_defs(self: &Arc<Self>) -> Result<AgentDefsChanges> {
+        let (new_defs, errors) = load_agent_defs(self.host.as_ref())?;
+        for e in errors.iter() {
+            log::error!("{}", e);
+        }
+
+        let mut changes = diff_agent_defs(&self.defs.lock().unwrap(), &new_defs);
+        if !changes.added.is_empty() || !changes.changed.is_empty() {
+            // global configs of the new entries, before the agents are created with them
+            self.host
+                .init_agent_global_configs(&new_defs)
+                .unwrap_or_else(|e| log::error!("Failed to init agent global configs: {}", e));
+        }
+        *self.defs.lock().unwrap() = new_defs;
+        changes.errors = errors.iter().map(|e| e.to_string()).collect();
+
+        // agents of the changed or removed definitions
+        let mut changed_agents = Vec::new();
+        let mut removed_agents = Vec::new();
+        {
+            let agents = self.agents.lock().unwrap();
+            for (agent_id, agent) in agents.iter() {
+                let agent = agent.lock().unwrap();
+                let def_name = agent.def_name().to_string();
+                if changes.changed.contains(&def_name) {
+                    changed_agents.push(agent_id.clone());
+                } else if changes.removed.contains(&def_name) {
+                    removed_agents.push(agent_id.clone());
+                }
+            }
+        }
+
+        for agent_id in changed_agents {
+            self.recreate_agent(&agent_id).unwrap_or_else(|e| {
+                log::error!("Failed to recreate agent {}: {}", agent_id, e);
+            });
+        }
+
+        for agent_id in removed_agents {
+            self.stop_agent(&agent_id).unwrap_or_else(|e| {
+                log::error!("Failed to stop agent {}: {}", agent_id, e);
+            });
+            let def_name = self
+                .agents
+                .lock()
+                .unwrap()
+                .get(&agent_id)
+                .map(|a| a.lock().unwrap().def_name().to_string())
+                .unwrap_or_default();
+            self.emit_error(
+                agent_id,
+                format!("Agent definition {} has been removed", def_name),
+            )
+            .unwrap_or_else(|e| log::error!("{}", e));
+        }
+
+        // nodes that could not be added without their definition
+        let mut new_nodes = Vec::new();
+        {
+            let flows = self.flows.lock().unwrap();
+            let agents = self.agents.lock().unwrap();
+            for (flow_name, flow) in flows.iter() {
+                for node in flow.nodes.iter() {
+                    if changes.added.contains(&node.name) && !agents.contains_key(&node.id) {
+                        let edges = flow
+                            .edges
+                            .iter()
+                            .filter(|edge| edge.source == node.id)
+                            .cloned()
+                            .collect::<Vec<_>>();
+                        new_nodes.push((flow_name.clone(), flow.resolve_node(node), edges));
+                    }
+                }
+            }
+        }
+        for (flow_name, node, edges) in new_nodes {
+            if let Err(e) = self.add_agent(&flow_name, &node) {
+                log::error!("Failed to add agent {}: {}", node.id, e);
+                continue;
+            }
+            for edge in edges.iter() {
+                self.add_edge(edge).unwrap_or_else(|e| {
+                    log::error!("Failed to add_edge {}: {}", edge.source, e);
+                });
+            }
+            if node.enabled {
+                self.start_agent(&node.id).unwrap_or_else(|e| {
+                    log::error!("Failed to start agent {}: {}", node.id, e);
+                });
+            }
+        }
+
+        self.emit_agent_defs(&changes)?;
+        Ok(changes)
+    }
+
+    // replaces the agent with a new one of the current definition, keeping its queue and edges
+    fn recreate_agent(self: &Arc<Self>, agent_id: &str) -> Result<()> {
+        let agent = {
+            let agents = self.agents.lock().unwrap();
+            let Some(a) = agents.get(agent_id) else {
+                bail!("Agent {} not found", agent_id);
+            };
+            a.clone()
+        };
+        let (def_name, config, running) = {
+            let agent = agent.lock().unwrap();
+            (
+                agent.def_name().to_string(),
+                agent.config().cloned(),
+                *agent.status() == agent::AgentStatus::Start,
+            )
+        };
+
+        self.stop_agent(agent_id)?;
+        let new_agent = agent::agent_new(self, agent_id.to_string(), &def_name, config)?;
+        {
+            let mut agents = self.agents.lock().unwrap();
+            agents.insert(agent_id.to_string(), Arc::new(Mutex::new(new_agent)));
+        }
+        log::info!("Agent {} recreated", agent_id);
+
+        if running {
+            self.start_agent(agent_id)?;
+        }
+        Ok(())
+    }
+
     pub fn get_agent_default_config(&self, def_name: &str) -> Option<AgentDefaultConfig> {
         let defs = self.defs.lock().unwrap();
         let Some(def) = defs.get(def_name) else {
@@ -163,10 +441,16 @@ impl AgentEnv {
         // insert renamed flow
         flow.name = Some(new_name.clone());
         flows.insert(new_name.clone(), flow);
+        self.tracer.rename_flow(old_name, &new_name);
+        for flow_name in self.agent_flow_names.lock().unwrap().values_mut() {
+            if flow_name == old_name {
+                *flow_name = new_name.clone();
+            }
+        }
         Ok(new_name)
     }
 
-    fn is_valid_flow_name(new_name: &str) -> bool {
+    pub(super) fn is_valid_flow_name(new_name: &str) -> bool {
         // Check if the name is empty
         if new_name.trim().is_empty() {
             return false;
@@ -178,10 +462,11 @@ impl AgentEnv {
             if new_name.starts_with('/') || new_name.ends_with('/') || new_name.contains("//") {
                 return false;
             }
-            // Disallow segments that starts with "."
-            if new_name.split('/').any(|segment| segment.starts_with('.')) {
-                return false;
-            }
+        }
+
+        // Disallow segments that starts with ".", such as ".."
+        if new_name.split('/').any(|segment| segment.starts_with('.')) {
+            return false;
         }
 
         // Check if the name contains invalid characters
@@ -206,7 +491,7 @@ impl AgentEnv {
         new_name
     }
 
-    pub fn add_agent_flow(&self, agent_flow: &AgentFlow) -> Result<()> {
+    pub fn add_agent_flow(self: &Arc<Self>, agent_flow: &AgentFlow) -> Result<()> {
         let name = agent_flow
             .name
             .clone()
@@ -220,12 +505,16 @@ impl AgentEnv {
             }
             flows.insert(name.clone(), agent_flow.clone());
         }
+        if agent_flow.trace {
+            self.tracer.set_flow_enabled(&name, true);
+        }
 
         // add nodes into agents
         for node in agent_flow.nodes.iter() {
-            self.add_agent(node).unwrap_or_else(|e| {
-                log::error!("Failed to add_agent_node {}: {}", node.id, e);
-            });
+            self.add_agent(&name, &agent_flow.resolve_node(node))
+                .unwrap_or_else(|e| {
+                    log::error!("Failed to add_agent_node {}: {}", node.id, e);
+                });
         }
 
         // add edges into edges
@@ -238,19 +527,34 @@ impl AgentEnv {
         Ok(())
     }
 
-    pub fn add_agent(&self, node: &AgentFlowNode) -> Result<()> {
+    /// Creates the agent of the node, which belongs to the given flow.
+    pub fn add_agent(self: &Arc<Self>, flow_name: &str, node: &AgentFlowNode) -> Result<()> {
         let mut agents = self.agents.lock().unwrap();
         if agents.contains_key(&node.id) {
             bail!("Agent {} already exists", node.id);
         }
-        if let Ok(agent) = agent::agent_new(
-            self.app.clone(),
-            self,
-            node.id.clone(),
-            &node.name,
-            node.config.clone(),
-        ) {
+        if let Ok(agent) = agent::agent_new(self, node.id.clone(), &node.name, node.config.clone())
+        {
             agents.insert(node.id.clone(), Arc::new(Mutex::new(agent)));
+            let queue = AgentQueue::new(node.queue.clone().unwrap_or_default());
+            self.agent_queues
+                .lock()
+                .unwrap()
+                .insert(node.id.clone(), Arc::new(queue));
+            self.agent_metrics
+                .lock()
+                .unwrap()
+                .insert(node.id.clone(), Arc::new(AgentMetrics::default()));
+            if let Some(supervisor) = &node.supervisor {
+                self.agent_supervisors
+                    .lock()
+                    .unwrap()
+                    .insert(node.id.clone(), supervisor.clone());
+            }
+            self.agent_flow_names
+                .lock()
+                .unwrap()
+                .insert(node.id.clone(), flow_name.to_string());
             log::info!("Agent {} created", node.id);
         } else {
             bail!("Failed to create agent {}", node.id);
@@ -275,6 +579,11 @@ impl AgentEnv {
             bail!("Target handle is empty");
         }
 
+        // edges already in the flows are kept, even if the kinds of the ports have changed since
+        if let Err(e) = self.check_edge_kinds(edge) {
+            log::warn!("{}", e);
+        }
+
         let mut edges = self.edges.lock().unwrap();
         if let Some(targets) = edges.get_mut(&edge.source) {
             if targets
@@ -305,6 +614,44 @@ impl AgentEnv {
         Ok(())
     }
 
+    /// Checks that the kind of the source port is accepted by the target port.
+    pub fn check_edge_kinds(&self, edge: &AgentFlowEdge) -> Result<()> {
+        let (Some(source_def), Some(target_def)) = (
+            self.agent_def_name(&edge.source),
+            self.agent_def_name(&edge.target),
+        ) else {
+            return Ok(());
+        };
+        let defs = self.defs.lock().unwrap();
+        let source_kind = defs
+            .get(&source_def)
+            .and_then(|def| def.output_kind(&edge.source_handle));
+        let target_kind = defs
+            .get(&target_def)
+            .and_then(|def| def.input_kind(&edge.target_handle));
+        if !port_kinds_match(source_kind, target_kind) {
+            bail!(
+                "Cannot connect {} ({}) of {} to {} ({}) of {}",
+                edge.source_handle,
+                source_kind.unwrap_or_default(),
+                source_def,
+                edge.target_handle,
+                target_kind.unwrap_or_default(),
+                target_def
+            );
+        }
+        Ok(())
+    }
+
+    fn agent_def_name(&self, agent_id: &str) -> Option<String> {
+        let agent = {
+            let agents = self.agents.lock().unwrap();
+            agents.get(agent_id)?.clone()
+        };
+        let agent = agent.lock().unwrap();
+        Some(agent.def_name().to_string())
+    }
+
     pub fn remove_agent(&self, agent_id: &str) -> Result<()> {
         // remove from edges
         {
@@ -329,6 +676,22 @@ impl AgentEnv {
             let mut agents = self.agents.lock().unwrap();
             agents.remove(agent_id);
         }
+        {
+            let mut agent_queues = self.agent_queues.lock().unwrap();
+            agent_queues.remove(agent_id);
+        }
+        {
+            let mut agent_metrics = self.agent_metrics.lock().unwrap();
+            agent_metrics.remove(agent_id);
+        }
+        {
+            let mut agent_supervisors = self.agent_supervisors.lock().unwrap();
+            agent_supervisors.remove(agent_id);
+        }
+        {
+            let mut agent_flow_names = self.agent_flow_names.lock().unwrap();
+            agent_flow_names.remove(agent_id);
+        }
 
         Ok(())
     }
@@ -347,20 +710,100 @@ impl AgentEnv {
         }
     }
 
-    pub fn start_agent_flow(&self, name: &str) -> Result<()> {
-        let flows = self.flows.lock().unwrap();
-        let Some(flow) = flows.get(name) else {
-            bail!("Agent flow {} not found", name);
-        };
-        for node in flow.nodes.iter() {
-            if !node.enabled {
+    /// Starts the enabled nodes of the flow, each after the nodes it sends to, so that no early
+    /// output is lost.
+    ///
+    /// If a node fails to start, the nodes started so far are stopped again, and the results end
+    /// with its error. Every agent has started or failed when `start_agent` returns.
+    pub fn start_agent_flow(&self, name: &str) -> Result<Vec<AgentFlowNodeResult>> {
+        let flow = self.agent_flow(name)?;
+        if !flow.enabled {
+            bail!("Agent flow {} is disabled", name);
+        }
+        let enabled_nodes: HashSet<&str> = flow
+            .nodes
+            .iter()
+            .filter(|node| node.enabled)
+            .map(|node| node.id.as_str())
+            .collect();
+        let mut results = Vec::new();
+        let mut started = Vec::new();
+        for node_id in flow.sorted_node_ids().into_iter().rev() {
+            if !enabled_nodes.contains(node_id.as_str()) {
                 continue;
             }
-            self.start_agent(&node.id).unwrap_or_else(|e| {
-                log::error!("Failed to start agent {}: {}", node.id, e);
+            let running = self.is_agent_running(&node_id);
+            if let Err(e) = self.start_agent(&node_id) {
+                log::error!("Failed to start agent {}: {}", node_id, e);
+                // the nodes started so far are stopped again, sources first
+                for started_id in started.iter().rev() {
+                    self.stop_agent(started_id).unwrap_or_else(|e| {
+                        log::error!("Failed to stop agent {}: {}", started_id, e);
+                    });
+                }
+                for result in results.iter_mut() {
+                    if started.contains(&result.node_id) {
+                        result.error = Some(format!("Stopped as {} failed to start", node_id));
+                    }
+                }
+                results.push(AgentFlowNodeResult {
+                    node_id,
+                    error: Some(e.to_string()),
+                });
+                break;
+            }
+            if !running {
+                started.push(node_id.clone());
+            }
+            results.push(AgentFlowNodeResult {
+                node_id,
+                error: None,
             });
         }
-        Ok(())
+        Ok(results)
+    }
+
+    /// Stops the nodes of the flow, each before the nodes it sends to.
+    ///
+    /// Unlike starting, this goes on past the nodes which fail to stop, and returns their errors.
+    pub fn stop_agent_flow(&self, name: &str) -> Result<Vec<AgentFlowNodeResult>> {
+        let flow = self.agent_flow(name)?;
+        let mut results = Vec::new();
+        for node_id in flow.sorted_node_ids() {
+            let error = self.stop_agent(&node_id).err().map(|e| {
+                log::error!("Failed to stop agent {}: {}", node_id, e);
+                e.to_string()
+            });
+            results.push(AgentFlowNodeResult { node_id, error });
+        }
+        Ok(results)
+    }
+
+    /// Stops the flow and starts it again, returning the results of starting its nodes.
+    pub fn restart_agent_flow(&self, name: &str) -> Result<Vec<AgentFlowNodeResult>> {
+        self.stop_agent_flow(name)?;
+        self.start_agent_flow(name)
+    }
+
+    fn agent_flow(&self, name: &str) -> Result<AgentFlow> {
+        self.flows
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .with_context(|| format!("Agent flow {} not found", name))
+    }
+
+    fn is_agent_running(&self, agent_id: &str) -> bool {
+        let agent = {
+            let agents = self.agents.lock().unwrap();
+            let Some(a) = agents.get(agent_id) else {
+                return false;
+            };
+            a.clone()
+        };
+        let agent = agent.lock().unwrap();
+        *agent.status() == agent::AgentStatus::Start
     }
 
     pub fn start_agent(&self, agent_id: &str) -> Result<()> {
@@ -389,70 +832,69 @@ impl AgentEnv {
         if agent_status == agent::AgentStatus::Init {
             log::info!("Starting agent {}", agent_id);
 
-            if uses_native_thread {
-                let (tx, rx) = std::sync::mpsc::channel();
-
-                {
-                    let mut agent_txs = self.agent_txs.lock().unwrap();
-                    agent_txs.insert(agent_id.to_string(), AgentMessageSender::Sync(tx.clone()));
+            // a new run gets a new queue, so that it never takes the stop message of the last one
+            let queue = {
+                let mut agent_queues = self.agent_queues.lock().unwrap();
+                let Some(queue) = agent_queues.get_mut(agent_id) else {
+                    bail!("Agent queue for {} not found", agent_id);
                 };
+                *queue = Arc::new(queue.renew());
+                queue.clone()
+            };
+            let metrics = self.agent_metrics(agent_id)?;
 
-                let agent_id = agent_id.to_string();
-                std::thread::spawn(move || {
-                    if let Err(e) = agent.lock().unwrap().start() {
-                        log::error!("Failed to start agent {}: {}", agent_id, e);
-                    }
+            if uses_native_thread {
+                // the agent starts on its thread, and the result is waited for here
+                let (started_tx, started_rx) = std::sync::mpsc::channel();
+                {
+                    let agent_id = agent_id.to_string();
+                    std::thread::spawn(move || {
+                        let started = agent.lock().unwrap().start();
+                        let failed = started.is_err();
+                        let _ = started_tx.send(started);
+                        if failed {
+                            return;
+                        }
 
-                    while let Ok(message) = rx.recv() {
-                        match message {
-                            AgentMessage::Input { ctx, data } => {
-                                agent
-                                    .lock()
-                                    .unwrap()
-                                    .process(ctx, data)
-                                    .unwrap_or_else(|e| {
-                                        log::error!("Process Error {}: {}", agent_id, e);
-                                    });
-                            }
-                            AgentMessage::Config { config } => {
-                                agent
-                                    .lock()
-                                    .unwrap()
-                                    .set_config(config)
-                                    .unwrap_or_else(|e| {
-                                        log::error!("Config Error {}: {}", agent_id, e);
-                                    });
-                            }
-                            AgentMessage::Stop => {
-                                break;
+                        loop {
+                            match queue.recv_blocking() {
+                                AgentMessage::Input { ctx, data } => {
+                                    process_input(&agent_id, &agent, &metrics, ctx, data);
+                                    queue.done_input();
+                                }
+                                AgentMessage::Config { config } => {
+                                    agent
+                                        .lock()
+                                        .unwrap()
+                                        .set_config(config)
+                                        .unwrap_or_else(|e| {
+                                            log::error!("Config Error {}: {}", agent_id, e);
+                                        });
+                                }
+                                AgentMessage::Stop => {
+                                    break;
+                                }
                             }
                         }
-                    }
-                });
+                    });
+                }
+                started_rx
+                    .recv()
+                    .with_context(|| format!("Agent {} quit while starting", agent_id))??;
             } else {
-                let (tx, mut rx) = mpsc::channel(32);
-
+                // the agent starts here in the runtime, and only its message loop in the task
                 {
-                    let mut agent_txs = self.agent_txs.lock().unwrap();
-                    agent_txs.insert(agent_id.to_string(), AgentMessageSender::Async(tx.clone()));
-                };
+                    let _guard = self.runtime.inner().enter();
+                    agent.lock().unwrap().start()?;
+                }
 
                 let agent_id = agent_id.to_string();
-                tauri::async_runtime::spawn(async move {
-                    if let Err(e) = agent.lock().unwrap().start() {
-                        log::error!("Failed to start agent {}: {}", agent_id, e);
-                    }
-
-                    while let Some(message) = rx.recv().await {
-                        match message {
+                self.spawn(async move {
+                    loop {
+                        match queue.recv().await {
                             AgentMessage::Input { ctx, data } => {
-                                agent
-                                    .lock()
-                                    .unwrap()
-                                    .process(ctx, data)
-                                    .unwrap_or_else(|e| {
-                                        log::error!("Process Error {}: {}", agent_id, e);
-                                    });
+                                process_input(&agent_id, &agent, &metrics, ctx, data);
+                                queue.done_input();
                             }
                             AgentMessage::Config { config } => {
                                 agent
@@ -464,7 +906,6 @@ impl AgentEnv {
                                     });
                             }
                             AgentMessage::Stop => {
-                                rx.close();
                                 return;
                             }
                         }
@@ -491,30 +932,9 @@ impl AgentEnv {
         if agent_status == agent::AgentStatus::Start {
             log::info!("Stopping agent {}", agent_id);
 
-            {
-                let mut agent_txs = self.agent_txs.lock().unwrap();
-                if let Some(tx) = agent_txs.remove(agent_id) {
-                    match tx {
-                        AgentMessageSender::Sync(tx) => {
-                            tx.send(AgentMessage::Stop).unwrap_or_else(|e| {
-                                log::error!(
-                                    "Failed to send stop message to agent {}: {}",
-                                    agent_id,
-                                    e
-                                );
-                            });
-                        }
-                        AgentMessageSender::Async(tx) => {
-                            tx.try_send(AgentMessage::Stop).unwrap_or_else(|e| {
-                                log::error!(
-                                    "Failed to send stop message to agent {}: {}",
-                                    agent_id,
-                                    e
-                                );
-                            });
-                        }
-                    }
-                }
+            if let Ok(queue) = self.agent_queue(agent_id) {
+                queue.close();
+                queue.send_control(AgentMessage::Stop);
             }
 
             agent.lock().unwrap().stop()?;
@@ -532,6 +952,24 @@ impl AgentEnv {
             a.clone()
         };
 
+        // the agent gets the config with the variables of its node
+        let config = match self.agent_variables(agent_id) {
+            Some(variables) => config.resolve_variables(&variables),
+            None => config,
+        };
+
+        // reject an invalid config before it reaches the agent
+        let def_name = {
+            let agent = agent.lock().unwrap();
+            agent.def_name().to_string()
+        };
+        {
+            let defs = self.defs.lock().unwrap();
+            if let Some(def) = defs.get(&def_name) {
+                def.validate_config(&config)?;
+            }
+        }
+
         let agent_status = {
             let agent = agent.lock().unwrap();
             agent.status().clone()
@@ -539,24 +977,8 @@ impl AgentEnv {
         if agent_status == agent::AgentStatus::Init {
             agent.lock().unwrap().set_config(config.clone())?;
         } else if agent_status == agent::AgentStatus::Start {
-            let tx = {
-                let agent_txs = self.agent_txs.lock().unwrap();
-                let Some(tx) = agent_txs.get(agent_id) else {
-                    bail!("Agent tx for {} not found", agent_id);
-                };
-                tx.clone()
-            };
-            let message = AgentMessage::Config { config };
-            match tx {
-                AgentMessageSender::Sync(tx) => {
-                    tx.send(message).context("Failed to send config message")?;
-                }
-                AgentMessageSender::Async(tx) => {
-                    tx.send(message)
-                        .await
-                        .context("Failed to send config message")?;
-                }
-            }
+            let queue = self.agent_queue(agent_id)?;
+            queue.send_control(AgentMessage::Config { config });
         }
         Ok(())
     }
@@ -583,24 +1005,25 @@ impl AgentEnv {
             let ch = ctx.ch().to_string();
             let message = AgentMessage::Input { ctx, data };
 
-            let tx = {
-                let agent_txs = self.agent_txs.lock().unwrap();
-                let Some(tx) = agent_txs.get(agent_id) else {
-                    bail!("Agent tx for {} not found", agent_id);
-                };
-                tx.clone()
-            };
-            match tx {
-                AgentMessageSender::Sync(tx) => {
-                    tx.send(message).context("Failed to send input message")?;
-                }
-                AgentMessageSender::Async(tx) => {
-                    tx.send(message)
-                        .await
-                        .context("Failed to send input message")?;
-                }
+            let queue = self.agent_queue(agent_id)?;
+            let sent = queue
+                .send_input(message)
+                .await
+                .context("Failed to send input message")?;
+            if sent.dropped > 0 {
+                self.emit_dropped(agent_id.to_string(), queue.dropped_total())
+                    .unwrap_or_else(|e| {
+                        log::error!("Failed to emit dropped message: {}", e);
+                    });
+            }
+            // an input dropped by the policy never reaches the agent
+            if !sent.queued {
+                return Ok(());
             }
 
+            if let Ok(metrics) = self.agent_metrics(agent_id) {
+                metrics.record_input(&ch);
+            }
             self.emit_input(agent_id.to_string(), ch)
                 .unwrap_or_else(|e| {
                     log::error!("Failed to emit input message: {}", e);
@@ -609,6 +1032,160 @@ impl AgentEnv {
         Ok(())
     }
 
+    fn agent_queue(&self, agent_id: &str) -> Result<Arc<AgentQueue>> {
+        let agent_queues = self.agent_queues.lock().unwrap();
+        let Some(queue) = agent_queues.get(agent_id) else {
+            bail!("Agent queue for {} not found", agent_id);
+        };
+        Ok(queue.clone())
+    }
+
+    /// Whether any agent has an input waiting or being processed.
+    #[cfg(any(test, feature = "flow-test"))]
+    pub(super) fn has_busy_agents(&self) -> bool {
+        let agent_queues = self.agent_queues.lock().unwrap();
+        agent_queues.values().any(|queue| queue.is_busy())
+    }
+
+    fn agent_metrics(&self, agent_id: &str) -> Result<Arc<AgentMetrics>> {
+        let agent_metrics = self.agent_metrics.lock().unwrap();
+        let Some(metrics) = agent_metrics.get(agent_id) else {
+            bail!("Agent metrics for {} not found", agent_id);
+        };
+        Ok(metrics.clone())
+    }
+
+    /// Starts a trace for the output of the agent, if it has none and its flow is traced.
+    pub(super) fn start_trace(&self, agent_id: &str, ctx: AgentContext) -> AgentContext {
+        if !self.tracer.is_enabled() || ctx.trace_id().is_some() {
+            return ctx;
+        }
+        let Some(flow_name) = self.flow_of_agent(agent_id) else {
+            return ctx;
+        };
+        if !self.tracer.is_flow_enabled(&flow_name) {
+            return ctx;
+        }
+        ctx.with_trace(self.tracer.new_trace(&flow_name))
+    }
+
+    /// Records a hop of the trace of the context with the data, if it has one.
+    pub(super) fn trace_hop(
+        &self,
+        source: &str,
+        ch: &str,
+        target: &str,
+        ctx: &AgentContext,
+        data: &AgentData,
+    ) {
+        if !self.tracer.is_enabled() {
+            return;
+        }
+        let Some(trace_id) = ctx.trace_id() else {
+            return;
+        };
+        let hop = AgentTraceHop {
+            source: source.to_string(),
+            ch: ch.to_string(),
+            target: target.to_string(),
+            target_ch: ctx.ch().to_string(),
+            time: chrono::Utc::now().timestamp_millis(),
+        };
+        self.tracer.record_hop(trace_id, hop, data);
+    }
+
+    /// The variables resolved in the config of the agent: those of the flow of its node, which for
+    /// the nodes of a subflow is the flow run by the subflow agent, with the values of its instance
+    /// over them.
+    pub(super) fn agent_variables(&self, agent_id: &str) -> Option<AgentConfigVariables> {
+        let flow_name = {
+            let agent_flow_names = self.agent_flow_names.lock().unwrap();
+            agent_flow_names.get(agent_id)?.clone()
+        };
+        let mut variables = {
+            let flows = self.flows.lock().unwrap();
+            flows.get(&flow_name)?.variables.clone()
+        };
+        let instance_name = self.instance_parents.lock().unwrap().get(agent_id).cloned();
+        if let Some(instance_name) = instance_name {
+            if let Some(instance) = self.flow_instances.lock().unwrap().get(&instance_name) {
+                variables.extend(instance.variables.clone());
+            }
+        }
+        Some(variables)
+    }
+
+    /// The flow the agent runs in, which is traced with it.
+    ///
+    /// The nodes of subflows run in the flow of their subflow agent, and the agents of an instance
+    /// in the flow used as the template.
+    pub(super) fn flow_of_agent(&self, agent_id: &str) -> Option<String> {
+        // nodes of subflow instances belong to the flow of their subflow agent
+        let mut agent_id = agent_id.to_string();
+        {
+            let subflow_parents = self.subflow_parents.lock().unwrap();
+            while let Some(parent) = subflow_parents.get(&agent_id) {
+                agent_id = parent.clone();
+            }
+        }
+
+        let agent_flow_names = self.agent_flow_names.lock().unwrap();
+        agent_flow_names.get(&agent_id).cloned()
+    }
+
+    pub(super) fn record_agent_output(&self, agent_id: &str, ch: &str) {
+        if let Ok(metrics) = self.agent_metrics(agent_id) {
+            metrics.record_output(ch);
+        }
+    }
+
+    /// Returns the metrics of all agents, with their queue depth and dropped messages.
+    pub fn get_agent_metrics(&self) -> BTreeMap<String, AgentMetricsSnapshot> {
+        let agent_metrics: Vec<(String, Arc<AgentMetrics>)> = {
+            let agent_metrics = self.agent_metrics.lock().unwrap();
+            agent_metrics
+                .iter()
+                .map(|(id, m)| (id.clone(), m.clone()))
+                .collect()
+        };
+        let agents = self.agents.lock().unwrap().clone();
+        let queue_stats = self.get_agent_queue_stats();
+
+        let mut result = BTreeMap::new();
+        for (agent_id, metrics) in agent_metrics {
+            let mut snapshot = metrics.snapshot();
+            if let Some(agent) = agents.get(&agent_id) {
+                snapshot.def_name = agent.lock().unwrap().def_name().to_string();
+            }
+            if let Some(stats) = queue_stats.get(&agent_id) {
+                snapshot.queue_depth = stats.queued;
+                snapshot.dropped = stats.dropped + stats.dropped_out;
+            }
+            result.insert(agent_id, snapshot);
+        }
+        result
+    }
+
+    /// Counts an output of the agent which was dropped because the message channel was full.
+    pub(super) fn count_dropped_out(&self, agent_id: &str) {
+        let Ok(queue) = self.agent_queue(agent_id) else {
+            return;
+        };
+        queue.count_dropped_out();
+        self.emit_dropped(agent_id.to_string(), queue.dropped_total())
+            .unwrap_or_else(|e| {
+                log::error!("Failed to emit dropped message: {}", e);
+            });
+    }
+
+    pub fn get_agent_queue_stats(&self) -> HashMap<String, AgentQueueStats> {
+        let agent_queues = self.agent_queues.lock().unwrap();
+        agent_queues
+            .iter()
+            .map(|(agent_id, queue)| (agent_id.clone(), queue.stats()))
+            .collect()
+    }
+
     pub async fn send_agent_out(
         &self,
         agent_id: String,
@@ -684,6 +1261,13 @@ impl AgentEnv {
     }
 
     pub fn quit(&self) {
+        self.quitting.store(true, Ordering::Relaxed);
+        self.quit_commands();
+        // the blobs are kept until the command agents have exited
+        self.blobs.remove_dir();
+    }
+
+    fn quit_commands(&self) {
         {
             // send QUIT command to all agents
             let mut agent_commands = self.commands.lock().unwrap();
@@ -725,15 +1309,35 @@ impl AgentEnv {
         }
     }
 
+    /// Creates an env on a `MemoryHost` whose message loop is replaced by the returned receiver,
+    /// so that tests can observe what the agents send out.
+    #[cfg(test)]
+    pub(crate) fn new_for_test() -> (
+        Arc<Self>,
+        Arc<super::host::MemoryHost>,
+        mpsc::Receiver<EnvAgentMessage>,
+    ) {
+        let host = Arc::new(super::host::MemoryHost::new());
+        let env = Self::init(host.clone()).unwrap();
+        let (tx, rx) = mpsc::channel(4096);
+        *env.tx.lock().unwrap() = Some(tx);
+        (env, host, rx)
+    }
+
     pub fn emit_error(&self, agent_id: String, message: String) -> Result<()> {
+        if let Ok(metrics) = self.agent_metrics(&agent_id) {
+            metrics.record_error();
+        }
+
         #[derive(Clone, Serialize)]
         struct ErrorMessage {
             agent_id: String,
             message: String,
         }
 
-        self.app
-            .emit(EMIT_ERROR, ErrorMessage { agent_id, message })
+        let payload = serde_json::to_value(ErrorMessage { agent_id, message })?;
+        self.host
+            .emit(EMIT_ERROR, payload)
             .context("Failed to emit error message")?;
 
         Ok(())
@@ -746,13 +1350,64 @@ impl AgentEnv {
             ch: String,
         }
 
-        self.app
-            .emit(EMIT_INPUT, InputMessage { agent_id, ch })
+        let payload = serde_json::to_value(InputMessage { agent_id, ch })?;
+        self.host
+            .emit(EMIT_INPUT, payload)
             .context("Failed to emit input message")?;
 
         Ok(())
     }
 
+    pub fn emit_dropped(&self, agent_id: String, dropped: u64) -> Result<()> {
+        #[derive(Clone, Serialize)]
+        struct DroppedMessage {
+            agent_id: String,
+            dropped: u64,
+        }
+
+        let payload = serde_json::to_value(DroppedMessage { agent_id, dropped })?;
+        self.host
+            .emit(EMIT_DROPPED, payload)
+            .context("Failed to emit dropped message")?;
+
+        Ok(())
+    }
+
+    pub fn emit_status(
+        &self,
+        agent_id: String,
+        status: &str,
+        message: Option<String>,
+    ) -> Result<()> {
+        #[derive(Clone, Serialize)]
+        struct StatusMessage<'a> {
+            agent_id: String,
+            status: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            message: Option<String>,
+        }
+
+        let payload = serde_json::to_value(StatusMessage {
+            agent_id,
+            status,
+            message,
+        })?;
+        self.host
+            .emit(EMIT_STATUS, payload)
+            .context("Failed to emit status message")?;
+
+        Ok(())
+    }
+
+    pub fn emit_agent_defs(&self, changes: &AgentDefsChanges) -> Result<()> {
+        let payload = serde_json::to_value(changes)?;
+        self.host
+            .emit(EMIT_AGENT_DEFS, payload)
+            .context("Failed to emit agent defs message")?;
+
+        Ok(())
+    }
+
     pub fn emit_display(&self, agent_id: String, key: String, data: AgentData) -> Result<()> {
         #[derive(Clone, Serialize)]
         struct DisplayMessage {
@@ -761,17 +1416,206 @@ impl AgentEnv {
             data: AgentData,
         }
 
-        self.app
-            .emit(
-                EMIT_DISPLAY,
-                DisplayMessage {
-                    agent_id,
-                    key,
-                    data,
-                },
-            )
+        let payload = serde_json::to_value(DisplayMessage {
+            agent_id,
+            key,
+            data,
+        })?;
+        self.host
+            .emit(EMIT_DISPLAY, payload)
             .context("Failed to emit display message")?;
 
         Ok(())
     }
 }
+
+// names of the definitions added, changed and removed from old to new
+fn diff_agent_defs(old: &AgentDefinitions, new: &AgentDefinitions) -> AgentDefsChanges {
+    let mut changes = AgentDefsChanges::default();
+    for (name, def) in new.iter() {
+        match old.get(name) {
+            None => changes.added.push(name.clone()),
+            Some(old_def) => {
+                if serde_json::to_value(old_def).ok() != serde_json::to_value(def).ok() {
+                    changes.changed.push(name.clone());
+                }
+            }
+        }
+    }
+    for name in old.keys() {
+        if !new.contains_key(name) {
+            changes.removed.push(name.clone());
+        }
+    }
+    changes.added.sort();
+    changes.changed.sort();
+    changes.removed.sort();
+    changes
+}
+
+fn process_input(
+    agent_id: &str,
+    agent: &Mutex<Box<dyn AsyncAgent>>,
+    metrics: &AgentMetrics,
+    ctx: AgentContext,
+    data: AgentData,
+) {
+    let start = Instant::now();
+    let result = agent.lock().unwrap().process(ctx, data);
+    metrics.record_process_time(start.elapsed());
+    // the error is counted by emit_error
+    if let Err(e) = result {
+        log::error!("Process Error {}: {}", agent_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemnk::agent::builtins::SUBFLOW_DEF_NAME;
+    use crate::mnemnk::agent::flow::{set_agent_flow_enabled, store_node_secrets};
+    use crate::mnemnk::agent::{AgentConfigEntry, AgentDefinition, AgentValue};
+
+    #[test]
+    fn test_diff_agent_defs() {
+        let old = AgentDefinitions::from([
+            ("a".to_string(), AgentDefinition::new("Command", "a", None)),
+            ("b".to_string(), AgentDefinition::new("Command", "b", None)),
+            ("c".to_string(), AgentDefinition::new("Command", "c", None)),
+        ]);
+        let new = AgentDefinitions::from([
+            ("a".to_string(), AgentDefinition::new("Command", "a", None)),
+            (
+                "b".to_string(),
+                AgentDefinition::new("Command", "b", None).with_inputs(vec!["data"]),
+            ),
+            ("d".to_string(), AgentDefinition::new("Command", "d", None)),
+        ]);
+
+        let changes = diff_agent_defs(&old, &new);
+        assert_eq!(changes.added, vec!["d"]);
+        assert_eq!(changes.changed, vec!["b"]);
+        assert_eq!(changes.removed, vec!["c"]);
+    }
+
+    #[test]
+    fn test_store_node_secrets() {
+        let (env, host, _rx) = AgentEnv::new_for_test();
+        let def =
+            AgentDefinition::new("Command", "secret_agent", None).with_default_config(vec![(
+                "token".into(),
+                AgentConfigEntry::new(AgentValue::new_string(""), "string").with_secret(),
+            )]);
+        env.defs
+            .lock()
+            .unwrap()
+            .insert("secret_agent".to_string(), def);
+
+        let mut flow: AgentFlow = serde_json::from_value(serde_json::json!({
+            "nodes": [
+                { "id": "a", "name": "secret_agent", "enabled": true, "config": { "token": "abc", "size": 1 } },
+            ],
+            "edges": [],
+        }))
+        .unwrap();
+        store_node_secrets(&env, "main", &mut flow).unwrap();
+
+        let config = flow.nodes[0].config.as_ref().unwrap();
+        assert_eq!(
+            config.get_string("token").as_deref(),
+            Some("secret:main:a.token")
+        );
+        assert_eq!(config.get_integer("size"), Some(1));
+        assert_eq!(host.secret("main:a.token").as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn test_start_and_stop_agent_flow() {
+        let (env, _host, _rx) = AgentEnv::new_for_test();
+        let flow: AgentFlow = serde_json::from_value(serde_json::json!({
+            "name": "main",
+            "nodes": [
+                { "id": "c", "name": "$template_string", "enabled": true, "config": { "template": "{{value}}" } },
+                { "id": "b", "name": "$template_string", "enabled": true, "config": { "template": "{{value}}" } },
+                { "id": "a", "name": "$template_string", "enabled": true, "config": { "template": "{{value}}" } },
+                { "id": "d", "name": "$template_string", "enabled": false, "config": { "template": "{{value}}" } },
+            ],
+            "edges": [
+                { "id": "e1", "source": "a", "source_handle": "string", "target": "b", "target_handle": "data" },
+                { "id": "e2", "source": "b", "source_handle": "string", "target": "c", "target_handle": "data" },
+            ],
+        }))
+        .unwrap();
+        assert!(flow.enabled);
+        assert_eq!(flow.sorted_node_ids(), vec!["a", "d", "b", "c"]);
+        env.add_agent_flow(&flow).unwrap();
+
+        let node_ids = |results: Vec<AgentFlowNodeResult>| {
+            assert!(results.iter().all(|r| r.error.is_none()));
+            results.into_iter().map(|r| r.node_id).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            node_ids(env.start_agent_flow("main").unwrap()),
+            vec!["c", "b", "a"]
+        );
+        assert_eq!(
+            node_ids(env.stop_agent_flow("main").unwrap()),
+            vec!["a", "d", "b", "c"]
+        );
+
+        set_agent_flow_enabled(&env, "main", false).unwrap();
+        assert!(env.start_agent_flow("main").is_err());
+        assert!(env.start_agent_flow("missing").is_err());
+
+        let cycle: AgentFlow = serde_json::from_value(serde_json::json!({
+            "nodes": [
+                { "id": "x", "name": "$template_string", "enabled": true },
+                { "id": "y", "name": "$template_string", "enabled": true },
+                { "id": "z", "name": "$template_string", "enabled": true },
+            ],
+            "edges": [
+                { "id": "e1", "source": "x", "source_handle": "string", "target": "y", "target_handle": "data" },
+                { "id": "e2", "source": "y", "source_handle": "string", "target": "x", "target_handle": "data" },
+            ],
+        }))
+        .unwrap();
+        assert_eq!(cycle.sorted_node_ids(), vec!["z", "x", "y"]);
+    }
+
+    #[test]
+    fn test_start_agent_flow_rollback() {
+        // the agents have started or failed when start_agent returns, in a task or not
+        for native_thread in [false, true] {
+            let (env, _host, _rx) = AgentEnv::new_for_test();
+            for def_name in ["$template_string", SUBFLOW_DEF_NAME] {
+                let mut defs = env.defs.lock().unwrap();
+                defs.get_mut(def_name).unwrap().native_thread = Some(native_thread);
+            }
+            let flow: AgentFlow = serde_json::from_value(serde_json::json!({
+                "name": "main",
+                "nodes": [
+                    { "id": "main:a", "name": "$template_string", "enabled": true, "config": { "template": "{{value}}" } },
+                    { "id": "main:sub", "name": "$subflow", "enabled": true, "config": { "flow": "main" } },
+                ],
+                "edges": [
+                    { "id": "e1", "source": "main:sub", "source_handle": "data", "target": "main:a", "target_handle": "data" },
+                ],
+            }))
+            .unwrap();
+            env.add_agent_flow(&flow).unwrap();
+
+            // main:a starts first, and is stopped again when main:sub fails
+            let results = env.start_agent_flow("main").unwrap();
+            assert_eq!(results.len(), 2);
+            assert_eq!(results[0].node_id, "main:a");
+            assert_eq!(
+                results[0].error.as_deref(),
+                Some("Stopped as main:sub failed to start")
+            );
+            assert_eq!(results[1].node_id, "main:sub");
+            assert!(results[1].error.is_some());
+            assert!(!env.is_agent_running("main:a"));
+            assert!(!env.is_agent_running("main:sub"));
+        }
+    }
+}
@@ -1,19 +1,32 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
 
 use anyhow::{bail, Context as _, Result};
 use serde::{Deserialize, Serialize};
-use tauri::{AppHandle, Manager, State};
 
+use super::builtins;
+use super::config::AgentConfigVariables;
+use super::definition::{AgentDefinitions, SupervisorConfig};
 use super::env::AgentEnv;
+use super::history::{self, AgentFlowDiff, AgentFlowHistoryInfo, AgentFlowOp, AgentFlowRevision};
+use super::host::AgentHost;
+use super::instance;
+use super::migration::{self, AGENT_FLOW_VERSION};
+use super::queue::AgentQueueConfig;
+use super::validation::{self, AgentFlowProblem, AgentFlowProblemLevel};
 use super::AgentConfig;
 use crate::mnemnk::settings;
 
 pub type AgentFlows = HashMap<String, AgentFlow>;
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AgentFlow {
+    // version of the file format, see migration.rs
+    #[serde(default)]
+    pub version: u64,
+
     pub nodes: Vec<AgentFlowNode>,
     pub edges: Vec<AgentFlowEdge>,
 
@@ -23,11 +36,47 @@ pub struct AgentFlow {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub viewport: Option<Viewport>,
 
+    // records the messages between the agents of this flow
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub trace: bool,
+
+    // referred to as ${name} in the configs of the nodes
+    #[serde(default, skip_serializing_if = "AgentConfigVariables::is_empty")]
+    pub variables: AgentConfigVariables,
+
+    // a disabled flow is not started, whatever the enabled flags of its nodes
+    #[serde(default = "default_enabled", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+
     #[serde(skip)]
     // Only set when reading/saving the file under the agent_flows_dir
     path: Option<PathBuf>,
 }
 
+impl Default for AgentFlow {
+    fn default() -> Self {
+        Self {
+            version: 0,
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            name: None,
+            viewport: None,
+            trace: false,
+            variables: AgentConfigVariables::new(),
+            enabled: default_enabled(),
+            path: None,
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Viewport {
     pub x: f64,
@@ -58,6 +107,85 @@ pub struct AgentFlowNode {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub height: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue: Option<AgentQueueConfig>,
+
+    // overrides the supervisor of the command agent definition
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supervisor: Option<SupervisorConfig>,
+}
+
+impl AgentFlow {
+    /// The node with the variables of this flow resolved in its config, as the agent gets it.
+    pub fn resolve_node(&self, node: &AgentFlowNode) -> AgentFlowNode {
+        let mut node = node.clone();
+        if let Some(config) = node.config.as_mut() {
+            *config = config.resolve_variables(&self.variables);
+        }
+        node
+    }
+
+    /// The ids of the nodes, each before the nodes it sends to, and otherwise in the order of the
+    /// file. The nodes in cycles come last.
+    pub fn sorted_node_ids(&self) -> Vec<String> {
+        let mut in_degrees: HashMap<&str, usize> = self
+            .nodes
+            .iter()
+            .map(|node| (node.id.as_str(), 0))
+            .collect();
+        let mut targets: HashMap<&str, Vec<&str>> = HashMap::new();
+        for edge in self.edges.iter() {
+            if edge.source == edge.target || !in_degrees.contains_key(edge.source.as_str()) {
+                continue;
+            }
+            let Some(in_degree) = in_degrees.get_mut(edge.target.as_str()) else {
+                continue;
+            };
+            *in_degree += 1;
+            targets
+                .entry(edge.source.as_str())
+                .or_default()
+                .push(edge.target.as_str());
+        }
+
+        let mut queue: VecDeque<&str> = self
+            .nodes
+            .iter()
+            .map(|node| node.id.as_str())
+            .filter(|id| in_degrees[id] == 0)
+            .collect();
+        let mut sorted: Vec<String> = Vec::new();
+        let mut visited: HashSet<&str> = HashSet::new();
+        while let Some(id) = queue.pop_front() {
+            if !visited.insert(id) {
+                continue;
+            }
+            sorted.push(id.to_string());
+            for target in targets.get(id).into_iter().flatten() {
+                let in_degree = in_degrees.get_mut(target).unwrap();
+                *in_degree -= 1;
+                if *in_degree == 0 {
+                    queue.push_back(*target);
+                }
+            }
+        }
+        for node in self.nodes.iter() {
+            if visited.insert(node.id.as_str()) {
+                sorted.push(node.id.clone());
+            }
+        }
+        sorted
+    }
+}
+
+/// What came of a node when its flow was started or stopped.
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentFlowNodeResult {
+    pub node_id: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 impl AgentFlowNode {
@@ -83,6 +211,8 @@ impl AgentFlowNode {
             y: None,
             width: None,
             height: None,
+            queue: None,
+            supervisor: None,
         })
     }
 }
@@ -96,9 +226,12 @@ pub struct AgentFlowEdge {
     pub target_handle: String,
 }
 
-pub fn init(app: &AppHandle) -> Result<()> {
-    let dir = agent_flows_dir(app).context("Agent flows directory not found")?;
-    let mut agent_flows: AgentFlows = read_agent_flows(&dir)?;
+pub fn init(env: &Arc<AgentEnv>) -> Result<()> {
+    let dir = agent_flows_dir(env.host().as_ref()).context("Agent flows directory not found")?;
+    let mut agent_flows: AgentFlows = {
+        let defs = env.defs.lock().unwrap();
+        read_agent_flows(&dir, &defs)?
+    };
     if agent_flows.is_empty() {
         agent_flows.insert(
             "main".to_string(),
@@ -108,52 +241,71 @@ pub fn init(app: &AppHandle) -> Result<()> {
             },
         );
     }
-    let env = app.state::<AgentEnv>();
     for (_name, agent_flow) in &agent_flows {
         env.add_agent_flow(agent_flow).unwrap_or_else(|e| {
             log::error!("Failed to add agent flow: {}", e);
         });
     }
+
+    // report the problems of the flows before they start
+    let defs = env.defs.lock().unwrap();
+    for (name, agent_flow) in &agent_flows {
+        for problem in validation::validate_agent_flow(agent_flow, &agent_flows, &defs) {
+            let node_id = problem.node_id.as_deref().unwrap_or("-");
+            match problem.level {
+                AgentFlowProblemLevel::Error => {
+                    log::error!("{} {}: {}", name, node_id, problem.message)
+                }
+                AgentFlowProblemLevel::Warning => {
+                    log::warn!("{} {}: {}", name, node_id, problem.message)
+                }
+            }
+        }
+    }
     Ok(())
 }
 
-pub fn ready(app: &AppHandle) -> Result<()> {
-    let env = app.state::<AgentEnv>();
+pub fn ready(env: &AgentEnv) -> Result<()> {
     let agent_flow_names;
     {
         let agent_flows = env.flows.lock().unwrap();
-        agent_flow_names = agent_flows.keys().cloned().collect::<Vec<_>>();
+        agent_flow_names = agent_flows
+            .iter()
+            .filter(|(_, flow)| flow.enabled)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
     }
     for name in agent_flow_names {
-        env.start_agent_flow(&name).unwrap_or_else(|e| {
+        if let Err(e) = env.start_agent_flow(&name) {
             log::error!("Failed to start agent flow: {}", e);
-        });
+        }
     }
     Ok(())
 }
 
-fn agent_flows_dir(app: &AppHandle) -> Option<PathBuf> {
-    let mnemnk_dir = settings::mnemnk_dir(app);
+pub(super) fn agent_flows_dir(host: &dyn AgentHost) -> Option<PathBuf> {
+    let mnemnk_dir = host.mnemnk_dir();
     if mnemnk_dir.is_none() {
         return None;
     }
-    let agent_flows_dir = PathBuf::from(mnemnk_dir.unwrap()).join("agent_flows");
+    let agent_flows_dir = mnemnk_dir.unwrap().join("agent_flows");
     if !agent_flows_dir.exists() {
         std::fs::create_dir(&agent_flows_dir).expect("Failed to create agent flows directory");
     }
     Some(agent_flows_dir)
 }
 
-fn read_agent_flows<P: AsRef<Path>>(dir: P) -> Result<AgentFlows> {
+fn read_agent_flows<P: AsRef<Path>>(dir: P, defs: &AgentDefinitions) -> Result<AgentFlows> {
     let mut flows: AgentFlows = Default::default();
     let base_dir = dir.as_ref().to_path_buf();
-    read_agent_flows_recursive(&base_dir, &base_dir, &mut flows)?;
+    read_agent_flows_recursive(&base_dir, &base_dir, defs, &mut flows)?;
     Ok(flows)
 }
 
 fn read_agent_flows_recursive<P: AsRef<Path>>(
     base_dir: &Path,
     current_dir: P,
+    defs: &AgentDefinitions,
     flows: &mut AgentFlows,
 ) -> Result<()> {
     for entry in std::fs::read_dir(current_dir)? {
@@ -162,7 +314,7 @@ fn read_agent_flows_recursive<P: AsRef<Path>>(
 
         if path.is_dir() {
             // Recursively process subdirectories
-            read_agent_flows_recursive(base_dir, &path, flows)?;
+            read_agent_flows_recursive(base_dir, &path, defs, flows)?;
         } else if path.is_file() && path.extension().unwrap_or_default() == "json" {
             // Set the flow name to be the relative path from base_dir (without extension)
             if let Some(relative_path) = path.strip_prefix(base_dir).ok() {
@@ -176,10 +328,15 @@ fn read_agent_flows_recursive<P: AsRef<Path>>(
                     continue;
                 }
 
-                // Process JSON files
-                let flow = read_agent_flow(flow_name.clone(), path.clone())?;
-
-                flows.insert(flow_name, flow);
+                // Process JSON files. Unreadable ones, e.g. from a newer version, are skipped
+                match read_agent_flow(flow_name.clone(), path.clone(), defs, true) {
+                    Ok(flow) => {
+                        flows.insert(flow_name, flow);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to read agent flow {}: {}", path.display(), e);
+                    }
+                }
             }
         }
     }
@@ -187,12 +344,32 @@ fn read_agent_flows_recursive<P: AsRef<Path>>(
     Ok(())
 }
 
-fn read_agent_flow(flow_name: String, path: PathBuf) -> Result<AgentFlow> {
+// Flows written by older versions are upgraded. If `save_upgraded` is set, the upgraded flow is
+// saved in place of the file, after copying the original to `<name>.v<version>.json.bak`.
+fn read_agent_flow(
+    flow_name: String,
+    path: PathBuf,
+    defs: &AgentDefinitions,
+    save_upgraded: bool,
+) -> Result<AgentFlow> {
     if !path.is_file() || path.extension().unwrap_or_default() != "json" {
         return Err(anyhow::anyhow!("Invalid file extension"));
     }
     let content = std::fs::read_to_string(&path)?;
-    let mut flow: AgentFlow = serde_json::from_str(&content)?;
+    let (mut flow, old_version) = parse_agent_flow(&content, defs)?;
+    if let Some(old_version) = old_version {
+        log::info!(
+            "Upgraded agent flow {} from version {} to {}",
+            flow_name,
+            old_version,
+            AGENT_FLOW_VERSION
+        );
+        if save_upgraded {
+            save_upgraded_agent_flow(&path, old_version, &flow).unwrap_or_else(|e| {
+                log::error!("Failed to save upgraded agent flow {}: {}", flow_name, e);
+            });
+        }
+    }
     let (nodes, edges) = copy_sub_flow(
         &flow_name,
         flow.nodes.iter().collect(),
@@ -205,12 +382,59 @@ fn read_agent_flow(flow_name: String, path: PathBuf) -> Result<AgentFlow> {
     Ok(flow)
 }
 
-pub fn rename_agent_flow(
-    app: &AppHandle,
-    env: &AgentEnv,
-    old_name: &str,
-    new_name: &str,
-) -> Result<String> {
+/// Parses the content of a flow file, upgraded to the current version. Returns the version of the
+/// content as well if it has been upgraded.
+pub fn parse_agent_flow(
+    content: &str,
+    defs: &AgentDefinitions,
+) -> Result<(AgentFlow, Option<u64>)> {
+    let mut value: serde_json::Value = serde_json::from_str(content)?;
+    let old_version = migration::migrate_agent_flow(&mut value, defs)?;
+    let flow: AgentFlow = serde_json::from_value(value)?;
+    Ok((flow, old_version))
+}
+
+fn save_upgraded_agent_flow(path: &Path, old_version: u64, flow: &AgentFlow) -> Result<()> {
+    let backup = path.with_extension(format!("v{}.json.bak", old_version));
+    // an earlier backup is the original, so it is never overwritten
+    if !backup.exists() {
+        std::fs::copy(path, &backup)
+            .with_context(|| format!("Failed to back up to {}", backup.display()))?;
+    }
+    write_agent_flow(path, flow)
+}
+
+// only the flag of the saved flow is changed, leaving out the edits not saved yet
+fn write_agent_flow_enabled(env: &AgentEnv, path: &Path, enabled: bool) -> Result<()> {
+    let content = std::fs::read_to_string(path)?;
+    let (mut flow, _) = {
+        let defs = env.defs.lock().unwrap();
+        parse_agent_flow(&content, &defs)?
+    };
+    flow.enabled = enabled;
+    write_agent_flow(path, &flow).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn write_agent_flow(path: &Path, agent_flow: &AgentFlow) -> Result<()> {
+    // remove the name field from the saving flow before saving
+    let mut agent_flow = agent_flow.clone();
+    agent_flow.name = None;
+    agent_flow.version = AGENT_FLOW_VERSION;
+    let content = serde_json::to_string_pretty(&agent_flow)?;
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+pub fn validate_agent_flow(env: &AgentEnv, name: &str) -> Result<Vec<AgentFlowProblem>> {
+    let flows = env.flows.lock().unwrap();
+    let Some(flow) = flows.get(name) else {
+        bail!("Agent flow {} not found", name);
+    };
+    let defs = env.defs.lock().unwrap();
+    Ok(validation::validate_agent_flow(flow, &flows, &defs))
+}
+
+pub fn rename_agent_flow(env: &AgentEnv, old_name: &str, new_name: &str) -> Result<String> {
     if old_name == new_name {
         return Ok(old_name.to_string());
     }
@@ -223,7 +447,8 @@ pub fn rename_agent_flow(
 
     // Check if the flow already saved
     if let Some(path) = flow.path.clone() {
-        let base_dir = agent_flows_dir(app).context("Agent flows directory not found")?;
+        let base_dir =
+            agent_flows_dir(env.host().as_ref()).context("Agent flows directory not found")?;
 
         let mut new_path = base_dir.clone();
 
@@ -244,6 +469,16 @@ pub fn rename_agent_flow(
         std::fs::rename(&path, &new_path)?;
         flow.path = Some(new_path);
 
+        // and its revisions
+        let old_revisions_dir = history::revisions_dir(&base_dir, old_name);
+        if old_revisions_dir.is_dir() {
+            let new_revisions_dir = history::revisions_dir(&base_dir, &new_name);
+            if let Some(parent) = new_revisions_dir.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::rename(&old_revisions_dir, &new_revisions_dir)?;
+        }
+
         // Clean up empty directories
         let mut old_dir = path.parent().context("no parent")?.to_path_buf();
         while old_dir != base_dir {
@@ -253,14 +488,22 @@ pub fn rename_agent_flow(
         }
     }
 
+    let mut flow_history = env.flow_history.lock().unwrap();
+    if let Some(h) = flow_history.remove(old_name) {
+        flow_history.insert(new_name.clone(), h);
+    }
+
     Ok(new_name)
 }
 
-pub fn delete_agent_flow(app: &AppHandle, env: &AgentEnv, name: &str) -> Result<()> {
+pub fn delete_agent_flow(env: &AgentEnv, name: &str) -> Result<()> {
     let mut flows = env.flows.lock().unwrap();
     let Some(flow) = flows.remove(name) else {
         bail!("flow::delete_agent_flow: Agent flow {} not found", name);
     };
+    env.tracer.set_flow_enabled(name, false);
+    // revisions are left in the directory, so that a flow of the same name can restore them
+    env.flow_history.lock().unwrap().remove(name);
 
     let Some(path) = &flow.path else {
         // Flow is not saved to a file, so just remove it from the flows
@@ -271,7 +514,8 @@ pub fn delete_agent_flow(app: &AppHandle, env: &AgentEnv, name: &str) -> Result<
     std::fs::remove_file(path).context("Failed to delete agent flow file")?;
 
     // Clean up empty directories
-    let base_dir = agent_flows_dir(app).context("Agent flows directory not found")?;
+    let base_dir =
+        agent_flows_dir(env.host().as_ref()).context("Agent flows directory not found")?;
     let mut old_dir = path.parent().context("no parent")?.to_path_buf();
     while old_dir != base_dir {
         // Try to remove directory (will only succeed if empty)
@@ -282,7 +526,12 @@ pub fn delete_agent_flow(app: &AppHandle, env: &AgentEnv, name: &str) -> Result<
     Ok(())
 }
 
-pub fn insert_agent_flow(env: State<AgentEnv>, agent_flow: AgentFlow) -> Result<()> {
+/// Inserts the flow synced from the editor, recording the edit if `record_history` is set.
+pub fn insert_agent_flow(
+    env: &AgentEnv,
+    agent_flow: AgentFlow,
+    record_history: bool,
+) -> Result<()> {
     let name = agent_flow
         .name
         .clone()
@@ -290,9 +539,23 @@ pub fn insert_agent_flow(env: State<AgentEnv>, agent_flow: AgentFlow) -> Result<
 
     let mut flows = env.flows.lock().unwrap();
     if let Some(flow) = flows.get_mut(&name) {
-        // if the flow already exists, we need to copy the path from the existing flow
+        // if the flow already exists, we need to copy the path, the trace, the variables and the
+        // enabled flag from the existing flow
         let mut agent_flow = agent_flow;
         agent_flow.path = flow.path.clone();
+        agent_flow.trace = flow.trace;
+        agent_flow.variables = flow.variables.clone();
+        agent_flow.enabled = flow.enabled;
+        if record_history && flow_edited(flow, &agent_flow) {
+            record(
+                env,
+                &name,
+                AgentFlowOp::Update {
+                    before: Box::new(flow.clone()),
+                    after: Box::new(agent_flow.clone()),
+                },
+            );
+        }
         flows.insert(name, agent_flow);
         return Ok(());
     }
@@ -301,20 +564,99 @@ pub fn insert_agent_flow(env: State<AgentEnv>, agent_flow: AgentFlow) -> Result<
     Ok(())
 }
 
-pub fn save_agent_flow(app: &AppHandle, env: State<AgentEnv>, agent_flow: AgentFlow) -> Result<()> {
+pub fn set_agent_flow_trace(env: &AgentEnv, name: &str, enabled: bool) -> Result<()> {
+    let mut flows = env.flows.lock().unwrap();
+    let Some(flow) = flows.get_mut(name) else {
+        bail!("Agent flow {} not found", name);
+    };
+    flow.trace = enabled;
+    env.tracer.set_flow_enabled(name, enabled);
+    Ok(())
+}
+
+/// Enables or parks the flow, starting or stopping its agents accordingly.
+///
+/// The flag is written into the saved file of the flow right away, so that a parked flow stays
+/// parked on the next launch.
+pub fn set_agent_flow_enabled(
+    env: &AgentEnv,
+    name: &str,
+    enabled: bool,
+) -> Result<Vec<AgentFlowNodeResult>> {
+    let path = {
+        let flows = env.flows.lock().unwrap();
+        let Some(flow) = flows.get(name) else {
+            bail!("Agent flow {} not found", name);
+        };
+        flow.path.clone()
+    };
+    if let Some(path) = path {
+        write_agent_flow_enabled(env, &path, enabled)?;
+    }
+    {
+        let mut flows = env.flows.lock().unwrap();
+        let Some(flow) = flows.get_mut(name) else {
+            bail!("Agent flow {} not found", name);
+        };
+        flow.enabled = enabled;
+    }
+    if enabled {
+        env.start_agent_flow(name)
+    } else {
+        env.stop_agent_flow(name)
+    }
+}
+
+/// Sets the variables of the flow, and creates again the agents whose configs resolve to other
+/// values. It can be undone like the other edits.
+pub fn set_agent_flow_variables(
+    env: &Arc<AgentEnv>,
+    flow_name: &str,
+    variables: AgentConfigVariables,
+) -> Result<AgentFlow> {
+    let current = env
+        .flows
+        .lock()
+        .unwrap()
+        .get(flow_name)
+        .cloned()
+        .with_context(|| format!("Agent flow {} not found", flow_name))?;
+    if current.variables == variables {
+        return Ok(current);
+    }
+    let mut target = current.clone();
+    target.variables = variables;
+    let op = AgentFlowOp::Update {
+        before: Box::new(current),
+        after: Box::new(target),
+    };
+    let flow = apply_agent_flow_op(env, flow_name, &op)?;
+    record(env, flow_name, op);
+    Ok(flow)
+}
+
+pub fn save_agent_flow(env: &Arc<AgentEnv>, agent_flow: AgentFlow) -> Result<()> {
     let name = agent_flow
         .name
         .clone()
         .context("Agent flow name not found")?;
+    let mut agent_flow = agent_flow;
     let path;
+    let base_dir =
+        agent_flows_dir(env.host().as_ref()).context("Agent flows directory not found")?;
     {
         let agent_flows = env.flows.lock().unwrap();
         let flow = agent_flows.get(&name).context("Agent flow not found")?;
+        // the trace, the variables and the enabled flag are set by set_agent_flow_trace,
+        // set_agent_flow_variables and set_agent_flow_enabled
+        agent_flow.trace = flow.trace;
+        agent_flow.variables = flow.variables.clone();
+        agent_flow.enabled = flow.enabled;
         if let Some(p) = &flow.path {
             path = p.clone();
         } else {
             // If flow.path is None, this is the first time saving the AgentFlow, so set the path
-            let mut new_path = agent_flows_dir(&app).context("Agent flows directory not found")?;
+            let mut new_path = base_dir.clone();
 
             let path_components: Vec<&str> = name.split('/').collect();
             for &component in &path_components[..path_components.len() - 1] {
@@ -331,25 +673,63 @@ pub fn save_agent_flow(app: &AppHandle, env: State<AgentEnv>, agent_flow: AgentF
         }
     }
 
-    // remove the name field from the saving flow before saving
-    let mut agent_flow_copy = agent_flow.clone();
-    agent_flow_copy.name = None;
-    let content = serde_json::to_string_pretty(&agent_flow_copy)?;
-    std::fs::write(&path, content)?;
+    // the values of secret entries go to the secret store, and the file refers to them
+    store_node_secrets(env, &name, &mut agent_flow)?;
 
-    // update the path in the flow
+    // keep the previous version before overwriting it
+    history::add_revision(&base_dir, &name, &path).unwrap_or_else(|e| {
+        log::error!("Failed to add a revision of {}: {}", name, e);
+    });
+    write_agent_flow(&path, &agent_flow)?;
+    agent_flow.version = AGENT_FLOW_VERSION;
+
+    // update the flow with the saved one and its path
     {
         let mut flows = env.flows.lock().unwrap();
-        let Some(flow) = flows.get_mut(&name) else {
+        if !flows.contains_key(&name) {
             bail!("Agent flow {} not found", name);
-        };
-        flow.path = Some(path);
+        }
+        agent_flow.path = Some(path);
+        flows.insert(name.clone(), agent_flow);
     }
 
+    // subflows referring to this flow and its instances pick up the saved version
+    builtins::restart_subflows(env, &name);
+    instance::restart_instances(env, &name);
+
     Ok(())
 }
 
-pub fn import_agent_flow(env: &AgentEnv, path: String) -> Result<AgentFlow> {
+/// Moves the values of the secret entries of the nodes into the secret store as
+/// `<flow name>:<node id>.<key>`, and replaces them with references to it.
+pub(super) fn store_node_secrets(
+    env: &AgentEnv,
+    flow_name: &str,
+    flow: &mut AgentFlow,
+) -> Result<()> {
+    let defs = env.defs.lock().unwrap();
+    for node in flow.nodes.iter_mut() {
+        let Some(config) = node.config.as_mut() else {
+            continue;
+        };
+        let secret_name = format!("{}:{}", flow_name, node.id);
+        settings::store_secret_config(
+            &secret_name,
+            config,
+            defs.get(&node.name),
+            |name, value| env.host().set_secret(name, value),
+        )?;
+    }
+    Ok(())
+}
+
+/// Adds the flow in the file under a unique name, with all the nodes disabled, and the given
+/// values over its variables.
+pub fn import_agent_flow(
+    env: &Arc<AgentEnv>,
+    path: String,
+    variables: AgentConfigVariables,
+) -> Result<AgentFlow> {
     let path = PathBuf::from(path);
 
     // Get the base name from the file name
@@ -370,8 +750,11 @@ pub fn import_agent_flow(env: &AgentEnv, path: String) -> Result<AgentFlow> {
         name = unique_flow_name(&agent_flows, &base_name);
     }
 
-    // Read the flow
-    let mut flow = read_agent_flow(name, path.clone())?;
+    // Read the flow, leaving the imported file as it is
+    let mut flow = {
+        let defs = env.defs.lock().unwrap();
+        read_agent_flow(name, path.clone(), &defs, false)?
+    };
 
     // reset path of the flow
     flow.path = None;
@@ -381,13 +764,15 @@ pub fn import_agent_flow(env: &AgentEnv, path: String) -> Result<AgentFlow> {
         node.enabled = false;
     }
 
+    flow.variables.extend(variables);
+
     env.add_agent_flow(&flow)
         .context("Failed to add agent flow")?;
 
     Ok(flow)
 }
 
-fn unique_flow_name(agent_flows: &AgentFlows, name: &str) -> String {
+pub fn unique_flow_name(agent_flows: &AgentFlows, name: &str) -> String {
     let mut new_name = name.to_string();
     let mut i = 1;
     while agent_flows.contains_key(&new_name) {
@@ -444,13 +829,25 @@ fn new_edge_id(source: &str, source_handle: &str, target: &str, target_handle: &
     )
 }
 
-pub fn add_agent_flow_node(env: &AgentEnv, flow_name: &str, node: &AgentFlowNode) -> Result<()> {
+pub fn add_agent_flow_node(
+    env: &Arc<AgentEnv>,
+    flow_name: &str,
+    node: &AgentFlowNode,
+) -> Result<()> {
     let mut flows = env.flows.lock().unwrap();
     let Some(flow) = flows.get_mut(flow_name) else {
         bail!("Agent flow {} not found", flow_name);
     };
-    env.add_agent(&node)?;
+    env.add_agent(flow_name, &flow.resolve_node(node))?;
     flow.nodes.push(node.clone());
+    record(
+        env,
+        flow_name,
+        AgentFlowOp::AddNode {
+            node: node.clone(),
+            edges: vec![],
+        },
+    );
     Ok(())
 }
 
@@ -459,17 +856,37 @@ pub fn remove_agent_flow_node(env: &AgentEnv, flow_name: &str, node_id: &str) ->
     let Some(flow) = flows.get_mut(flow_name) else {
         bail!("Agent flow {} not found", flow_name);
     };
+    if let Some(node) = flow.nodes.iter().find(|node| node.id == node_id) {
+        // the edges are removed by the editor separately, but kept with the node in case
+        let edges = flow
+            .edges
+            .iter()
+            .filter(|edge| edge.source == node_id || edge.target == node_id)
+            .cloned()
+            .collect();
+        record(
+            env,
+            flow_name,
+            AgentFlowOp::RemoveNode {
+                node: node.clone(),
+                edges,
+            },
+        );
+    }
     flow.nodes.retain(|node| node.id != node_id);
     env.remove_agent(&node_id)
 }
 
 pub fn add_agent_flow_edge(env: &AgentEnv, flow_name: &str, edge: &AgentFlowEdge) -> Result<()> {
+    // new edges are rejected when the kinds of the ports don't match
+    env.check_edge_kinds(edge)?;
     let mut flows = env.flows.lock().unwrap();
     let Some(flow) = flows.get_mut(flow_name) else {
         bail!("Agent flow {} not found", flow_name);
     };
     env.add_edge(edge)?;
     flow.edges.push(edge.clone());
+    record(env, flow_name, AgentFlowOp::AddEdge(edge.clone()));
     Ok(())
 }
 
@@ -480,7 +897,267 @@ pub fn remove_agent_flow_edge(env: &AgentEnv, flow_name: &str, edge_id: &str) ->
     };
     if let Some(idx) = flow.edges.iter().position(|edge| edge.id == edge_id) {
         env.remove_edge(&flow.edges[idx]);
-        flow.edges.remove(idx);
+        let edge = flow.edges.remove(idx);
+        record(env, flow_name, AgentFlowOp::RemoveEdge(edge));
     }
     Ok(())
 }
+
+// History
+
+fn record(env: &AgentEnv, flow_name: &str, op: AgentFlowOp) {
+    env.flow_history
+        .lock()
+        .unwrap()
+        .entry(flow_name.to_string())
+        .or_default()
+        .record(op);
+}
+
+// whether the nodes or the edges are changed, other than the positions of the nodes
+fn flow_edited(old: &AgentFlow, new: &AgentFlow) -> bool {
+    old.nodes.len() != new.nodes.len()
+        || old
+            .nodes
+            .iter()
+            .zip(new.nodes.iter())
+            .any(|(a, b)| node_value(a) != node_value(b))
+        || serde_json::to_value(&old.edges).ok() != serde_json::to_value(&new.edges).ok()
+}
+
+// the node without its position and size
+fn node_value(node: &AgentFlowNode) -> Option<serde_json::Value> {
+    let mut node = node.clone();
+    node.x = None;
+    node.y = None;
+    node.width = None;
+    node.height = None;
+    serde_json::to_value(node).ok()
+}
+
+pub fn get_agent_flow_history(env: &AgentEnv, flow_name: &str) -> AgentFlowHistoryInfo {
+    env.flow_history
+        .lock()
+        .unwrap()
+        .get(flow_name)
+        .map(|h| h.info())
+        .unwrap_or_default()
+}
+
+/// Undoes the last edit of the flow, and returns the flow if there was one.
+pub fn undo_agent_flow(env: &Arc<AgentEnv>, flow_name: &str) -> Result<Option<AgentFlow>> {
+    let Some(op) = env
+        .flow_history
+        .lock()
+        .unwrap()
+        .get_mut(flow_name)
+        .and_then(|h| h.pop_undo())
+    else {
+        return Ok(None);
+    };
+    match apply_agent_flow_op(env, flow_name, &op.inverse()) {
+        Ok(flow) => {
+            history_mut(env, flow_name, |h| h.push_redo(op));
+            Ok(Some(flow))
+        }
+        Err(e) => {
+            history_mut(env, flow_name, |h| h.push_undo(op));
+            Err(e)
+        }
+    }
+}
+
+/// Redoes the last undone edit of the flow, and returns the flow if there was one.
+pub fn redo_agent_flow(env: &Arc<AgentEnv>, flow_name: &str) -> Result<Option<AgentFlow>> {
+    let Some(op) = env
+        .flow_history
+        .lock()
+        .unwrap()
+        .get_mut(flow_name)
+        .and_then(|h| h.pop_redo())
+    else {
+        return Ok(None);
+    };
+    match apply_agent_flow_op(env, flow_name, &op) {
+        Ok(flow) => {
+            history_mut(env, flow_name, |h| h.push_undo(op));
+            Ok(Some(flow))
+        }
+        Err(e) => {
+            history_mut(env, flow_name, |h| h.push_redo(op));
+            Err(e)
+        }
+    }
+}
+
+fn history_mut(env: &AgentEnv, flow_name: &str, f: impl FnOnce(&mut history::AgentFlowHistory)) {
+    f(env
+        .flow_history
+        .lock()
+        .unwrap()
+        .entry(flow_name.to_string())
+        .or_default());
+}
+
+// Applies the edit to the flow, and brings the agents and the edges in line with it.
+fn apply_agent_flow_op(
+    env: &Arc<AgentEnv>,
+    flow_name: &str,
+    op: &AgentFlowOp,
+) -> Result<AgentFlow> {
+    let (flow, to_start) = {
+        let mut flows = env.flows.lock().unwrap();
+        let Some(flow) = flows.get_mut(flow_name) else {
+            bail!("Agent flow {} not found", flow_name);
+        };
+        let mut target = flow.clone();
+        op.apply_to(&mut target);
+        let to_start = reconcile_agents(env, flow_name, flow, &target);
+        *flow = target;
+        (flow.clone(), to_start)
+    };
+
+    // agents such as subflows look up the flows on start
+    for agent_id in to_start {
+        env.start_agent(&agent_id).unwrap_or_else(|e| {
+            log::error!("Failed to start agent {}: {}", agent_id, e);
+        });
+    }
+    Ok(flow)
+}
+
+// Adds and removes the agents and the edges of `current` to match `target`, and returns the
+// enabled agents to be started. Agents whose nodes are changed other than in position, or whose
+// configs resolve to other values of the variables, are created again.
+fn reconcile_agents(
+    env: &Arc<AgentEnv>,
+    flow_name: &str,
+    current: &AgentFlow,
+    target: &AgentFlow,
+) -> Vec<String> {
+    let current_nodes: HashMap<&str, &AgentFlowNode> =
+        current.nodes.iter().map(|n| (n.id.as_str(), n)).collect();
+    let target_nodes: HashMap<&str, &AgentFlowNode> =
+        target.nodes.iter().map(|n| (n.id.as_str(), n)).collect();
+
+    // nodes removed or changed
+    let mut recreated = HashSet::new();
+    for node in current.nodes.iter() {
+        match target_nodes.get(node.id.as_str()) {
+            Some(t)
+                if node_value(&target.resolve_node(t))
+                    == node_value(&current.resolve_node(node)) =>
+            {
+                continue
+            }
+            Some(_) => {
+                recreated.insert(node.id.as_str());
+            }
+            None => {}
+        }
+        env.remove_agent(&node.id).unwrap_or_else(|e| {
+            log::error!("Failed to remove agent {}: {}", node.id, e);
+        });
+    }
+
+    // nodes added or changed
+    let mut to_start = Vec::new();
+    for node in target.nodes.iter() {
+        if current_nodes.contains_key(node.id.as_str()) && !recreated.contains(node.id.as_str()) {
+            continue;
+        }
+        if let Err(e) = env.add_agent(flow_name, &target.resolve_node(node)) {
+            log::error!("Failed to add agent {}: {}", node.id, e);
+            continue;
+        }
+        if node.enabled {
+            to_start.push(node.id.clone());
+        }
+    }
+
+    // edges, including the ones of the agents created again
+    let target_edges: HashSet<&str> = target.edges.iter().map(|e| e.id.as_str()).collect();
+    let current_edges: HashSet<&str> = current.edges.iter().map(|e| e.id.as_str()).collect();
+    for edge in current.edges.iter() {
+        if !target_edges.contains(edge.id.as_str()) {
+            env.remove_edge(edge);
+        }
+    }
+    for edge in target.edges.iter() {
+        if current_edges.contains(edge.id.as_str())
+            && !recreated.contains(edge.source.as_str())
+            && !recreated.contains(edge.target.as_str())
+        {
+            continue;
+        }
+        env.add_edge(edge).unwrap_or_else(|e| {
+            log::error!("Failed to add edge {}: {}", edge.id, e);
+        });
+    }
+
+    to_start
+}
+
+// Revisions
+
+pub fn get_agent_flow_revisions(env: &AgentEnv, flow_name: &str) -> Result<Vec<AgentFlowRevision>> {
+    let dir = agent_flows_dir(env.host().as_ref()).context("Agent flows directory not found")?;
+    history::list_revisions(&dir, flow_name)
+}
+
+fn read_agent_flow_revision(env: &AgentEnv, flow_name: &str, revision: &str) -> Result<AgentFlow> {
+    let dir = agent_flows_dir(env.host().as_ref()).context("Agent flows directory not found")?;
+    let path = history::revision_path(&dir, flow_name, revision)?;
+    let defs = env.defs.lock().unwrap();
+    let mut flow = read_agent_flow(flow_name.to_string(), path, &defs, false)?;
+    flow.path = None;
+    Ok(flow)
+}
+
+/// Differences from the revision to the other one, or to the current flow if `other` is `None`.
+pub fn diff_agent_flow_revision(
+    env: &AgentEnv,
+    flow_name: &str,
+    revision: &str,
+    other: Option<&str>,
+) -> Result<AgentFlowDiff> {
+    let old = read_agent_flow_revision(env, flow_name, revision)?;
+    let new = match other {
+        Some(other) => read_agent_flow_revision(env, flow_name, other)?,
+        None => env
+            .flows
+            .lock()
+            .unwrap()
+            .get(flow_name)
+            .cloned()
+            .with_context(|| format!("Agent flow {} not found", flow_name))?,
+    };
+    Ok(history::diff_agent_flows(&old, &new))
+}
+
+/// Replaces the flow with the revision, including its variables, which can be undone. The file is
+/// not saved.
+pub fn restore_agent_flow_revision(
+    env: &Arc<AgentEnv>,
+    flow_name: &str,
+    revision: &str,
+) -> Result<AgentFlow> {
+    let mut restored = read_agent_flow_revision(env, flow_name, revision)?;
+    let current = env
+        .flows
+        .lock()
+        .unwrap()
+        .get(flow_name)
+        .cloned()
+        .with_context(|| format!("Agent flow {} not found", flow_name))?;
+    // the trace and the enabled flag are kept, as they are not undone
+    restored.trace = current.trace;
+    restored.enabled = current.enabled;
+    let op = AgentFlowOp::Update {
+        before: Box::new(current),
+        after: Box::new(restored),
+    };
+    let flow = apply_agent_flow_op(env, flow_name, &op)?;
+    record(env, flow_name, op);
+    Ok(flow)
+}
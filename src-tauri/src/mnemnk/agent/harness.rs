@@ -0,0 +1,828 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::async_runtime::RuntimeHandle;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+#[cfg(test)]
+use super::config::AgentConfig;
+use super::context::AgentContext;
+use super::data::AgentData;
+use super::definition::{init_agent_defs, AgentDefinitions};
+use super::env::{AgentEnv, EMIT_DISPLAY, EMIT_ERROR};
+use super::flow::{self, AgentFlow};
+use super::host::MemoryHost;
+use super::message::{self, EnvAgentMessage};
+
+// Flow test harness
+//
+// Runs an agent flow on a MemoryHost and a current-thread runtime whose clock is paused.
+// Time only moves forward in `advance`, jumping from one timer to the next, so that
+// flows with $delay, $interval_timer or $throttle_time give the same results on every run.
+
+// scheduler rounds without any message before the flow is considered idle
+const IDLE_ROUNDS: usize = 16;
+
+// real time to wait for the agents on native threads, which the paused clock does not drive
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_FLOW_NAME: &str = "harness";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum FlowEvent {
+    Out {
+        time_ms: u64,
+        agent_id: String,
+        ch: String,
+        data: AgentData,
+    },
+    Display {
+        time_ms: u64,
+        agent_id: String,
+        key: String,
+        data: AgentData,
+    },
+    Error {
+        time_ms: u64,
+        agent_id: String,
+        message: String,
+    },
+}
+
+impl FlowEvent {
+    fn from_emitted(time_ms: u64, event: &str, payload: Value) -> Option<Self> {
+        #[derive(Deserialize)]
+        struct DisplayMessage {
+            agent_id: String,
+            key: String,
+            data: AgentData,
+        }
+
+        #[derive(Deserialize)]
+        struct ErrorMessage {
+            agent_id: String,
+            message: String,
+        }
+
+        match event {
+            EMIT_DISPLAY => {
+                let m: DisplayMessage = serde_json::from_value(payload).ok()?;
+                Some(FlowEvent::Display {
+                    time_ms,
+                    agent_id: m.agent_id,
+                    key: m.key,
+                    data: m.data,
+                })
+            }
+            EMIT_ERROR => {
+                let m: ErrorMessage = serde_json::from_value(payload).ok()?;
+                Some(FlowEvent::Error {
+                    time_ms,
+                    agent_id: m.agent_id,
+                    message: m.message,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct FlowHarness {
+    runtime: Runtime,
+    recorder: Recorder,
+    flow_name: String,
+}
+
+impl FlowHarness {
+    /// Loads the flow into a fresh env and starts its enabled nodes.
+    ///
+    /// Node ids are kept as they are in the flow, so that they can be used in `input` and `outputs`.
+    pub fn new(flow: AgentFlow) -> Result<Self> {
+        Self::new_with_flows(flow, vec![])
+    }
+
+    /// Same as `new`, but also loads other flows without starting them, e.g. for subflows.
+    pub fn new_with_flows(mut flow: AgentFlow, other_flows: Vec<AgentFlow>) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .context("Failed to build runtime")?;
+
+        let host = Arc::new(MemoryHost::new());
+        let env = AgentEnv::init_with_runtime(
+            host.clone(),
+            RuntimeHandle::Tokio(runtime.handle().clone()),
+        )?;
+        let (tx, rx) = mpsc::channel(4096);
+        {
+            let mut tx_lock = env.tx.lock().unwrap();
+            *tx_lock = Some(tx);
+        }
+
+        let flow_name = flow
+            .name
+            .get_or_insert_with(|| DEFAULT_FLOW_NAME.to_string())
+            .clone();
+        for flow in other_flows.iter().chain(std::iter::once(&flow)) {
+            env.add_agent_flow(flow)?;
+            let agents = env.agents.lock().unwrap();
+            for node in flow.nodes.iter() {
+                if !agents.contains_key(&node.id) {
+                    bail!("Failed to create agent {} ({})", node.id, node.name);
+                }
+            }
+        }
+        for result in env.start_agent_flow(&flow_name)? {
+            if let Some(e) = result.error {
+                bail!("Failed to start agent {}: {}", result.node_id, e);
+            }
+        }
+
+        let start = runtime.block_on(async { Instant::now() });
+        let mut harness = Self {
+            runtime,
+            recorder: Recorder {
+                env,
+                host,
+                rx,
+                start,
+                events: Vec::new(),
+            },
+            flow_name,
+        };
+        harness.run_until_idle();
+        Ok(harness)
+    }
+
+    #[cfg(test)]
+    pub fn env(&self) -> &Arc<AgentEnv> {
+        &self.recorder.env
+    }
+
+    /// Sends the data to the node on the given channel, and runs the flow until it is idle.
+    ///
+    /// `node` is either the id or the title of a node.
+    pub fn input(&mut self, node: &str, ch: &str, data: AgentData) -> Result<()> {
+        let agent_id = self.node_id(node)?;
+        let recorder = &mut self.recorder;
+        self.runtime.block_on(async {
+            recorder
+                .env
+                .agent_input(&agent_id, AgentContext::new_with_ch(ch), data)
+                .await?;
+            recorder.run_until_idle().await;
+            Ok(())
+        })
+    }
+
+    /// Sets the config of the agent as the app does, and runs the flow until it is idle.
+    ///
+    /// `agent_id` is the id of any agent, including the nodes of subflows.
+    #[cfg(test)]
+    pub fn set_config(&mut self, agent_id: &str, config: AgentConfig) -> Result<()> {
+        let recorder = &mut self.recorder;
+        self.runtime.block_on(async {
+            recorder.env.set_agent_config(agent_id, config).await?;
+            recorder.run_until_idle().await;
+            Ok(())
+        })
+    }
+
+    /// Moves the virtual clock forward, firing the timers that are due on the way.
+    pub fn advance(&mut self, duration: Duration) {
+        let recorder = &mut self.recorder;
+        self.runtime.block_on(async {
+            let deadline = Instant::now() + duration;
+            recorder.run_until(deadline).await;
+        });
+    }
+
+    pub fn run_until_idle(&mut self) {
+        let recorder = &mut self.recorder;
+        self.runtime.block_on(recorder.run_until_idle());
+    }
+
+    /// Virtual time since the flow was started.
+    #[cfg(test)]
+    pub fn elapsed(&self) -> Duration {
+        let _guard = self.runtime.enter();
+        self.recorder.start.elapsed()
+    }
+
+    /// Every output, display and error seen so far, in order.
+    #[cfg(test)]
+    pub fn events(&self) -> &[FlowEvent] {
+        &self.recorder.events
+    }
+
+    pub fn take_events(&mut self) -> Vec<FlowEvent> {
+        std::mem::take(&mut self.recorder.events)
+    }
+
+    /// Data sent out by the node on the given channel.
+    pub fn outputs(&self, node: &str, ch: &str) -> Vec<AgentData> {
+        let Ok(node_id) = self.node_id(node) else {
+            return vec![];
+        };
+        self.recorder
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                FlowEvent::Out {
+                    agent_id,
+                    ch: out_ch,
+                    data,
+                    ..
+                } if *agent_id == node_id && out_ch == ch => Some(data.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[cfg(test)]
+    pub fn errors(&self) -> Vec<String> {
+        self.recorder
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                FlowEvent::Error {
+                    agent_id, message, ..
+                } => Some(format!("{}: {}", agent_id, message)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn node_id(&self, node: &str) -> Result<String> {
+        let flows = self.recorder.env.flows.lock().unwrap();
+        let flow = flows.get(&self.flow_name).context("Agent flow not found")?;
+        flow.nodes
+            .iter()
+            .find(|n| n.id == node)
+            .or_else(|| flow.nodes.iter().find(|n| n.title.as_deref() == Some(node)))
+            .map(|n| n.id.clone())
+            .with_context(|| format!("Node {} not found", node))
+    }
+}
+
+impl Drop for FlowHarness {
+    fn drop(&mut self) {
+        let env = &self.recorder.env;
+        let agent_ids = {
+            let agents = env.agents.lock().unwrap();
+            agents.keys().cloned().collect::<Vec<_>>()
+        };
+        for agent_id in agent_ids {
+            env.stop_agent(&agent_id).unwrap_or_else(|e| {
+                log::error!("Failed to stop agent {}: {}", agent_id, e);
+            });
+        }
+    }
+}
+
+// upgraded like the flows of the app, with the definitions of the harness
+fn read_flow(path: &Path, defs: &AgentDefinitions) -> Result<AgentFlow> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let (flow, _) = flow::parse_agent_flow(&content, defs)
+        .with_context(|| format!("Invalid agent flow {}", path.display()))?;
+    Ok(flow)
+}
+
+// Takes the place of the env message loop, recording every message before dispatching it.
+struct Recorder {
+    env: Arc<AgentEnv>,
+    host: Arc<MemoryHost>,
+    rx: mpsc::Receiver<EnvAgentMessage>,
+    start: Instant,
+    events: Vec<FlowEvent>,
+}
+
+impl Recorder {
+    fn time_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    fn record_emitted(&mut self) {
+        let time_ms = self.time_ms();
+        for (event, payload) in self.host.take_emitted() {
+            if let Some(event) = FlowEvent::from_emitted(time_ms, &event, payload) {
+                self.events.push(event);
+            }
+        }
+    }
+
+    async fn dispatch(&mut self, message: EnvAgentMessage) {
+        self.record_emitted();
+        match message {
+            EnvAgentMessage::AgentOut { agent, ctx, data } => {
+                self.events.push(FlowEvent::Out {
+                    time_ms: self.time_ms(),
+                    agent_id: agent.clone(),
+                    ch: ctx.ch().to_string(),
+                    data: data.clone(),
+                });
+                message::agent_out(&self.env, agent, ctx, data).await;
+            }
+            EnvAgentMessage::BoardOut { name, ctx, data } => {
+                message::board_out(&self.env, name, ctx, data).await;
+            }
+        }
+    }
+
+    // Yielding never moves the paused clock, so this stops at the current virtual time.
+    //
+    // Agents on native threads run outside the runtime, so the flow is not idle while any agent
+    // has an input left, and their threads are given real time to take it.
+    async fn run_until_idle(&mut self) {
+        let busy_deadline = std::time::Instant::now() + BUSY_TIMEOUT;
+        let mut idle = 0;
+        while idle < IDLE_ROUNDS {
+            tokio::task::yield_now().await;
+            match self.rx.try_recv() {
+                Ok(message) => {
+                    self.dispatch(message).await;
+                    idle = 0;
+                }
+                Err(_) if self.env.has_busy_agents() => {
+                    if std::time::Instant::now() > busy_deadline {
+                        log::warn!("Agents are still busy after {:?}", BUSY_TIMEOUT);
+                        idle += 1;
+                    } else {
+                        std::thread::sleep(Duration::from_millis(1));
+                        idle = 0;
+                    }
+                }
+                Err(_) => idle += 1,
+            }
+        }
+        self.record_emitted();
+    }
+
+    // Waiting here lets the runtime auto-advance the paused clock to the next timer.
+    async fn run_until(&mut self, deadline: Instant) {
+        loop {
+            let message = tokio::select! {
+                biased;
+                message = self.rx.recv() => message,
+                _ = tokio::time::sleep_until(deadline) => None,
+            };
+            let Some(message) = message else {
+                break;
+            };
+            self.dispatch(message).await;
+        }
+        self.run_until_idle().await;
+    }
+}
+
+// Test cases
+//
+// A JSON file describing the flow to load, the steps to run and the outputs to expect:
+//
+// {
+//   "flow": "my_flow.json",
+//   "steps": [
+//     { "input": { "node": "in", "ch": "value", "data": { "kind": "integer", "value": 1 } } },
+//     { "advance_ms": 1000 }
+//   ],
+//   "expect": [
+//     { "node": "out", "ch": "value", "data": [{ "kind": "integer", "value": 2 }] }
+//   ]
+// }
+//
+// Paths are relative to the case file. "flows" lists other flows to load without starting them,
+// such as the ones referenced by $subflow nodes, named by their path without ".json".
+
+#[derive(Debug, Deserialize)]
+pub struct FlowTestCase {
+    pub flow: PathBuf,
+
+    // other flows to load, named by their path without ".json", e.g. for subflows
+    #[serde(default)]
+    pub flows: Vec<PathBuf>,
+
+    #[serde(default)]
+    pub steps: Vec<FlowTestStep>,
+
+    #[serde(default)]
+    pub expect: Vec<FlowTestExpect>,
+
+    // directory of the case file
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowTestStep {
+    Input {
+        node: String,
+        ch: String,
+        data: AgentData,
+    },
+    AdvanceMs(u64),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlowTestExpect {
+    pub node: String,
+    pub ch: String,
+    pub data: Vec<AgentData>,
+}
+
+pub struct FlowTestReport {
+    pub events: Vec<FlowEvent>,
+    pub failures: Vec<String>,
+}
+
+impl FlowTestCase {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut case: FlowTestCase = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid test case {}", path.display()))?;
+        case.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(case)
+    }
+
+    pub fn run(&self) -> Result<FlowTestReport> {
+        let defs = init_agent_defs(&MemoryHost::new())?;
+        let flow = read_flow(&self.dir.join(&self.flow), &defs)?;
+        let mut other_flows = Vec::new();
+        for path in self.flows.iter() {
+            let mut other_flow = read_flow(&self.dir.join(path), &defs)?;
+            let name = path.with_extension("").to_string_lossy().replace('\\', "/");
+            other_flow.name = Some(name);
+            other_flows.push(other_flow);
+        }
+        let mut harness = FlowHarness::new_with_flows(flow, other_flows)?;
+
+        for step in self.steps.iter() {
+            match step {
+                FlowTestStep::Input { node, ch, data } => {
+                    harness.input(node, ch, data.clone())?;
+                }
+                FlowTestStep::AdvanceMs(ms) => {
+                    harness.advance(Duration::from_millis(*ms));
+                }
+            }
+        }
+
+        let mut failures = Vec::new();
+        for expect in self.expect.iter() {
+            let outputs = harness.outputs(&expect.node, &expect.ch);
+            if outputs != expect.data {
+                failures.push(format!(
+                    "{} {}: expected {}, got {}",
+                    expect.node,
+                    expect.ch,
+                    serde_json::to_string(&expect.data)?,
+                    serde_json::to_string(&outputs)?
+                ));
+            }
+        }
+
+        Ok(FlowTestReport {
+            events: harness.take_events(),
+            failures,
+        })
+    }
+}
+
+/// Runs the test case files given on the command line, and returns whether all of them passed.
+///
+/// `mnemnk-flow-test [--events] <case.json>...`
+pub fn run_flow_test_cli(args: impl IntoIterator<Item = String>) -> Result<bool> {
+    let mut print_events = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--events" => print_events = true,
+            _ if arg.starts_with("--") => bail!("Unknown argument: {}", arg),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        bail!("Usage: mnemnk-flow-test [--events] <case.json>...");
+    }
+
+    let mut passed = true;
+    for path in paths {
+        let report = FlowTestCase::from_file(&path).and_then(|case| case.run());
+        match report {
+            Ok(report) => {
+                if print_events {
+                    for event in report.events.iter() {
+                        println!("{}", serde_json::to_string(event)?);
+                    }
+                }
+                if report.failures.is_empty() {
+                    println!("ok {}", path);
+                } else {
+                    passed = false;
+                    println!("FAILED {}", path);
+                    for failure in report.failures {
+                        println!("    {}", failure);
+                    }
+                }
+            }
+            Err(e) => {
+                passed = false;
+                println!("FAILED {}", path);
+                println!("    {:#}", e);
+            }
+        }
+    }
+    Ok(passed)
+}
+
+/// An enabled node of a flow in JSON, for the tests.
+#[cfg(test)]
+pub(crate) fn node(id: &str, name: &str, config: Value) -> Value {
+    serde_json::json!({ "id": id, "name": name, "enabled": true, "config": config })
+}
+
+/// An edge of a flow in JSON, for the tests.
+#[cfg(test)]
+pub(crate) fn edge(source: &str, source_handle: &str, target: &str, target_handle: &str) -> Value {
+    serde_json::json!({
+        "id": format!("{}-{}", source, target),
+        "source": source,
+        "source_handle": source_handle,
+        "target": target,
+        "target_handle": target_handle,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemnk::agent::{AgentConfigVariables, AgentValue};
+    use serde_json::json;
+
+    fn harness(nodes: Value, edges: Value) -> FlowHarness {
+        let flow = json!({ "nodes": nodes, "edges": edges });
+        FlowHarness::new(serde_json::from_value(flow).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_rhai_expr_chain() {
+        let mut h = harness(
+            json!([
+                node("double", "$rhai_expr", json!({ "expr": "value * 2" })),
+                node("inc", "$rhai_expr", json!({ "expr": "value + 1" })),
+            ]),
+            json!([edge("double", "data", "inc", "data")]),
+        );
+
+        h.input("double", "data", AgentData::new_integer(3))
+            .unwrap();
+        h.input("double", "data", AgentData::new_integer(5))
+            .unwrap();
+
+        assert_eq!(
+            h.outputs("inc", "data"),
+            vec![AgentData::new_integer(7), AgentData::new_integer(11)]
+        );
+        assert!(h.errors().is_empty());
+    }
+
+    #[test]
+    fn test_metrics() {
+        let mut h = harness(
+            json!([
+                node("double", "$rhai_expr", json!({ "expr": "value * 2" })),
+                node("inc", "$rhai_expr", json!({ "expr": "value + 1" })),
+            ]),
+            json!([edge("double", "data", "inc", "data")]),
+        );
+
+        h.input("double", "data", AgentData::new_integer(3))
+            .unwrap();
+        h.input("double", "data", AgentData::new_integer(5))
+            .unwrap();
+
+        let metrics = h.env().get_agent_metrics();
+        let double = &metrics["double"];
+        assert_eq!(double.def_name, "$rhai_expr");
+        assert_eq!(double.inputs["data"], 2);
+        assert_eq!(double.outputs["data"], 2);
+        assert_eq!(double.process_time.count, 2);
+        assert_eq!(metrics["inc"].inputs["data"], 2);
+        assert_eq!(metrics["inc"].errors, 0);
+    }
+
+    #[test]
+    fn test_trace() {
+        let flow = json!({
+            "nodes": [
+                node("double", "$rhai_expr", json!({ "expr": "value * 2" })),
+                node("inc", "$rhai_expr", json!({ "expr": "value + 1" })),
+                node("square", "$rhai_expr", json!({ "expr": "value * value" })),
+            ],
+            "edges": [
+                edge("double", "data", "inc", "data"),
+                edge("inc", "data", "square", "in"),
+            ],
+            "trace": true,
+        });
+        let mut h = FlowHarness::new(serde_json::from_value(flow).unwrap()).unwrap();
+
+        h.input("double", "data", AgentData::new_integer(3))
+            .unwrap();
+
+        let traces = h.env().tracer.traces();
+        assert_eq!(traces.len(), 1);
+        let hops: Vec<_> = traces[0]
+            .hops
+            .iter()
+            .map(|h| {
+                (
+                    h.hop.source.as_str(),
+                    h.hop.target.as_str(),
+                    h.hop.target_ch.as_str(),
+                    h.preview.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            hops,
+            vec![
+                ("double", "inc", "data", "integer: 6"),
+                ("inc", "square", "in", "integer: 7"),
+            ]
+        );
+
+        // no traces are recorded once it is turned off
+        crate::mnemnk::agent::flow::set_agent_flow_trace(h.env(), DEFAULT_FLOW_NAME, false)
+            .unwrap();
+        h.input("double", "data", AgentData::new_integer(3))
+            .unwrap();
+        assert_eq!(h.env().tracer.traces().len(), 1);
+    }
+
+    #[test]
+    fn test_flow_variables() {
+        let flow = json!({
+            "nodes": [node("multiply", "$rhai_expr", json!({ "expr": "value * ${n}" }))],
+            "edges": [],
+            "variables": { "n": "2" },
+        });
+        let mut h = FlowHarness::new(serde_json::from_value(flow).unwrap()).unwrap();
+
+        h.input("multiply", "data", AgentData::new_integer(3))
+            .unwrap();
+
+        // the running agent is given the config with the new values
+        let variables = AgentConfigVariables::from([("n".to_string(), "3".to_string())]);
+        crate::mnemnk::agent::flow::set_agent_flow_variables(h.env(), DEFAULT_FLOW_NAME, variables)
+            .unwrap();
+        h.run_until_idle();
+        h.input("multiply", "data", AgentData::new_integer(3))
+            .unwrap();
+
+        // and so is a config set afterwards
+        let mut config = AgentConfig::new();
+        config.set("expr".into(), AgentValue::new_string("value * ${n} + 1"));
+        h.set_config("multiply", config).unwrap();
+        h.input("multiply", "data", AgentData::new_integer(3))
+            .unwrap();
+
+        assert_eq!(
+            h.outputs("multiply", "data"),
+            vec![
+                AgentData::new_integer(6),
+                AgentData::new_integer(9),
+                AgentData::new_integer(10)
+            ]
+        );
+        assert!(h.errors().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_node() {
+        let mut h = harness(json!([]), json!([]));
+        assert!(h.input("missing", "data", AgentData::new_unit()).is_err());
+    }
+
+    #[test]
+    fn test_unknown_agent_def() {
+        let flow = json!({ "nodes": [node("x", "$no_such_agent", json!({}))], "edges": [] });
+        assert!(FlowHarness::new(serde_json::from_value(flow).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_delay() {
+        let mut h = harness(
+            json!([node(
+                "delay",
+                "$delay",
+                json!({ "delay": 1000, "max_num_data": 10 })
+            )]),
+            json!([]),
+        );
+
+        h.input("delay", "data", AgentData::new_integer(1)).unwrap();
+        assert!(h.outputs("delay", "data").is_empty());
+
+        h.advance(Duration::from_millis(999));
+        assert!(h.outputs("delay", "data").is_empty());
+
+        h.advance(Duration::from_millis(1));
+        assert_eq!(h.outputs("delay", "data"), vec![AgentData::new_integer(1)]);
+        assert_eq!(h.elapsed(), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_interval_timer() {
+        let mut h = harness(
+            json!([node(
+                "timer",
+                "$interval_timer",
+                json!({ "interval": "1s" })
+            )]),
+            json!([]),
+        );
+
+        h.advance(Duration::from_millis(3500));
+
+        let times = h
+            .events()
+            .iter()
+            .filter_map(|event| match event {
+                FlowEvent::Out { time_ms, .. } => Some(*time_ms),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(times, vec![1000, 2000, 3000]);
+    }
+
+    #[test]
+    fn test_throttle_time() {
+        let mut h = harness(
+            json!([node(
+                "throttle",
+                "$throttle_time",
+                json!({ "time": "1s", "max_num_data": 1 })
+            )]),
+            json!([]),
+        );
+
+        for i in 1..=3 {
+            h.input("throttle", "data", AgentData::new_integer(i))
+                .unwrap();
+        }
+        assert_eq!(
+            h.outputs("throttle", "data"),
+            vec![AgentData::new_integer(1)]
+        );
+
+        h.advance(Duration::from_secs(5));
+        assert_eq!(
+            h.outputs("throttle", "data"),
+            vec![AgentData::new_integer(1), AgentData::new_integer(3)]
+        );
+    }
+
+    #[test]
+    fn test_case() {
+        let dir = std::env::temp_dir().join(format!("mnemnk-harness-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let flow = json!({
+            "nodes": [node("double", "$rhai_expr", json!({ "expr": "value * 2" }))],
+            "edges": [],
+        });
+        std::fs::write(dir.join("flow.json"), flow.to_string()).unwrap();
+        let case = json!({
+            "flow": "flow.json",
+            "steps": [
+                { "input": { "node": "double", "ch": "data", "data": { "kind": "integer", "value": 2 } } },
+                { "advance_ms": 100 },
+            ],
+            "expect": [
+                { "node": "double", "ch": "data", "data": [{ "kind": "integer", "value": 4 }] },
+                { "node": "double", "ch": "other", "data": [{ "kind": "integer", "value": 4 }] },
+            ],
+        });
+        std::fs::write(dir.join("case.json"), case.to_string()).unwrap();
+
+        let report = FlowTestCase::from_file(dir.join("case.json"))
+            .unwrap()
+            .run()
+            .unwrap();
+        assert_eq!(report.events.len(), 1);
+        assert_eq!(report.failures.len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context as _, Result};
+use serde::Serialize;
+
+use super::flow::{AgentFlow, AgentFlowEdge, AgentFlowNode};
+
+// edits kept for undo per flow
+const MAX_UNDO: usize = 100;
+
+// saved versions kept per flow
+const MAX_REVISIONS: usize = 50;
+
+// under the agent flows directory, skipped when reading the flows
+const REVISIONS_DIR: &str = ".revisions";
+
+/// An edit of a flow, kept to be undone.
+#[derive(Debug, Clone)]
+pub enum AgentFlowOp {
+    AddNode {
+        node: AgentFlowNode,
+        edges: Vec<AgentFlowEdge>,
+    },
+    RemoveNode {
+        node: AgentFlowNode,
+        edges: Vec<AgentFlowEdge>,
+    },
+    AddEdge(AgentFlowEdge),
+    RemoveEdge(AgentFlowEdge),
+
+    // the whole flow is replaced, such as when it is synced from the editor or restored
+    Update {
+        before: Box<AgentFlow>,
+        after: Box<AgentFlow>,
+    },
+}
+
+impl AgentFlowOp {
+    pub fn inverse(&self) -> Self {
+        match self {
+            AgentFlowOp::AddNode { node, edges } => AgentFlowOp::RemoveNode {
+                node: node.clone(),
+                edges: edges.clone(),
+            },
+            AgentFlowOp::RemoveNode { node, edges } => AgentFlowOp::AddNode {
+                node: node.clone(),
+                edges: edges.clone(),
+            },
+            AgentFlowOp::AddEdge(edge) => AgentFlowOp::RemoveEdge(edge.clone()),
+            AgentFlowOp::RemoveEdge(edge) => AgentFlowOp::AddEdge(edge.clone()),
+            AgentFlowOp::Update { before, after } => AgentFlowOp::Update {
+                before: after.clone(),
+                after: before.clone(),
+            },
+        }
+    }
+
+    /// Applies the edit to the nodes, the edges and the variables of the flow, without touching the
+    /// agents. The trace and the enabled flag are not part of the edits.
+    pub fn apply_to(&self, flow: &mut AgentFlow) {
+        match self {
+            AgentFlowOp::AddNode { node, edges } => {
+                if !flow.nodes.iter().any(|n| n.id == node.id) {
+                    flow.nodes.push(node.clone());
+                }
+                for edge in edges {
+                    if !flow.edges.iter().any(|e| e.id == edge.id) {
+                        flow.edges.push(edge.clone());
+                    }
+                }
+            }
+            AgentFlowOp::RemoveNode { node, .. } => {
+                flow.nodes.retain(|n| n.id != node.id);
+                flow.edges
+                    .retain(|e| e.source != node.id && e.target != node.id);
+            }
+            AgentFlowOp::AddEdge(edge) => {
+                if !flow.edges.iter().any(|e| e.id == edge.id) {
+                    flow.edges.push(edge.clone());
+                }
+            }
+            AgentFlowOp::RemoveEdge(edge) => {
+                flow.edges.retain(|e| e.id != edge.id);
+            }
+            AgentFlowOp::Update { after, .. } => {
+                flow.nodes = after.nodes.clone();
+                flow.edges = after.edges.clone();
+                flow.viewport = after.viewport.clone();
+                flow.variables = after.variables.clone();
+            }
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            AgentFlowOp::AddNode { node, .. } => format!("Add {}", node.id),
+            AgentFlowOp::RemoveNode { node, .. } => format!("Remove {}", node.id),
+            AgentFlowOp::AddEdge(edge) => format!("Connect {} to {}", edge.source, edge.target),
+            AgentFlowOp::RemoveEdge(edge) => {
+                format!("Disconnect {} from {}", edge.source, edge.target)
+            }
+            AgentFlowOp::Update { .. } => "Update".to_string(),
+        }
+    }
+}
+
+/// Undo and redo stacks of a flow.
+#[derive(Debug, Default)]
+pub struct AgentFlowHistory {
+    undo: VecDeque<AgentFlowOp>,
+    redo: Vec<AgentFlowOp>,
+}
+
+impl AgentFlowHistory {
+    /// Records a new edit, which makes the edits undone so far unable to be redone.
+    pub fn record(&mut self, op: AgentFlowOp) {
+        self.redo.clear();
+        self.push_undo(op);
+    }
+
+    pub fn pop_undo(&mut self) -> Option<AgentFlowOp> {
+        self.undo.pop_back()
+    }
+
+    pub fn push_undo(&mut self, op: AgentFlowOp) {
+        if self.undo.len() >= MAX_UNDO {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(op);
+    }
+
+    pub fn pop_redo(&mut self) -> Option<AgentFlowOp> {
+        self.redo.pop()
+    }
+
+    pub fn push_redo(&mut self, op: AgentFlowOp) {
+        self.redo.push(op);
+    }
+
+    pub fn info(&self) -> AgentFlowHistoryInfo {
+        AgentFlowHistoryInfo {
+            undo: self.undo.iter().rev().map(AgentFlowOp::label).collect(),
+            redo: self.redo.iter().rev().map(AgentFlowOp::label).collect(),
+        }
+    }
+}
+
+/// Labels of the edits to be undone and redone, the next one first.
+#[derive(Debug, Default, Serialize)]
+pub struct AgentFlowHistoryInfo {
+    pub undo: Vec<String>,
+    pub redo: Vec<String>,
+}
+
+// Revisions
+
+/// A saved version of a flow file.
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentFlowRevision {
+    pub id: String,
+
+    // when the version was saved, in milliseconds since the epoch
+    pub timestamp: i64,
+}
+
+pub fn revisions_dir(agent_flows_dir: &Path, flow_name: &str) -> PathBuf {
+    flow_name
+        .split('/')
+        .fold(agent_flows_dir.join(REVISIONS_DIR), |dir, component| {
+            dir.join(component)
+        })
+}
+
+/// Keeps the current content of the flow file as a revision, before it is overwritten.
+pub fn add_revision(agent_flows_dir: &Path, flow_name: &str, path: &Path) -> Result<()> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    let timestamp = std::fs::metadata(path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)?
+        .as_millis();
+    let dir = revisions_dir(agent_flows_dir, flow_name);
+    std::fs::create_dir_all(&dir)?;
+    std::fs::copy(path, dir.join(format!("{}.json", timestamp)))
+        .context("Failed to copy agent flow revision")?;
+
+    let revisions = list_revisions(agent_flows_dir, flow_name)?;
+    for revision in revisions.iter().skip(MAX_REVISIONS) {
+        let _ = std::fs::remove_file(dir.join(format!("{}.json", revision.id)));
+    }
+    Ok(())
+}
+
+/// Revisions of the flow, the newest first.
+pub fn list_revisions(agent_flows_dir: &Path, flow_name: &str) -> Result<Vec<AgentFlowRevision>> {
+    let dir = revisions_dir(agent_flows_dir, flow_name);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut revisions = Vec::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().unwrap_or_default() != "json" {
+            continue;
+        }
+        let Some(id) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+            continue;
+        };
+        let Ok(timestamp) = id.parse::<i64>() else {
+            continue;
+        };
+        revisions.push(AgentFlowRevision { id, timestamp });
+    }
+    revisions.sort_by_key(|r| std::cmp::Reverse(r.timestamp));
+    Ok(revisions)
+}
+
+pub fn revision_path(agent_flows_dir: &Path, flow_name: &str, id: &str) -> Result<PathBuf> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+        bail!("Invalid revision {}", id);
+    }
+    let path = revisions_dir(agent_flows_dir, flow_name).join(format!("{}.json", id));
+    if !path.is_file() {
+        bail!("Revision {} of {} not found", id, flow_name);
+    }
+    Ok(path)
+}
+
+// Diff
+
+/// Differences between two versions of a flow, ignoring the positions of the nodes.
+#[derive(Debug, Default, Serialize)]
+pub struct AgentFlowDiff {
+    pub added_nodes: Vec<AgentFlowNode>,
+    pub removed_nodes: Vec<AgentFlowNode>,
+    pub changed_nodes: Vec<AgentFlowNodeDiff>,
+    pub added_edges: Vec<AgentFlowEdge>,
+    pub removed_edges: Vec<AgentFlowEdge>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AgentFlowNodeDiff {
+    // id in the newer version
+    pub id: String,
+    pub name: String,
+
+    // such as "enabled", "title" or "config.script"
+    pub changes: Vec<String>,
+}
+
+/// Compares the old version of a flow with the new one.
+///
+/// Node ids are renumbered whenever a flow is read, so nodes that don't have the same id are
+/// matched by their agent definitions, in the order of the nodes.
+pub fn diff_agent_flows(old: &AgentFlow, new: &AgentFlow) -> AgentFlowDiff {
+    let mut diff = AgentFlowDiff::default();
+
+    // old node id -> new node
+    let mut matched: HashMap<&str, &AgentFlowNode> = HashMap::new();
+    let new_ids: HashSet<&str> = new.nodes.iter().map(|n| n.id.as_str()).collect();
+    let old_ids: HashSet<&str> = old.nodes.iter().map(|n| n.id.as_str()).collect();
+    for node in new.nodes.iter().filter(|n| old_ids.contains(n.id.as_str())) {
+        matched.insert(node.id.as_str(), node);
+    }
+    let mut unmatched_new: Vec<&AgentFlowNode> = new
+        .nodes
+        .iter()
+        .filter(|n| !old_ids.contains(n.id.as_str()))
+        .collect();
+    for node in old
+        .nodes
+        .iter()
+        .filter(|n| !new_ids.contains(n.id.as_str()))
+    {
+        if let Some(idx) = unmatched_new.iter().position(|n| n.name == node.name) {
+            matched.insert(node.id.as_str(), unmatched_new.remove(idx));
+        } else {
+            diff.removed_nodes.push(node.clone());
+        }
+    }
+    diff.added_nodes = unmatched_new.into_iter().cloned().collect();
+
+    for old_node in old.nodes.iter() {
+        let Some(new_node) = matched.get(old_node.id.as_str()) else {
+            continue;
+        };
+        let changes = node_changes(old_node, new_node);
+        if !changes.is_empty() {
+            diff.changed_nodes.push(AgentFlowNodeDiff {
+                id: new_node.id.clone(),
+                name: new_node.name.clone(),
+                changes,
+            });
+        }
+    }
+
+    // edges, with the old node ids translated into the new ones
+    let edge_key = |edge: &AgentFlowEdge, source: &str, target: &str| {
+        (
+            source.to_string(),
+            edge.source_handle.clone(),
+            target.to_string(),
+            edge.target_handle.clone(),
+        )
+    };
+    let new_edges: HashSet<_> = new
+        .edges
+        .iter()
+        .map(|e| edge_key(e, &e.source, &e.target))
+        .collect();
+    let mut old_edges = HashSet::new();
+    for edge in old.edges.iter() {
+        let source = matched.get(edge.source.as_str());
+        let target = matched.get(edge.target.as_str());
+        let key = source
+            .zip(target)
+            .map(|(source, target)| edge_key(edge, &source.id, &target.id));
+        match key {
+            Some(key) if new_edges.contains(&key) => {
+                old_edges.insert(key);
+            }
+            _ => diff.removed_edges.push(edge.clone()),
+        }
+    }
+    for edge in new.edges.iter() {
+        if !old_edges.contains(&edge_key(edge, &edge.source, &edge.target)) {
+            diff.added_edges.push(edge.clone());
+        }
+    }
+
+    diff
+}
+
+fn node_changes(old: &AgentFlowNode, new: &AgentFlowNode) -> Vec<String> {
+    let mut changes = Vec::new();
+    if old.enabled != new.enabled {
+        changes.push("enabled".to_string());
+    }
+    if old.title != new.title {
+        changes.push("title".to_string());
+    }
+    let old_config = serde_json::to_value(&old.config).unwrap_or_default();
+    let new_config = serde_json::to_value(&new.config).unwrap_or_default();
+    let empty = serde_json::Map::new();
+    let old_config = old_config.as_object().unwrap_or(&empty);
+    let new_config = new_config.as_object().unwrap_or(&empty);
+    let mut keys: Vec<&String> = old_config.keys().chain(new_config.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        if old_config.get(key) != new_config.get(key) {
+            changes.push(format!("config.{}", key));
+        }
+    }
+    for (name, old_value, new_value) in [
+        (
+            "queue",
+            serde_json::to_value(&old.queue),
+            serde_json::to_value(&new.queue),
+        ),
+        (
+            "supervisor",
+            serde_json::to_value(&old.supervisor),
+            serde_json::to_value(&new.supervisor),
+        ),
+    ] {
+        if old_value.ok() != new_value.ok() {
+            changes.push(name.to_string());
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn flow(value: serde_json::Value) -> AgentFlow {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_undo_redo_ops() {
+        let mut flow = flow(json!({
+            "nodes": [
+                { "id": "a", "name": "$unit_input", "enabled": true },
+                { "id": "b", "name": "$rhai_expr", "enabled": true, "config": { "script": "value * 2" } },
+            ],
+            "edges": [
+                { "id": "e", "source": "a", "source_handle": "unit", "target": "b", "target_handle": "data" },
+            ],
+        }));
+        let node = flow.nodes[1].clone();
+        let edges = flow.edges.clone();
+
+        let mut history = AgentFlowHistory::default();
+        let op = AgentFlowOp::RemoveNode { node, edges };
+        op.apply_to(&mut flow);
+        history.record(op);
+        assert_eq!(flow.nodes.len(), 1);
+        assert!(flow.edges.is_empty());
+
+        // undo brings back the node with its config and edges
+        let op = history.pop_undo().unwrap();
+        op.inverse().apply_to(&mut flow);
+        history.push_redo(op);
+        assert_eq!(flow.nodes[1].id, "b");
+        assert_eq!(
+            flow.nodes[1].config.as_ref().unwrap().get_string("script"),
+            Some("value * 2".to_string())
+        );
+        assert_eq!(flow.edges.len(), 1);
+        assert_eq!(history.info().redo, vec!["Remove b"]);
+
+        let op = history.pop_redo().unwrap();
+        op.apply_to(&mut flow);
+        history.push_undo(op);
+        assert_eq!(flow.nodes.len(), 1);
+
+        // a new edit drops the redo
+        let op = history.pop_undo().unwrap();
+        history.push_redo(op);
+        history.record(AgentFlowOp::AddEdge(AgentFlowEdge::default()));
+        assert!(history.pop_redo().is_none());
+    }
+
+    #[test]
+    fn test_update_op() {
+        let mut flow = flow(json!({
+            "nodes": [{ "id": "a", "name": "$rhai_expr", "enabled": true }],
+            "edges": [],
+            "variables": { "model": "small" },
+            "trace": true,
+        }));
+        let mut after = flow.clone();
+        after.nodes.clear();
+        after
+            .variables
+            .insert("model".to_string(), "large".to_string());
+        after.trace = false;
+        let op = AgentFlowOp::Update {
+            before: Box::new(flow.clone()),
+            after: Box::new(after),
+        };
+
+        op.apply_to(&mut flow);
+        assert!(flow.nodes.is_empty());
+        assert_eq!(flow.variables["model"], "large");
+        assert!(flow.trace);
+
+        op.inverse().apply_to(&mut flow);
+        assert_eq!(flow.nodes.len(), 1);
+        assert_eq!(flow.variables["model"], "small");
+    }
+
+    #[test]
+    fn test_diff_agent_flows() {
+        let old = flow(json!({
+            "nodes": [
+                { "id": "main:$rhai_expr:1", "name": "$rhai_expr", "enabled": true, "config": { "script": "value" } },
+                { "id": "main:$delay:2", "name": "$delay", "enabled": true },
+                { "id": "main:$to_json:3", "name": "$to_json", "enabled": false },
+            ],
+            "edges": [
+                { "id": "e1", "source": "main:$rhai_expr:1", "source_handle": "data", "target": "main:$delay:2", "target_handle": "data" },
+            ],
+        }));
+        // read again with new ids, and edited
+        let new = flow(json!({
+            "nodes": [
+                { "id": "main:$rhai_expr:11", "name": "$rhai_expr", "enabled": true, "config": { "script": "value * 2" } },
+                { "id": "main:$delay:12", "name": "$delay", "enabled": true, "x": 100.0 },
+                { "id": "main:$to_text:13", "name": "$to_text", "enabled": false },
+            ],
+            "edges": [],
+        }));
+
+        let diff = diff_agent_flows(&old, &new);
+        assert_eq!(diff.added_nodes.len(), 1);
+        assert_eq!(diff.added_nodes[0].name, "$to_text");
+        assert_eq!(diff.removed_nodes.len(), 1);
+        assert_eq!(diff.removed_nodes[0].name, "$to_json");
+        assert_eq!(diff.changed_nodes.len(), 1);
+        assert_eq!(diff.changed_nodes[0].id, "main:$rhai_expr:11");
+        assert_eq!(diff.changed_nodes[0].changes, vec!["config.script"]);
+        assert_eq!(diff.removed_edges.len(), 1);
+        assert!(diff.added_edges.is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+#[cfg(any(test, feature = "flow-test"))]
+use std::collections::HashMap;
+use std::path::PathBuf;
+#[cfg(any(test, feature = "flow-test"))]
+use std::sync::Mutex;
+
+use anyhow::{Context as _, Result};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_shell::process::{
+    CommandChild as ShellCommandChild, CommandEvent as ShellCommandEvent,
+};
+use tauri_plugin_shell::ShellExt;
+use tokio::sync::mpsc;
+
+use crate::mnemnk::store::MnemnkDatabase;
+use crate::mnemnk::{secrets, settings};
+
+use super::config::AgentConfig;
+#[cfg(any(test, feature = "flow-test"))]
+use super::config::AgentConfigs;
+use super::definition::AgentDefinitions;
+
+/// Everything the agent runtime needs from the outside world.
+///
+/// The Tauri app provides one through `TauriHost`, and the headless runtime provides its own,
+/// so that `AgentEnv` and the agents never touch an `AppHandle` directly.
+pub trait AgentHost: Send + Sync + 'static {
+    /// Root directory holding `agents/`, `agent_flows/` and `data/`.
+    fn mnemnk_dir(&self) -> Option<PathBuf>;
+
+    fn init_agent_global_configs(&self, defs: &AgentDefinitions) -> Result<()>;
+
+    fn agent_global_config(&self, def_name: &str) -> Option<AgentConfig>;
+
+    /// Value of the secret referred to as `"secret:<name>"` in the configs.
+    fn secret(&self, name: &str) -> Option<String>;
+
+    /// Stores the secret, failing if there is no secret store to keep it.
+    fn set_secret(&self, name: &str, value: &str) -> Result<()>;
+
+    fn store(&self) -> Option<MnemnkDatabase>;
+
+    fn emit(&self, event: &str, payload: Value) -> Result<()>;
+
+    fn spawn_command(
+        &self,
+        cmd: &str,
+        args: &[String],
+        dir: &str,
+    ) -> Result<(mpsc::Receiver<CommandEvent>, Box<dyn CommandChild>)>;
+}
+
+#[derive(Debug, Clone)]
+pub enum CommandEvent {
+    // a line from stdout, without the trailing newline
+    Stdout(Vec<u8>),
+
+    // a line from stderr, without the trailing newline
+    Stderr(Vec<u8>),
+
+    Error(String),
+
+    Terminated(Option<i32>),
+}
+
+pub trait CommandChild: Send + Sync {
+    fn write(&mut self, buf: &[u8]) -> Result<()>;
+
+    fn kill(self: Box<Self>) -> Result<()>;
+}
+
+// Tauri
+
+pub struct TauriHost {
+    app: AppHandle,
+}
+
+impl TauriHost {
+    pub fn new(app: AppHandle) -> Self {
+        Self { app }
+    }
+}
+
+impl AgentHost for TauriHost {
+    fn mnemnk_dir(&self) -> Option<PathBuf> {
+        settings::mnemnk_dir(&self.app).map(PathBuf::from)
+    }
+
+    fn init_agent_global_configs(&self, defs: &AgentDefinitions) -> Result<()> {
+        settings::init_agent_global_configs(&self.app, defs)
+    }
+
+    fn agent_global_config(&self, def_name: &str) -> Option<AgentConfig> {
+        settings::get_agent_global_config(&self.app, def_name)
+    }
+
+    fn secret(&self, name: &str) -> Option<String> {
+        secrets::get_secret(&self.app, name)
+    }
+
+    fn set_secret(&self, name: &str, value: &str) -> Result<()> {
+        secrets::set_secret(&self.app, name, value)
+    }
+
+    fn store(&self) -> Option<MnemnkDatabase> {
+        self.app
+            .try_state::<MnemnkDatabase>()
+            .map(|state| state.inner().clone())
+    }
+
+    fn emit(&self, event: &str, payload: Value) -> Result<()> {
+        self.app
+            .emit(event, payload)
+            .with_context(|| format!("Failed to emit {}", event))
+    }
+
+    fn spawn_command(
+        &self,
+        cmd: &str,
+        args: &[String],
+        dir: &str,
+    ) -> Result<(mpsc::Receiver<CommandEvent>, Box<dyn CommandChild>)> {
+        let (mut shell_rx, child) = self
+            .app
+            .shell()
+            .command(cmd)
+            .args(args)
+            .current_dir(dir)
+            .spawn()
+            .context("Failed to spawn command")?;
+
+        let (tx, rx) = mpsc::channel(64);
+        tauri::async_runtime::spawn(async move {
+            while let Some(event) = shell_rx.recv().await {
+                let event = match event {
+                    ShellCommandEvent::Stdout(line) => CommandEvent::Stdout(line),
+                    ShellCommandEvent::Stderr(line) => CommandEvent::Stderr(line),
+                    ShellCommandEvent::Error(e) => CommandEvent::Error(e),
+                    ShellCommandEvent::Terminated(status) => CommandEvent::Terminated(status.code),
+                    _ => continue,
+                };
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok((rx, Box::new(child)))
+    }
+}
+
+impl CommandChild for ShellCommandChild {
+    fn write(&mut self, buf: &[u8]) -> Result<()> {
+        ShellCommandChild::write(self, buf)?;
+        Ok(())
+    }
+
+    fn kill(self: Box<Self>) -> Result<()> {
+        ShellCommandChild::kill(*self)?;
+        Ok(())
+    }
+}
+
+// In-memory
+
+/// A host without Tauri, files or processes.
+///
+/// Global configs are kept in memory and emitted events are recorded, so that agents can be
+/// driven and inspected from tests and the flow harness.
+#[cfg(any(test, feature = "flow-test"))]
+#[derive(Default)]
+pub struct MemoryHost {
+    agent_configs: Mutex<AgentConfigs>,
+    secrets: Mutex<HashMap<String, String>>,
+    emitted: Mutex<Vec<(String, Value)>>,
+}
+
+#[cfg(any(test, feature = "flow-test"))]
+impl MemoryHost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns and clears the events emitted so far.
+    pub fn take_emitted(&self) -> Vec<(String, Value)> {
+        let mut emitted = self.emitted.lock().unwrap();
+        std::mem::take(&mut *emitted)
+    }
+}
+
+#[cfg(any(test, feature = "flow-test"))]
+impl AgentHost for MemoryHost {
+    fn mnemnk_dir(&self) -> Option<PathBuf> {
+        None
+    }
+
+    fn init_agent_global_configs(&self, defs: &AgentDefinitions) -> Result<()> {
+        // keeps the configs set so far, like the settings of the app on reload
+        let mut configs = self.agent_configs.lock().unwrap();
+        let value = serde_json::to_value(&*configs)?;
+        *configs = settings::load_agent_global_configs(Some(value), defs);
+        Ok(())
+    }
+
+    fn agent_global_config(&self, def_name: &str) -> Option<AgentConfig> {
+        let configs = self.agent_configs.lock().unwrap();
+        configs.get(def_name).cloned()
+    }
+
+    fn secret(&self, name: &str) -> Option<String> {
+        self.secrets.lock().unwrap().get(name).cloned()
+    }
+
+    fn set_secret(&self, name: &str, value: &str) -> Result<()> {
+        let mut secrets = self.secrets.lock().unwrap();
+        secrets.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn store(&self) -> Option<MnemnkDatabase> {
+        None
+    }
+
+    fn emit(&self, event: &str, payload: Value) -> Result<()> {
+        let mut emitted = self.emitted.lock().unwrap();
+        emitted.push((event.to_string(), payload));
+        Ok(())
+    }
+
+    fn spawn_command(
+        &self,
+        cmd: &str,
+        _args: &[String],
+        _dir: &str,
+    ) -> Result<(mpsc::Receiver<CommandEvent>, Box<dyn CommandChild>)> {
+        anyhow::bail!("MemoryHost cannot spawn commands: {}", cmd)
+    }
+}
@@ -0,0 +1,357 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use super::config::AgentConfigVariables;
+use super::env::AgentEnv;
+use super::flow::{self, AgentFlowEdge};
+
+// stored in agent_flows/, which skips the files starting with a dot
+const INSTANCES_JSON: &str = ".instances.json";
+
+/// A copy of a flow running with its own values of the variables.
+///
+/// The agents of an instance have the ids of the nodes of the flow prefixed by the instance name,
+/// so that any number of instances of the same flow run side by side.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentFlowInstance {
+    pub name: String,
+
+    // name of the flow used as the template
+    pub flow: String,
+
+    // values over the variables of the flow
+    #[serde(default)]
+    pub variables: AgentConfigVariables,
+
+    // started again on launch
+    #[serde(default)]
+    pub running: bool,
+
+    // ids of the agents while running
+    #[serde(skip)]
+    agents: Vec<String>,
+}
+
+pub fn init(env: &AgentEnv) -> Result<()> {
+    let Some(path) = instances_path(env) else {
+        return Ok(());
+    };
+    if !path.exists() {
+        return Ok(());
+    }
+    let content = std::fs::read_to_string(&path)?;
+    let instances: Vec<AgentFlowInstance> = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut flow_instances = env.flow_instances.lock().unwrap();
+    for instance in instances {
+        flow_instances.insert(instance.name.clone(), instance);
+    }
+    Ok(())
+}
+
+/// Starts the instances which were running when the app quit.
+pub fn ready(env: &Arc<AgentEnv>) -> Result<()> {
+    let names: Vec<String> = {
+        let flow_instances = env.flow_instances.lock().unwrap();
+        flow_instances
+            .values()
+            .filter(|instance| instance.running)
+            .map(|instance| instance.name.clone())
+            .collect()
+    };
+    for name in names {
+        start_agent_flow_instance(env, &name).unwrap_or_else(|e| {
+            log::error!("Failed to start agent flow instance {}: {}", name, e);
+        });
+    }
+    Ok(())
+}
+
+pub fn list_agent_flow_instances(env: &AgentEnv) -> Vec<AgentFlowInstance> {
+    env.flow_instances
+        .lock()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect()
+}
+
+/// Adds a new instance of the flow, stopped.
+pub fn create_agent_flow_instance(
+    env: &AgentEnv,
+    flow_name: &str,
+    name: &str,
+    variables: AgentConfigVariables,
+) -> Result<AgentFlowInstance> {
+    if !is_valid_instance_name(name) {
+        bail!("Invalid instance name: {}", name);
+    }
+    if !env.flows.lock().unwrap().contains_key(flow_name) {
+        bail!("Agent flow {} not found", flow_name);
+    }
+    let instance = AgentFlowInstance {
+        name: name.to_string(),
+        flow: flow_name.to_string(),
+        variables,
+        running: false,
+        agents: vec![],
+    };
+    {
+        let mut flow_instances = env.flow_instances.lock().unwrap();
+        if flow_instances.contains_key(name) {
+            bail!("Agent flow instance {} already exists", name);
+        }
+        flow_instances.insert(name.to_string(), instance.clone());
+    }
+    save_instances(env)?;
+    Ok(instance)
+}
+
+/// Creates the agents of the instance from the current flow, and starts them.
+///
+/// Like the nodes of a subflow, they all run while the instance runs, regardless of their enabled
+/// flag in the flow.
+pub fn start_agent_flow_instance(env: &Arc<AgentEnv>, name: &str) -> Result<()> {
+    let instance = get_instance(env, name)?;
+    if !instance.agents.is_empty() {
+        return Ok(());
+    }
+
+    let mut flow = env
+        .flows
+        .lock()
+        .unwrap()
+        .get(&instance.flow)
+        .cloned()
+        .with_context(|| format!("Agent flow {} not found", instance.flow))?;
+    flow.variables.extend(instance.variables.clone());
+
+    let mut agents = Vec::new();
+    for node in flow.nodes.iter() {
+        let mut node = flow.resolve_node(node);
+        node.id = instance_node_id(name, &node.id);
+        if let Err(e) = env.add_agent(&instance.flow, &node) {
+            remove_agents(env, &agents);
+            return Err(e);
+        }
+        env.instance_parents
+            .lock()
+            .unwrap()
+            .insert(node.id.clone(), name.to_string());
+        agents.push(node.id);
+    }
+    for edge in flow.edges.iter() {
+        let edge = AgentFlowEdge {
+            id: instance_node_id(name, &edge.id),
+            source: instance_node_id(name, &edge.source),
+            source_handle: edge.source_handle.clone(),
+            target: instance_node_id(name, &edge.target),
+            target_handle: edge.target_handle.clone(),
+        };
+        env.add_edge(&edge).unwrap_or_else(|e| {
+            log::error!("Failed to add_edge {}: {}", edge.source, e);
+        });
+    }
+    for agent_id in agents.iter() {
+        env.start_agent(agent_id).unwrap_or_else(|e| {
+            log::error!("Failed to start agent {}: {}", agent_id, e);
+        });
+    }
+
+    set_agents(env, name, agents, true)?;
+    save_instances(env)
+}
+
+/// Stops and removes the agents of the instance, keeping the instance.
+pub fn stop_agent_flow_instance(env: &AgentEnv, name: &str) -> Result<()> {
+    let instance = get_instance(env, name)?;
+    remove_agents(env, &instance.agents);
+    set_agents(env, name, vec![], false)?;
+    save_instances(env)
+}
+
+pub fn delete_agent_flow_instance(env: &AgentEnv, name: &str) -> Result<()> {
+    let instance = get_instance(env, name)?;
+    remove_agents(env, &instance.agents);
+    env.flow_instances.lock().unwrap().remove(name);
+    save_instances(env)
+}
+
+/// Restarts the running instances of the given flow, so that they pick up its changes.
+pub fn restart_instances(env: &Arc<AgentEnv>, flow_name: &str) {
+    let names: Vec<String> = {
+        let flow_instances = env.flow_instances.lock().unwrap();
+        flow_instances
+            .values()
+            .filter(|instance| instance.flow == flow_name && !instance.agents.is_empty())
+            .map(|instance| instance.name.clone())
+            .collect()
+    };
+    for name in names {
+        log::info!("Restarting agent flow instance {}", name);
+        stop_agent_flow_instance(env, &name)
+            .and_then(|_| start_agent_flow_instance(env, &name))
+            .unwrap_or_else(|e| {
+                log::error!("Failed to restart agent flow instance {}: {}", name, e);
+            });
+    }
+}
+
+fn get_instance(env: &AgentEnv, name: &str) -> Result<AgentFlowInstance> {
+    env.flow_instances
+        .lock()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .with_context(|| format!("Agent flow instance {} not found", name))
+}
+
+fn set_agents(env: &AgentEnv, name: &str, agents: Vec<String>, running: bool) -> Result<()> {
+    let mut flow_instances = env.flow_instances.lock().unwrap();
+    let Some(instance) = flow_instances.get_mut(name) else {
+        bail!("Agent flow instance {} not found", name);
+    };
+    instance.agents = agents;
+    instance.running = running;
+    Ok(())
+}
+
+fn remove_agents(env: &AgentEnv, agents: &[String]) {
+    for agent_id in agents {
+        env.remove_agent(agent_id).unwrap_or_else(|e| {
+            log::error!("Failed to remove agent {}: {}", agent_id, e);
+        });
+        env.instance_parents.lock().unwrap().remove(agent_id);
+    }
+}
+
+fn instance_node_id(instance_name: &str, node_id: &str) -> String {
+    format!("{}#{}", instance_name, node_id)
+}
+
+// the name is the prefix of the agent ids, before '#'
+fn is_valid_instance_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+fn instances_path(env: &AgentEnv) -> Option<PathBuf> {
+    flow::agent_flows_dir(env.host().as_ref()).map(|dir| dir.join(INSTANCES_JSON))
+}
+
+fn save_instances(env: &AgentEnv) -> Result<()> {
+    let Some(path) = instances_path(env) else {
+        return Ok(());
+    };
+    let instances = list_agent_flow_instances(env);
+    let content = serde_json::to_string_pretty(&instances)?;
+    std::fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemnk::agent::flow::AgentFlow;
+    use crate::mnemnk::agent::{AgentContext, AgentValue};
+    use serde_json::json;
+
+    #[test]
+    fn test_agent_flow_instances() {
+        let (env, _host, _rx) = AgentEnv::new_for_test();
+        let flow: AgentFlow = serde_json::from_value(json!({
+            "name": "summarize",
+            "variables": { "app": "" },
+            "nodes": [
+                { "id": "summarize:a", "name": "$template_string", "enabled": false, "config": { "template": "${app}: {{value}}" } },
+                { "id": "summarize:b", "name": "$template_string", "enabled": false, "config": { "template": "{{value}}" } },
+            ],
+            "edges": [
+                { "id": "e1", "source": "summarize:a", "source_handle": "string", "target": "summarize:b", "target_handle": "data" },
+            ],
+        }))
+        .unwrap();
+        env.add_agent_flow(&flow).unwrap();
+
+        for app in ["code", "browser"] {
+            let variables = AgentConfigVariables::from([("app".to_string(), app.to_string())]);
+            create_agent_flow_instance(&env, "summarize", app, variables).unwrap();
+            start_agent_flow_instance(&env, app).unwrap();
+        }
+        assert!(create_agent_flow_instance(&env, "summarize", "code", Default::default()).is_err());
+        assert!(create_agent_flow_instance(&env, "summarize", "a:b", Default::default()).is_err());
+
+        let template = |agent_id: &str| {
+            let agents = env.agents.lock().unwrap();
+            let agent = agents.get(agent_id).unwrap().lock().unwrap();
+            agent.config().unwrap().get_string("template")
+        };
+        assert_eq!(
+            template("code#summarize:a").as_deref(),
+            Some("code: {{value}}")
+        );
+        assert_eq!(
+            template("browser#summarize:a").as_deref(),
+            Some("browser: {{value}}")
+        );
+        assert!(env.edges.lock().unwrap().contains_key("code#summarize:a"));
+
+        // the agents of an instance run in the template flow, with the values of the instance
+        {
+            let agents = env.agents.lock().unwrap();
+            let agent = agents.get("code#summarize:a").unwrap().lock().unwrap();
+            assert_eq!(agent.flow_name(), "summarize");
+        }
+        assert_eq!(
+            env.agent_variables("code#summarize:a")
+                .and_then(|variables| variables.get("app").cloned())
+                .as_deref(),
+            Some("code")
+        );
+        flow::set_agent_flow_trace(&env, "summarize", true).unwrap();
+        let ctx = env.start_trace("code#summarize:a", AgentContext::new_with_ch("data"));
+        assert!(ctx.trace_id().is_some());
+
+        // and pick up the changes of the template
+        {
+            let mut flows = env.flows.lock().unwrap();
+            let node = &mut flows.get_mut("summarize").unwrap().nodes[0];
+            let config = node.config.as_mut().unwrap();
+            config.set(
+                "template".into(),
+                AgentValue::new_string("${app}! {{value}}"),
+            );
+        }
+        restart_instances(&env, "summarize");
+        assert_eq!(
+            template("browser#summarize:a").as_deref(),
+            Some("browser! {{value}}")
+        );
+
+        stop_agent_flow_instance(&env, "code").unwrap();
+        assert!(!env.agents.lock().unwrap().contains_key("code#summarize:a"));
+        assert!(env
+            .agents
+            .lock()
+            .unwrap()
+            .contains_key("browser#summarize:a"));
+
+        let instances = list_agent_flow_instances(&env);
+        assert_eq!(instances.len(), 2);
+        assert!(instances.iter().any(|i| i.name == "browser" && i.running));
+        assert!(instances.iter().any(|i| i.name == "code" && !i.running));
+
+        delete_agent_flow_instance(&env, "browser").unwrap();
+        assert!(env.instance_parents.lock().unwrap().is_empty());
+        assert!(!env
+            .agents
+            .lock()
+            .unwrap()
+            .contains_key("browser#summarize:a"));
+        assert_eq!(list_agent_flow_instances(&env).len(), 1);
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::{Context as _, Result};
-use tauri::{AppHandle, Manager};
+use tokio::sync::mpsc::error::TrySendError;
 
 use super::{context::AgentContext, data::AgentData, env::AgentEnv};
 
@@ -53,9 +53,18 @@ pub fn try_send_agent_out(
             .clone()
             .context("tx is not initialized")?;
     }
-    env_tx
-        .try_send(EnvAgentMessage::AgentOut { agent, ctx, data })
-        .context("Failed to try_send AgentOut message")
+    let message = EnvAgentMessage::AgentOut {
+        agent: agent.clone(),
+        ctx,
+        data,
+    };
+    if let Err(e) = env_tx.try_send(message) {
+        if matches!(e, TrySendError::Full(_)) {
+            env.count_dropped_out(&agent);
+        }
+        return Err(e).context("Failed to try_send AgentOut message");
+    }
+    Ok(())
 }
 
 pub fn try_send_board_out(
@@ -79,8 +88,8 @@ pub fn try_send_board_out(
 }
 
 // Processing AgentOut message
-pub async fn agent_out(app: &AppHandle, source_agent: String, ctx: AgentContext, data: AgentData) {
-    let env = app.state::<AgentEnv>();
+pub async fn agent_out(env: &AgentEnv, source_agent: String, ctx: AgentContext, data: AgentData) {
+    env.record_agent_output(&source_agent, ctx.ch());
 
     let targets;
     {
@@ -92,6 +101,8 @@ pub async fn agent_out(app: &AppHandle, source_agent: String, ctx: AgentContext,
         return;
     }
 
+    let ctx = env.start_trace(&source_agent, ctx);
+
     for target in targets.unwrap() {
         let (target_agent, source_handle, target_handle) = target;
 
@@ -116,6 +127,7 @@ pub async fn agent_out(app: &AppHandle, source_agent: String, ctx: AgentContext,
         };
 
         let target_ctx = ctx.with_ch(target_ch);
+        env.trace_hop(&source_agent, ctx.ch(), &target_agent, &target_ctx, &data);
 
         env.agent_input(&target_agent, target_ctx, data.clone())
             .await
@@ -125,9 +137,7 @@ pub async fn agent_out(app: &AppHandle, source_agent: String, ctx: AgentContext,
     }
 }
 
-pub async fn board_out(app: &AppHandle, name: String, ctx: AgentContext, data: AgentData) {
-    let env = app.state::<AgentEnv>();
-
+pub async fn board_out(env: &AgentEnv, name: String, ctx: AgentContext, data: AgentData) {
     let board_nodes;
     {
         let env_board_nodes = env.board_out_agents.lock().unwrap();
@@ -138,6 +148,7 @@ pub async fn board_out(app: &AppHandle, name: String, ctx: AgentContext, data: A
         return;
     };
 
+    let trace_source = format!("board:{}", name);
     for node in board_nodes {
         // Perhaps we could process this by send_message_to BoardOutAgent
 
@@ -158,6 +169,7 @@ pub async fn board_out(app: &AppHandle, name: String, ctx: AgentContext, data: A
                 target_handle.clone()
             };
             let target_ctx = ctx.with_ch(target_ch);
+            env.trace_hop(&trace_source, ctx.ch(), &target_agent, &target_ctx, &data);
             env.agent_input(&target_agent, target_ctx, data.clone())
                 .await
                 .unwrap_or_else(|e| {
@@ -0,0 +1,297 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Serialize;
+
+// upper bounds of the buckets of the processing time, in seconds
+const PROCESS_TIME_BUCKETS: [f64; 10] =
+    [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Runtime counters of an agent.
+///
+/// They are kept across restarts of the agent, and removed with it.
+#[derive(Default)]
+pub struct AgentMetrics {
+    state: Mutex<MetricsState>,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    inputs: HashMap<String, u64>,
+    outputs: HashMap<String, u64>,
+    errors: u64,
+    process_time: Histogram,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Histogram {
+    // upper bounds in seconds
+    pub bounds: Vec<f64>,
+
+    // number of observations in each bucket, not cumulative, with the last one for +Inf
+    pub counts: Vec<u64>,
+
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            bounds: PROCESS_TIME_BUCKETS.to_vec(),
+            counts: vec![0; PROCESS_TIME_BUCKETS.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, secs: f64) {
+        let i = self
+            .bounds
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[i] += 1;
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AgentMetricsSnapshot {
+    pub def_name: String,
+    pub inputs: BTreeMap<String, u64>,
+    pub outputs: BTreeMap<String, u64>,
+    pub errors: u64,
+    pub process_time: Histogram,
+    pub queue_depth: usize,
+    pub dropped: u64,
+}
+
+impl AgentMetrics {
+    pub fn record_input(&self, ch: &str) {
+        let mut state = self.state.lock().unwrap();
+        *state.inputs.entry(ch.to_string()).or_default() += 1;
+    }
+
+    pub fn record_output(&self, ch: &str) {
+        let mut state = self.state.lock().unwrap();
+        *state.outputs.entry(ch.to_string()).or_default() += 1;
+    }
+
+    pub fn record_error(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.errors += 1;
+    }
+
+    pub fn record_process_time(&self, elapsed: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.process_time.observe(elapsed.as_secs_f64());
+    }
+
+    /// Returns the counters. The agent related fields are filled by `AgentEnv`.
+    pub fn snapshot(&self) -> AgentMetricsSnapshot {
+        let state = self.state.lock().unwrap();
+        AgentMetricsSnapshot {
+            inputs: state.inputs.clone().into_iter().collect(),
+            outputs: state.outputs.clone().into_iter().collect(),
+            errors: state.errors,
+            process_time: state.process_time.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Formats the metrics of agents in the Prometheus text exposition format.
+pub fn to_prometheus_text(metrics: &BTreeMap<String, AgentMetricsSnapshot>) -> String {
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "mnemnk_agent_inputs_total",
+        "counter",
+        "Messages received by the agent.",
+    );
+    for (agent_id, m) in metrics {
+        for (ch, count) in &m.inputs {
+            let labels = labels(agent_id, m, Some(ch));
+            let _ = writeln!(out, "mnemnk_agent_inputs_total{{{}}} {}", labels, count);
+        }
+    }
+
+    header(
+        &mut out,
+        "mnemnk_agent_outputs_total",
+        "counter",
+        "Messages emitted by the agent.",
+    );
+    for (agent_id, m) in metrics {
+        for (ch, count) in &m.outputs {
+            let labels = labels(agent_id, m, Some(ch));
+            let _ = writeln!(out, "mnemnk_agent_outputs_total{{{}}} {}", labels, count);
+        }
+    }
+
+    header(
+        &mut out,
+        "mnemnk_agent_errors_total",
+        "counter",
+        "Errors of the agent.",
+    );
+    for (agent_id, m) in metrics {
+        let labels = labels(agent_id, m, None);
+        let _ = writeln!(out, "mnemnk_agent_errors_total{{{}}} {}", labels, m.errors);
+    }
+
+    header(
+        &mut out,
+        "mnemnk_agent_process_seconds",
+        "histogram",
+        "Time spent in process of the agent.",
+    );
+    for (agent_id, m) in metrics {
+        let labels = labels(agent_id, m, None);
+        let h = &m.process_time;
+        let mut cumulative = 0;
+        for (bound, count) in h.bounds.iter().zip(h.counts.iter()) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "mnemnk_agent_process_seconds_bucket{{{},le=\"{}\"}} {}",
+                labels, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "mnemnk_agent_process_seconds_bucket{{{},le=\"+Inf\"}} {}",
+            labels, h.count
+        );
+        let _ = writeln!(
+            out,
+            "mnemnk_agent_process_seconds_sum{{{}}} {}",
+            labels, h.sum
+        );
+        let _ = writeln!(
+            out,
+            "mnemnk_agent_process_seconds_count{{{}}} {}",
+            labels, h.count
+        );
+    }
+
+    header(
+        &mut out,
+        "mnemnk_agent_queue_depth",
+        "gauge",
+        "Messages waiting in the queue of the agent.",
+    );
+    for (agent_id, m) in metrics {
+        let labels = labels(agent_id, m, None);
+        let _ = writeln!(
+            out,
+            "mnemnk_agent_queue_depth{{{}}} {}",
+            labels, m.queue_depth
+        );
+    }
+
+    header(
+        &mut out,
+        "mnemnk_agent_dropped_total",
+        "counter",
+        "Messages of the agent dropped by its queue or the message channel.",
+    );
+    for (agent_id, m) in metrics {
+        let labels = labels(agent_id, m, None);
+        let _ = writeln!(
+            out,
+            "mnemnk_agent_dropped_total{{{}}} {}",
+            labels, m.dropped
+        );
+    }
+
+    out
+}
+
+fn header(out: &mut String, name: &str, ty: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, ty);
+}
+
+fn labels(agent_id: &str, m: &AgentMetricsSnapshot, ch: Option<&str>) -> String {
+    let mut labels = format!(
+        "agent=\"{}\",def=\"{}\"",
+        escape_label(agent_id),
+        escape_label(&m.def_name)
+    );
+    if let Some(ch) = ch {
+        let _ = write!(labels, ",ch=\"{}\"", escape_label(ch));
+    }
+    labels
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut h = Histogram::default();
+        h.observe(0.00005);
+        h.observe(0.002);
+        h.observe(0.005);
+        h.observe(10.0);
+        assert_eq!(h.count, 4);
+        assert_eq!(h.counts[0], 1);
+        assert_eq!(h.counts[3], 2);
+        assert_eq!(h.counts[PROCESS_TIME_BUCKETS.len()], 1);
+    }
+
+    #[test]
+    fn test_to_prometheus_text() {
+        let metrics = AgentMetrics::default();
+        metrics.record_input("data");
+        metrics.record_input("data");
+        metrics.record_output("out");
+        metrics.record_error();
+        metrics.record_process_time(Duration::from_millis(20));
+
+        let mut snapshot = metrics.snapshot();
+        snapshot.def_name = "$rhai_expr".into();
+        snapshot.queue_depth = 3;
+        let text = to_prometheus_text(&BTreeMap::from([("main:\"a\"".to_string(), snapshot)]));
+
+        let labels = r#"agent="main:\"a\"",def="$rhai_expr""#;
+        for line in [
+            format!("mnemnk_agent_inputs_total{{{},ch=\"data\"}} 2", labels),
+            format!("mnemnk_agent_outputs_total{{{},ch=\"out\"}} 1", labels),
+            format!("mnemnk_agent_errors_total{{{}}} 1", labels),
+            format!(
+                "mnemnk_agent_process_seconds_bucket{{{},le=\"0.01\"}} 0",
+                labels
+            ),
+            format!(
+                "mnemnk_agent_process_seconds_bucket{{{},le=\"0.05\"}} 1",
+                labels
+            ),
+            format!(
+                "mnemnk_agent_process_seconds_bucket{{{},le=\"+Inf\"}} 1",
+                labels
+            ),
+            format!("mnemnk_agent_process_seconds_count{{{}}} 1", labels),
+            format!("mnemnk_agent_queue_depth{{{}}} 3", labels),
+            "# TYPE mnemnk_agent_process_seconds histogram".to_string(),
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {}", line);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context as _, Result};
+use serde_json::{Map, Value};
+
+use super::definition::AgentDefinitions;
+
+/// Version of the agent flow files written by this app.
+///
+/// Bump it together with a new entry in `MIGRATIONS` whenever a builtin agent is renamed or its
+/// config keys or channels change.
+pub const AGENT_FLOW_VERSION: u64 = 1;
+
+// (def name, old, new)
+type Renames = &'static [(&'static str, &'static str, &'static str)];
+
+type Fixup = fn(&mut Map<String, Value>, &AgentDefinitions);
+
+// changes of the agents between two versions of the flow files
+struct Migration {
+    // (old, new)
+    defs: &'static [(&'static str, &'static str)],
+
+    // renamed config keys, inputs and outputs, looked up with the new def name
+    config_keys: Renames,
+    inputs: Renames,
+    outputs: Renames,
+
+    // anything the tables above can't express
+    fixup: Option<Fixup>,
+}
+
+// MIGRATIONS[i] upgrades a flow from version i to i + 1
+static MIGRATIONS: [Migration; 1] = [
+    // files written before the version field, up to 0.18.8
+    Migration {
+        // 0.16.0: Database agent is renamed to Event Database
+        defs: &[("$database", "$event_database")],
+        config_keys: &[],
+        inputs: &[],
+        // 0.18.3: filter agents output to true or false channels
+        outputs: &[
+            ("$boolean_filter", "data", "true"),
+            ("$regex_list_filter", "data", "true"),
+            ("$rhai_filter", "data", "true"),
+        ],
+        fixup: Some(fixup_v0),
+    },
+];
+
+/// Upgrades the flow read from a file to `AGENT_FLOW_VERSION`.
+///
+/// Returns the version of the file if it has been upgraded, or `None` if it is already current.
+pub fn migrate_agent_flow(flow: &mut Value, defs: &AgentDefinitions) -> Result<Option<u64>> {
+    let Some(flow) = flow.as_object_mut() else {
+        bail!("Agent flow is not an object");
+    };
+    let version = match flow.get("version") {
+        Some(v) => v.as_u64().context("Invalid agent flow version")?,
+        None => 0,
+    };
+    if version > AGENT_FLOW_VERSION {
+        bail!(
+            "Agent flow version {} is newer than this app supports ({})",
+            version,
+            AGENT_FLOW_VERSION
+        );
+    }
+    if version == AGENT_FLOW_VERSION {
+        return Ok(None);
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration.apply(flow, defs);
+    }
+    flow.insert("version".into(), AGENT_FLOW_VERSION.into());
+    Ok(Some(version))
+}
+
+impl Migration {
+    fn apply(&self, flow: &mut Map<String, Value>, defs: &AgentDefinitions) {
+        let mut node_defs: HashMap<String, String> = HashMap::new();
+        for node in objects_mut(flow, "nodes") {
+            let Some(mut name) = node.get("name").and_then(Value::as_str).map(String::from) else {
+                continue;
+            };
+            if let Some((_, new)) = self.defs.iter().find(|(old, _)| *old == name) {
+                name = new.to_string();
+                node.insert("name".into(), name.clone().into());
+            }
+            if let Some(config) = node.get_mut("config").and_then(Value::as_object_mut) {
+                for (_, old, new) in self.config_keys.iter().filter(|(def, ..)| *def == name) {
+                    if let Some(value) = config.remove(*old) {
+                        config.insert(new.to_string(), value);
+                    }
+                }
+            }
+            if let Some(id) = node.get("id").and_then(Value::as_str) {
+                node_defs.insert(id.to_string(), name);
+            }
+        }
+
+        for edge in objects_mut(flow, "edges") {
+            rename_handle(edge, "source", "source_handle", self.outputs, &node_defs);
+            rename_handle(edge, "target", "target_handle", self.inputs, &node_defs);
+        }
+
+        if let Some(fixup) = self.fixup {
+            fixup(flow, defs);
+        }
+    }
+}
+
+fn rename_handle(
+    edge: &mut Map<String, Value>,
+    node_key: &str,
+    handle_key: &str,
+    renames: Renames,
+    node_defs: &HashMap<String, String>,
+) {
+    let Some(def_name) = edge
+        .get(node_key)
+        .and_then(Value::as_str)
+        .and_then(|id| node_defs.get(id))
+    else {
+        return;
+    };
+    let Some(handle) = edge.get(handle_key).and_then(Value::as_str) else {
+        return;
+    };
+    if let Some((_, _, new)) = renames
+        .iter()
+        .find(|(def, old, _)| def == def_name && *old == handle)
+    {
+        edge.insert(handle_key.into(), new.to_string().into());
+    }
+}
+
+fn objects_mut<'a>(
+    flow: &'a mut Map<String, Value>,
+    key: &str,
+) -> impl Iterator<Item = &'a mut Map<String, Value>> {
+    flow.get_mut(key)
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object_mut)
+}
+
+fn fixup_v0(flow: &mut Map<String, Value>, defs: &AgentDefinitions) {
+    // 0.14.0: text values in configs are strings, not arrays of lines
+    for node in objects_mut(flow, "nodes") {
+        let Some(def) = node
+            .get("name")
+            .and_then(Value::as_str)
+            .and_then(|name| defs.get(name))
+        else {
+            continue;
+        };
+        let Some(config) = node.get_mut("config").and_then(Value::as_object_mut) else {
+            continue;
+        };
+        for (key, entry) in def.default_config.iter().flatten() {
+            if entry.type_.as_deref() != Some("text") {
+                continue;
+            }
+            let Some(lines) = config.get(key).and_then(Value::as_array) else {
+                continue;
+            };
+            let text = lines
+                .iter()
+                .map(|line| line.as_str().unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join("\n");
+            config.insert(key.clone(), text.into());
+        }
+    }
+
+    // 0.14.0: edges have channels, and "*" passes everything as before
+    for edge in objects_mut(flow, "edges") {
+        for key in ["source_handle", "target_handle"] {
+            if edge
+                .get(key)
+                .and_then(Value::as_str)
+                .is_none_or(str::is_empty)
+            {
+                edge.insert(key.into(), "*".into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemnk::agent::builtins;
+    use serde_json::json;
+
+    #[test]
+    fn test_migrate_agent_flow() {
+        let mut defs = AgentDefinitions::new();
+        builtins::init_agent_defs(&mut defs);
+
+        let mut flow = json!({
+            "nodes": [
+                { "id": "db", "name": "$database", "enabled": true },
+                { "id": "f", "name": "$regex_list_filter", "enabled": true, "config": { "field": "", "regex_list": ["a", "b"] } },
+            ],
+            "edges": [
+                { "id": "e1", "source": "f", "source_handle": "data", "target": "db", "target_handle": "event" },
+                { "id": "e2", "source": "db", "target": "f" },
+            ],
+        });
+        assert_eq!(migrate_agent_flow(&mut flow, &defs).unwrap(), Some(0));
+        assert_eq!(flow["version"], json!(AGENT_FLOW_VERSION));
+        assert_eq!(flow["nodes"][0]["name"], json!("$event_database"));
+        assert_eq!(flow["nodes"][1]["config"]["regex_list"], json!("a\nb"));
+        assert_eq!(flow["edges"][0]["source_handle"], json!("true"));
+        assert_eq!(flow["edges"][0]["target_handle"], json!("event"));
+        assert_eq!(flow["edges"][1]["source_handle"], json!("*"));
+        assert_eq!(flow["edges"][1]["target_handle"], json!("*"));
+
+        // already current
+        assert_eq!(migrate_agent_flow(&mut flow, &defs).unwrap(), None);
+
+        let mut flow = json!({ "version": AGENT_FLOW_VERSION + 1, "nodes": [], "edges": [] });
+        assert!(migrate_agent_flow(&mut flow, &defs).is_err());
+    }
+}
@@ -1,20 +1,39 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
 use anyhow::Result;
 use serde_json::Value;
 use tauri::{AppHandle, Manager, State};
 
+use crate::mnemnk::{secrets, settings};
+
 mod agent;
+mod blob;
 mod builtins;
+mod bundle;
 mod config;
 mod context;
 mod data;
 mod definition;
 mod env;
 mod flow;
+#[cfg(any(test, feature = "flow-test"))]
+mod harness;
+mod history;
+mod host;
+mod instance;
 mod message;
+mod metrics;
+mod migration;
 mod output;
+mod queue;
+mod trace;
+mod validation;
 
 pub use agent::{Agent, AgentStatus, AsAgent, AsAgentData};
-pub use config::{AgentConfig, AgentConfigs};
+pub use bundle::{AgentFlowBundleImport, AgentFlowBundleInfo};
+pub use config::{AgentConfig, AgentConfigVariables, AgentConfigs};
 pub use context::AgentContext;
 pub use data::{AgentData, AgentValue, AgentValueMap};
 pub use definition::{
@@ -22,33 +41,57 @@ pub use definition::{
     AgentDisplayConfigEntry,
 };
 pub use env::AgentEnv;
-pub use flow::{AgentFlow, AgentFlowEdge, AgentFlowNode};
+pub use flow::{AgentFlow, AgentFlowEdge, AgentFlowNode, AgentFlowNodeResult};
+#[cfg(feature = "flow-test")]
+pub use harness::run_flow_test_cli;
+pub use history::{AgentFlowDiff, AgentFlowHistoryInfo, AgentFlowRevision};
+pub use host::{AgentHost, CommandChild, CommandEvent, TauriHost};
+pub use instance::AgentFlowInstance;
+pub use metrics::{to_prometheus_text, AgentMetricsSnapshot};
 pub use output::AgentOutput;
+pub use queue::AgentQueueStats;
+pub use trace::TraceRecord;
+pub use validation::AgentFlowProblem;
 
 pub fn init(app: &AppHandle) -> Result<()> {
-    AgentEnv::init(app)?;
-    flow::init(app)?;
+    let env = init_env(Arc::new(TauriHost::new(app.clone())))?;
+    app.manage(env);
     Ok(())
 }
 
+/// Creates an `AgentEnv` on the given host and loads the agent flows into it.
+pub fn init_env(host: Arc<dyn AgentHost>) -> Result<Arc<AgentEnv>> {
+    let env = AgentEnv::init(host)?;
+    flow::init(&env)?;
+    instance::init(&env).unwrap_or_else(|e| {
+        log::error!("Failed to load agent flow instances: {}", e);
+    });
+    Ok(env)
+}
+
 pub fn ready(app: &AppHandle) -> Result<()> {
-    flow::ready(app)?;
+    let env = app.state::<Arc<AgentEnv>>();
+    ready_env(&env)
+}
 
-    let env = app.state::<AgentEnv>();
+/// Starts the enabled agents, the message loop and the watcher of the agent definitions.
+pub fn ready_env(env: &Arc<AgentEnv>) -> Result<()> {
+    flow::ready(env)?;
+    instance::ready(env)?;
     env.spawn_message_loop()?;
-
+    env.spawn_agent_defs_watcher();
     Ok(())
 }
 
 pub fn quit(app: &AppHandle) {
-    let env = app.state::<AgentEnv>();
+    let env = app.state::<Arc<AgentEnv>>();
     env.quit();
 }
 
 // Tauri Commands
 
 #[tauri::command]
-pub fn get_agent_defs_cmd(env: State<AgentEnv>) -> Result<Value, String> {
+pub fn get_agent_defs_cmd(env: State<Arc<AgentEnv>>) -> Result<Value, String> {
     let defs: AgentDefinitions;
     {
         let env_defs = env.defs.lock().unwrap();
@@ -57,31 +100,71 @@ pub fn get_agent_defs_cmd(env: State<AgentEnv>) -> Result<Value, String> {
     serde_json::to_value(defs).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+pub fn reload_agent_defs_cmd(env: State<Arc<AgentEnv>>) -> Result<Value, String> {
+    let changes = env.reload_agent_defs().map_err(|e| e.to_string())?;
+    serde_json::to_value(changes).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub async fn set_agent_config_cmd(
     app: AppHandle,
     agent_id: String,
     config: AgentConfig,
 ) -> Result<(), String> {
-    let env = app.state::<AgentEnv>();
+    let env = app.state::<Arc<AgentEnv>>();
     env.set_agent_config(&agent_id, config)
         .await
         .map_err(|e| e.to_string())
 }
 #[tauri::command]
-pub fn start_agent_cmd(env: State<AgentEnv>, agent_id: String) -> Result<(), String> {
+pub fn start_agent_cmd(env: State<Arc<AgentEnv>>, agent_id: String) -> Result<(), String> {
     env.start_agent(&agent_id).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub fn stop_agent_cmd(env: State<AgentEnv>, agent_id: String) -> Result<(), String> {
+pub fn stop_agent_cmd(env: State<Arc<AgentEnv>>, agent_id: String) -> Result<(), String> {
     env.stop_agent(&agent_id).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+pub fn get_agent_metrics_cmd(env: State<Arc<AgentEnv>>) -> BTreeMap<String, AgentMetricsSnapshot> {
+    env.get_agent_metrics()
+}
+
+#[tauri::command]
+pub fn get_agent_traces_cmd(
+    env: State<Arc<AgentEnv>>,
+    trace_id: Option<String>,
+) -> Vec<TraceRecord> {
+    match trace_id {
+        Some(trace_id) => env.tracer.trace(&trace_id).into_iter().collect(),
+        None => env.tracer.traces(),
+    }
+}
+
+#[tauri::command]
+pub fn clear_agent_traces_cmd(env: State<Arc<AgentEnv>>) {
+    env.tracer.clear();
+}
+
+#[tauri::command]
+pub fn get_agent_queue_stats_cmd(env: State<Arc<AgentEnv>>) -> HashMap<String, AgentQueueStats> {
+    env.get_agent_queue_stats()
+}
+
 // flow commands
 
 #[tauri::command]
-pub fn get_agent_flows_cmd(env: State<AgentEnv>) -> Result<Value, String> {
+pub fn validate_agent_flow_cmd(
+    env: State<Arc<AgentEnv>>,
+    flow_name: String,
+) -> Result<Vec<AgentFlowProblem>, String> {
+    flow::validate_agent_flow(&env, &flow_name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_agent_flows_cmd(env: State<Arc<AgentEnv>>) -> Result<Value, String> {
     let agent_flows;
     {
         let flows = env.flows.lock().unwrap();
@@ -92,52 +175,187 @@ pub fn get_agent_flows_cmd(env: State<AgentEnv>) -> Result<Value, String> {
 }
 
 #[tauri::command]
-pub fn new_agent_flow_cmd(env: State<AgentEnv>, name: String) -> Result<AgentFlow, String> {
+pub fn new_agent_flow_cmd(env: State<Arc<AgentEnv>>, name: String) -> Result<AgentFlow, String> {
     let flow = env.new_agent_flow(&name).map_err(|e| e.to_string())?;
     Ok(flow)
 }
 
 #[tauri::command]
 pub fn rename_agent_flow_cmd(
-    app: AppHandle,
-    env: State<AgentEnv>,
+    env: State<Arc<AgentEnv>>,
     old_name: String,
     new_name: String,
 ) -> Result<String, String> {
-    flow::rename_agent_flow(&app, &env, &old_name, &new_name).map_err(|e| e.to_string())
+    flow::rename_agent_flow(&env, &old_name, &new_name).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub fn delete_agent_flow_cmd(
-    app: AppHandle,
-    env: State<AgentEnv>,
+pub fn delete_agent_flow_cmd(env: State<Arc<AgentEnv>>, name: String) -> Result<(), String> {
+    flow::delete_agent_flow(&env, &name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn start_agent_flow_cmd(
+    env: State<Arc<AgentEnv>>,
+    name: String,
+) -> Result<Vec<AgentFlowNodeResult>, String> {
+    env.start_agent_flow(&name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn stop_agent_flow_cmd(
+    env: State<Arc<AgentEnv>>,
     name: String,
+) -> Result<Vec<AgentFlowNodeResult>, String> {
+    env.stop_agent_flow(&name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn restart_agent_flow_cmd(
+    env: State<Arc<AgentEnv>>,
+    name: String,
+) -> Result<Vec<AgentFlowNodeResult>, String> {
+    env.restart_agent_flow(&name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_agent_flow_enabled_cmd(
+    env: State<Arc<AgentEnv>>,
+    name: String,
+    enabled: bool,
+) -> Result<Vec<AgentFlowNodeResult>, String> {
+    flow::set_agent_flow_enabled(&env, &name, enabled).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_agent_flow_trace_cmd(
+    env: State<Arc<AgentEnv>>,
+    name: String,
+    enabled: bool,
 ) -> Result<(), String> {
-    flow::delete_agent_flow(&app, &env, &name).map_err(|e| e.to_string())
+    flow::set_agent_flow_trace(&env, &name, enabled).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub fn insert_agent_flow_cmd(env: State<AgentEnv>, agent_flow: AgentFlow) -> Result<(), String> {
-    flow::insert_agent_flow(env, agent_flow).map_err(|e| e.to_string())
+pub fn set_agent_flow_variables_cmd(
+    env: State<Arc<AgentEnv>>,
+    flow_name: String,
+    variables: AgentConfigVariables,
+) -> Result<AgentFlow, String> {
+    flow::set_agent_flow_variables(&env, &flow_name, variables).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub fn save_agent_flow_cmd(
-    app: AppHandle,
-    env: State<AgentEnv>,
+pub fn insert_agent_flow_cmd(
+    env: State<Arc<AgentEnv>>,
     agent_flow: AgentFlow,
+    record_history: Option<bool>,
 ) -> Result<(), String> {
-    flow::save_agent_flow(&app, env, agent_flow).map_err(|e| e.to_string())
+    flow::insert_agent_flow(&env, agent_flow, record_history.unwrap_or(true))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn save_agent_flow_cmd(env: State<Arc<AgentEnv>>, agent_flow: AgentFlow) -> Result<(), String> {
+    flow::save_agent_flow(&env, agent_flow).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub fn import_agent_flow_cmd(env: State<AgentEnv>, path: String) -> Result<AgentFlow, String> {
-    flow::import_agent_flow(&env, path).map_err(|e| e.to_string())
+pub fn import_agent_flow_cmd(
+    env: State<Arc<AgentEnv>>,
+    path: String,
+    variables: Option<AgentConfigVariables>,
+) -> Result<AgentFlow, String> {
+    flow::import_agent_flow(&env, path, variables.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn export_agent_flow_bundle_cmd(
+    env: State<Arc<AgentEnv>>,
+    flow_name: String,
+    path: String,
+) -> Result<(), String> {
+    bundle::export_agent_flow_bundle(&env, &flow_name, Path::new(&path)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn inspect_agent_flow_bundle_cmd(
+    env: State<Arc<AgentEnv>>,
+    path: String,
+) -> Result<AgentFlowBundleInfo, String> {
+    bundle::inspect_agent_flow_bundle(&env, Path::new(&path)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn import_agent_flow_bundle_cmd(
+    app: AppHandle,
+    env: State<Arc<AgentEnv>>,
+    path: String,
+    options: Option<AgentFlowBundleImport>,
+) -> Result<AgentFlow, String> {
+    let mut options = options.unwrap_or_default();
+    // secrets of the global configs go to the secret store before anything is imported
+    {
+        let defs = env.defs.lock().unwrap();
+        for (agent_name, config) in options.global_configs.iter_mut() {
+            settings::store_secret_config(
+                agent_name,
+                config,
+                defs.get(agent_name),
+                |name, value| secrets::set_secret(&app, name, value),
+            )
+            .map_err(|e| e.to_string())?;
+        }
+    }
+    let agent_flow = bundle::import_agent_flow_bundle(&env, Path::new(&path), &options)
+        .map_err(|e| e.to_string())?;
+    if !options.global_configs.is_empty() {
+        settings::merge_agent_global_configs(&app, options.global_configs)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(agent_flow)
+}
+
+#[tauri::command]
+pub fn list_agent_flow_instances_cmd(env: State<Arc<AgentEnv>>) -> Vec<AgentFlowInstance> {
+    instance::list_agent_flow_instances(&env)
+}
+
+#[tauri::command]
+pub fn create_agent_flow_instance_cmd(
+    env: State<Arc<AgentEnv>>,
+    flow_name: String,
+    name: String,
+    variables: Option<AgentConfigVariables>,
+) -> Result<AgentFlowInstance, String> {
+    instance::create_agent_flow_instance(&env, &flow_name, &name, variables.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn start_agent_flow_instance_cmd(
+    env: State<Arc<AgentEnv>>,
+    name: String,
+) -> Result<(), String> {
+    instance::start_agent_flow_instance(&env, &name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn stop_agent_flow_instance_cmd(env: State<Arc<AgentEnv>>, name: String) -> Result<(), String> {
+    instance::stop_agent_flow_instance(&env, &name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn delete_agent_flow_instance_cmd(
+    env: State<Arc<AgentEnv>>,
+    name: String,
+) -> Result<(), String> {
+    instance::delete_agent_flow_instance(&env, &name).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 pub fn new_agent_flow_node_cmd(
-    env: State<AgentEnv>,
+    env: State<Arc<AgentEnv>>,
     flow_name: String,
     def_name: String,
 ) -> Result<AgentFlowNode, String> {
@@ -146,7 +364,7 @@ pub fn new_agent_flow_node_cmd(
 
 #[tauri::command]
 pub fn add_agent_flow_node_cmd(
-    env: State<AgentEnv>,
+    env: State<Arc<AgentEnv>>,
     flow_name: String,
     node: AgentFlowNode,
 ) -> Result<(), String> {
@@ -155,7 +373,7 @@ pub fn add_agent_flow_node_cmd(
 
 #[tauri::command]
 pub fn remove_agent_flow_node_cmd(
-    env: State<AgentEnv>,
+    env: State<Arc<AgentEnv>>,
     flow_name: String,
     node_id: String,
 ) -> Result<(), String> {
@@ -164,7 +382,7 @@ pub fn remove_agent_flow_node_cmd(
 
 #[tauri::command]
 pub fn add_agent_flow_edge_cmd(
-    env: State<AgentEnv>,
+    env: State<Arc<AgentEnv>>,
     flow_name: String,
     edge: AgentFlowEdge,
 ) -> Result<(), String> {
@@ -173,13 +391,65 @@ pub fn add_agent_flow_edge_cmd(
 
 #[tauri::command]
 pub fn remove_agent_flow_edge_cmd(
-    env: State<AgentEnv>,
+    env: State<Arc<AgentEnv>>,
     flow_name: String,
     edge_id: String,
 ) -> Result<(), String> {
     flow::remove_agent_flow_edge(&env, &flow_name, &edge_id).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+pub fn get_agent_flow_history_cmd(
+    env: State<Arc<AgentEnv>>,
+    flow_name: String,
+) -> AgentFlowHistoryInfo {
+    flow::get_agent_flow_history(&env, &flow_name)
+}
+
+#[tauri::command]
+pub fn undo_agent_flow_cmd(
+    env: State<Arc<AgentEnv>>,
+    flow_name: String,
+) -> Result<Option<AgentFlow>, String> {
+    flow::undo_agent_flow(&env, &flow_name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn redo_agent_flow_cmd(
+    env: State<Arc<AgentEnv>>,
+    flow_name: String,
+) -> Result<Option<AgentFlow>, String> {
+    flow::redo_agent_flow(&env, &flow_name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_agent_flow_revisions_cmd(
+    env: State<Arc<AgentEnv>>,
+    flow_name: String,
+) -> Result<Vec<AgentFlowRevision>, String> {
+    flow::get_agent_flow_revisions(&env, &flow_name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn diff_agent_flow_revision_cmd(
+    env: State<Arc<AgentEnv>>,
+    flow_name: String,
+    revision: String,
+    other: Option<String>,
+) -> Result<AgentFlowDiff, String> {
+    flow::diff_agent_flow_revision(&env, &flow_name, &revision, other.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn restore_agent_flow_revision_cmd(
+    env: State<Arc<AgentEnv>>,
+    flow_name: String,
+    revision: String,
+) -> Result<AgentFlow, String> {
+    flow::restore_agent_flow_revision(&env, &flow_name, &revision).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn copy_sub_flow_cmd(
     flow_name: String,
@@ -0,0 +1,418 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use super::agent::AgentMessage;
+
+const DEFAULT_CAPACITY: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentQueueConfig {
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+
+    #[serde(default)]
+    pub policy: AgentQueuePolicy,
+}
+
+impl Default for AgentQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            policy: AgentQueuePolicy::default(),
+        }
+    }
+}
+
+fn default_capacity() -> usize {
+    DEFAULT_CAPACITY
+}
+
+/// What to do with an input when the queue of the agent is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentQueuePolicy {
+    /// Wait until the agent takes an input.
+    #[default]
+    Block,
+
+    /// Drop the oldest waiting input.
+    DropOldest,
+
+    /// Drop the incoming input.
+    DropNewest,
+
+    /// Replace the waiting input of the same channel, or drop the oldest one.
+    CoalesceLatest,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentQueueStats {
+    pub capacity: usize,
+    pub policy: AgentQueuePolicy,
+    pub queued: usize,
+
+    // inputs dropped by the policy
+    pub dropped: u64,
+
+    // outputs dropped because the env message channel was full
+    pub dropped_out: u64,
+}
+
+/// What became of an input sent to a queue.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AgentQueueSent {
+    // whether the input itself was queued, and not dropped by the policy
+    pub queued: bool,
+
+    // inputs dropped by the policy, including this one if it was not queued
+    pub dropped: u64,
+}
+
+/// Inbox of an agent.
+///
+/// Inputs are subject to the capacity and policy of the node, while config and stop messages
+/// are always queued. Async agents wait with `recv`, and native thread agents with `recv_blocking`.
+pub struct AgentQueue {
+    state: Mutex<QueueState>,
+
+    // wakes the async receiver
+    not_empty: Notify,
+
+    // wakes the native thread receiver
+    ready: Condvar,
+
+    // wakes the senders blocked by a full queue
+    not_full: Notify,
+
+    dropped: AtomicU64,
+    dropped_out: AtomicU64,
+
+    // inputs queued or being processed, until the agent calls `done_input`
+    in_flight: AtomicUsize,
+}
+
+struct QueueState {
+    config: AgentQueueConfig,
+    messages: VecDeque<AgentMessage>,
+    closed: bool,
+}
+
+impl AgentQueue {
+    pub fn new(config: AgentQueueConfig) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                config,
+                messages: VecDeque::new(),
+                closed: false,
+            }),
+            not_empty: Notify::new(),
+            ready: Condvar::new(),
+            not_full: Notify::new(),
+            dropped: AtomicU64::new(0),
+            dropped_out: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns an empty queue for the next run of the agent, with the same config and counters.
+    ///
+    /// The previous run keeps its own queue until it receives the stop message.
+    pub fn renew(&self) -> Self {
+        let config = self.state.lock().unwrap().config.clone();
+        let queue = Self::new(config);
+        queue
+            .dropped
+            .store(self.dropped.load(Ordering::Relaxed), Ordering::Relaxed);
+        queue
+            .dropped_out
+            .store(self.dropped_out.load(Ordering::Relaxed), Ordering::Relaxed);
+        queue
+    }
+
+    /// Rejects further inputs, and releases the senders waiting for space.
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        drop(state);
+        self.not_full.notify_waiters();
+    }
+
+    /// Queues an input according to the policy, and tells whether it was queued and how many
+    /// inputs were dropped by the policy.
+    pub async fn send_input(&self, message: AgentMessage) -> Result<AgentQueueSent> {
+        loop {
+            let not_full = self.not_full.notified();
+            tokio::pin!(not_full);
+            not_full.as_mut().enable();
+
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.closed {
+                    bail!("Agent queue is closed");
+                }
+
+                let capacity = state.config.capacity.max(1);
+                let is_full = state.messages.len() >= capacity;
+                let policy = state.config.policy;
+                if !is_full || policy != AgentQueuePolicy::Block {
+                    let drop_newest = is_full && policy == AgentQueuePolicy::DropNewest;
+                    // waiting inputs removed to make room
+                    let removed = match policy {
+                        AgentQueuePolicy::Block | AgentQueuePolicy::DropNewest => 0,
+                        AgentQueuePolicy::DropOldest => {
+                            if is_full {
+                                state.remove_oldest_input()
+                            } else {
+                                0
+                            }
+                        }
+                        AgentQueuePolicy::CoalesceLatest => {
+                            if let Some(pos) = state.position_of_same_ch(&message) {
+                                state.messages.remove(pos);
+                                1
+                            } else if is_full {
+                                state.remove_oldest_input()
+                            } else {
+                                0
+                            }
+                        }
+                    };
+                    if !drop_newest {
+                        state.messages.push_back(message);
+                        self.in_flight.fetch_add(1, Ordering::Relaxed);
+                    }
+                    drop(state);
+
+                    if removed > 0 {
+                        self.in_flight
+                            .fetch_sub(removed as usize, Ordering::Relaxed);
+                    }
+                    let dropped = removed + drop_newest as u64;
+                    if dropped > 0 {
+                        self.dropped.fetch_add(dropped, Ordering::Relaxed);
+                    }
+                    self.wake_receiver();
+                    return Ok(AgentQueueSent {
+                        queued: !drop_newest,
+                        dropped,
+                    });
+                }
+            }
+
+            // Block policy with a full queue
+            not_full.await;
+        }
+    }
+
+    /// Queues a config or stop message, regardless of the capacity.
+    pub fn send_control(&self, message: AgentMessage) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.messages.push_back(message);
+        }
+        self.wake_receiver();
+    }
+
+    pub async fn recv(&self) -> AgentMessage {
+        loop {
+            let not_empty = self.not_empty.notified();
+            if let Some(message) = self.try_recv() {
+                return message;
+            }
+            not_empty.await;
+        }
+    }
+
+    pub fn recv_blocking(&self) -> AgentMessage {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(message) = state.messages.pop_front() {
+                drop(state);
+                self.not_full.notify_waiters();
+                return message;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+
+    fn try_recv(&self) -> Option<AgentMessage> {
+        let message = {
+            let mut state = self.state.lock().unwrap();
+            state.messages.pop_front()
+        };
+        if message.is_some() {
+            self.not_full.notify_waiters();
+        }
+        message
+    }
+
+    fn wake_receiver(&self) {
+        self.not_empty.notify_one();
+        self.ready.notify_one();
+    }
+
+    /// Tells that the agent has finished processing an input taken from this queue.
+    pub fn done_input(&self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Whether an input is waiting or being processed.
+    pub fn is_busy(&self) -> bool {
+        self.in_flight.load(Ordering::Relaxed) > 0
+    }
+
+    pub fn count_dropped_out(&self) -> u64 {
+        self.dropped_out.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Total number of inputs and outputs dropped so far.
+    pub fn dropped_total(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed) + self.dropped_out.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> AgentQueueStats {
+        let state = self.state.lock().unwrap();
+        AgentQueueStats {
+            capacity: state.config.capacity,
+            policy: state.config.policy,
+            queued: state.messages.len(),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            dropped_out: self.dropped_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl QueueState {
+    fn remove_oldest_input(&mut self) -> u64 {
+        let pos = self
+            .messages
+            .iter()
+            .position(|m| matches!(m, AgentMessage::Input { .. }));
+        match pos {
+            Some(pos) => {
+                self.messages.remove(pos);
+                1
+            }
+            None => 0,
+        }
+    }
+
+    fn position_of_same_ch(&self, message: &AgentMessage) -> Option<usize> {
+        let AgentMessage::Input { ctx, .. } = message else {
+            return None;
+        };
+        self.messages.iter().rposition(|m| match m {
+            AgentMessage::Input { ctx: c, .. } => c.ch() == ctx.ch(),
+            _ => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemnk::agent::{AgentContext, AgentData};
+
+    fn queue(capacity: usize, policy: AgentQueuePolicy) -> AgentQueue {
+        AgentQueue::new(AgentQueueConfig { capacity, policy })
+    }
+
+    fn input(ch: &str, value: i64) -> AgentMessage {
+        AgentMessage::Input {
+            ctx: AgentContext::new_with_ch(ch),
+            data: AgentData::new_integer(value),
+        }
+    }
+
+    fn send(queue: &AgentQueue, message: AgentMessage) -> u64 {
+        send_input(queue, message).dropped
+    }
+
+    fn send_input(queue: &AgentQueue, message: AgentMessage) -> AgentQueueSent {
+        tauri::async_runtime::block_on(queue.send_input(message)).unwrap()
+    }
+
+    fn drain(queue: &AgentQueue) -> Vec<(String, i64)> {
+        let mut values = vec![];
+        while let Some(message) = queue.try_recv() {
+            if let AgentMessage::Input { ctx, data } = message {
+                values.push((ctx.ch().to_string(), data.as_i64().unwrap()));
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let q = queue(2, AgentQueuePolicy::DropNewest);
+        assert_eq!(send(&q, input("a", 1)), 0);
+        assert_eq!(send(&q, input("a", 2)), 0);
+        assert_eq!(
+            send_input(&q, input("a", 3)),
+            AgentQueueSent {
+                queued: false,
+                dropped: 1
+            }
+        );
+        assert_eq!(drain(&q), vec![("a".into(), 1), ("a".into(), 2)]);
+        assert_eq!(q.stats().dropped, 1);
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let q = queue(2, AgentQueuePolicy::DropOldest);
+        send(&q, input("a", 1));
+        q.send_control(AgentMessage::Stop);
+        send(&q, input("a", 2));
+        send(&q, input("a", 3));
+        assert_eq!(q.stats().queued, 2);
+        assert_eq!(drain(&q), vec![("a".into(), 3)]);
+        assert_eq!(q.stats().dropped, 2);
+    }
+
+    #[test]
+    fn test_coalesce_latest() {
+        let q = queue(2, AgentQueuePolicy::CoalesceLatest);
+        send(&q, input("a", 1));
+        send(&q, input("b", 2));
+        assert!(send_input(&q, input("a", 3)).queued);
+        assert_eq!(send(&q, input("c", 4)), 1);
+        assert_eq!(drain(&q), vec![("a".into(), 3), ("c".into(), 4)]);
+    }
+
+    #[test]
+    fn test_block() {
+        let q = std::sync::Arc::new(queue(1, AgentQueuePolicy::Block));
+        send(&q, input("a", 1));
+
+        let sender = {
+            let q = q.clone();
+            std::thread::spawn(move || send(&q, input("a", 2)))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!sender.is_finished());
+
+        assert!(matches!(q.recv_blocking(), AgentMessage::Input { .. }));
+        assert_eq!(sender.join().unwrap(), 0);
+        assert_eq!(drain(&q), vec![("a".into(), 2)]);
+    }
+
+    #[test]
+    fn test_close_and_renew() {
+        let q = queue(1, AgentQueuePolicy::DropNewest);
+        send(&q, input("a", 1));
+        send(&q, input("a", 2));
+        q.close();
+        assert!(tauri::async_runtime::block_on(q.send_input(input("a", 3))).is_err());
+
+        let q = q.renew();
+        assert_eq!(q.stats().queued, 0);
+        assert_eq!(q.stats().dropped, 1);
+        assert_eq!(send(&q, input("a", 4)), 0);
+    }
+}
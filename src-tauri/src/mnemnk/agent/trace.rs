@@ -0,0 +1,198 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::data::{AgentData, AgentValue};
+
+// number of traces kept by the recorder
+const MAX_TRACES: usize = 100;
+
+// number of hops kept for a trace, to stop loops from growing it forever
+const MAX_HOPS: usize = 256;
+
+const PREVIEW_MAX_CHARS: usize = 200;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AgentTraceHop {
+    // source agent id, or "board:<name>" for boards
+    pub source: String,
+    pub ch: String,
+    pub target: String,
+    pub target_ch: String,
+
+    // milliseconds since the epoch
+    pub time: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TraceRecord {
+    pub id: String,
+    pub flow: String,
+    pub hops: Vec<TraceRecordHop>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TraceRecordHop {
+    #[serde(flatten)]
+    pub hop: AgentTraceHop,
+    pub preview: String,
+}
+
+/// Keeps the recent traces of the flows with tracing enabled.
+#[derive(Default)]
+pub struct TraceRecorder {
+    // true if any flow is traced, so that untraced messages cost an atomic load
+    enabled: AtomicBool,
+
+    flows: Mutex<HashSet<String>>,
+    traces: Mutex<VecDeque<TraceRecord>>,
+    counter: AtomicUsize,
+}
+
+impl TraceRecorder {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn is_flow_enabled(&self, flow_name: &str) -> bool {
+        self.flows.lock().unwrap().contains(flow_name)
+    }
+
+    pub fn set_flow_enabled(&self, flow_name: &str, enabled: bool) {
+        let mut flows = self.flows.lock().unwrap();
+        if enabled {
+            flows.insert(flow_name.to_string());
+        } else {
+            flows.remove(flow_name);
+        }
+        self.enabled.store(!flows.is_empty(), Ordering::Relaxed);
+    }
+
+    pub fn rename_flow(&self, old_name: &str, new_name: &str) {
+        let mut flows = self.flows.lock().unwrap();
+        if flows.remove(old_name) {
+            flows.insert(new_name.to_string());
+        }
+    }
+
+    /// Starts a new trace of the flow, and returns its id.
+    pub fn new_trace(&self, flow_name: &str) -> String {
+        let count = self.counter.fetch_add(1, Ordering::Relaxed);
+        let id = format!("{:x}-{}", chrono::Utc::now().timestamp_millis(), count);
+
+        let mut traces = self.traces.lock().unwrap();
+        if traces.len() >= MAX_TRACES {
+            traces.pop_front();
+        }
+        traces.push_back(TraceRecord {
+            id: id.clone(),
+            flow: flow_name.to_string(),
+            hops: Vec::new(),
+        });
+        id
+    }
+
+    pub fn record_hop(&self, trace_id: &str, hop: AgentTraceHop, data: &AgentData) {
+        let mut traces = self.traces.lock().unwrap();
+        // the trace may have been pushed out by newer ones
+        let Some(trace) = traces.iter_mut().rev().find(|t| t.id == trace_id) else {
+            return;
+        };
+        if trace.hops.len() >= MAX_HOPS {
+            return;
+        }
+        trace.hops.push(TraceRecordHop {
+            hop,
+            preview: data_preview(data),
+        });
+    }
+
+    pub fn traces(&self) -> Vec<TraceRecord> {
+        self.traces.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn trace(&self, trace_id: &str) -> Option<TraceRecord> {
+        let traces = self.traces.lock().unwrap();
+        traces.iter().find(|t| t.id == trace_id).cloned()
+    }
+
+    pub fn clear(&self) {
+        self.traces.lock().unwrap().clear();
+    }
+}
+
+/// Short JSON of the data, without the image pixels.
+pub fn data_preview(data: &AgentData) -> String {
+    let value = preview_value(&data.value);
+    let mut preview = format!("{}: {}", data.kind, value);
+    if let Some((i, _)) = preview.char_indices().nth(PREVIEW_MAX_CHARS) {
+        preview.truncate(i);
+        preview.push_str("...");
+    }
+    preview
+}
+
+fn preview_value(value: &AgentValue) -> Value {
+    match value {
+        AgentValue::Image(image) => Value::String(format!(
+            "<image {}x{}>",
+            image.get_width(),
+            image.get_height()
+        )),
+        AgentValue::Array(array) => {
+            Value::Array(array.iter().take(10).map(preview_value).collect())
+        }
+        AgentValue::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(k, v)| (k.clone(), preview_value(v)))
+                .collect(),
+        ),
+        AgentValue::String(s) => Value::String(s.chars().take(PREVIEW_MAX_CHARS + 1).collect()),
+        _ => value.to_json_value(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hop(source: &str, target: &str) -> AgentTraceHop {
+        AgentTraceHop {
+            source: source.into(),
+            ch: "data".into(),
+            target: target.into(),
+            target_ch: "data".into(),
+            time: 0,
+        }
+    }
+
+    #[test]
+    fn test_recorder() {
+        let recorder = TraceRecorder::default();
+        assert!(!recorder.is_enabled());
+        recorder.set_flow_enabled("main", true);
+        assert!(recorder.is_enabled());
+
+        let id = recorder.new_trace("main");
+        recorder.record_hop(&id, hop("a", "b"), &AgentData::new_integer(1));
+        recorder.record_hop(&id, hop("b", "c"), &AgentData::new_string("x".repeat(500)));
+
+        let trace = recorder.trace(&id).unwrap();
+        assert_eq!(trace.hops.len(), 2);
+        assert_eq!(trace.hops[0].preview, "integer: 1");
+        assert!(trace.hops[1].preview.ends_with("..."));
+
+        for _ in 0..MAX_TRACES {
+            recorder.new_trace("main");
+        }
+        assert!(recorder.trace(&id).is_none());
+        assert_eq!(recorder.traces().len(), MAX_TRACES);
+
+        recorder.set_flow_enabled("main", false);
+        assert!(!recorder.is_enabled());
+    }
+}
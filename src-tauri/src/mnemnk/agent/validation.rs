@@ -0,0 +1,321 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::Serialize;
+
+use super::builtins::{BOARD_IN_DEF_NAME, BOARD_OUT_DEF_NAME, CONFIG_BOARD_NAME};
+use super::config::AgentConfig;
+use super::definition::AgentDefinitions;
+use super::flow::{AgentFlow, AgentFlows};
+
+// agents that hold the data back, so that a cycle through them doesn't spin
+static CYCLE_BREAKERS: [&str; 3] = ["$delay", "$throttle_time", "$interval_timer"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentFlowProblemLevel {
+    /// The node or the edge cannot work.
+    Error,
+
+    /// The flow runs, but probably not as intended.
+    Warning,
+}
+
+/// A problem of a flow, found before it runs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AgentFlowProblem {
+    pub level: AgentFlowProblemLevel,
+
+    // node to be highlighted, if the problem belongs to one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edge_id: Option<String>,
+
+    pub message: String,
+}
+
+impl AgentFlowProblem {
+    fn error(node_id: Option<&str>, edge_id: Option<&str>, message: String) -> Self {
+        Self {
+            level: AgentFlowProblemLevel::Error,
+            node_id: node_id.map(String::from),
+            edge_id: edge_id.map(String::from),
+            message,
+        }
+    }
+
+    fn warning(node_id: Option<&str>, edge_id: Option<&str>, message: String) -> Self {
+        Self {
+            level: AgentFlowProblemLevel::Warning,
+            node_id: node_id.map(String::from),
+            edge_id: edge_id.map(String::from),
+            message,
+        }
+    }
+}
+
+/// Checks the flow against the agent definitions.
+///
+/// Boards are shared by all the flows, so the other flows are looked at for their readers and
+/// writers.
+pub fn validate_agent_flow(
+    flow: &AgentFlow,
+    flows: &AgentFlows,
+    defs: &AgentDefinitions,
+) -> Vec<AgentFlowProblem> {
+    let mut problems = Vec::new();
+
+    // nodes
+    let mut node_ids = HashSet::new();
+    for node in flow.nodes.iter() {
+        if !node_ids.insert(node.id.as_str()) {
+            problems.push(AgentFlowProblem::error(
+                Some(&node.id),
+                None,
+                format!("Duplicate node id {}", node.id),
+            ));
+        }
+        let Some(def) = defs.get(&node.name) else {
+            problems.push(AgentFlowProblem::error(
+                Some(&node.id),
+                None,
+                format!("Unknown agent definition {}", node.name),
+            ));
+            continue;
+        };
+        if let Some(config) = &node.config {
+            if let Err(e) = def.validate_config(config) {
+                problems.push(AgentFlowProblem::error(Some(&node.id), None, e.to_string()));
+            }
+        }
+    }
+    let node_defs: HashMap<&str, &str> = flow
+        .nodes
+        .iter()
+        .map(|node| (node.id.as_str(), node.name.as_str()))
+        .collect();
+
+    // edges
+    let mut edge_ids = HashSet::new();
+    let mut graph: HashMap<&str, Vec<&str>> = HashMap::new();
+    for edge in flow.edges.iter() {
+        if !edge_ids.insert(edge.id.as_str()) {
+            problems.push(AgentFlowProblem::error(
+                Some(&edge.source),
+                Some(&edge.id),
+                format!("Duplicate edge id {}", edge.id),
+            ));
+        }
+        let source_def = node_defs.get(edge.source.as_str());
+        let target_def = node_defs.get(edge.target.as_str());
+        if source_def.is_none() || target_def.is_none() {
+            let missing = if source_def.is_none() {
+                &edge.source
+            } else {
+                &edge.target
+            };
+            let node_id = source_def
+                .map(|_| edge.source.as_str())
+                .or(target_def.map(|_| edge.target.as_str()));
+            problems.push(AgentFlowProblem::error(
+                node_id,
+                Some(&edge.id),
+                format!("Edge {} refers to a missing node {}", edge.id, missing),
+            ));
+            continue;
+        }
+        if let Some(outputs) = source_def
+            .and_then(|name| defs.get(*name))
+            .and_then(|def| def.outputs.as_ref())
+        {
+            if !outputs
+                .iter()
+                .any(|ch| ch == "*" || *ch == edge.source_handle)
+            {
+                problems.push(AgentFlowProblem::error(
+                    Some(&edge.source),
+                    Some(&edge.id),
+                    format!("Unknown output {} of {}", edge.source_handle, edge.source),
+                ));
+            }
+        }
+        if let Some(inputs) = target_def
+            .and_then(|name| defs.get(*name))
+            .and_then(|def| def.inputs.as_ref())
+        {
+            if !inputs
+                .iter()
+                .any(|ch| ch == "*" || *ch == edge.target_handle)
+            {
+                problems.push(AgentFlowProblem::error(
+                    Some(&edge.target),
+                    Some(&edge.id),
+                    format!("Unknown input {} of {}", edge.target_handle, edge.target),
+                ));
+            }
+        }
+        graph
+            .entry(edge.source.as_str())
+            .or_default()
+            .push(edge.target.as_str());
+    }
+
+    // required inputs
+    for node in flow.nodes.iter() {
+        let Some(required_inputs) = defs
+            .get(&node.name)
+            .and_then(|def| def.required_inputs.as_ref())
+        else {
+            continue;
+        };
+        for ch in required_inputs {
+            let connected = flow
+                .edges
+                .iter()
+                .any(|edge| edge.target == node.id && edge.target_handle == *ch);
+            if !connected {
+                problems.push(AgentFlowProblem::warning(
+                    Some(&node.id),
+                    None,
+                    format!("Input {} is not connected", ch),
+                ));
+            }
+        }
+    }
+
+    // boards
+    let board_names = |def_name: &str| -> HashSet<String> {
+        flows
+            .values()
+            .filter(|f| f.name != flow.name)
+            .chain(std::iter::once(flow))
+            .flat_map(|f| f.nodes.iter())
+            .filter(|node| node.name == def_name)
+            .filter_map(|node| board_name(node.config.as_ref()))
+            .collect()
+    };
+    let written = board_names(BOARD_IN_DEF_NAME);
+    let read = board_names(BOARD_OUT_DEF_NAME);
+    for node in flow.nodes.iter() {
+        let Some(name) = board_name(node.config.as_ref()) else {
+            continue;
+        };
+        if node.name == BOARD_IN_DEF_NAME && !read.contains(&name) {
+            problems.push(AgentFlowProblem::warning(
+                Some(&node.id),
+                None,
+                format!("Board {} is never read", name),
+            ));
+        } else if node.name == BOARD_OUT_DEF_NAME && !written.contains(&name) {
+            // command agents can still write it with .BOARD
+            problems.push(AgentFlowProblem::warning(
+                Some(&node.id),
+                None,
+                format!("Board {} is never written by {}", name, BOARD_IN_DEF_NAME),
+            ));
+        }
+    }
+
+    // cycles, in the graph without the agents that break them
+    let breaker = |id: &str| {
+        node_defs
+            .get(id)
+            .is_some_and(|name| CYCLE_BREAKERS.contains(name))
+    };
+    for node in flow.nodes.iter() {
+        if breaker(&node.id) {
+            continue;
+        }
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([node.id.as_str()]);
+        let mut cyclic = false;
+        while let Some(id) = queue.pop_front() {
+            for next in graph.get(id).into_iter().flatten() {
+                if *next == node.id {
+                    cyclic = true;
+                    break;
+                }
+                if !breaker(next) && visited.insert(*next) {
+                    queue.push_back(next);
+                }
+            }
+            if cyclic {
+                break;
+            }
+        }
+        if cyclic {
+            problems.push(AgentFlowProblem::warning(
+                Some(&node.id),
+                None,
+                format!("In a cycle without any of {}", CYCLE_BREAKERS.join(", ")),
+            ));
+        }
+    }
+
+    problems
+}
+
+fn board_name(config: Option<&AgentConfig>) -> Option<String> {
+    config
+        .and_then(|c| c.get_string(CONFIG_BOARD_NAME))
+        .filter(|name| !name.is_empty() && name != "*")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemnk::agent::builtins;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_agent_flow() {
+        let mut defs = AgentDefinitions::new();
+        builtins::init_agent_defs(&mut defs);
+
+        let flow: AgentFlow = serde_json::from_value(json!({
+            "name": "main",
+            "nodes": [
+                { "id": "a", "name": "$string_join", "enabled": true },
+                { "id": "b", "name": "$unknown", "enabled": true },
+                { "id": "b", "name": "$board_in", "enabled": true, "config": { "$board": "notes" } },
+                { "id": "c", "name": "$template_string", "enabled": true, "config": { "template": "{{value}}" } },
+                { "id": "d", "name": "$template_string", "enabled": true, "config": { "template": "{{value}}" } },
+            ],
+            "edges": [
+                { "id": "e1", "source": "a", "source_handle": "string", "target": "x", "target_handle": "data" },
+                { "id": "e2", "source": "c", "source_handle": "string", "target": "d", "target_handle": "data" },
+                { "id": "e3", "source": "d", "source_handle": "string", "target": "c", "target_handle": "data" },
+            ],
+        }))
+        .unwrap();
+
+        let problems = validate_agent_flow(&flow, &AgentFlows::new(), &defs);
+        let messages = |node_id: &str| {
+            problems
+                .iter()
+                .filter(|p| p.node_id.as_deref() == Some(node_id))
+                .map(|p| p.message.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages("a"),
+            vec![
+                "Edge e1 refers to a missing node x",
+                "Input strings is not connected"
+            ]
+        );
+        assert!(messages("b").contains(&"Duplicate node id b"));
+        assert!(messages("b").contains(&"Unknown agent definition $unknown"));
+        assert!(messages("b").contains(&"Board notes is never read"));
+        assert_eq!(messages("c").len(), 1);
+        assert!(messages("c")[0].starts_with("In a cycle"));
+
+        // a delay in the cycle
+        let mut flow = flow;
+        flow.nodes[4].name = "$delay".into();
+        flow.nodes[4].config = None;
+        let problems = validate_agent_flow(&flow, &AgentFlows::new(), &defs);
+        assert!(!problems.iter().any(|p| p.message.starts_with("In a cycle")));
+    }
+}
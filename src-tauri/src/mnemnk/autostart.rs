@@ -1,4 +1,4 @@
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
 use tauri::{AppHandle, Manager};
@@ -7,7 +7,7 @@ use tauri_plugin_autostart::{MacosLauncher, ManagerExt};
 use crate::mnemnk::settings::CoreSettings;
 
 pub fn init(app: &AppHandle) -> Result<()> {
-    let setting = app.state::<Mutex<CoreSettings>>();
+    let setting = app.state::<Arc<Mutex<CoreSettings>>>();
     let is_autostart;
     {
         let setting = setting.lock().unwrap();
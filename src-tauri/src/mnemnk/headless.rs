@@ -0,0 +1,297 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::{bail, Context as _, Result};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::mnemnk::agent::{
+    self, AgentConfig, AgentConfigs, AgentDefinitions, AgentHost, CommandChild, CommandEvent,
+};
+use crate::mnemnk::secrets::SecretStore;
+use crate::mnemnk::settings::{self, CoreSettings};
+use crate::mnemnk::store::{self, MnemnkDatabase};
+
+const SETTINGS_JSON: &str = "settings.json";
+
+// Headless runtime
+//
+// Runs the agent flows under a mnemnk directory without any Tauri window.
+// Settings are read from `<dir>/settings.json` (the same "core" / "agents" layout as the app),
+// and the events that the app would send to the frontend are written to a JSONL sink.
+
+pub struct HeadlessOptions {
+    // mnemnk directory holding agents/, agent_flows/ and data/
+    pub dir: PathBuf,
+
+    // JSONL file for emitted events. "-" writes to stdout, None writes to the log.
+    pub events: Option<String>,
+}
+
+impl HeadlessOptions {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut dir = None;
+        let mut events = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--dir" => dir = Some(args.next().context("--dir requires a path")?),
+                "--events" => events = Some(args.next().context("--events requires a path")?),
+                _ => bail!("Unknown argument: {}", arg),
+            }
+        }
+
+        let dir = dir.context("Usage: mnemnk-headless --dir <mnemnk_dir> [--events <file|->]")?;
+
+        Ok(Self {
+            dir: PathBuf::from(dir),
+            events,
+        })
+    }
+}
+
+pub async fn run(options: HeadlessOptions) -> Result<()> {
+    let host = Arc::new(HeadlessHost::new(options.dir, options.events)?);
+
+    let data_dir = host.mnemnk_dir.join("data");
+    if !data_dir.exists() {
+        std::fs::create_dir_all(&data_dir).context("Failed to create data directory")?;
+    }
+    let store = store::open(data_dir, host.core_settings.clone())
+        .await
+        .context("Failed to initialize store")?;
+    let _ = host.store.set(store.clone());
+    store::start_auto_backup(&store).await?;
+
+    let env = agent::init_env(host.clone())?;
+    agent::ready_env(&env)?;
+    log::info!("Mnemnk headless runtime is ready.");
+
+    tokio::signal::ctrl_c()
+        .await
+        .context("Failed to wait for ctrl-c")?;
+
+    log::info!("Exiting Mnemnk headless runtime...");
+    env.quit();
+    store::close(&store).await;
+
+    Ok(())
+}
+
+pub struct HeadlessHost {
+    mnemnk_dir: PathBuf,
+
+    // raw "agents" value of settings.json, merged with the defs in init_agent_global_configs
+    agents_value: Option<Value>,
+
+    // secrets.enc in the mnemnk directory
+    secrets: SecretStore,
+
+    core_settings: Arc<Mutex<CoreSettings>>,
+    agent_configs: Mutex<AgentConfigs>,
+    store: OnceLock<MnemnkDatabase>,
+    sink: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl HeadlessHost {
+    pub fn new(mnemnk_dir: PathBuf, events: Option<String>) -> Result<Self> {
+        if !mnemnk_dir.exists() {
+            std::fs::create_dir_all(&mnemnk_dir).context("Failed to create mnemnk directory")?;
+        }
+
+        let mut settings_value: Value = Value::Null;
+        let settings_path = mnemnk_dir.join(SETTINGS_JSON);
+        if settings_path.exists() {
+            let content = std::fs::read_to_string(&settings_path)
+                .with_context(|| format!("Failed to read {}", settings_path.display()))?;
+            settings_value = serde_json::from_str(&content)
+                .with_context(|| format!("Invalid JSON {}", settings_path.display()))?;
+        }
+        let core_settings = settings::load_core_settings(settings_value.get("core").cloned())?;
+
+        let secrets = SecretStore::open_dir(&mnemnk_dir)?;
+
+        let sink: Option<Mutex<Box<dyn Write + Send>>> = match events.as_deref() {
+            None => None,
+            Some("-") => Some(Mutex::new(Box::new(std::io::stdout()))),
+            Some(path) => {
+                let file = File::options()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open {}", path))?;
+                Some(Mutex::new(Box::new(file)))
+            }
+        };
+
+        Ok(Self {
+            mnemnk_dir,
+            agents_value: settings_value.get("agents").cloned(),
+            secrets,
+            core_settings: Arc::new(Mutex::new(core_settings)),
+            agent_configs: Default::default(),
+            store: OnceLock::new(),
+            sink,
+        })
+    }
+}
+
+impl AgentHost for HeadlessHost {
+    fn mnemnk_dir(&self) -> Option<PathBuf> {
+        Some(self.mnemnk_dir.clone())
+    }
+
+    fn init_agent_global_configs(&self, defs: &AgentDefinitions) -> Result<()> {
+        let configs = settings::load_agent_global_configs(self.agents_value.clone(), defs);
+        *self.agent_configs.lock().unwrap() = configs;
+        Ok(())
+    }
+
+    fn agent_global_config(&self, def_name: &str) -> Option<AgentConfig> {
+        let configs = self.agent_configs.lock().unwrap();
+        configs.get(def_name).cloned()
+    }
+
+    fn secret(&self, name: &str) -> Option<String> {
+        self.secrets.get(name)
+    }
+
+    fn set_secret(&self, name: &str, value: &str) -> Result<()> {
+        self.secrets.set(name, value)
+    }
+
+    fn store(&self) -> Option<MnemnkDatabase> {
+        self.store.get().cloned()
+    }
+
+    fn emit(&self, event: &str, payload: Value) -> Result<()> {
+        let Some(sink) = &self.sink else {
+            log::info!("{} {}", event, payload);
+            return Ok(());
+        };
+        let line = json!({ "event": event, "payload": payload }).to_string();
+        let mut sink = sink.lock().unwrap();
+        writeln!(sink, "{}", line).context("Failed to write event")?;
+        sink.flush().context("Failed to flush events")?;
+        Ok(())
+    }
+
+    fn spawn_command(
+        &self,
+        cmd: &str,
+        args: &[String],
+        dir: &str,
+    ) -> Result<(mpsc::Receiver<CommandEvent>, Box<dyn CommandChild>)> {
+        let mut child = tokio::process::Command::new(cmd)
+            .args(args)
+            .current_dir(dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to spawn {}", cmd))?;
+
+        let mut stdin = child.stdin.take().context("Failed to open stdin")?;
+        let stdout = child.stdout.take().context("Failed to open stdout")?;
+        let stderr = child.stderr.take().context("Failed to open stderr")?;
+
+        let (tx, rx) = mpsc::channel(64);
+
+        // stdin writer
+        let (stdin_tx, mut stdin_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        tauri::async_runtime::spawn(async move {
+            while let Some(buf) = stdin_rx.recv().await {
+                if let Err(e) = stdin.write_all(&buf).await {
+                    log::error!("Failed to write to stdin: {}", e);
+                    break;
+                }
+                stdin.flush().await.unwrap_or_default();
+            }
+        });
+
+        // stdout / stderr readers
+        let stdout_tx = tx.clone();
+        let stdout_reader = tauri::async_runtime::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if stdout_tx
+                    .send(CommandEvent::Stdout(line.into_bytes()))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        let stderr_tx = tx.clone();
+        let stderr_reader = tauri::async_runtime::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if stderr_tx
+                    .send(CommandEvent::Stderr(line.into_bytes()))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        // wait for the process, and report termination after the remaining output
+        let (kill_tx, kill_rx) = oneshot::channel::<()>();
+        tauri::async_runtime::spawn(async move {
+            let code = tokio::select! {
+                status = child.wait() => match status {
+                    Ok(status) => status.code(),
+                    Err(e) => {
+                        let _ = tx.send(CommandEvent::Error(e.to_string())).await;
+                        None
+                    }
+                },
+                _ = kill_rx => {
+                    child.kill().await.unwrap_or_else(|e| {
+                        log::error!("Failed to kill command: {}", e);
+                    });
+                    None
+                }
+            };
+            let _ = stdout_reader.await;
+            let _ = stderr_reader.await;
+            let _ = tx.send(CommandEvent::Terminated(code)).await;
+        });
+
+        Ok((
+            rx,
+            Box::new(HeadlessCommandChild {
+                stdin_tx,
+                kill_tx: Some(kill_tx),
+            }),
+        ))
+    }
+}
+
+struct HeadlessCommandChild {
+    stdin_tx: mpsc::UnboundedSender<Vec<u8>>,
+    kill_tx: Option<oneshot::Sender<()>>,
+}
+
+impl CommandChild for HeadlessCommandChild {
+    fn write(&mut self, buf: &[u8]) -> Result<()> {
+        self.stdin_tx
+            .send(buf.to_vec())
+            .context("Command stdin is closed")
+    }
+
+    fn kill(mut self: Box<Self>) -> Result<()> {
+        if let Some(kill_tx) = self.kill_tx.take() {
+            let _ = kill_tx.send(());
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
 pub mod agent;
 pub mod autostart;
+pub mod headless;
+pub mod secrets;
 pub mod settings;
 pub mod shortcut;
 pub mod store;
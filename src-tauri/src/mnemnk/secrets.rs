@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context as _, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::mnemnk::agent::{AgentConfig, AgentValue};
+
+/// Prefix of the config values referring to a secret, such as `"secret:openai_api_key"`.
+pub const SECRET_PREFIX: &str = "secret:";
+
+const SECRETS_FILE: &str = "secrets.enc";
+const KEY_FILE: &str = "secret.key";
+
+/// Environment variable holding the passphrase, used instead of the key file when it is set.
+pub const PASSPHRASE_ENV: &str = "MNEMNK_SECRETS_PASSPHRASE";
+
+const FORMAT_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+
+// what the key of the file is made from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Kdf {
+    KeyFile,
+    Argon2id,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SecretsFile {
+    version: u32,
+    kdf: Kdf,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+pub enum SecretKeySource {
+    /// 32 random bytes in a file, created on first use.
+    KeyFile(PathBuf),
+
+    /// A passphrase given by the user, stretched with Argon2id.
+    Passphrase(String),
+}
+
+/// Secrets encrypted with ChaCha20-Poly1305 in a single file.
+///
+/// Values are never written in plain text. Agent configs refer to them by name, and they are
+/// resolved only when the config is handed to the agent.
+pub struct SecretStore {
+    path: PathBuf,
+    key_source: SecretKeySource,
+    salt: Mutex<Vec<u8>>,
+    secrets: Mutex<BTreeMap<String, String>>,
+}
+
+impl SecretStore {
+    /// Opens the store in the directory, with the passphrase in `MNEMNK_SECRETS_PASSPHRASE` if it
+    /// is set, or the key file next to the store otherwise.
+    pub fn open_dir(dir: &Path) -> Result<Self> {
+        let key_source = match std::env::var(PASSPHRASE_ENV) {
+            Ok(passphrase) if !passphrase.is_empty() => SecretKeySource::Passphrase(passphrase),
+            _ => SecretKeySource::KeyFile(dir.join(KEY_FILE)),
+        };
+        Self::open(dir.join(SECRETS_FILE), key_source)
+    }
+
+    pub fn open(path: PathBuf, key_source: SecretKeySource) -> Result<Self> {
+        let store = Self {
+            path,
+            key_source,
+            salt: Mutex::new(Vec::new()),
+            secrets: Mutex::new(BTreeMap::new()),
+        };
+        if store.path.exists() {
+            store.load()?;
+        }
+        Ok(store)
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.secrets.lock().unwrap().get(name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.secrets.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn set(&self, name: &str, value: &str) -> Result<()> {
+        if name.is_empty() {
+            bail!("Secret name is empty");
+        }
+        self.secrets
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), value.to_string());
+        self.save()
+    }
+
+    pub fn remove(&self, name: &str) -> Result<()> {
+        self.secrets.lock().unwrap().remove(name);
+        self.save()
+    }
+
+    fn load(&self) -> Result<()> {
+        let content = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        let file: SecretsFile = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid secrets file {}", self.path.display()))?;
+        if file.version != FORMAT_VERSION {
+            bail!("Unsupported secrets file version: {}", file.version);
+        }
+        if file.kdf != self.kdf() {
+            bail!(
+                "{} is encrypted with a {}",
+                self.path.display(),
+                match file.kdf {
+                    Kdf::KeyFile => "key file",
+                    Kdf::Argon2id => "passphrase",
+                }
+            );
+        }
+
+        let salt = STANDARD.decode(&file.salt).context("Invalid salt")?;
+        let nonce = STANDARD.decode(&file.nonce).context("Invalid nonce")?;
+        let data = STANDARD.decode(&file.data).context("Invalid data")?;
+        if nonce.len() != 12 {
+            bail!("Invalid nonce");
+        }
+
+        let cipher = ChaCha20Poly1305::new(&self.key(&salt, false)?);
+        let plain = cipher
+            .decrypt(Nonce::from_slice(&nonce), data.as_ref())
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt secrets: wrong key or passphrase"))?;
+        let secrets: BTreeMap<String, String> =
+            serde_json::from_slice(&plain).context("Invalid secrets")?;
+
+        *self.salt.lock().unwrap() = salt;
+        *self.secrets.lock().unwrap() = secrets;
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let plain = serde_json::to_vec(&*self.secrets.lock().unwrap())?;
+
+        let salt = {
+            let mut salt = self.salt.lock().unwrap();
+            if self.kdf() == Kdf::Argon2id && salt.is_empty() {
+                let mut bytes = [0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut bytes);
+                *salt = bytes.to_vec();
+            }
+            salt.clone()
+        };
+
+        let cipher = ChaCha20Poly1305::new(&self.key(&salt, true)?);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let data = cipher
+            .encrypt(&nonce, plain.as_ref())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt secrets"))?;
+
+        let file = SecretsFile {
+            version: FORMAT_VERSION,
+            kdf: self.kdf(),
+            salt: STANDARD.encode(&salt),
+            nonce: STANDARD.encode(nonce),
+            data: STANDARD.encode(data),
+        };
+        write_private(&self.path, serde_json::to_string_pretty(&file)?.as_bytes())
+    }
+
+    fn kdf(&self) -> Kdf {
+        match self.key_source {
+            SecretKeySource::KeyFile(_) => Kdf::KeyFile,
+            SecretKeySource::Passphrase(_) => Kdf::Argon2id,
+        }
+    }
+
+    fn key(&self, salt: &[u8], create: bool) -> Result<Key> {
+        match &self.key_source {
+            SecretKeySource::KeyFile(path) => {
+                if !path.exists() {
+                    if !create {
+                        bail!("Key file not found: {}", path.display());
+                    }
+                    let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+                    write_private(path, &key)?;
+                    return Ok(key);
+                }
+                let bytes = std::fs::read(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                if bytes.len() != 32 {
+                    bail!("Invalid key file: {}", path.display());
+                }
+                Ok(*Key::from_slice(&bytes))
+            }
+            SecretKeySource::Passphrase(passphrase) => {
+                let mut key = Key::default();
+                argon2::Argon2::default()
+                    .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                    .map_err(|e| anyhow::anyhow!("Failed to derive key: {}", e))?;
+                Ok(key)
+            }
+        }
+    }
+}
+
+// writes the file readable only by the user where the platform supports it
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    std::io::Write::write_all(&mut file, contents)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Name of the secret referred to by the value, if it is a reference.
+pub fn secret_ref(value: &AgentValue) -> Option<&str> {
+    value.as_str().and_then(|s| s.strip_prefix(SECRET_PREFIX))
+}
+
+/// The config with the references to secrets replaced by their values.
+pub fn resolve_secrets(
+    config: &AgentConfig,
+    secret: impl Fn(&str) -> Option<String>,
+) -> AgentConfig {
+    let mut resolved = AgentConfig::default();
+    for (key, value) in config {
+        match secret_ref(value) {
+            Some(name) => {
+                let value = secret(name).unwrap_or_else(|| {
+                    log::warn!("Secret not found: {}", name);
+                    String::new()
+                });
+                resolved.set(key.clone(), AgentValue::new_string(value));
+            }
+            None => resolved.set(key.clone(), value.clone()),
+        }
+    }
+    resolved
+}
+
+// Tauri
+
+pub fn init(app: &AppHandle) -> Result<()> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .context("Failed to get app data directory")?;
+    let store = SecretStore::open_dir(&dir)?;
+    app.manage(store);
+    Ok(())
+}
+
+pub fn get_secret(app: &AppHandle, name: &str) -> Option<String> {
+    app.try_state::<SecretStore>()
+        .and_then(|store| store.get(name))
+}
+
+pub fn set_secret(app: &AppHandle, name: &str, value: &str) -> Result<()> {
+    app.try_state::<SecretStore>()
+        .context("Secret store is not available")?
+        .set(name, value)
+}
+
+#[tauri::command]
+pub fn get_secret_names_cmd(store: State<SecretStore>) -> Vec<String> {
+    store.names()
+}
+
+#[tauri::command]
+pub fn set_secret_cmd(
+    store: State<SecretStore>,
+    name: String,
+    value: String,
+) -> Result<(), String> {
+    store.set(&name, &value).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn remove_secret_cmd(store: State<SecretStore>, name: String) -> Result<(), String> {
+    store.remove(&name).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_store() {
+        let dir = std::env::temp_dir().join(format!("mnemnk-secrets-test-{}", std::process::id()));
+        let path = dir.join(SECRETS_FILE);
+
+        let store =
+            SecretStore::open(path.clone(), SecretKeySource::Passphrase("pass".into())).unwrap();
+        store.set("api_key", "sk-123").unwrap();
+
+        // the value is not in the file
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("sk-123"));
+
+        let store =
+            SecretStore::open(path.clone(), SecretKeySource::Passphrase("pass".into())).unwrap();
+        assert_eq!(store.get("api_key").as_deref(), Some("sk-123"));
+        assert!(SecretStore::open(path.clone(), SecretKeySource::Passphrase("x".into())).is_err());
+        assert!(SecretStore::open(path, SecretKeySource::KeyFile(dir.join(KEY_FILE))).is_err());
+
+        let mut config = AgentConfig::default();
+        config.set("api_key".into(), AgentValue::new_string("secret:api_key"));
+        config.set("model".into(), AgentValue::new_string("gpt"));
+        let resolved = resolve_secrets(&config, |name| store.get(name));
+        assert_eq!(resolved.get_string("api_key").as_deref(), Some("sk-123"));
+        assert_eq!(resolved.get_string("model").as_deref(), Some("gpt"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -4,15 +4,22 @@ use serde_json::Value;
 use std::{
     collections::HashMap,
     path::PathBuf,
-    sync::{LazyLock, Mutex},
+    sync::{Arc, LazyLock, Mutex},
 };
 use tauri::{AppHandle, Manager, State};
+use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
 use tauri_plugin_store::StoreExt;
 
-use crate::mnemnk::agent::{AgentConfig, AgentConfigs, AgentDefinitions};
+use crate::mnemnk::agent::{
+    AgentConfig, AgentConfigs, AgentDefinition, AgentDefinitions, AgentEnv, AgentValue,
+};
+use crate::mnemnk::secrets::{self, SecretStore, SECRET_PREFIX};
 
 const SETTINGS_JSON: &str = "settings.json";
 
+// secret entries of the agent global configs stored by older versions, moved into the secret store
+const SECRETS_JSON: &str = "secrets.json";
+
 pub fn init(app: &AppHandle) -> Result<()> {
     init_core_settings(app)?;
     Ok(())
@@ -21,7 +28,7 @@ pub fn init(app: &AppHandle) -> Result<()> {
 pub fn save(app: &AppHandle) -> Result<()> {
     let store = app.store(SETTINGS_JSON)?;
 
-    let core_settings = app.state::<Mutex<CoreSettings>>();
+    let core_settings = app.state::<Arc<Mutex<CoreSettings>>>();
     let settings_json;
     {
         let core_settings = core_settings.lock().unwrap();
@@ -46,7 +53,7 @@ pub fn quit(_app: &AppHandle) {
 
 pub fn mnemnk_dir(app: &AppHandle) -> Option<String> {
     let mut mnemnk_dir;
-    let settings = app.state::<Mutex<CoreSettings>>();
+    let settings = app.state::<Arc<Mutex<CoreSettings>>>();
     {
         let settings = settings.lock().unwrap();
         mnemnk_dir = settings.mnemnk_dir.clone();
@@ -134,25 +141,29 @@ impl Default for CoreSettings {
 fn init_core_settings(app: &AppHandle) -> Result<()> {
     let store = app.store(SETTINGS_JSON)?;
 
-    let core_settings: CoreSettings;
-    if let Some(store_value) = store.get("core") {
-        let mut value = serde_json::to_value(CoreSettings::default())
-            .context("Failed to serialize default core settings")?;
-        json_merge(&mut value, store_value.clone());
-
-        core_settings = serde_json::from_value(value).unwrap_or_else(|e| {
-            log::error!("Failed to load core settings: {}", e);
-            CoreSettings::default()
-        });
-    } else {
-        core_settings = CoreSettings::default();
-    }
+    let core_settings = load_core_settings(store.get("core"))?;
 
-    app.manage(Mutex::new(core_settings));
+    app.manage(Arc::new(Mutex::new(core_settings)));
 
     Ok(())
 }
 
+/// Builds core settings from a stored "core" value, filling in missing fields with the defaults.
+pub fn load_core_settings(store_value: Option<Value>) -> Result<CoreSettings> {
+    let Some(store_value) = store_value else {
+        return Ok(CoreSettings::default());
+    };
+
+    let mut value = serde_json::to_value(CoreSettings::default())
+        .context("Failed to serialize default core settings")?;
+    json_merge(&mut value, store_value);
+
+    Ok(serde_json::from_value(value).unwrap_or_else(|e| {
+        log::error!("Failed to load core settings: {}", e);
+        CoreSettings::default()
+    }))
+}
+
 fn json_merge(a: &mut Value, b: Value) {
     if let Value::Object(a) = a {
         if let Value::Object(b) = b {
@@ -170,7 +181,7 @@ fn json_merge(a: &mut Value, b: Value) {
 }
 
 #[tauri::command]
-pub fn get_core_settings_cmd(settings: State<Mutex<CoreSettings>>) -> Result<Value, String> {
+pub fn get_core_settings_cmd(settings: State<Arc<Mutex<CoreSettings>>>) -> Result<Value, String> {
     let settings = settings.lock().unwrap();
     let json = serde_json::to_value(&*settings).map_err(|e| e.to_string())?;
     Ok(json)
@@ -179,7 +190,7 @@ pub fn get_core_settings_cmd(settings: State<Mutex<CoreSettings>>) -> Result<Val
 #[tauri::command]
 pub fn set_core_settings_cmd(
     app: AppHandle,
-    settings: State<Mutex<CoreSettings>>,
+    settings: State<Arc<Mutex<CoreSettings>>>,
     new_settings: Value,
 ) -> Result<(), String> {
     if new_settings.is_null() {
@@ -205,9 +216,67 @@ pub fn set_core_settings_cmd(
 
 pub fn init_agent_global_configs(app: &AppHandle, agent_defs: &AgentDefinitions) -> Result<()> {
     let store = app.store(SETTINGS_JSON)?;
+    let secrets_store = app.store(SECRETS_JSON)?;
+
+    // the configs in memory are kept when the agent definitions are reloaded
+    let agents_value = match app.try_state::<Mutex<AgentConfigs>>() {
+        Some(configs) => Some(serde_json::to_value(&*configs.lock().unwrap())?),
+        None if app.try_state::<SecretStore>().is_some() => {
+            merge_secret_configs(store.get("agents"), secrets_store.get("agents"))
+        }
+        None => {
+            // they would be written into settings.json in plain text otherwise
+            if secrets_store.has("agents") {
+                let message = format!(
+                    "Secret store is not available, secrets in {} are not loaded",
+                    SECRETS_JSON
+                );
+                log::warn!("{}", message);
+                app.dialog()
+                    .message(message)
+                    .kind(MessageDialogKind::Warning)
+                    .show(|_| {});
+            }
+            store.get("agents")
+        }
+    };
+    let mut agent_global_configs = load_agent_global_configs(agents_value, agent_defs);
+
+    // move the secrets still in plain text into the secret store
+    let mut moved = false;
+    if let Some(secret_store) = app.try_state::<SecretStore>() {
+        for (agent_name, config) in agent_global_configs.iter_mut() {
+            moved |= store_secret_config(
+                agent_name,
+                config,
+                agent_defs.get(agent_name),
+                |name, value| secret_store.set(name, value),
+            )?;
+        }
+    }
+    if moved {
+        store.set("agents", serde_json::to_value(&agent_global_configs)?);
+        secrets_store.delete("agents");
+    }
+
+    match app.try_state::<Mutex<AgentConfigs>>() {
+        Some(configs) => *configs.lock().unwrap() = agent_global_configs,
+        None => {
+            app.manage(Mutex::new(agent_global_configs));
+        }
+    }
 
+    Ok(())
+}
+
+/// Builds agent global configs from a stored "agents" value, merged with the global configs
+/// declared in the agent definitions.
+pub fn load_agent_global_configs(
+    store_value: Option<Value>,
+    agent_defs: &AgentDefinitions,
+) -> AgentConfigs {
     let mut agent_global_configs = AgentConfigs::default();
-    if let Some(value) = store.get("agents") {
+    if let Some(value) = store_value {
         agent_global_configs = serde_json::from_value(value).unwrap_or_else(|e| {
             log::error!("Failed to load agent settings: {}", e);
             Default::default()
@@ -233,9 +302,53 @@ pub fn init_agent_global_configs(app: &AppHandle, agent_defs: &AgentDefinitions)
         }
     }
 
-    app.manage(Mutex::new(agent_global_configs));
+    agent_global_configs
+}
 
-    Ok(())
+/// Moves the values of the secret entries into the secret store as `<agent_name>.<key>`, and
+/// replaces them with references to it.
+///
+/// Empty values and the ones already referring to a secret are left as they are. Returns whether
+/// any value has been moved.
+pub fn store_secret_config(
+    agent_name: &str,
+    config: &mut AgentConfig,
+    agent_def: Option<&AgentDefinition>,
+    mut set_secret: impl FnMut(&str, &str) -> Result<()>,
+) -> Result<bool> {
+    let Some(agent_def) = agent_def else {
+        return Ok(false);
+    };
+    let mut moved = false;
+    for key in agent_def.secret_keys() {
+        let Some(value) = config.get_string(key) else {
+            continue;
+        };
+        if value.is_empty() || value.starts_with(SECRET_PREFIX) {
+            continue;
+        }
+        let name = format!("{}.{}", agent_name, key);
+        set_secret(&name, &value)?;
+        config.set(
+            key.to_string(),
+            AgentValue::new_string(format!("{}{}", SECRET_PREFIX, name)),
+        );
+        moved = true;
+    }
+    Ok(moved)
+}
+
+/// Merges the secret entries stored by older versions into the stored "agents" value.
+fn merge_secret_configs(
+    agents_value: Option<Value>,
+    secrets_value: Option<Value>,
+) -> Option<Value> {
+    let Some(secrets_value) = secrets_value else {
+        return agents_value;
+    };
+    let mut value = agents_value.unwrap_or_else(|| Value::Object(Default::default()));
+    json_merge(&mut value, secrets_value);
+    Some(value)
 }
 
 pub fn get_agent_global_config(app: &AppHandle, agent_name: &str) -> Option<AgentConfig> {
@@ -244,6 +357,21 @@ pub fn get_agent_global_config(app: &AppHandle, agent_name: &str) -> Option<Agen
     configs.get(agent_name).cloned()
 }
 
+/// Sets the given values over the global configs of the agents, keeping the other values.
+pub fn merge_agent_global_configs(app: &AppHandle, configs: AgentConfigs) -> Result<()> {
+    {
+        let agent_configs = app.state::<Mutex<AgentConfigs>>();
+        let mut agent_configs = agent_configs.lock().unwrap();
+        for (agent_name, config) in configs {
+            let agent_config = agent_configs.entry(agent_name).or_default();
+            for (key, value) in config {
+                agent_config.set(key, value);
+            }
+        }
+    }
+    save(app)
+}
+
 #[tauri::command]
 pub fn get_agent_global_configs_cmd(
     agent_configs: State<Mutex<AgentConfigs>>,
@@ -260,7 +388,19 @@ pub fn set_agent_global_config_cmd(
     agent_name: String,
     agent_config: Value,
 ) -> Result<(), String> {
-    let config: AgentConfig = serde_json::from_value(agent_config).map_err(|e| e.to_string())?;
+    let mut config: AgentConfig =
+        serde_json::from_value(agent_config).map_err(|e| e.to_string())?;
+    if let Some(env) = app.try_state::<Arc<AgentEnv>>() {
+        // fails without the secret store, so that secrets are never saved in plain text
+        let defs = env.defs.lock().unwrap();
+        store_secret_config(
+            &agent_name,
+            &mut config,
+            defs.get(&agent_name),
+            |name, value| secrets::set_secret(&app, name, value),
+        )
+        .map_err(|e| e.to_string())?;
+    }
     {
         let mut configs = agent_configs.lock().unwrap();
         configs.insert(agent_name, config);
@@ -268,3 +408,45 @@ pub fn set_agent_global_config_cmd(
     save(&app).map_err(|e| e.to_string())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemnk::agent::AgentConfigEntry;
+
+    #[test]
+    fn test_store_secret_config() {
+        let def = AgentDefinition::new("Builtin", "$api", None).with_global_config(vec![
+            (
+                "address".into(),
+                AgentConfigEntry::new(AgentValue::new_string(""), "string"),
+            ),
+            (
+                "api_key".into(),
+                AgentConfigEntry::new(AgentValue::new_string(""), "string").with_secret(),
+            ),
+        ]);
+        let mut config = AgentConfig::default();
+        config.set("address".into(), AgentValue::new_string("localhost:3296"));
+        config.set("api_key".into(), AgentValue::new_string("key"));
+
+        let mut secrets = HashMap::new();
+        let mut set_secret = |name: &str, value: &str| {
+            secrets.insert(name.to_string(), value.to_string());
+            Ok(())
+        };
+        assert!(store_secret_config("$api", &mut config, Some(&def), &mut set_secret).unwrap());
+        assert_eq!(
+            config.get_string("address").as_deref(),
+            Some("localhost:3296")
+        );
+        assert_eq!(
+            config.get_string("api_key").as_deref(),
+            Some("secret:$api.api_key")
+        );
+
+        // references are not moved again
+        assert!(!store_secret_config("$api", &mut config, Some(&def), &mut set_secret).unwrap());
+        assert_eq!(secrets.get("$api.api_key").map(String::as_str), Some("key"));
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
 use tauri::{AppHandle, Manager};
@@ -8,7 +8,7 @@ use crate::mnemnk;
 use crate::mnemnk::settings::CoreSettings;
 
 pub fn init(app: &AppHandle) -> Result<()> {
-    let settings = app.state::<Mutex<CoreSettings>>();
+    let settings = app.state::<Arc<Mutex<CoreSettings>>>();
     let shortcut_key;
     {
         let settings = settings.lock().unwrap();
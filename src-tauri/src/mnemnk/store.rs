@@ -1,7 +1,7 @@
 use std::fs;
 use std::io::{BufRead, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{LazyLock, Mutex};
+use std::sync::{Arc, LazyLock, Mutex};
 use std::time::Duration;
 
 use anyhow::{bail, Context as _, Result};
@@ -145,8 +145,11 @@ enum StoreEvent {
     },
 }
 
+#[derive(Clone)]
 pub struct MnemnkDatabase {
     db: Surreal<Db>,
+    data_dir: PathBuf,
+    settings: Arc<Mutex<CoreSettings>>,
     event_tx: mpsc::Sender<StoreEvent>,
 }
 
@@ -155,27 +158,42 @@ const MNEMNK_DB: &str = "mnemnk";
 
 pub async fn init(app: &AppHandle) -> Result<()> {
     let data_dir = data_dir(app).context("data_dir is not set")?;
-    let db_path = PathBuf::from(&data_dir).join("store.db");
+    let settings = app.state::<Arc<Mutex<CoreSettings>>>().inner().clone();
+
+    let state = open(data_dir, settings).await?;
+    app.manage(state.clone());
+
+    start_auto_backup(&state).await?;
+
+    Ok(())
+}
+
+// Open the store under the given data directory and start its event loop.
+pub async fn open(data_dir: PathBuf, settings: Arc<Mutex<CoreSettings>>) -> Result<MnemnkDatabase> {
+    let db_path = data_dir.join("store.db");
 
     if !db_path.exists() {
         // Check if the restore file exists
         let restore_path = data_dir.join("restore.surql");
         if restore_path.exists() {
             log::info!("Found restore file: {}", restore_path.display());
-            restore_from_backup(app, &restore_path).await?;
+            restore_from_backup(&data_dir, &restore_path).await?;
             std::process::exit(0);
         }
     }
 
     let db = try_init_database(&db_path).await?;
 
-    let event_tx = start_event_loop(app);
-
-    app.manage(MnemnkDatabase { db, event_tx });
-
-    start_auto_backup(app).await?;
+    let (event_tx, event_rx) = mpsc::channel::<StoreEvent>(1000);
+    let state = MnemnkDatabase {
+        db,
+        data_dir,
+        settings,
+        event_tx,
+    };
+    start_event_loop(state.clone(), event_rx);
 
-    Ok(())
+    Ok(state)
 }
 
 async fn try_init_database(path: &Path) -> Result<Surreal<Db>> {
@@ -213,9 +231,8 @@ async fn try_init_database(path: &Path) -> Result<Surreal<Db>> {
     Ok(db)
 }
 
-async fn restore_from_backup(app: &AppHandle, restore_file: &Path) -> Result<Surreal<Db>> {
-    let data_dir = data_dir(app).context("data_dir is not set")?;
-    let db_path = PathBuf::from(&data_dir).join("store.db");
+async fn restore_from_backup(data_dir: &Path, restore_file: &Path) -> Result<Surreal<Db>> {
+    let db_path = data_dir.join("store.db");
 
     log::info!("Create a new database at: {}", db_path.display());
     let db = create_new_database(&db_path).await?;
@@ -234,11 +251,8 @@ async fn create_new_database(db_path: &Path) -> Result<Surreal<Db>> {
     Ok(db)
 }
 
-fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
-    // TODO: the number of events should be configurable
-    let (event_tx, mut event_rx) = mpsc::channel::<StoreEvent>(1000);
-
-    let app_clone = app.clone();
+// TODO: the number of events should be configurable
+fn start_event_loop(state: MnemnkDatabase, mut event_rx: mpsc::Receiver<StoreEvent>) {
     tauri::async_runtime::spawn(async move {
         log::info!("Store event loop started");
 
@@ -247,13 +261,13 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
 
             match event {
                 StoreEvent::Backup { response } => {
-                    let result = process_backup(&app_clone).await;
+                    let result = process_backup(&state).await;
                     response.send(result).unwrap_or_else(|_| {
                         log::error!("Failed to send backup result");
                     });
                 }
                 StoreEvent::CreateEvent { event } => {
-                    let result = process_create_event(&app_clone, event).await;
+                    let result = process_create_event(&state, event).await;
                     // response.send(result).unwrap_or_else(|_| {
                     //     log::error!("Failed to send create_event result");
                     // });
@@ -262,7 +276,7 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
                     });
                 }
                 StoreEvent::DailyStats { response } => {
-                    let result = process_daily_stats(&app_clone).await;
+                    let result = process_daily_stats(&state).await;
                     response.send(result).unwrap_or_else(|_| {
                         log::error!("Failed to send daily stats");
                     });
@@ -274,14 +288,13 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
                     return_before,
                     response,
                 } => {
-                    let result =
-                        process_delete(&app_clone, database, table, key, return_before).await;
+                    let result = process_delete(&state, database, table, key, return_before).await;
                     response.send(result).unwrap_or_else(|e| {
                         log::error!("Failed to send delete result: {}", e);
                     });
                 }
                 StoreEvent::ExportEvents { path, response } => {
-                    let result = process_export_events(&app_clone, path).await;
+                    let result = process_export_events(&state, path).await;
                     response.send(result).unwrap_or_else(|_| {
                         log::error!("Failed to send export events");
                     });
@@ -292,13 +305,13 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
                     day,
                     response,
                 } => {
-                    let result = process_find_events_by_ymd(&app_clone, year, month, day).await;
+                    let result = process_find_events_by_ymd(&state, year, month, day).await;
                     response.send(result).unwrap_or_else(|_| {
                         log::error!("Failed to send find events by ymd");
                     });
                 }
                 StoreEvent::ImportEvents { path, response } => {
-                    let result = process_import_events(&app_clone, path).await;
+                    let result = process_import_events(&state, path).await;
                     response.send(result).unwrap_or_else(|_| {
                         log::error!("Failed to send import events");
                     });
@@ -310,7 +323,7 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
                     value,
                     response,
                 } => {
-                    let result = process_insert(&app_clone, database, table, key, value).await;
+                    let result = process_insert(&state, database, table, key, value).await;
                     response.send(result).unwrap_or_else(|e| {
                         log::error!("Failed to send insert result: {}", e);
                     });
@@ -321,13 +334,13 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
                     bindings,
                     response,
                 } => {
-                    let result = process_query(&app_clone, database, query, bindings).await;
+                    let result = process_query(&state, database, query, bindings).await;
                     response.send(result).unwrap_or_else(|e| {
                         log::error!("Failed to send query result: {}", e);
                     });
                 }
                 StoreEvent::ReindexText { response } => {
-                    let result = process_reindex_text(&app_clone).await;
+                    let result = process_reindex_text(&state).await;
                     response.send(result).unwrap_or_else(|_| {
                         log::error!("Failed to send reindex text");
                     });
@@ -336,13 +349,13 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
                     day_start_hour,
                     response,
                 } => {
-                    let result = process_reindex_ymd(&app_clone, day_start_hour).await;
+                    let result = process_reindex_ymd(&state, day_start_hour).await;
                     response.send(result).unwrap_or_else(|_| {
                         log::error!("Failed to send reindex ymd");
                     });
                 }
                 StoreEvent::SearchEvents { query, response } => {
-                    let result = process_search_events(&app_clone, query).await;
+                    let result = process_search_events(&state, query).await;
                     response.send(result).unwrap_or_else(|_| {
                         log::error!("Failed to send search events");
                     });
@@ -353,7 +366,7 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
                     key,
                     response,
                 } => {
-                    let result = process_select(&app_clone, database, table, key).await;
+                    let result = process_select(&state, database, table, key).await;
                     response.send(result).unwrap_or_else(|e| {
                         log::error!("Failed to send select result: {}", e);
                     });
@@ -372,7 +385,7 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
                     value,
                     response,
                 } => {
-                    let result = process_update(&app_clone, database, table, key, value).await;
+                    let result = process_update(&state, database, table, key, value).await;
                     response.send(result).unwrap_or_else(|e| {
                         log::error!("Failed to send update result: {}", e);
                     });
@@ -386,7 +399,7 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
                     response,
                 } => {
                     let result =
-                        process_update_merge(&app_clone, database, table, key, value, return_after)
+                        process_update_merge(&state, database, table, key, value, return_after)
                             .await;
                     response.send(result).unwrap_or_else(|e| {
                         log::error!("Failed to send update_merge result: {}", e);
@@ -399,7 +412,7 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
                     value,
                     response,
                 } => {
-                    let result = process_upsert(&app_clone, database, table, key, value).await;
+                    let result = process_upsert(&state, database, table, key, value).await;
                     response.send(result).unwrap_or_else(|e| {
                         log::error!("Failed to send upsert result: {}", e);
                     });
@@ -413,7 +426,7 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
                     response,
                 } => {
                     let result =
-                        process_upsert_merge(&app_clone, database, table, key, value, return_after)
+                        process_upsert_merge(&state, database, table, key, value, return_after)
                             .await;
                     response.send(result).unwrap_or_else(|e| {
                         log::error!("Failed to send upsert_merge result: {}", e);
@@ -423,13 +436,14 @@ fn start_event_loop(app: &AppHandle) -> mpsc::Sender<StoreEvent> {
         }
         log::info!("Store event loop terminated");
     });
-
-    event_tx
 }
 
 pub async fn quit(app: &AppHandle) {
     let state = app.state::<MnemnkDatabase>();
+    close(&state).await;
+}
 
+pub async fn close(state: &MnemnkDatabase) {
     let (tx, rx) = oneshot::channel();
     if let Err(e) = state
         .event_tx
@@ -454,14 +468,12 @@ pub async fn quit(app: &AppHandle) {
 }
 
 pub fn delete(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     return_before: bool,
 ) -> Result<Option<serde_json::Value>> {
-    let state = app.state::<MnemnkDatabase>();
-
     let (tx, rx) = std::sync::mpsc::channel();
     let event = StoreEvent::Delete {
         database,
@@ -477,13 +489,12 @@ pub fn delete(
 }
 
 async fn process_delete(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     return_before: bool,
 ) -> Result<Option<serde_json::Value>> {
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
     db.use_db(database).await?;
 
@@ -511,14 +522,12 @@ async fn process_delete(
 }
 
 pub fn insert(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     value: serde_json::Value,
 ) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
-
     let (tx, rx) = std::sync::mpsc::channel();
     let event = StoreEvent::Insert {
         database,
@@ -534,13 +543,12 @@ pub fn insert(
 }
 
 async fn process_insert(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     value: serde_json::Value,
 ) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
     db.use_db(database).await?;
     let _: Option<Record> = db.insert((table, key)).content(value).await?;
@@ -548,13 +556,11 @@ async fn process_insert(
 }
 
 pub fn query(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     query: String,
     bindings: Option<serde_json::Value>,
 ) -> Result<Vec<serde_json::Value>> {
-    let state = app.state::<MnemnkDatabase>();
-
     let (tx, rx) = std::sync::mpsc::channel();
     let event = StoreEvent::Query {
         database,
@@ -569,13 +575,11 @@ pub fn query(
 }
 
 async fn process_query(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     query: String,
     bindings: Option<serde_json::Value>,
 ) -> Result<Vec<serde_json::Value>> {
-    let state = app.state::<MnemnkDatabase>();
-
     // use the database
     let db = &state.db;
     db.use_db(database).await?;
@@ -602,13 +606,11 @@ async fn process_query(
 }
 
 pub fn select(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
 ) -> Result<Option<serde_json::Value>> {
-    let state = app.state::<MnemnkDatabase>();
-
     let (tx, rx) = std::sync::mpsc::channel();
     let event = StoreEvent::Select {
         database,
@@ -623,12 +625,11 @@ pub fn select(
 }
 
 async fn process_select(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
 ) -> Result<Option<serde_json::Value>> {
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
     db.use_db(database).await?;
 
@@ -660,14 +661,12 @@ async fn process_select(
 }
 
 pub fn update(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     value: serde_json::Value,
 ) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
-
     let (tx, rx) = std::sync::mpsc::channel();
     let event = StoreEvent::Update {
         database,
@@ -683,13 +682,12 @@ pub fn update(
 }
 
 async fn process_update(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     value: serde_json::Value,
 ) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
     db.use_db(database).await?;
     let _: Option<Record> = db.update((table, key)).content(value).await?;
@@ -697,15 +695,13 @@ async fn process_update(
 }
 
 pub fn update_merge(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     value: serde_json::Value,
     return_after: bool,
 ) -> Result<Option<serde_json::Value>> {
-    let state = app.state::<MnemnkDatabase>();
-
     let (tx, rx) = std::sync::mpsc::channel();
     let event = StoreEvent::UpdateMerge {
         database,
@@ -722,14 +718,13 @@ pub fn update_merge(
 }
 
 async fn process_update_merge(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     value: serde_json::Value,
     return_after: bool,
 ) -> Result<Option<serde_json::Value>> {
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
     db.use_db(database).await?;
 
@@ -758,14 +753,12 @@ async fn process_update_merge(
 }
 
 pub fn upsert(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     value: serde_json::Value,
 ) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
-
     let (tx, rx) = std::sync::mpsc::channel();
     let event = StoreEvent::Upsert {
         database,
@@ -781,13 +774,12 @@ pub fn upsert(
 }
 
 async fn process_upsert(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     value: serde_json::Value,
 ) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
     db.use_db(database).await?;
     let _: Option<Record> = db.upsert((table, key)).content(value).await?;
@@ -795,15 +787,13 @@ async fn process_upsert(
 }
 
 pub fn upsert_merge(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     value: serde_json::Value,
     return_after: bool,
 ) -> Result<Option<serde_json::Value>> {
-    let state = app.state::<MnemnkDatabase>();
-
     let (tx, rx) = std::sync::mpsc::channel();
     let event = StoreEvent::UpsertMerge {
         database,
@@ -820,14 +810,13 @@ pub fn upsert_merge(
 }
 
 async fn process_upsert_merge(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     database: String,
     table: String,
     key: String,
     value: serde_json::Value,
     return_after: bool,
 ) -> Result<Option<serde_json::Value>> {
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
     db.use_db(database).await?;
 
@@ -855,7 +844,7 @@ async fn process_upsert_merge(
     }
 }
 
-pub fn create_event(app: &AppHandle, data: AgentData) -> Result<()> {
+pub fn create_event(state: &MnemnkDatabase, data: AgentData) -> Result<()> {
     let kind = data.kind;
     let Some(mut map) = data.value.as_object().cloned() else {
         return Err(anyhow::anyhow!("store: data is not an object"));
@@ -877,8 +866,7 @@ pub fn create_event(app: &AppHandle, data: AgentData) -> Result<()> {
     let local_offset = local_dt.offset().local_minus_utc() as i64;
 
     let day_start_hour: u32 = {
-        let settings = app.state::<Mutex<CoreSettings>>();
-        let settings = settings.lock().unwrap();
+        let settings = state.settings.lock().unwrap();
         settings.day_start_hour.unwrap_or(0)
     };
     let (local_y, local_ym, local_ymd) = adjust_local_ymd(local_dt, day_start_hour);
@@ -904,10 +892,10 @@ pub fn create_event(app: &AppHandle, data: AgentData) -> Result<()> {
                 .context("wrong image_id type")?
                 .to_string();
 
-            let app = app.clone();
+            let state = state.clone();
             let kind = kind.clone();
             tauri::async_runtime::spawn(async move {
-                save_image_value(&app, kind, image_id, image)
+                save_image_value(&state, kind, image_id, image)
                     .await
                     .unwrap_or_else(|e| {
                         log::error!("Failed to save image: {}", e);
@@ -933,7 +921,6 @@ pub fn create_event(app: &AppHandle, data: AgentData) -> Result<()> {
 
     // log::debug!("store: create_event: {:?}", event);
 
-    let state = app.state::<MnemnkDatabase>();
     // let (tx, rx) = std::sync::mpsc::channel();
     let create_event = StoreEvent::CreateEvent {
         event,
@@ -955,10 +942,9 @@ pub fn create_event(app: &AppHandle, data: AgentData) -> Result<()> {
     Ok(())
 }
 
-async fn process_create_event(app: &AppHandle, event: Event) -> Result<()> {
+async fn process_create_event(state: &MnemnkDatabase, event: Event) -> Result<()> {
     // log::debug!("store: process_create_event: {:?}", event);
 
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
 
     db.use_db(MNEMNK_DB)
@@ -974,12 +960,12 @@ async fn process_create_event(app: &AppHandle, event: Event) -> Result<()> {
 
 // Image
 async fn save_image_value(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     kind: String,
     image_id: String,
     value: AgentValue,
 ) -> Result<()> {
-    let image_dir = image_dir(app, &kind)?;
+    let image_dir = image_dir(&state.data_dir, &kind)?;
 
     let mut image: Option<PhotonImage> = None;
     if value.is_string() {
@@ -1003,11 +989,10 @@ async fn save_image_value(
 
     let filename = &image_id[9..];
 
-    let settings = app.state::<Mutex<CoreSettings>>();
     let thumbnail_width;
     let thumbnail_height;
     {
-        let settings = settings.lock().unwrap();
+        let settings = state.settings.lock().unwrap();
         thumbnail_width = settings.thumbnail_width.clone();
         thumbnail_height = settings.thumbnail_height.clone();
     }
@@ -1019,16 +1004,12 @@ async fn save_image_value(
     Ok(())
 }
 
-fn image_dir(app: &AppHandle, kind: &str) -> Result<PathBuf> {
-    if let Some(data_dir) = data_dir(app) {
-        let image_dir = PathBuf::from(data_dir).join(kind).join("image");
-        if !image_dir.exists() {
-            std::fs::create_dir_all(&image_dir).context("Failed to create image directory")?;
-        }
-        Ok(image_dir)
-    } else {
-        Err(anyhow::anyhow!("data_dir is not set"))
+fn image_dir(data_dir: &Path, kind: &str) -> Result<PathBuf> {
+    let image_dir = data_dir.join(kind).join("image");
+    if !image_dir.exists() {
+        std::fs::create_dir_all(&image_dir).context("Failed to create image directory")?;
     }
+    Ok(image_dir)
 }
 
 fn make_thumbnail(image: &PhotonImage, width: Option<u32>, height: Option<u32>) -> PhotonImage {
@@ -1093,7 +1074,8 @@ pub fn handle_mimg_protocol(app: &AppHandle, request: Request<Vec<u8>>) -> Respo
             .unwrap();
     }
 
-    let screen_dir = image_dir(app, &kind).unwrap(); // TODO: handle error
+    let state = app.state::<MnemnkDatabase>();
+    let screen_dir = image_dir(&state.data_dir, &kind).unwrap(); // TODO: handle error
 
     let path = screen_dir.join(date).join(format!("{}.png", filename));
     if path.exists() {
@@ -1143,9 +1125,7 @@ pub struct DailyStats {
     count: i32,
 }
 
-async fn daily_stats(app: &AppHandle) -> Result<Vec<DailyStats>> {
-    let state = app.state::<MnemnkDatabase>();
-
+async fn daily_stats(state: &MnemnkDatabase) -> Result<Vec<DailyStats>> {
     let (tx, rx) = oneshot::channel();
     state
         .event_tx
@@ -1155,7 +1135,7 @@ async fn daily_stats(app: &AppHandle) -> Result<Vec<DailyStats>> {
     rx.await.context("Failed to receive daily stats")?
 }
 
-async fn process_daily_stats(app: &AppHandle) -> Result<Vec<DailyStats>> {
+async fn process_daily_stats(state: &MnemnkDatabase) -> Result<Vec<DailyStats>> {
     let sql = r#"
         SELECT
             local_ymd AS date,
@@ -1167,7 +1147,6 @@ async fn process_daily_stats(app: &AppHandle) -> Result<Vec<DailyStats>> {
         ;
     "#;
 
-    let state = app.state::<MnemnkDatabase>();
     let db = state.db.clone();
     db.use_db(MNEMNK_DB).await?;
     let mut result = db.query(sql).await?;
@@ -1176,8 +1155,8 @@ async fn process_daily_stats(app: &AppHandle) -> Result<Vec<DailyStats>> {
 }
 
 #[tauri::command]
-pub async fn daily_stats_cmd(app: AppHandle) -> Result<Vec<DailyStats>, String> {
-    let result = daily_stats(&app).await.map_err(|e| e.to_string())?;
+pub async fn daily_stats_cmd(state: State<'_, MnemnkDatabase>) -> Result<Vec<DailyStats>, String> {
+    let result = daily_stats(&state).await.map_err(|e| e.to_string())?;
     Ok(result)
 }
 
@@ -1204,13 +1183,11 @@ pub struct EventRecord {
 }
 
 async fn find_events_by_ymd(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     year: i32,
     month: i32,
     day: i32,
 ) -> Result<Vec<EventRecordInternal>> {
-    let state = app.state::<MnemnkDatabase>();
-
     let (tx, rx) = oneshot::channel();
     state
         .event_tx
@@ -1226,7 +1203,7 @@ async fn find_events_by_ymd(
 }
 
 async fn process_find_events_by_ymd(
-    app: &AppHandle,
+    state: &MnemnkDatabase,
     year: i32,
     month: i32,
     day: i32,
@@ -1248,7 +1225,6 @@ async fn process_find_events_by_ymd(
         ;
         "#;
     let local_ymd = year * 10000 + month * 100 + day;
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
     db.use_db(MNEMNK_DB).await?;
     let mut result = db.query(sql).bind(("local_ymd", local_ymd)).await?;
@@ -1258,12 +1234,12 @@ async fn process_find_events_by_ymd(
 
 #[tauri::command]
 pub async fn find_events_by_ymd_cmd(
-    app: AppHandle,
+    state: State<'_, MnemnkDatabase>,
     year: i32,
     month: i32,
     day: i32,
 ) -> Result<Vec<EventRecord>, String> {
-    let result = find_events_by_ymd(&app, year, month, day)
+    let result = find_events_by_ymd(&state, year, month, day)
         .await
         .map_err(|e| e.to_string())?;
     let events = result
@@ -1284,15 +1260,15 @@ pub async fn find_events_by_ymd_cmd(
 
 #[tauri::command]
 pub async fn reindex_ymd_cmd(
-    app: AppHandle,
-    settings: State<'_, Mutex<CoreSettings>>,
+    state: State<'_, MnemnkDatabase>,
+    settings: State<'_, Arc<Mutex<CoreSettings>>>,
 ) -> Result<(), String> {
     let day_start_hour;
     {
         let settings = settings.lock().unwrap();
         day_start_hour = settings.day_start_hour.unwrap_or(0);
     }
-    reindex_ymd(&app, day_start_hour)
+    reindex_ymd(&state, day_start_hour)
         .await
         .map_err(|e| e.to_string())?;
     Ok(())
@@ -1311,9 +1287,7 @@ struct LocalYmd {
     local_ymd: i64,
 }
 
-async fn reindex_ymd(app: &AppHandle, day_start_hour: u32) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
-
+async fn reindex_ymd(state: &MnemnkDatabase, day_start_hour: u32) -> Result<()> {
     let (tx, rx) = oneshot::channel();
     state
         .event_tx
@@ -1326,9 +1300,8 @@ async fn reindex_ymd(app: &AppHandle, day_start_hour: u32) -> Result<()> {
     rx.await.context("Failed to receive reindex_ymd")?
 }
 
-async fn process_reindex_ymd(app: &AppHandle, day_start_hour: u32) -> Result<()> {
+async fn process_reindex_ymd(state: &MnemnkDatabase, day_start_hour: u32) -> Result<()> {
     log::info!("store: reindexing local_ymd...");
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
     db.use_db(MNEMNK_DB).await?;
     let mut result = db
@@ -1381,9 +1354,7 @@ struct TextTokens {
     text_tokens: Option<String>,
 }
 
-async fn reindex_text(app: &AppHandle) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
-
+async fn reindex_text(state: &MnemnkDatabase) -> Result<()> {
     let (tx, rx) = oneshot::channel();
     state
         .event_tx
@@ -1393,8 +1364,7 @@ async fn reindex_text(app: &AppHandle) -> Result<()> {
     rx.await.context("Failed to receive reindex_text")?
 }
 
-async fn process_reindex_text(app: &AppHandle) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
+async fn process_reindex_text(state: &MnemnkDatabase) -> Result<()> {
     let db = &state.db;
     db.use_db(MNEMNK_DB).await?;
     log::info!("store::init: reindexing text");
@@ -1421,21 +1391,19 @@ async fn process_reindex_text(app: &AppHandle) -> Result<()> {
 }
 
 #[tauri::command]
-pub async fn reindex_text_cmd(app: AppHandle) -> Result<(), String> {
-    reindex_text(&app).await.map_err(|e| e.to_string())?;
+pub async fn reindex_text_cmd(state: State<'_, MnemnkDatabase>) -> Result<(), String> {
+    reindex_text(&state).await.map_err(|e| e.to_string())?;
     Ok(())
 }
 
 // search events
 
-async fn search_events(app: &AppHandle, query: String) -> Result<Vec<EventRecordInternal>> {
+async fn search_events(state: &MnemnkDatabase, query: String) -> Result<Vec<EventRecordInternal>> {
     let tokenized_query = tokenize_text(&query);
     if tokenized_query.is_empty() {
         return Ok(Vec::new());
     }
 
-    let state = app.state::<MnemnkDatabase>();
-
     let (tx, rx) = oneshot::channel();
     state
         .event_tx
@@ -1448,7 +1416,10 @@ async fn search_events(app: &AppHandle, query: String) -> Result<Vec<EventRecord
     rx.await.context("Failed to receive search_events")?
 }
 
-async fn process_search_events(app: &AppHandle, query: String) -> Result<Vec<EventRecordInternal>> {
+async fn process_search_events(
+    state: &MnemnkDatabase,
+    query: String,
+) -> Result<Vec<EventRecordInternal>> {
     let sql = r#"
         SELECT 
             id,
@@ -1466,7 +1437,6 @@ async fn process_search_events(app: &AppHandle, query: String) -> Result<Vec<Eve
         ;
         "#;
 
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
     db.use_db(MNEMNK_DB).await?;
     let mut result = db.query(sql).bind(("query", query)).await?;
@@ -1475,8 +1445,11 @@ async fn process_search_events(app: &AppHandle, query: String) -> Result<Vec<Eve
 }
 
 #[tauri::command]
-pub async fn search_events_cmd(app: AppHandle, query: String) -> Result<Vec<EventRecord>, String> {
-    let result = search_events(&app, query)
+pub async fn search_events_cmd(
+    state: State<'_, MnemnkDatabase>,
+    query: String,
+) -> Result<Vec<EventRecord>, String> {
+    let result = search_events(&state, query)
         .await
         .map_err(|e| e.to_string())?;
     let events = result
@@ -1495,9 +1468,7 @@ pub async fn search_events_cmd(app: AppHandle, query: String) -> Result<Vec<Even
 
 // Export
 
-pub async fn export_events(app: &AppHandle, path: &str) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
-
+pub async fn export_events(state: &MnemnkDatabase, path: &str) -> Result<()> {
     let (tx, rx) = oneshot::channel();
     state
         .event_tx
@@ -1510,9 +1481,7 @@ pub async fn export_events(app: &AppHandle, path: &str) -> Result<()> {
     rx.await.context("Failed to receive export_events")?
 }
 
-pub async fn process_export_events(app: &AppHandle, path: String) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
-
+pub async fn process_export_events(state: &MnemnkDatabase, path: String) -> Result<()> {
     log::info!("Exporting events to {}", path);
 
     // Query to get all events with necessary fields (excluding local_y, local_ym, local_ymd, text_tokens)
@@ -1582,15 +1551,18 @@ struct ExportEventRecord {
 }
 
 #[tauri::command]
-pub async fn export_events_cmd(app: AppHandle, path: String) -> Result<(), String> {
-    export_events(&app, &path).await.map_err(|e| e.to_string())
+pub async fn export_events_cmd(
+    state: State<'_, MnemnkDatabase>,
+    path: String,
+) -> Result<(), String> {
+    export_events(&state, &path)
+        .await
+        .map_err(|e| e.to_string())
 }
 
 // import
 
-pub async fn import_events(app: &AppHandle, path: &str) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
-
+pub async fn import_events(state: &MnemnkDatabase, path: &str) -> Result<()> {
     let (tx, rx) = oneshot::channel();
     state
         .event_tx
@@ -1603,9 +1575,7 @@ pub async fn import_events(app: &AppHandle, path: &str) -> Result<()> {
     rx.await.context("Failed to receive import_events")?
 }
 
-pub async fn process_import_events(app: &AppHandle, path: String) -> Result<()> {
-    let state = app.state::<MnemnkDatabase>();
-
+pub async fn process_import_events(state: &MnemnkDatabase, path: String) -> Result<()> {
     log::info!("Importing events from {}", path);
 
     let file = fs::File::open(path)?;
@@ -1637,8 +1607,7 @@ pub async fn process_import_events(app: &AppHandle, path: String) -> Result<()>
 
     // Get day_start_hour setting, default to 0 if not set
     let day_start_hour = {
-        let settings = app.state::<Mutex<CoreSettings>>();
-        let settings = settings.lock().unwrap();
+        let settings = state.settings.lock().unwrap();
         settings.day_start_hour.unwrap_or(0)
     };
 
@@ -1726,16 +1695,20 @@ pub async fn process_import_events(app: &AppHandle, path: String) -> Result<()>
 }
 
 #[tauri::command]
-pub async fn import_events_cmd(app: AppHandle, path: String) -> Result<(), String> {
-    import_events(&app, &path).await.map_err(|e| e.to_string())
+pub async fn import_events_cmd(
+    state: State<'_, MnemnkDatabase>,
+    path: String,
+) -> Result<(), String> {
+    import_events(&state, &path)
+        .await
+        .map_err(|e| e.to_string())
 }
 
 // Backup
 
-async fn process_backup(app: &AppHandle) -> Result<()> {
+async fn process_backup(state: &MnemnkDatabase) -> Result<()> {
     let enable_auto_backup = {
-        let settings = app.state::<Mutex<CoreSettings>>();
-        let settings = settings.lock().unwrap();
+        let settings = state.settings.lock().unwrap();
         settings.enable_auto_backup.unwrap_or(true)
     };
 
@@ -1744,8 +1717,7 @@ async fn process_backup(app: &AppHandle) -> Result<()> {
         return Ok(());
     }
 
-    let data_dir = data_dir(app).context("data_dir is not set")?;
-    let backup_dir = PathBuf::from(&data_dir).join("backups");
+    let backup_dir = state.data_dir.join("backups");
 
     if !backup_dir.exists() {
         std::fs::create_dir_all(&backup_dir)?;
@@ -1754,22 +1726,20 @@ async fn process_backup(app: &AppHandle) -> Result<()> {
     let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
     let backup_path = backup_dir.join(format!("backup_{}.surql", timestamp));
 
-    let state = app.state::<MnemnkDatabase>();
     let db = &state.db;
     db.use_db(MNEMNK_DB).await?;
     db.export(&backup_path).await?;
 
     log::info!("Database backup created: {}", backup_path.display());
 
-    cleanup_old_backups(app, &backup_dir)?;
+    cleanup_old_backups(state, &backup_dir)?;
 
     Ok(())
 }
 
-fn cleanup_old_backups(app: &AppHandle, backup_dir: &Path) -> Result<()> {
+fn cleanup_old_backups(state: &MnemnkDatabase, backup_dir: &Path) -> Result<()> {
     let max_backup_count = {
-        let settings = app.state::<Mutex<CoreSettings>>();
-        let settings = settings.lock().unwrap();
+        let settings = state.settings.lock().unwrap();
         settings.max_backup_count.unwrap_or(7) as usize
     };
 
@@ -1801,10 +1771,9 @@ fn cleanup_old_backups(app: &AppHandle, backup_dir: &Path) -> Result<()> {
     Ok(())
 }
 
-pub async fn start_auto_backup(app: &AppHandle) -> Result<()> {
+pub async fn start_auto_backup(state: &MnemnkDatabase) -> Result<()> {
     let (enable_auto_backup, backup_interval_hours) = {
-        let settings = app.state::<Mutex<CoreSettings>>();
-        let settings = settings.lock().unwrap();
+        let settings = state.settings.lock().unwrap();
         (
             settings.enable_auto_backup.unwrap_or(false),
             settings.backup_interval_hours.unwrap_or(24),
@@ -1816,7 +1785,6 @@ pub async fn start_auto_backup(app: &AppHandle) -> Result<()> {
         return Ok(());
     }
 
-    let state = app.state::<MnemnkDatabase>();
     let event_tx = state.event_tx.clone();
 
     let backup_interval = Duration::from_secs(backup_interval_hours as u64 * 3600);
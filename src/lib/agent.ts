@@ -4,10 +4,18 @@ import { getContext, setContext } from "svelte";
 
 import type {
   AgentFlow,
+  AgentFlowBundleImport,
+  AgentFlowBundleInfo,
+  AgentFlowDiff,
   AgentFlowEdge,
+  AgentFlowHistory,
+  AgentFlowInstance,
   AgentFlowNode,
   AgentFlowNodeConfig,
   AgentFlowNodeDisplay,
+  AgentFlowNodeResult,
+  AgentFlowProblem,
+  AgentFlowRevision,
   SAgentConfig,
   SAgentDefaultConfig,
   SAgentDefinitions,
@@ -16,6 +24,7 @@ import type {
   SAgentFlowEdge,
   SAgentFlowNode,
   SAgentFlows,
+  TraceRecord,
   Viewport,
 } from "./types";
 
@@ -43,6 +52,21 @@ export async function importAgentFlow(path: string): Promise<SAgentFlow> {
   return await invoke("import_agent_flow_cmd", { path });
 }
 
+export async function exportAgentFlowBundle(flowName: string, path: string): Promise<void> {
+  await invoke("export_agent_flow_bundle_cmd", { flowName, path });
+}
+
+export async function inspectAgentFlowBundle(path: string): Promise<AgentFlowBundleInfo> {
+  return await invoke("inspect_agent_flow_bundle_cmd", { path });
+}
+
+export async function importAgentFlowBundle(
+  path: string,
+  options?: AgentFlowBundleImport,
+): Promise<SAgentFlow> {
+  return await invoke("import_agent_flow_bundle_cmd", { path, options });
+}
+
 export async function newAgentFlow(name: string): Promise<SAgentFlow> {
   return await invoke("new_agent_flow_cmd", { name });
 }
@@ -59,8 +83,104 @@ export async function saveAgentFlow(agentFlow: SAgentFlow): Promise<void> {
   await invoke("save_agent_flow_cmd", { agentFlow });
 }
 
-export async function insertAgentFlow(agentFlow: SAgentFlow): Promise<void> {
-  await invoke("insert_agent_flow_cmd", { agentFlow });
+export async function insertAgentFlow(
+  agentFlow: SAgentFlow,
+  recordHistory: boolean = true,
+): Promise<void> {
+  await invoke("insert_agent_flow_cmd", { agentFlow, recordHistory });
+}
+
+export async function setAgentFlowVariables(
+  flowName: string,
+  variables: Record<string, string>,
+): Promise<SAgentFlow> {
+  return await invoke("set_agent_flow_variables_cmd", { flowName, variables });
+}
+
+export async function listAgentFlowInstances(): Promise<AgentFlowInstance[]> {
+  return await invoke("list_agent_flow_instances_cmd");
+}
+
+export async function createAgentFlowInstance(
+  flowName: string,
+  name: string,
+  variables?: Record<string, string>,
+): Promise<AgentFlowInstance> {
+  return await invoke("create_agent_flow_instance_cmd", { flowName, name, variables });
+}
+
+export async function startAgentFlowInstance(name: string): Promise<void> {
+  await invoke("start_agent_flow_instance_cmd", { name });
+}
+
+export async function stopAgentFlowInstance(name: string): Promise<void> {
+  await invoke("stop_agent_flow_instance_cmd", { name });
+}
+
+export async function deleteAgentFlowInstance(name: string): Promise<void> {
+  await invoke("delete_agent_flow_instance_cmd", { name });
+}
+
+export async function startAgentFlow(name: string): Promise<AgentFlowNodeResult[]> {
+  return await invoke("start_agent_flow_cmd", { name });
+}
+
+export async function stopAgentFlow(name: string): Promise<AgentFlowNodeResult[]> {
+  return await invoke("stop_agent_flow_cmd", { name });
+}
+
+export async function restartAgentFlow(name: string): Promise<AgentFlowNodeResult[]> {
+  return await invoke("restart_agent_flow_cmd", { name });
+}
+
+export async function setAgentFlowEnabled(
+  name: string,
+  enabled: boolean,
+): Promise<AgentFlowNodeResult[]> {
+  return await invoke("set_agent_flow_enabled_cmd", { name, enabled });
+}
+
+export async function setAgentFlowTrace(name: string, enabled: boolean): Promise<void> {
+  await invoke("set_agent_flow_trace_cmd", { name, enabled });
+}
+
+export async function validateAgentFlow(flowName: string): Promise<AgentFlowProblem[]> {
+  return await invoke("validate_agent_flow_cmd", { flowName });
+}
+
+export async function getAgentFlowHistory(flowName: string): Promise<AgentFlowHistory> {
+  return await invoke("get_agent_flow_history_cmd", { flowName });
+}
+
+export async function undoAgentFlow(flowName: string): Promise<SAgentFlow | null> {
+  return await invoke("undo_agent_flow_cmd", { flowName });
+}
+
+export async function redoAgentFlow(flowName: string): Promise<SAgentFlow | null> {
+  return await invoke("redo_agent_flow_cmd", { flowName });
+}
+
+export async function getAgentFlowRevisions(flowName: string): Promise<AgentFlowRevision[]> {
+  return await invoke("get_agent_flow_revisions_cmd", { flowName });
+}
+
+export async function diffAgentFlowRevision(
+  flowName: string,
+  revision: string,
+  other?: string,
+): Promise<AgentFlowDiff> {
+  return await invoke("diff_agent_flow_revision_cmd", { flowName, revision, other });
+}
+
+export async function restoreAgentFlowRevision(
+  flowName: string,
+  revision: string,
+): Promise<SAgentFlow> {
+  return await invoke("restore_agent_flow_revision_cmd", { flowName, revision });
+}
+
+export async function getAgentTraces(traceId?: string): Promise<TraceRecord[]> {
+  return await invoke("get_agent_traces_cmd", { traceId });
 }
 
 const agentDefinitionsKey = Symbol("agentDefinitions");
@@ -144,6 +264,9 @@ export function deserializeAgentFlow(
     edges: validEdges.map((edge) => deserializeAgentFlowEdge(edge)),
     name: flow.name,
     viewport: flow.viewport,
+    trace: flow.trace,
+    variables: flow.variables,
+    enabled: flow.enabled,
   };
 }
 
@@ -163,6 +286,8 @@ export function deserializeAgentFlowNode(
       title: node.title,
       config: deserializeAgentConfig(node.config, default_config),
       display: deserializeAgentDisplayConfig(display_config),
+      queue: node.queue,
+      supervisor: node.supervisor,
     },
     position: {
       x: node.x,
@@ -246,15 +371,62 @@ export function serializeAgentFlow(
   name: string,
   agent_defs: SAgentDefinitions,
   viewport: Viewport,
+  trace?: boolean,
 ): SAgentFlow {
   return {
     nodes: nodes.map((node) => serializeAgentFlowNode(node, agent_defs)),
     edges: edges.map((edge) => serializeAgentFlowEdge(edge)),
     name,
     viewport,
+    trace,
+  };
+}
+
+// removes the values of the secret config entries, before the flow leaves the app
+export function removeSecretConfigs(flow: SAgentFlow, agent_defs: SAgentDefinitions): SAgentFlow {
+  return {
+    ...flow,
+    nodes: flow.nodes.map((node) => {
+      const secretKeys = (agent_defs[node.name]?.default_config ?? [])
+        .filter(([_, entry]) => entry.secret)
+        .map(([key, _]) => key);
+      if (!node.config || secretKeys.length === 0) {
+        return node;
+      }
+      const config = { ...node.config };
+      for (const key of secretKeys) {
+        delete config[key];
+      }
+      return { ...node, config };
+    }),
   };
 }
 
+// whether an output of the source kind can be connected to an input of the target kind,
+// the same as port_kinds_match in definition.rs
+export function portKindsMatch(source?: string, target?: string): boolean {
+  if (!source || !target) {
+    return true;
+  }
+  const targets = target.split("|").map((t) => t.trim());
+  return source
+    .split("|")
+    .map((s) => s.trim())
+    .some((s) => targets.some((t) => s === "*" || t === "*" || s === t));
+}
+
+// color of the handles of the ports with the kind
+export function portKindColor(kind?: string): string | null {
+  if (!kind || kind === "*") {
+    return null;
+  }
+  let hash = 0;
+  for (const c of kind.split("|")[0].trim()) {
+    hash = (hash * 31 + c.charCodeAt(0)) % 360;
+  }
+  return `hsl(${hash}, 70%, 55%)`;
+}
+
 export function serializeAgentFlowNode(
   node: AgentFlowNode,
   agent_defs: SAgentDefinitions,
@@ -272,6 +444,8 @@ export function serializeAgentFlowNode(
     y: node.position.y,
     width: node.width,
     height: node.height,
+    queue: node.data.queue,
+    supervisor: node.data.supervisor,
   };
 }
 
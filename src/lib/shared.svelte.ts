@@ -2,7 +2,13 @@ import { listen, type UnlistenFn } from "@tauri-apps/api/event";
 
 import { writable, type Writable } from "svelte/store";
 
-import type { DisplayMessage, ErrorMessage, InputMessage } from "@/lib/types";
+import type {
+  DisplayMessage,
+  DroppedMessage,
+  ErrorMessage,
+  InputMessage,
+  StatusMessage,
+} from "@/lib/types";
 
 // Display Message
 
@@ -68,6 +74,43 @@ export function subscribeInputMessage(
 
 let unlistenInput: UnlistenFn | null = null;
 
+// Dropped Message
+let droppedMessageStore: Map<string, Writable<number>> = new Map<string, Writable<number>>();
+
+export function subscribeDroppedMessage(
+  agentId: string,
+  callback: (dropped: number) => void,
+): () => void {
+  let droppedStore = droppedMessageStore.get(agentId);
+  if (!droppedStore) {
+    droppedStore = writable(0);
+    droppedMessageStore.set(agentId, droppedStore);
+  }
+  return droppedStore.subscribe(callback);
+}
+
+let unlistenDropped: UnlistenFn | null = null;
+
+// Status Message
+let statusMessageStore: Map<string, Writable<{ status: string; message?: string }>> = new Map<
+  string,
+  Writable<{ status: string; message?: string }>
+>();
+
+export function subscribeStatusMessage(
+  agentId: string,
+  callback: (status: { status: string; message?: string }) => void,
+): () => void {
+  let statusStore = statusMessageStore.get(agentId);
+  if (!statusStore) {
+    statusStore = writable({ status: "" });
+    statusMessageStore.set(agentId, statusStore);
+  }
+  return statusStore.subscribe(callback);
+}
+
+let unlistenStatus: UnlistenFn | null = null;
+
 //
 
 $effect.root(() => {
@@ -110,10 +153,36 @@ $effect.root(() => {
     unlistenInput = unlistenFn;
   });
 
+  // Listen for dropped messages
+  listen<DroppedMessage>("mnemnk:dropped", (event) => {
+    const { agent_id, dropped } = event.payload;
+    let droppedStore = droppedMessageStore.get(agent_id);
+    if (!droppedStore) {
+      return;
+    }
+    droppedStore.set(dropped);
+  }).then((unlistenFn) => {
+    unlistenDropped = unlistenFn;
+  });
+
+  // Listen for status messages
+  listen<StatusMessage>("mnemnk:status", (event) => {
+    const { agent_id, status, message } = event.payload;
+    let statusStore = statusMessageStore.get(agent_id);
+    if (!statusStore) {
+      return;
+    }
+    statusStore.set({ status, message });
+  }).then((unlistenFn) => {
+    unlistenStatus = unlistenFn;
+  });
+
   return () => {
     unlistenDisplay?.();
     unlistenError?.();
     unlistenInput?.();
+    unlistenDropped?.();
+    unlistenStatus?.();
   };
 });
 
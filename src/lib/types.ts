@@ -6,12 +6,15 @@ export type SAgentGlobalConfigs = Record<string, SAgentConfigs>;
 export type SAgentDefinition = {
   kind: string;
   name: string;
+  version?: string;
   title: string | null;
   description: string | null;
   category: string | null;
   path: string;
   inputs: string[] | null;
   outputs: string[] | null;
+  input_kinds?: Record<string, string>;
+  output_kinds?: Record<string, string>;
   default_config: SAgentDefaultConfig | null;
   global_config: SAgentGlobalConfig | null;
   display_config: SAgentDisplayConfig | null;
@@ -26,6 +29,12 @@ export type SAgentConfigEntry = {
   title?: string | null;
   description?: string | null;
   hidden?: boolean | null;
+  min?: number;
+  max?: number;
+  step?: number;
+  enum?: any[];
+  required?: boolean;
+  secret?: boolean;
 };
 
 export type SAgentConfigValueType =
@@ -35,7 +44,9 @@ export type SAgentConfigValueType =
   | "number"
   | "string"
   | "text"
-  | "object";
+  | "object"
+  | "file"
+  | "directory";
 
 export type SAgentDisplayConfig = [string, SAgentDisplayConfigEntry][];
 
@@ -59,10 +70,14 @@ export type SAgentDisplayConfigType =
 export type SAgentFlows = Record<string, SAgentFlow>;
 
 export type SAgentFlow = {
+  version?: number;
   nodes: SAgentFlowNode[];
   edges: SAgentFlowEdge[];
   name: string;
   viewport: Viewport | null;
+  trace?: boolean;
+  variables?: Record<string, string>;
+  enabled?: boolean;
 };
 
 export type SAgentConfigs = Record<string, SAgentConfig>;
@@ -78,6 +93,21 @@ export type SAgentFlowNode = {
   y: number;
   width?: number;
   height?: number;
+  queue?: SAgentQueueConfig;
+  supervisor?: SAgentSupervisorConfig;
+};
+
+export type SAgentSupervisorConfig = {
+  restart?: "never" | "on_failure" | "always";
+  backoff_ms?: number;
+  max_backoff_ms?: number;
+  max_restarts?: number;
+  window_secs?: number;
+};
+
+export type SAgentQueueConfig = {
+  capacity: number;
+  policy: "block" | "drop_oldest" | "drop_newest" | "coalesce_latest";
 };
 
 export type SAgentFlowEdge = {
@@ -93,6 +123,9 @@ export type AgentFlow = {
   edges: AgentFlowEdge[];
   name: string;
   viewport: Viewport | null;
+  trace?: boolean;
+  variables?: Record<string, string>;
+  enabled?: boolean;
 };
 
 export type AgentFlowNode = Node & {
@@ -105,6 +138,75 @@ export type AgentFlowNodeData = {
   title: string | null;
   config: AgentFlowNodeConfig | null;
   display: AgentFlowNodeDisplay | null;
+  queue?: SAgentQueueConfig;
+  supervisor?: SAgentSupervisorConfig;
+  problems?: AgentFlowProblem[];
+};
+
+export type AgentFlowProblem = {
+  level: "error" | "warning";
+  node_id?: string;
+  edge_id?: string;
+  message: string;
+};
+
+export type AgentFlowHistory = {
+  undo: string[];
+  redo: string[];
+};
+
+export type AgentFlowRevision = {
+  id: string;
+  timestamp: number;
+};
+
+export type AgentFlowDiff = {
+  added_nodes: SAgentFlowNode[];
+  removed_nodes: SAgentFlowNode[];
+  changed_nodes: { id: string; name: string; changes: string[] }[];
+  added_edges: SAgentFlowEdge[];
+  removed_edges: SAgentFlowEdge[];
+};
+
+export type AgentFlowBundleAgent = {
+  name: string;
+  kind: string;
+  version?: string;
+};
+
+export type AgentFlowBundleManifest = {
+  format: number;
+  app_version: string;
+  flows: string[];
+  agents: AgentFlowBundleAgent[];
+  boards: string[];
+  global_configs: SAgentConfigs;
+  variables: Record<string, string>;
+};
+
+export type AgentFlowBundleInfo = {
+  manifest: AgentFlowBundleManifest;
+  missing_agents: string[];
+  other_versions: AgentFlowBundleAgent[];
+};
+
+export type AgentFlowBundleImport = {
+  boards?: Record<string, string>;
+  configs?: Record<string, SAgentConfig>;
+  global_configs?: SAgentConfigs;
+  variables?: Record<string, string>;
+};
+
+export type AgentFlowNodeResult = {
+  node_id: string;
+  error?: string;
+};
+
+export type AgentFlowInstance = {
+  name: string;
+  flow: string;
+  variables: Record<string, string>;
+  running: boolean;
 };
 
 export type AgentFlowNodeConfig = Record<string, any>;
@@ -177,3 +279,34 @@ export type InputMessage = {
   agent_id: string;
   ch: string;
 };
+
+export type TraceRecord = {
+  id: string;
+  flow: string;
+  hops: {
+    source: string;
+    ch: string;
+    target: string;
+    target_ch: string;
+    time: number;
+    preview: string;
+  }[];
+};
+
+export type StatusMessage = {
+  agent_id: string;
+  status: string;
+  message?: string;
+};
+
+export type DroppedMessage = {
+  agent_id: string;
+  dropped: number;
+};
+
+export type AgentDefsMessage = {
+  added: string[];
+  changed: string[];
+  removed: string[];
+  errors: string[];
+};
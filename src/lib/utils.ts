@@ -73,3 +73,17 @@ export async function setAgentGlobalConfig(
 ): Promise<void> {
   await invoke("set_agent_global_config_cmd", { agentName, agentConfig });
 }
+
+// secrets
+
+export async function getSecretNames(): Promise<string[]> {
+  return await invoke("get_secret_names_cmd");
+}
+
+export async function setSecret(name: string, value: string): Promise<void> {
+  await invoke("set_secret_cmd", { name, value });
+}
+
+export async function removeSecret(name: string): Promise<void> {
+  await invoke("remove_secret_cmd", { name });
+}